@@ -0,0 +1,65 @@
+use crate::map::AssetPaths;
+use crate::map::bsp::BSP;
+use crate::map::dependencies::Dependency;
+use crate::map::load_options::BspLoadOptions;
+
+/// Load the entities and texture directory of a map and print a manifest of
+/// its external dependencies, searched for in `asset_paths`. Returns the
+/// process exit code: non-zero when the map fails to parse or a required
+/// dependency is missing. Spec violations fail the check, the issues loading
+/// tolerates anyway are listed after the manifest.
+pub fn run_preflight_check(path: &String, asset_paths: AssetPaths) -> i32 {
+    let options: BspLoadOptions = BspLoadOptions {
+        asset_paths,
+        metadata_only: true,
+        ..BspLoadOptions::strict()
+    };
+    let bsp: BSP = match BSP::from_file_with_options(path, &options) {
+        Ok(bsp) => bsp,
+        Err(error) => {
+            eprintln!("FAILED to load {}: {}", path, error);
+            return 2;
+        },
+    };
+    let dependencies: Vec<Dependency> = bsp.dependencies();
+    let mut missing_required: usize = 0;
+    println!("Dependency manifest for {}", path);
+    for dependency in dependencies.iter() {
+        match &dependency.resolved {
+            Some(resolved) => println!(
+                "  [found]   {} {} -> {}{}",
+                dependency.kind,
+                dependency.name,
+                resolved.display(),
+                dependency.size.map_or(String::new(), |size: u64| format!(" ({} bytes)", size)),
+            ),
+            None => {
+                if dependency.required {
+                    missing_required += 1;
+                }
+                println!(
+                    "  [{}] {} {}, searched: {}",
+                    if dependency.required { "MISSING" } else { "missing" },
+                    dependency.kind,
+                    dependency.name,
+                    dependency.searched.iter()
+                        .map(|path: &std::path::PathBuf| path.display().to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                );
+            },
+        }
+    }
+    println!(
+        "{} dependencies, {} required missing",
+        dependencies.len(),
+        missing_required,
+    );
+    if !bsp.load_issues.is_empty() {
+        println!("{} load issues", bsp.load_issues.len());
+        for issue in bsp.load_issues.iter() {
+            println!("  [{}] {}", issue.context, issue.message);
+        }
+    }
+    return if missing_required > 0 { 1 } else { 0 };
+}
@@ -0,0 +1,304 @@
+use std::path::PathBuf;
+
+use slog::Level;
+
+use crate::core::frame_pacing::{FramePacing, MAX_FPS_CAP, MIN_FPS_CAP};
+use crate::core::scale::{DEFAULT_WORLD_SCALE, MAX_WORLD_SCALE, MIN_WORLD_SCALE};
+use crate::map::AssetPaths;
+use crate::map::load_options::BspLoadOptions;
+
+pub const DEFAULT_MAP: &str = "maps/crossfire.bsp";
+const DEFAULT_WIDTH: u32 = 1280;
+const DEFAULT_HEIGHT: u32 = 720;
+// Player movement ticks per second, as GoldSrc servers run by default
+const DEFAULT_TICK_RATE: u32 = 100;
+// Vertical fields of view the projection can represent, in degrees
+const MIN_FOV: usize = 1;
+const MAX_FOV: usize = 179;
+
+pub const USAGE: &str = "\
+Usage: lambda [map.bsp] [options]
+       lambda --check <map.bsp>
+       lambda --self-test
+
+Options:
+    --width <pixels>      Window width, default 1280
+    --height <pixels>     Window height, default 720
+    --fullscreen          Borderless fullscreen, Alt+Enter switches at runtime
+    --monitor <index>     Monitor to open the window on, default the primary monitor
+    --wad-dir <path>      Directory searched for WADs, repeat to search several in order
+    --sky-dir <path>      Directory skybox textures are loaded from
+    --fov <degrees>       Vertical field of view
+    --frame-pacing <mode> vsync, uncapped or a frame rate cap in fps, default vsync
+    --tick-rate <hz>      Simulation ticks per second, default 100
+    --world-scale <units> World units per metre that speeds, clip planes and fog scale with, default 39.37
+    --camera-path <file>  Play a camera path on start, F9 plays it again
+    --capture             Step the camera path at 60 fps and save every frame to screenshots/capture
+    --novis               Skip the visibility lists and draw every leaf
+    --log-level <level>   One of critical, error, warning, info, debug or trace
+    --check               Print the map's dependency manifest and exit
+    --export-obj <file>   Write the map's world geometry as a Wavefront OBJ and exit
+    --self-test           Load, draw and screenshot a built in map, printing PASS or FAIL per stage
+    --help                Print this message";
+
+/// Settings given on the command line, anything not given keeps its default
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub map: String,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    pub monitor: Option<usize>, // Index into the available monitors, None for the primary one
+    pub wad_dirs: Vec<PathBuf>, // In search order, empty keeps the default WAD directory
+    pub sky_dir: Option<PathBuf>,
+    pub fov: Option<usize>, // None keeps the camera's default
+    pub tick_rate: u32, // Simulation ticks per second, independent of the frame rate
+    pub world_scale: f32, // World units per metre
+    pub frame_pacing: FramePacing,
+    pub camera_path: Option<PathBuf>, // Knots of a camera path to play
+    pub capture: bool, // Play the camera path at a fixed step, saving each frame
+    pub novis: bool,
+    pub log_level: Level,
+    pub check: bool, // Run the preflight check on the map instead of opening a window
+    pub export_obj: Option<PathBuf>, // Export the loaded map here instead of opening a window
+    pub self_test: bool, // Run the pipeline on the embedded micro map and exit
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        return Options {
+            map: DEFAULT_MAP.to_string(),
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            fullscreen: false,
+            monitor: None,
+            wad_dirs: Vec::new(),
+            sky_dir: None,
+            fov: None,
+            tick_rate: DEFAULT_TICK_RATE,
+            world_scale: DEFAULT_WORLD_SCALE,
+            frame_pacing: FramePacing::default(),
+            camera_path: None,
+            capture: false,
+            novis: false,
+            log_level: Level::Trace,
+            check: false,
+            export_obj: None,
+            self_test: false,
+            help: false,
+        };
+    }
+}
+
+impl Options {
+
+    /// Parse the arguments after the program name. The error describes the
+    /// first argument that could not be used.
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options: Options = Options::default();
+        let mut map: Option<String> = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| -> Result<&String, String> {
+                return args.next().ok_or(format!("{} needs a value", flag));
+            };
+            match arg.as_str() {
+                "--width" => options.width = parse_size(arg, value(arg)?)?,
+                "--height" => options.height = parse_size(arg, value(arg)?)?,
+                "--fullscreen" => options.fullscreen = true,
+                "--monitor" => {
+                    let monitor: &String = value(arg)?;
+                    options.monitor = Some(monitor.parse::<usize>()
+                        .map_err(|_| format!("--monitor must be a monitor index, got {:?}", monitor))?);
+                },
+                "--wad-dir" => options.wad_dirs.push(PathBuf::from(value(arg)?)),
+                "--sky-dir" => options.sky_dir = Some(PathBuf::from(value(arg)?)),
+                "--fov" => {
+                    let fov: &String = value(arg)?;
+                    options.fov = Some(fov.parse::<usize>().ok()
+                        .filter(|fov: &usize| (MIN_FOV..=MAX_FOV).contains(fov))
+                        .ok_or(format!("--fov must be between {} and {} degrees, got {:?}", MIN_FOV, MAX_FOV, fov))?);
+                },
+                "--frame-pacing" => {
+                    let frame_pacing: &String = value(arg)?;
+                    options.frame_pacing = FramePacing::parse(frame_pacing).ok_or(format!(
+                        "--frame-pacing must be vsync, uncapped or a cap from {} to {} fps, got {:?}",
+                        MIN_FPS_CAP,
+                        MAX_FPS_CAP,
+                        frame_pacing,
+                    ))?;
+                },
+                "--tick-rate" => {
+                    let tick_rate: &String = value(arg)?;
+                    options.tick_rate = tick_rate.parse::<u32>().ok()
+                        .filter(|tick_rate: &u32| *tick_rate > 0)
+                        .ok_or(format!("--tick-rate must be a positive number of ticks per second, got {:?}", tick_rate))?;
+                },
+                "--world-scale" => {
+                    let world_scale: &String = value(arg)?;
+                    options.world_scale = world_scale.parse::<f32>().ok()
+                        .filter(|world_scale: &f32| (MIN_WORLD_SCALE..=MAX_WORLD_SCALE).contains(world_scale))
+                        .ok_or(format!(
+                            "--world-scale must be between {} and {} units per metre, got {:?}",
+                            MIN_WORLD_SCALE,
+                            MAX_WORLD_SCALE,
+                            world_scale,
+                        ))?;
+                },
+                "--camera-path" => options.camera_path = Some(PathBuf::from(value(arg)?)),
+                "--capture" => options.capture = true,
+                "--novis" => options.novis = true,
+                "--log-level" => {
+                    let level: &String = value(arg)?;
+                    options.log_level = level.parse::<Level>()
+                        .map_err(|_| format!("Unknown log level {:?}", level))?;
+                },
+                "--check" => options.check = true,
+                "--export-obj" => options.export_obj = Some(PathBuf::from(value(arg)?)),
+                "--self-test" => options.self_test = true,
+                "--help" | "-h" => options.help = true,
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                path => {
+                    if let Some(previous) = map.replace(path.to_string()) {
+                        return Err(format!("Only one map can be given, got {} and {}", previous, path));
+                    }
+                },
+            };
+        }
+        if options.check && map.is_none() {
+            return Err("--check needs a map".to_string());
+        }
+        if options.capture && options.camera_path.is_none() {
+            return Err("--capture needs a --camera-path".to_string());
+        }
+        if let Some(map) = map {
+            options.map = map;
+        }
+        return Ok(options);
+    }
+
+    /// Default asset directories with the ones given replacing them
+    pub fn asset_paths(&self) -> AssetPaths {
+        let mut asset_paths: AssetPaths = AssetPaths::default();
+        if !self.wad_dirs.is_empty() {
+            asset_paths.wad_dirs = self.wad_dirs.clone();
+        }
+        if let Some(sky_dir) = &self.sky_dir {
+            asset_paths.sky_dir = sky_dir.clone();
+        }
+        return asset_paths;
+    }
+
+    pub fn load_options(&self) -> BspLoadOptions {
+        return BspLoadOptions {
+            skip_vis: self.novis,
+            asset_paths: self.asset_paths(),
+            ..BspLoadOptions::lenient()
+        };
+    }
+
+}
+
+fn parse_size(flag: &str, value: &String) -> Result<u32, String> {
+    return value.parse::<u32>().ok()
+        .filter(|size: &u32| *size > 0)
+        .ok_or(format!("{} must be a positive number of pixels, got {:?}", flag, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg: &&str| arg.to_string()).collect();
+        return Options::parse(&args);
+    }
+
+    #[test]
+    fn no_arguments_keep_the_defaults() {
+        let options: Options = parse(&[]).unwrap();
+        assert_eq!(options, Options::default());
+        assert_eq!(options.map, DEFAULT_MAP);
+        assert_eq!((options.width, options.height), (1280, 720));
+        assert_eq!(options.tick_rate, 100);
+        assert_eq!(options.world_scale, DEFAULT_WORLD_SCALE);
+        assert_eq!(options.frame_pacing, FramePacing::VSync);
+        assert_eq!(options.asset_paths(), AssetPaths::default());
+    }
+
+    #[test]
+    fn options_are_read_around_the_map() {
+        let options: Options = parse(&[
+            "--width", "800", "maps/test3.bsp", "--height", "600", "--fullscreen", "--fov", "90",
+            "--novis", "--log-level", "debug", "--sky-dir", "gfx/env", "--frame-pacing", "144",
+        ]).unwrap();
+        assert_eq!(options.map, "maps/test3.bsp");
+        assert_eq!((options.width, options.height, options.fov), (800, 600, Some(90)));
+        assert!(options.fullscreen && options.novis);
+        assert_eq!(options.log_level, Level::Debug);
+        assert_eq!(options.frame_pacing, FramePacing::Capped(144));
+        assert_eq!(options.asset_paths().sky_dir, PathBuf::from("gfx/env"));
+        assert!(options.load_options().skip_vis);
+    }
+
+    #[test]
+    fn repeated_wad_dirs_are_searched_in_order() {
+        let options: Options = parse(&["--wad-dir", "valve", "map.bsp", "--wad-dir", "cstrike"]).unwrap();
+        assert_eq!(options.wad_dirs, vec![PathBuf::from("valve"), PathBuf::from("cstrike")]);
+        assert_eq!(options.asset_paths().wad_dirs, options.wad_dirs);
+    }
+
+    #[test]
+    fn bad_numbers_are_rejected() {
+        for args in [
+            ["--width", "0"],
+            ["--width", "wide"],
+            ["--height", "-720"],
+            ["--fov", "180"],
+            ["--fov", "0"],
+            ["--monitor", "first"],
+            ["--tick-rate", "0"],
+            ["--frame-pacing", "5"],
+            ["--world-scale", "0.5"],
+            ["--world-scale", "300"],
+            ["--world-scale", "metric"],
+        ] {
+            let error: String = parse(&args).unwrap_err();
+            assert!(error.starts_with(args[0]), "{:?} gave {}", args, error);
+        }
+        assert_eq!(parse(&["--width"]).unwrap_err(), "--width needs a value");
+    }
+
+    #[test]
+    fn world_scale_takes_units_per_metre() {
+        assert_eq!(parse(&["--world-scale", "64"]).unwrap().world_scale, 64.0);
+        assert_eq!(parse(&["--world-scale", "1"]).unwrap().world_scale, MIN_WORLD_SCALE);
+        assert_eq!(parse(&["--world-scale", "256"]).unwrap().world_scale, MAX_WORLD_SCALE);
+    }
+
+    #[test]
+    fn unknown_flags_and_extra_maps_are_errors() {
+        assert_eq!(parse(&["--wireframe"]).unwrap_err(), "Unknown option --wireframe");
+        assert!(parse(&["a.bsp", "b.bsp"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--check"]).is_err());
+        assert!(parse(&["--capture"]).is_err());
+    }
+
+    #[test]
+    fn self_test_needs_no_map() {
+        let options: Options = parse(&["--self-test"]).unwrap();
+        assert!(options.self_test);
+        assert_eq!(options.map, DEFAULT_MAP);
+    }
+
+    #[test]
+    fn export_obj_takes_an_output_path() {
+        let options: Options = parse(&["maps/test3.bsp", "--export-obj", "out/test3.obj"]).unwrap();
+        assert_eq!(options.export_obj, Some(PathBuf::from("out/test3.obj")));
+        assert_eq!(options.map, "maps/test3.bsp");
+        assert_eq!(parse(&["--export-obj"]).unwrap_err(), "--export-obj needs a value");
+    }
+
+}
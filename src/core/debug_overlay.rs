@@ -0,0 +1,346 @@
+use glium::glutin;
+use glium::glutin::event::WindowEvent;
+use std::collections::VecDeque;
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+use crate::core::entity_inspector::EntityInspector;
+use crate::core::frame_pacing::{FramePacing, MAX_FPS_CAP, MIN_FPS_CAP};
+use crate::core::scale::{ScaleDefaults, ScaleOverrides, ScaleSettings, MAX_WORLD_SCALE, MIN_WORLD_SCALE};
+use crate::core::imgui_platform::ImguiPlatform;
+use crate::core::stats::{MapStats, StatsTracker};
+use crate::core::texture_browser::TextureBrowser;
+use crate::core::timestep::FixedTimestep;
+use crate::map::bsp30::ContentType;
+use crate::map::bsp_renderable::{BSPRenderable, LightstyleStats};
+use crate::map::load_options::LoadIssues;
+use crate::rendering::renderable::{LightstyleMode, RenderSettings};
+use crate::rendering::renderer::{RenderStats, Renderer};
+use crate::util::memory::MemoryReport;
+
+// Frames kept for the frame time graph
+const FRAME_HISTORY: usize = 240;
+// Top of the frame time graph in milliseconds, two frames at 60 Hz
+const GRAPH_MAX_MS: f32 = 33.3;
+const GRAPH_SIZE: [f32; 2] = [0.0, 60.0];
+const WINDOW_POSITION: [f32; 2] = [10.0, 10.0];
+// Period the measured tick rate is averaged over
+const TICK_RATE_WINDOW: Duration = Duration::from_secs(1);
+// Cap offered when switching to a capped frame rate for the first time
+const DEFAULT_FPS_CAP: u32 = 60;
+const RELOAD_ERROR_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+
+/// What the overlay shows about the last frame, gathered from the renderable
+/// after it has been drawn
+pub struct DebugStats {
+    pub camera_position: glm::Vec3,
+    pub pitch: f32, // Degrees
+    pub yaw: f32,
+    pub leaf: Option<usize>, // None when the camera is outside the tree
+    pub contents: Option<ContentType>,
+    pub visible_leaves: usize,
+    pub render: RenderStats,
+    pub texture_bytes: usize,
+    pub lightmap_bytes: usize,
+    pub lightstyles: LightstyleStats,
+}
+
+impl DebugStats {
+
+    pub fn collect(renderable: &BSPRenderable) -> Self {
+        let leaf: Option<usize> = renderable.camera_leaf();
+        let memory: MemoryReport = renderable.memory_report();
+        return DebugStats {
+            camera_position: renderable.camera().position(),
+            pitch: renderable.camera().pitch(),
+            yaw: renderable.camera().yaw(),
+            leaf,
+            contents: leaf.and_then(|leaf: usize| renderable.leaf_contents(leaf)),
+            visible_leaves: renderable.visible_leaf_count(leaf),
+            render: renderable.renderer().stats(),
+            texture_bytes: memory.bytes("gpu textures"),
+            lightmap_bytes: memory.bytes("gpu lightmaps"),
+            lightstyles: renderable.lightstyle_stats(),
+        };
+    }
+
+}
+
+/// imgui windows of frame timing, camera and render counters, the entity
+/// inspector and the texture browser, hidden until toggled. Window events only reach imgui while it is shown.
+pub struct DebugOverlay {
+    context: imgui::Context,
+    platform: ImguiPlatform,
+    entity_inspector: EntityInspector,
+    texture_browser: TextureBrowser,
+    visible: bool,
+    frame_times: VecDeque<f32>, // Milliseconds, oldest first
+    tick_window_start: Instant,
+    window_ticks: usize, // Ticks run since the window started
+    measured_tick_rate: f32, // Ticks per second over the last full window
+    tick_time: Duration, // Time the last ticks took to run, per tick
+    fps_cap: u32, // Last cap chosen, kept while another pacing is selected
+    reload_error: Option<String>, // Why the last map reload failed, cleared by a successful one
+}
+
+impl DebugOverlay {
+
+    /// Overlay for a window of `size` physical pixels, drawn by `renderer`
+    pub fn new(renderer: &dyn Renderer, size: glutin::dpi::PhysicalSize<u32>, scale_factor: f64) -> Result<Self> {
+        let mut context: imgui::Context = imgui::Context::create();
+        let platform: ImguiPlatform = ImguiPlatform::new(&mut context, size, scale_factor);
+        renderer.init_imgui(&mut context)?;
+        return Ok(DebugOverlay {
+            context,
+            platform,
+            entity_inspector: EntityInspector::new(),
+            texture_browser: TextureBrowser::new(),
+            visible: false,
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+            tick_window_start: Instant::now(),
+            window_ticks: 0,
+            measured_tick_rate: 0.0,
+            tick_time: Duration::ZERO,
+            fps_cap: DEFAULT_FPS_CAP,
+            reload_error: None,
+        });
+    }
+
+    pub fn visible(&self) -> bool {
+        return self.visible;
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Pass a window event to imgui while the overlay is shown, returning
+    /// whether imgui wants the input for itself
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let io: &mut imgui::Io = self.context.io_mut();
+        self.platform.handle_event(io, event);
+        return match event {
+            WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_) => io.want_capture_keyboard,
+            WindowEvent::CursorMoved { .. } | WindowEvent::MouseInput { .. } | WindowEvent::MouseWheel { .. } => io.want_capture_mouse,
+            _ => false,
+        };
+    }
+
+    /// Record how long a frame took, tracked while hidden so the graph is
+    /// full when the overlay is shown
+    pub fn record_frame(&mut self, delta: Duration) {
+        if self.frame_times.len() == FRAME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(delta.as_secs_f32() * 1000.0);
+    }
+
+    /// Forget the selections and thumbnails of the previous map after a
+    /// reload, or show why the reload failed
+    pub fn map_reloaded(&mut self, renderable: &BSPRenderable, result: std::result::Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.entity_inspector = EntityInspector::new();
+                self.texture_browser.clear(renderable);
+                self.reload_error = None;
+            },
+            Err(error) => self.reload_error = Some(error),
+        };
+    }
+
+    /// Record the simulation ticks run in one pass of the event loop and how
+    /// long they took altogether
+    pub fn record_ticks(&mut self, ticks: usize, update_time: Duration) {
+        if ticks > 0 {
+            self.tick_time = update_time / ticks as u32;
+        }
+        self.window_ticks += ticks;
+        let elapsed: Duration = self.tick_window_start.elapsed();
+        if elapsed >= TICK_RATE_WINDOW {
+            self.measured_tick_rate = self.window_ticks as f32 / elapsed.as_secs_f32();
+            self.window_ticks = 0;
+            self.tick_window_start = Instant::now();
+        }
+    }
+
+    /// Average frame rate over the recorded frames
+    pub fn fps(&self) -> f32 {
+        let total_ms: f32 = self.frame_times.iter().sum();
+        if total_ms <= 0.0 {
+            return 0.0;
+        }
+        return self.frame_times.len() as f32 * 1000.0 / total_ms;
+    }
+
+    fn map_stats(ui: &imgui::Ui, stats: &MapStats) {
+        ui.text(format!("  {} loads, {:.1} minutes played", stats.loads, stats.time / 60.0));
+        ui.text(format!("  {:.0} units travelled, {:.0} furthest from spawn", stats.distance, stats.furthest));
+        ui.text(format!("  {} screenshots", stats.screenshots));
+    }
+
+    /// Units per metre and the tunables derived from it. A ticked tunable is
+    /// overridden and keeps its value when the scale changes.
+    fn world_scale(ui: &imgui::Ui, scale: &mut ScaleSettings) {
+        let mut world_scale: f32 = scale.world_scale();
+        if ui.slider("Units per metre", MIN_WORLD_SCALE, MAX_WORLD_SCALE, &mut world_scale) {
+            scale.set_world_scale(world_scale);
+        }
+        let derived: ScaleDefaults = scale.derived();
+        let mut overrides: ScaleOverrides = *scale.overrides();
+        let tunables: [(&str, &mut Option<f32>, f32); 8] = [
+            ("Forward speed", &mut overrides.forward_speed, derived.forward_speed),
+            ("Side speed", &mut overrides.side_speed, derived.side_speed),
+            ("Up speed", &mut overrides.up_speed, derived.up_speed),
+            ("Gravity", &mut overrides.gravity, derived.gravity),
+            ("Near plane", &mut overrides.near_plane, derived.near_plane),
+            ("Far plane", &mut overrides.far_plane, derived.far_plane),
+            ("Fog start", &mut overrides.fog_start, derived.fog_start),
+            ("Fog end", &mut overrides.fog_end, derived.fog_end),
+        ];
+        for (label, value, default) in tunables {
+            let mut overridden: bool = value.is_some();
+            if ui.checkbox(format!("##{}", label), &mut overridden) {
+                *value = if overridden { Some(default) } else { None };
+            }
+            ui.same_line();
+            match value {
+                Some(value) => {
+                    ui.input_float(label, value).build();
+                },
+                None => ui.text_disabled(format!("{} {:.1}", label, default)),
+            };
+        }
+        if overrides != *scale.overrides() {
+            scale.set_overrides(|current: &mut ScaleOverrides| *current = overrides);
+        }
+    }
+
+    /// Draw the overlay over the renderable's current frame, between its
+    /// render and the end of the frame. Does nothing while hidden.
+    pub fn render(
+        &mut self,
+        renderable: &mut BSPRenderable,
+        settings: &mut RenderSettings,
+        pacing: &mut FramePacing,
+        timestep: &FixedTimestep,
+        statistics: &StatsTracker,
+        scale: &mut ScaleSettings,
+        delta: Duration,
+    ) {
+        if !self.visible {
+            return;
+        }
+        self.platform.prepare_frame(self.context.io_mut(), delta);
+        let stats: DebugStats = DebugStats::collect(renderable);
+        let fps: f32 = self.fps();
+        let measured_tick_rate: f32 = self.measured_tick_rate;
+        let tick_time: Duration = self.tick_time;
+        let fps_cap: &mut u32 = &mut self.fps_cap;
+        let reload_error: &Option<String> = &self.reload_error;
+        let frame_times: &[f32] = self.frame_times.make_contiguous();
+        let load_issues: &LoadIssues = &renderable.bsp().load_issues;
+        let ui: &mut imgui::Ui = self.context.new_frame();
+        ui.window("Debug")
+            .position(WINDOW_POSITION, imgui::Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                if let Some(error) = reload_error {
+                    ui.text_colored(RELOAD_ERROR_COLOR, format!("Reload failed: {}", error));
+                    ui.separator();
+                }
+                ui.text(format!("{:.1} FPS", fps));
+                ui.plot_lines("##frame_times", frame_times)
+                    .overlay_text(format!("{:.2} ms", frame_times.last().copied().unwrap_or(0.0)))
+                    .scale_min(0.0)
+                    .scale_max(GRAPH_MAX_MS)
+                    .graph_size(GRAPH_SIZE)
+                    .build();
+                ui.text(format!("{:.0} ticks/s of {:.0} Hz", measured_tick_rate, timestep.tick_rate()));
+                ui.text(format!(
+                    "Tick {:.3} ms, {:.1} ms carried over",
+                    tick_time.as_secs_f32() * 1000.0,
+                    timestep.remainder().as_secs_f32() * 1000.0,
+                ));
+                ui.separator();
+                let position: &glm::Vec3 = &stats.camera_position;
+                ui.text(format!("Position {:.1} {:.1} {:.1}", position.x, position.y, position.z));
+                ui.text(format!("Pitch {:.1} Yaw {:.1}", stats.pitch, stats.yaw));
+                match stats.leaf {
+                    Some(leaf) => ui.text(format!(
+                        "Leaf {} ({})",
+                        leaf,
+                        stats.contents.map_or("unknown contents".to_string(), |contents: ContentType| format!("{:?}", contents)),
+                    )),
+                    None => ui.text("Outside the map"),
+                };
+                ui.text(format!("Visible leaves {}", stats.visible_leaves));
+                ui.separator();
+                ui.text(format!("Faces {}", stats.render.faces_rendered));
+                ui.text(format!("Draw calls {}", stats.render.draw_calls));
+                ui.text(format!(
+                    "Instanced {} drawing {} entities, individual {}",
+                    stats.render.instanced_draw_calls,
+                    stats.render.instances,
+                    stats.render.draw_calls - stats.render.instanced_draw_calls,
+                ));
+                ui.text(format!("Triangles {}", stats.render.triangles));
+                ui.separator();
+                ui.text(format!("Textures {:.1} KiB", stats.texture_bytes as f32 / 1024.0));
+                ui.text(format!("Lightmaps {:.1} KiB", stats.lightmap_bytes as f32 / 1024.0));
+                if !load_issues.is_empty()
+                    && ui.collapsing_header(format!("Load issues ({})", load_issues.len()), imgui::TreeNodeFlags::empty()) {
+                    for issue in load_issues.iter() {
+                        ui.text_wrapped(format!("[{}] {}", issue.context, issue.message));
+                    }
+                }
+                if ui.collapsing_header("Light styles", imgui::TreeNodeFlags::empty()) {
+                    for (label, mode) in [("GPU", LightstyleMode::Gpu), ("CPU", LightstyleMode::Cpu), ("Off", LightstyleMode::Off)] {
+                        if ui.radio_button_bool(label, settings.lightstyle_mode == mode) {
+                            settings.lightstyle_mode = mode;
+                        }
+                    }
+                    let lightstyles: &LightstyleStats = &stats.lightstyles;
+                    ui.text(format!(
+                        "{} lightmaps, {} texels in {} uploads",
+                        lightstyles.faces, lightstyles.texels, lightstyles.uploads,
+                    ));
+                    ui.text(format!("{} waiting for later frames", lightstyles.pending));
+                    ui.text(format!("{:.3} ms", lightstyles.time.as_secs_f32() * 1000.0));
+                }
+                if ui.collapsing_header("Statistics", imgui::TreeNodeFlags::empty()) {
+                    if let Some(map) = statistics.current() {
+                        ui.text("This map");
+                        DebugOverlay::map_stats(ui, map);
+                    }
+                    ui.text("All maps");
+                    DebugOverlay::map_stats(ui, &statistics.stats().totals());
+                }
+                if ui.collapsing_header("World scale", imgui::TreeNodeFlags::empty()) {
+                    DebugOverlay::world_scale(ui, scale);
+                }
+                if ui.collapsing_header("Frame pacing", imgui::TreeNodeFlags::empty()) {
+                    if ui.radio_button_bool("VSync", pacing.vsync()) {
+                        *pacing = FramePacing::VSync;
+                    }
+                    if ui.radio_button_bool("Capped", matches!(pacing, FramePacing::Capped(_))) {
+                        *pacing = FramePacing::Capped(*fps_cap);
+                    }
+                    if ui.radio_button_bool("Uncapped", *pacing == FramePacing::Uncapped) {
+                        *pacing = FramePacing::Uncapped;
+                    }
+                    if let FramePacing::Capped(fps) = pacing {
+                        ui.slider("FPS cap", MIN_FPS_CAP, MAX_FPS_CAP, fps);
+                        *fps_cap = *fps;
+                    }
+                    ui.text_disabled("VSync is switched when the window is created");
+                }
+            });
+        self.entity_inspector.draw(ui, renderable);
+        self.texture_browser.draw(ui, renderable, settings);
+        renderable.renderer().render_imgui(self.context.render());
+    }
+
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use crate::map::bsp::BSP;
+use crate::map::bsp30;
+use crate::map::bsp_renderable::BSPRenderable;
+use crate::scene::entity::Entity;
+
+const WINDOW_POSITION: [f32; 2] = [10.0, 400.0];
+const WINDOW_SIZE: [f32; 2] = [360.0, 480.0];
+// Height of the entity list, the selected entity's details go below it
+const LIST_HEIGHT: f32 = 220.0;
+
+/// imgui window listing the map's entities by classname, with the
+/// properties of the selected one
+pub struct EntityInspector {
+    filter: String, // Case insensitive part of a classname
+    selected: Option<usize>, // Index into the map's entities
+}
+
+impl EntityInspector {
+
+    pub fn new() -> Self {
+        return EntityInspector {
+            filter: String::new(),
+            selected: None,
+        };
+    }
+
+    /// Draw the window. Teleporting and highlighting are applied to the
+    /// renderable straight away.
+    pub fn draw(&mut self, ui: &imgui::Ui, renderable: &mut BSPRenderable) {
+        let mut teleport: Option<glm::Vec3> = None;
+        let mut highlight: Option<(usize, bool)> = None;
+        let mut fire: Option<(String, usize)> = None;
+        ui.window("Entities")
+            .position(WINDOW_POSITION, imgui::Condition::FirstUseEver)
+            .size(WINDOW_SIZE, imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.input_text("Filter", &mut self.filter).build();
+                ui.text_disabled(format!("{} events pending", renderable.pending_events()));
+                let filter: String = self.filter.to_ascii_lowercase();
+                let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+                for (i, entity) in renderable.entities().iter().enumerate() {
+                    let classname: &str = entity.find_property(&"classname".to_string()).map_or("", String::as_str);
+                    if classname.to_ascii_lowercase().contains(&filter) {
+                        groups.entry(classname).or_default().push(i);
+                    }
+                }
+                ui.child_window("entity_list").size([0.0, LIST_HEIGHT]).border(true).build(|| {
+                    for (classname, indices) in groups.iter() {
+                        let Some(_node) = ui.tree_node(format!("{} ({})", classname, indices.len())) else {
+                            continue;
+                        };
+                        for i in indices.iter() {
+                            let label: String = match renderable.entities()[*i].find_property(&"targetname".to_string()) {
+                                Some(targetname) => format!("{} {}##{}", i, targetname, i),
+                                None => format!("{}##{}", i, i),
+                            };
+                            if ui.selectable_config(label).selected(self.selected == Some(*i)).build() {
+                                self.selected = Some(*i);
+                            }
+                        }
+                    }
+                });
+                let Some((entity_index, entity)) = self.selected
+                    .and_then(|i: usize| renderable.entities().get(i).map(|entity: &Entity| (i, entity))) else {
+                    ui.text_disabled("No entity selected");
+                    return;
+                };
+                let mut properties: Vec<(&String, &String)> = entity.properties().iter().collect();
+                properties.sort();
+                for (key, value) in properties {
+                    ui.text(format!("{} = {}", key, value));
+                }
+                if let Some(origin) = entity_origin(entity) {
+                    if ui.button("Teleport camera here") {
+                        teleport = Some(origin);
+                    }
+                }
+                if let Some(target) = entity.find_property(&"target".to_string()) {
+                    if ui.button(format!("Fire {}", target)) {
+                        fire = Some((target.clone(), entity_index));
+                    }
+                }
+                let Some(model) = BSP::brush_model_index(entity).filter(|model: &usize| *model < renderable.models().len()) else {
+                    return;
+                };
+                ui.separator();
+                ui.text(format!("Model {}", model));
+                let bounds: &bsp30::Model = &renderable.models()[model].model;
+                ui.text(format!("Lower {:.1} {:.1} {:.1}", bounds.lower.x, bounds.lower.y, bounds.lower.z));
+                ui.text(format!("Upper {:.1} {:.1} {:.1}", bounds.upper.x, bounds.upper.y, bounds.upper.z));
+                let render_mode: Option<bsp30::RenderMode> = entity.find_property(&"rendermode".to_string())
+                    .and_then(|render_mode: &String| render_mode.trim().parse::<u64>().ok())
+                    .map_or(Some(bsp30::RenderMode::RenderModeNormal), num::FromPrimitive::from_u64);
+                match render_mode {
+                    Some(render_mode) => ui.text(format!("Render mode {:?}", render_mode)),
+                    None => ui.text("Render mode unknown"),
+                };
+                let mut highlighted: bool = renderable.is_entity_highlighted(entity_index);
+                if ui.checkbox("Highlight", &mut highlighted) {
+                    highlight = Some((entity_index, highlighted));
+                }
+            });
+        if let Some(origin) = teleport {
+            renderable.camera_mut().set_position(origin);
+            info!(&crate::LOGGER, "Teleported camera to {:?}", origin);
+        }
+        if let Some((entity_index, highlighted)) = highlight {
+            renderable.set_entity_highlighted(entity_index, highlighted);
+        }
+        if let Some((target, activator)) = fire {
+            info!(&crate::LOGGER, "Firing {} from entity {}", target, activator);
+            renderable.fire_target(&target, Some(activator));
+        }
+    }
+
+}
+
+/// `origin` key of an entity, brush entities without an origin brush have none
+fn entity_origin(entity: &Entity) -> Option<glm::Vec3> {
+    let origin: Vec<f32> = entity.find_property(&"origin".to_string())?
+        .split_whitespace()
+        .map_while(|component: &str| component.parse::<f32>().ok())
+        .collect();
+    let [x, y, z] = origin[..] else {
+        return None;
+    };
+    return Some(glm::vec3(x, y, z));
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::time::Duration;
+
+// Frame rates a cap can be set to
+pub const MIN_FPS_CAP: u32 = 10;
+pub const MAX_FPS_CAP: u32 = 1000;
+// Waits shorter than this are spun out, the OS sleeps too coarsely for them
+pub const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// How often frames are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FramePacing {
+    #[default]
+    VSync, // Presenting waits for the monitor's refresh, set when the window is created
+    Capped(u32), // At most this many frames per second, timed from the previous present
+    Uncapped, // Draw continuously, for benchmarking
+}
+
+impl FramePacing {
+
+    /// `vsync`, `uncapped` or a frame rate cap in frames per second
+    pub fn parse(value: &str) -> Option<FramePacing> {
+        return match value.to_ascii_lowercase().as_str() {
+            "vsync" => Some(FramePacing::VSync),
+            "uncapped" => Some(FramePacing::Uncapped),
+            fps => fps.parse::<u32>().ok()
+                .filter(|fps: &u32| (MIN_FPS_CAP..=MAX_FPS_CAP).contains(fps))
+                .map(FramePacing::Capped),
+        };
+    }
+
+    pub fn vsync(&self) -> bool {
+        return *self == FramePacing::VSync;
+    }
+
+    /// Shortest time between presents, none when frames are not capped
+    pub fn interval(&self) -> Option<Duration> {
+        return match self {
+            FramePacing::Capped(fps) => Some(Duration::from_secs(1) / (*fps).max(1)),
+            FramePacing::VSync | FramePacing::Uncapped => None,
+        };
+    }
+
+    /// Time left to wait before drawing when `since_present` has passed
+    /// since the previous present. Zero once the frame is due, including when
+    /// the last frame overran its budget, so a slow frame is never followed by
+    /// a wait.
+    pub fn wait_time(&self, since_present: Duration) -> Duration {
+        return self.interval().map_or(Duration::ZERO, |interval: Duration| interval.saturating_sub(since_present));
+    }
+
+}
+
+impl fmt::Display for FramePacing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            FramePacing::VSync => write!(f, "vsync"),
+            FramePacing::Capped(fps) => write!(f, "capped at {} fps", fps),
+            FramePacing::Uncapped => write!(f, "uncapped"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_parse_by_name_or_frame_rate() {
+        assert_eq!(FramePacing::parse("vsync"), Some(FramePacing::VSync));
+        assert_eq!(FramePacing::parse("Uncapped"), Some(FramePacing::Uncapped));
+        assert_eq!(FramePacing::parse("144"), Some(FramePacing::Capped(144)));
+        assert_eq!(FramePacing::parse("1000"), Some(FramePacing::Capped(MAX_FPS_CAP)));
+        assert_eq!(FramePacing::parse("9"), None);
+        assert_eq!(FramePacing::parse("1001"), None);
+        assert_eq!(FramePacing::parse("fast"), None);
+    }
+
+    #[test]
+    fn capped_frames_wait_out_the_rest_of_their_interval() {
+        let pacing: FramePacing = FramePacing::Capped(100);
+        assert_eq!(pacing.interval(), Some(Duration::from_millis(10)));
+        assert_eq!(pacing.wait_time(Duration::ZERO), Duration::from_millis(10));
+        assert_eq!(pacing.wait_time(Duration::from_millis(4)), Duration::from_millis(6));
+        assert_eq!(pacing.wait_time(Duration::from_millis(10)), Duration::ZERO);
+    }
+
+    #[test]
+    fn overrunning_frames_do_not_wait() {
+        let pacing: FramePacing = FramePacing::Capped(60);
+        assert_eq!(pacing.wait_time(Duration::from_millis(17)), Duration::ZERO);
+        assert_eq!(pacing.wait_time(Duration::from_secs(1)), Duration::ZERO);
+    }
+
+    #[test]
+    fn vsync_and_uncapped_frames_never_wait() {
+        for pacing in [FramePacing::VSync, FramePacing::Uncapped] {
+            assert_eq!(pacing.interval(), None);
+            assert_eq!(pacing.wait_time(Duration::ZERO), Duration::ZERO);
+            assert_eq!(pacing.wait_time(Duration::from_millis(5)), Duration::ZERO);
+        }
+        assert!(FramePacing::VSync.vsync() && !FramePacing::Uncapped.vsync());
+    }
+
+}
@@ -0,0 +1,120 @@
+use glium::glutin;
+use glium::glutin::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+use imgui::{Io, Key};
+use std::time::Duration;
+
+// Lines of text scrolled per pixel of a touchpad scroll
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// Feeds window events and frame timing to imgui. Positions and sizes are
+/// handed over in logical pixels with the scale factor as the framebuffer
+/// scale, as imgui expects.
+pub struct ImguiPlatform {
+    scale_factor: f64,
+}
+
+impl ImguiPlatform {
+
+    /// Glue for a window of `size` physical pixels. The context is set up to
+    /// keep no ini file, window layouts are not saved between runs.
+    pub fn new(context: &mut imgui::Context, size: glutin::dpi::PhysicalSize<u32>, scale_factor: f64) -> Self {
+        context.set_ini_filename(None);
+        let platform: ImguiPlatform = ImguiPlatform { scale_factor };
+        platform.set_display_size(context.io_mut(), size);
+        return platform;
+    }
+
+    fn set_display_size(&self, io: &mut Io, size: glutin::dpi::PhysicalSize<u32>) {
+        let logical: glutin::dpi::LogicalSize<f32> = size.to_logical(self.scale_factor);
+        io.display_size = [logical.width, logical.height];
+        io.display_framebuffer_scale = [self.scale_factor as f32, self.scale_factor as f32];
+    }
+
+    /// Pass a window event on to imgui
+    pub fn handle_event(&mut self, io: &mut Io, event: &WindowEvent) {
+        match event {
+            WindowEvent::Resized(size) => self.set_display_size(io, *size),
+            WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                self.scale_factor = *scale_factor;
+                self.set_display_size(io, **new_inner_size);
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let position: glutin::dpi::LogicalPosition<f32> = position.to_logical(self.scale_factor);
+                io.add_mouse_pos_event([position.x, position.y]);
+            },
+            WindowEvent::CursorLeft { .. } => io.add_mouse_pos_event([f32::MAX, f32::MAX]),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button: imgui::MouseButton = match button {
+                    MouseButton::Left => imgui::MouseButton::Left,
+                    MouseButton::Right => imgui::MouseButton::Right,
+                    MouseButton::Middle => imgui::MouseButton::Middle,
+                    MouseButton::Other(_) => return,
+                };
+                io.add_mouse_button_event(button, *state == ElementState::Pressed);
+            },
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => io.add_mouse_wheel_event([*x, *y]),
+                MouseScrollDelta::PixelDelta(position) => io.add_mouse_wheel_event([
+                    position.x as f32 / PIXELS_PER_LINE,
+                    position.y as f32 / PIXELS_PER_LINE,
+                ]),
+            },
+            WindowEvent::ModifiersChanged(modifiers) => {
+                io.add_key_event(Key::ModShift, modifiers.shift());
+                io.add_key_event(Key::ModCtrl, modifiers.ctrl());
+                io.add_key_event(Key::ModAlt, modifiers.alt());
+                io.add_key_event(Key::ModSuper, modifiers.logo());
+            },
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode.and_then(imgui_key) {
+                    io.add_key_event(key, input.state == ElementState::Pressed);
+                }
+            },
+            WindowEvent::ReceivedCharacter(character) if !character.is_control() => io.add_input_character(*character),
+            _ => (),
+        };
+    }
+
+    /// Advance imgui's clock before building a frame
+    pub fn prepare_frame(&self, io: &mut Io, delta: Duration) {
+        io.update_delta_time(delta);
+    }
+
+}
+
+/// imgui key for the keys its widgets use for navigation, editing and shortcuts
+fn imgui_key(key: VirtualKeyCode) -> Option<Key> {
+    return Some(match key {
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Left => Key::LeftArrow,
+        VirtualKeyCode::Right => Key::RightArrow,
+        VirtualKeyCode::Up => Key::UpArrow,
+        VirtualKeyCode::Down => Key::DownArrow,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Return => Key::Enter,
+        VirtualKeyCode::NumpadEnter => Key::KeypadEnter,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::LControl => Key::LeftCtrl,
+        VirtualKeyCode::RControl => Key::RightCtrl,
+        VirtualKeyCode::LShift => Key::LeftShift,
+        VirtualKeyCode::RShift => Key::RightShift,
+        VirtualKeyCode::LAlt => Key::LeftAlt,
+        VirtualKeyCode::RAlt => Key::RightAlt,
+        VirtualKeyCode::LWin => Key::LeftSuper,
+        VirtualKeyCode::RWin => Key::RightSuper,
+        VirtualKeyCode::A => Key::A,
+        VirtualKeyCode::C => Key::C,
+        VirtualKeyCode::V => Key::V,
+        VirtualKeyCode::X => Key::X,
+        VirtualKeyCode::Y => Key::Y,
+        VirtualKeyCode::Z => Key::Z,
+        _ => return None,
+    });
+}
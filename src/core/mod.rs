@@ -0,0 +1,12 @@
+pub mod shutdown;
+pub mod check;
+pub mod self_test;
+pub mod cli;
+pub mod frame_pacing;
+pub mod scale;
+pub mod imgui_platform;
+pub mod debug_overlay;
+pub mod entity_inspector;
+pub mod texture_browser;
+pub mod timestep;
+pub mod stats;
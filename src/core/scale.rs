@@ -0,0 +1,178 @@
+// GoldSrc maps are built at roughly one unit per inch
+pub const DEFAULT_WORLD_SCALE: f32 = 39.37;
+// World scales that can be set, in units per metre
+pub const MIN_WORLD_SCALE: f32 = 1.0;
+pub const MAX_WORLD_SCALE: f32 = 256.0;
+
+// Tunables in world units at the default scale, the stock GoldSrc values
+const FORWARD_SPEED: f32 = 400.0; // cl_forwardspeed
+const SIDE_SPEED: f32 = 400.0; // cl_sidespeed
+const UP_SPEED: f32 = 320.0; // cl_upspeed
+const GRAVITY: f32 = 800.0; // sv_gravity
+const NEAR_PLANE: f32 = 4.0;
+const FAR_PLANE: f32 = 16384.0; // Covers the largest map the engine allows diagonally
+const FOG_START: f32 = 512.0; // Linear fog range when an env_fog gives neither a density nor distances
+const FOG_END: f32 = 4096.0;
+
+/// Distance based tunables in world units, all derived from a single world
+/// scale in units per metre
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleDefaults {
+    pub forward_speed: f32, // Units per second
+    pub side_speed: f32,
+    pub up_speed: f32,
+    pub gravity: f32, // Units per second squared
+    pub near_plane: f32,
+    pub far_plane: f32,
+    pub fog_start: f32,
+    pub fog_end: f32,
+}
+
+impl ScaleDefaults {
+
+    /// Stock values scaled from the default world scale to `units_per_metre`,
+    /// non positive scales keep the default
+    pub fn from_world_scale(units_per_metre: f32) -> Self {
+        let scale: f32 = if units_per_metre > 0.0 { units_per_metre } else { DEFAULT_WORLD_SCALE };
+        let factor: f32 = scale / DEFAULT_WORLD_SCALE;
+        return ScaleDefaults {
+            forward_speed: FORWARD_SPEED * factor,
+            side_speed: SIDE_SPEED * factor,
+            up_speed: UP_SPEED * factor,
+            gravity: GRAVITY * factor,
+            near_plane: NEAR_PLANE * factor,
+            far_plane: FAR_PLANE * factor,
+            fog_start: FOG_START * factor,
+            fog_end: FOG_END * factor,
+        };
+    }
+
+}
+
+impl Default for ScaleDefaults {
+    fn default() -> Self {
+        return ScaleDefaults::from_world_scale(DEFAULT_WORLD_SCALE);
+    }
+}
+
+/// World scale plus any values explicitly set by the user. Overridden values
+/// survive a change of world scale, everything else is re-derived from it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleSettings {
+    world_scale: f32,
+    overrides: ScaleOverrides,
+    effective: ScaleDefaults,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ScaleOverrides {
+    pub forward_speed: Option<f32>,
+    pub side_speed: Option<f32>,
+    pub up_speed: Option<f32>,
+    pub gravity: Option<f32>,
+    pub near_plane: Option<f32>,
+    pub far_plane: Option<f32>,
+    pub fog_start: Option<f32>,
+    pub fog_end: Option<f32>,
+}
+
+impl ScaleSettings {
+
+    pub fn new(world_scale: f32) -> Self {
+        let mut settings: ScaleSettings = ScaleSettings {
+            world_scale,
+            overrides: ScaleOverrides::default(),
+            effective: ScaleDefaults::default(),
+        };
+        settings.rederive();
+        return settings;
+    }
+
+    pub fn world_scale(&self) -> f32 {
+        return self.world_scale;
+    }
+
+    pub fn set_world_scale(&mut self, world_scale: f32) {
+        self.world_scale = world_scale;
+        self.rederive();
+    }
+
+    pub fn overrides(&self) -> &ScaleOverrides {
+        return &self.overrides;
+    }
+
+    /// Apply a change to the explicit overrides, `None` reverts a value to its derived default
+    pub fn set_overrides(&mut self, apply: impl FnOnce(&mut ScaleOverrides)) {
+        apply(&mut self.overrides);
+        self.rederive();
+    }
+
+    /// Values without an override as derived from the world scale
+    pub fn derived(&self) -> ScaleDefaults {
+        return ScaleDefaults::from_world_scale(self.world_scale);
+    }
+
+    pub fn effective(&self) -> &ScaleDefaults {
+        return &self.effective;
+    }
+
+    fn rederive(&mut self) {
+        let derived: ScaleDefaults = self.derived();
+        let overrides: &ScaleOverrides = &self.overrides;
+        self.effective = ScaleDefaults {
+            forward_speed: overrides.forward_speed.unwrap_or(derived.forward_speed),
+            side_speed: overrides.side_speed.unwrap_or(derived.side_speed),
+            up_speed: overrides.up_speed.unwrap_or(derived.up_speed),
+            gravity: overrides.gravity.unwrap_or(derived.gravity),
+            near_plane: overrides.near_plane.unwrap_or(derived.near_plane),
+            far_plane: overrides.far_plane.unwrap_or(derived.far_plane),
+            fog_start: overrides.fog_start.unwrap_or(derived.fog_start),
+            fog_end: overrides.fog_end.unwrap_or(derived.fog_end),
+        };
+    }
+
+}
+
+impl Default for ScaleSettings {
+    fn default() -> Self {
+        return ScaleSettings::new(DEFAULT_WORLD_SCALE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_scale_gives_the_stock_values() {
+        let defaults: ScaleDefaults = ScaleDefaults::default();
+        assert_eq!(defaults.forward_speed, 400.0);
+        assert_eq!(defaults.up_speed, 320.0);
+        assert_eq!(defaults.near_plane, 4.0);
+        assert_eq!(defaults.far_plane, 16384.0);
+        assert_eq!(defaults.fog_start, 512.0);
+        assert_eq!(ScaleDefaults::from_world_scale(0.0), defaults);
+        assert_eq!(ScaleDefaults::from_world_scale(-1.0), defaults);
+    }
+
+    #[test]
+    fn values_scale_with_units_per_metre() {
+        let doubled: ScaleDefaults = ScaleDefaults::from_world_scale(DEFAULT_WORLD_SCALE * 2.0);
+        assert!((doubled.forward_speed - 800.0).abs() < 1e-3);
+        assert!((doubled.gravity - 1600.0).abs() < 1e-3);
+        assert!((doubled.far_plane - 32768.0).abs() < 1e-2);
+        assert!((doubled.fog_end - 8192.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn overrides_survive_a_change_of_scale() {
+        let mut settings: ScaleSettings = ScaleSettings::default();
+        settings.set_overrides(|overrides: &mut ScaleOverrides| overrides.far_plane = Some(9000.0));
+        settings.set_world_scale(DEFAULT_WORLD_SCALE * 2.0);
+        assert_eq!(settings.effective().far_plane, 9000.0);
+        assert!((settings.effective().near_plane - 8.0).abs() < 1e-4);
+        settings.set_overrides(|overrides: &mut ScaleOverrides| overrides.far_plane = None);
+        assert_eq!(settings.effective().far_plane, settings.derived().far_plane);
+    }
+
+}
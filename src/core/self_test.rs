@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::core::stats::content_key;
+use crate::map::bsp::{BSP, FaceLightmaps};
+use crate::map::bsp_renderable::{BSPRenderable, LIGHTMAP_ATLAS_SIZE};
+use crate::map::load_options::ParseStrictness;
+use crate::map::micro_map::{self, MICRO_MAP_NAME, MICRO_TEXTURES};
+use crate::map::wad::{MipmapTexture, TextureSource};
+use crate::rendering::glutin_platform::GlutinPlatform;
+use crate::rendering::renderable::{RenderSettings, Renderable};
+use crate::rendering::renderer::{Platform, Renderer};
+use crate::rendering::view::camera::Camera;
+use crate::resource::image::Image;
+
+const WINDOW_WIDTH: usize = 320;
+const WINDOW_HEIGHT: usize = 240;
+const LOG_DIR: &str = "logs";
+
+/// Outcome of one stage, skipped stages need something the machine does not have
+enum Stage {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+/// Load the embedded micro map through the whole pipeline, printing PASS,
+/// FAIL or SKIP for each stage. Returns the process exit code: non-zero when
+/// any stage fails. Stages after a failure that need its result are not run.
+pub fn run_self_test() -> i32 {
+    let mut failed: usize = 0;
+    let mut report = |name: &str, stage: Stage| {
+        let (status, detail): (&str, String) = match stage {
+            Stage::Pass(detail) => ("PASS", detail),
+            Stage::Fail(detail) => {
+                failed += 1;
+                ("FAIL", detail)
+            },
+            Stage::Skip(detail) => ("SKIP", detail),
+        };
+        println!("  [{}] {:<10} {}", status, name, detail);
+    };
+    println!("Self-test on the embedded {}", MICRO_MAP_NAME);
+    report("logs", check_log_dir());
+    let bsp: BSP = match micro_map::load_micro_map(ParseStrictness::Strict) {
+        Ok(bsp) => {
+            report("parse", Stage::Pass(format!("{} faces, {} leaves, {} entities", bsp.faces.len(), bsp.leaves.len(), bsp.entities.len())));
+            bsp
+        },
+        Err(error) => {
+            report("parse", Stage::Fail(error.to_string()));
+            println!("{} stages failed", failed);
+            return 1;
+        },
+    };
+    report("wad", check_textures(&bsp));
+    report("atlas", check_atlas(&bsp));
+    report("gpu", check_gpu(bsp));
+    println!("{} stages failed", failed);
+    return if failed > 0 { 1 } else { 0 };
+}
+
+/// The log directory can be created and written to
+fn check_log_dir() -> Stage {
+    let probe: PathBuf = PathBuf::from(LOG_DIR).join(format!(".self-test-{}", std::process::id()));
+    let written: std::io::Result<()> = std::fs::create_dir_all(LOG_DIR)
+        .and_then(|_| std::fs::write(&probe, b"lambda"))
+        .and_then(|_| std::fs::remove_file(&probe));
+    return match written {
+        Ok(()) => Stage::Pass(format!("{} is writable", LOG_DIR)),
+        Err(error) => Stage::Fail(format!("Unable to write to {}: {}", LOG_DIR, error)),
+    };
+}
+
+/// Every texture was decoded from the embedded WAD rather than substituted
+fn check_textures(bsp: &BSP) -> Stage {
+    if !bsp.missing_textures.is_empty() {
+        return Stage::Fail(format!("{} textures missing", bsp.missing_textures.len()));
+    }
+    let from_wad: usize = bsp.m_textures.iter()
+        .filter(|texture: &&MipmapTexture| matches!(texture.source, TextureSource::Wad(_)))
+        .count();
+    if from_wad != MICRO_TEXTURES.len() {
+        return Stage::Fail(format!("{} of {} textures decoded from the WAD", from_wad, MICRO_TEXTURES.len()));
+    }
+    return Stage::Pass(format!("{} textures decoded", from_wad));
+}
+
+/// Every face's lightmap packs into the smallest atlas
+fn check_atlas(bsp: &BSP) -> Stage {
+    let lightmaps: Vec<Image> = bsp.m_lightmaps.iter()
+        .map(|lightmaps: &FaceLightmaps| lightmaps.composite(&bsp.light_styles))
+        .collect();
+    let lit: usize = lightmaps.iter().filter(|lightmap: &&Image| !lightmap.data.is_empty()).count();
+    if lit != bsp.faces.len() {
+        return Stage::Fail(format!("{} of {} faces have lightmaps", lit, bsp.faces.len()));
+    }
+    return match BSPRenderable::pack_lightmaps(&lightmaps, LIGHTMAP_ATLAS_SIZE as usize) {
+        Ok((atlas, _)) => Stage::Pass(format!("{} lightmaps packed into a {}x{} atlas", lit, atlas.m_image.width, atlas.m_image.height)),
+        Err(error) => Stage::Fail(format!("Unable to pack lightmaps: {}", error)),
+    };
+}
+
+/// Upload the map, draw one frame and read it back. Skipped when no window
+/// or GL context can be created, e.g. on a machine without a display.
+fn check_gpu(bsp: BSP) -> Stage {
+    // Creating the event loop panics when there is no display server to connect to
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let platform: std::thread::Result<GlutinPlatform> = std::panic::catch_unwind(|| GlutinPlatform::new(false, false));
+    std::panic::set_hook(hook);
+    let Ok(platform) = platform else {
+        return Stage::Skip(String::from("No display to open a window on"));
+    };
+    let display: glium::Display = match platform.create_window_and_context(WINDOW_WIDTH, WINDOW_HEIGHT, "Lambda self-test".to_string(), None) {
+        Ok(display) => display,
+        Err(error) => return Stage::Skip(error.to_string()),
+    };
+    let (width, height): (u32, u32) = display.get_framebuffer_dimensions();
+    let renderer: Rc<dyn Renderer> = match platform.create_renderer(display) {
+        Ok(renderer) => Rc::from(renderer),
+        Err(error) => return Stage::Fail(format!("Failed to create renderer: {}", error)),
+    };
+    let camera: Camera = crate::spawn_camera(&bsp, width, height);
+    let mut renderable: BSPRenderable = match BSPRenderable::new(renderer, Box::new(bsp), Box::new(camera)) {
+        Ok(renderable) => renderable,
+        Err(error) => return Stage::Fail(format!("Failed to upload the map: {}", error)),
+    };
+    let mut settings: RenderSettings = RenderSettings::default();
+    settings.projection = renderable.camera().projection_matrix();
+    settings.view = renderable.camera().view_matrix();
+    settings.pitch = renderable.camera().pitch();
+    settings.yaw = renderable.camera().yaw();
+    renderable.renderer().begin_frame();
+    Renderable::render(&mut renderable, &settings);
+    if let Err(error) = renderable.renderer().end_frame() {
+        return Stage::Fail(format!("Failed to present the frame: {}", error));
+    }
+    let screenshot: Image = match renderable.renderer().screenshot() {
+        Ok(screenshot) => screenshot,
+        Err(error) => return Stage::Fail(format!("Failed to read back the frame: {}", error)),
+    };
+    // A frame of a single colour is only the clear colour, nothing was drawn
+    let first: &[u8] = &screenshot.data[..screenshot.channels.min(screenshot.data.len())];
+    if screenshot.data.chunks(screenshot.channels.max(1)).all(|pixel: &[u8]| pixel == first) {
+        return Stage::Fail(format!("{}x{} frame is blank", screenshot.width, screenshot.height));
+    }
+    return Stage::Pass(format!("{}x{} frame, checksum {}", screenshot.width, screenshot.height, content_key(&screenshot.data)));
+}
@@ -0,0 +1,141 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookOutcome {
+    Completed,
+    TimedOut,
+    Panicked,
+}
+
+struct ShutdownHook {
+    name: String,
+    timeout: Duration,
+    hook: Box<dyn FnOnce() + Send>,
+}
+
+/// Runs registered shutdown hooks in registration order, each bounded by
+/// its own timeout
+pub struct ShutdownCoordinator {
+    hooks: Vec<ShutdownHook>,
+    shutting_down: bool, // Set once shutdown begins, nothing more should be drawn or queued
+}
+
+impl ShutdownCoordinator {
+
+    pub fn new() -> Self {
+        return ShutdownCoordinator {
+            hooks: Vec::new(),
+            shutting_down: false,
+        };
+    }
+
+    pub fn register(&mut self, name: &str, timeout: Duration, hook: impl FnOnce() + Send + 'static) {
+        self.hooks.push(ShutdownHook {
+            name: name.to_string(),
+            timeout,
+            hook: Box::new(hook),
+        });
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        return self.shutting_down;
+    }
+
+    /// Run all registered hooks, returning the outcome of each in order. A hook
+    /// that exceeds its timeout is left running detached and shutdown continues.
+    pub fn shutdown(&mut self) -> Vec<(String, HookOutcome)> {
+        self.shutting_down = true;
+        let mut outcomes: Vec<(String, HookOutcome)> = Vec::with_capacity(self.hooks.len());
+        for hook in self.hooks.drain(..) {
+            info!(&crate::LOGGER, "Running shutdown hook: {}", hook.name);
+            let (tx, rx): (Sender<()>, Receiver<()>) = mpsc::channel();
+            let callback: Box<dyn FnOnce() + Send> = hook.hook;
+            let spawned = thread::Builder::new()
+                .name(format!("shutdown-{}", hook.name))
+                .spawn(move || {
+                    callback();
+                    let _ = tx.send(());
+                });
+            let outcome: HookOutcome = match spawned {
+                Ok(_) => match rx.recv_timeout(hook.timeout) {
+                    Ok(()) => HookOutcome::Completed,
+                    Err(RecvTimeoutError::Timeout) => HookOutcome::TimedOut,
+                    // Sender dropped without sending, the hook panicked
+                    Err(RecvTimeoutError::Disconnected) => HookOutcome::Panicked,
+                },
+                Err(error) => {
+                    error!(&crate::LOGGER, "Unable to spawn shutdown hook {}: {}", hook.name, error);
+                    HookOutcome::Panicked
+                },
+            };
+            match outcome {
+                HookOutcome::Completed => debug!(&crate::LOGGER, "Shutdown hook {} completed", hook.name),
+                HookOutcome::TimedOut => warn!(&crate::LOGGER, "Shutdown hook {} timed out after {:?}, continuing", hook.name, hook.timeout),
+                HookOutcome::Panicked => error!(&crate::LOGGER, "Shutdown hook {} panicked", hook.name),
+            }
+            outcomes.push((hook.name, outcome));
+        }
+        return outcomes;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const SHORT_TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn hooks_run_in_registration_order() {
+        let ran: Arc<Mutex<Vec<&'static str>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator: ShutdownCoordinator = ShutdownCoordinator::new();
+        for name in ["first", "second", "third"] {
+            let ran: Arc<Mutex<Vec<&'static str>>> = ran.clone();
+            coordinator.register(name, DEFAULT_HOOK_TIMEOUT, move || ran.lock().unwrap().push(name));
+        }
+        assert!(!coordinator.is_shutting_down());
+        let outcomes: Vec<(String, HookOutcome)> = coordinator.shutdown();
+        assert!(coordinator.is_shutting_down());
+        assert_eq!(*ran.lock().unwrap(), vec!["first", "second", "third"]);
+        assert!(outcomes.iter().all(|(_, outcome): &(String, HookOutcome)| *outcome == HookOutcome::Completed));
+    }
+
+    #[test]
+    fn slow_hooks_time_out_and_later_hooks_still_run() {
+        let mut coordinator: ShutdownCoordinator = ShutdownCoordinator::new();
+        coordinator.register("slow", SHORT_TIMEOUT, || thread::sleep(SHORT_TIMEOUT * 10));
+        coordinator.register("fast", SHORT_TIMEOUT, || {});
+        let started: std::time::Instant = std::time::Instant::now();
+        let outcomes: Vec<(String, HookOutcome)> = coordinator.shutdown();
+        assert!(started.elapsed() < SHORT_TIMEOUT * 5, "shutdown waited for the slow hook");
+        assert_eq!(outcomes, vec![
+            ("slow".to_string(), HookOutcome::TimedOut),
+            ("fast".to_string(), HookOutcome::Completed),
+        ]);
+    }
+
+    #[test]
+    fn panicking_hooks_are_reported() {
+        let mut coordinator: ShutdownCoordinator = ShutdownCoordinator::new();
+        coordinator.register("panics", DEFAULT_HOOK_TIMEOUT, || panic!("fake hook failure"));
+        coordinator.register("after", DEFAULT_HOOK_TIMEOUT, || {});
+        let outcomes: Vec<(String, HookOutcome)> = coordinator.shutdown();
+        assert_eq!(outcomes[0].1, HookOutcome::Panicked);
+        assert_eq!(outcomes[1].1, HookOutcome::Completed);
+    }
+
+    #[test]
+    fn hooks_run_only_once() {
+        let mut coordinator: ShutdownCoordinator = ShutdownCoordinator::new();
+        coordinator.register("once", DEFAULT_HOOK_TIMEOUT, || {});
+        assert_eq!(coordinator.shutdown().len(), 1);
+        assert!(coordinator.shutdown().is_empty());
+    }
+
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub const STATS_PATH: &str = "stats.toml";
+// Moving faster than this between two ticks is a teleport, such as a respawn
+// or a map reload, and does not count as distance travelled
+const TELEPORT_SPEED: f32 = 4096.0;
+
+/// Play statistics of one map, or the totals over every map
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapStats {
+    pub name: String, // Path the map was last loaded from
+    pub loads: u64,
+    pub time: f64, // Seconds spent in the map, paused ticks excluded
+    pub distance: f64, // World units travelled, teleports excluded
+    pub furthest: f64, // Furthest the camera has been from the spawn point
+    pub screenshots: u64,
+}
+
+/// Statistics of every map played, keyed by a hash of the map's contents so
+/// a renamed file keeps its numbers and an edited one starts afresh
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayStats {
+    pub maps: BTreeMap<String, MapStats>,
+}
+
+impl PlayStats {
+
+    /// Sums over every map, loads included
+    pub fn totals(&self) -> MapStats {
+        return self.maps.values().fold(MapStats::default(), |totals: MapStats, map: &MapStats| MapStats {
+            name: String::new(),
+            loads: totals.loads + map.loads,
+            time: totals.time + map.time,
+            distance: totals.distance + map.distance,
+            furthest: totals.furthest.max(map.furthest),
+            screenshots: totals.screenshots + map.screenshots,
+        });
+    }
+
+    /// Write as TOML, one `[maps."<key>"]` table per map
+    pub fn to_toml(&self) -> String {
+        let mut text: String = String::from("# Lambda play statistics\n");
+        for (key, map) in self.maps.iter() {
+            text.push_str(&format!("\n[maps.{}]\n", quote(key)));
+            text.push_str(&format!("name = {}\n", quote(&map.name)));
+            text.push_str(&format!("loads = {}\n", map.loads));
+            text.push_str(&format!("time = {:?}\n", map.time));
+            text.push_str(&format!("distance = {:?}\n", map.distance));
+            text.push_str(&format!("furthest = {:?}\n", map.furthest));
+            text.push_str(&format!("screenshots = {}\n", map.screenshots));
+        }
+        return text;
+    }
+
+    /// Read the TOML written by `to_toml`. Fails on anything else, keys
+    /// outside a map table, unknown keys and values of the wrong type included.
+    pub fn from_toml(text: &str) -> Result<PlayStats> {
+        let mut stats: PlayStats = PlayStats::default();
+        let mut current: Option<String> = None;
+        for (number, line) in text.lines().enumerate() {
+            let line: &str = line.trim();
+            let invalid = |message: &str| -> Error {
+                return Error::new(ErrorKind::InvalidData, format!("Line {}: {}", number + 1, message));
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix("[maps.").and_then(|table: &str| table.strip_suffix(']')) {
+                let key: String = unquote(table.trim()).ok_or_else(|| invalid("map key is not a quoted string"))?;
+                stats.maps.entry(key.clone()).or_default();
+                current = Some(key);
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(invalid("expected a table or a key = value pair"));
+            };
+            let Some(map) = current.as_ref().and_then(|key: &String| stats.maps.get_mut(key)) else {
+                return Err(invalid("value outside a map table"));
+            };
+            let value: &str = value.trim();
+            let number = || -> Result<f64> {
+                return value.parse::<f64>().ok()
+                    .filter(|number: &f64| number.is_finite() && *number >= 0.0)
+                    .ok_or_else(|| invalid("expected a non-negative number"));
+            };
+            let count = || -> Result<u64> {
+                return value.parse::<u64>().map_err(|_| invalid("expected a count"));
+            };
+            match name.trim() {
+                "name" => map.name = unquote(value).ok_or_else(|| invalid("name is not a quoted string"))?,
+                "loads" => map.loads = count()?,
+                "time" => map.time = number()?,
+                "distance" => map.distance = number()?,
+                "furthest" => map.furthest = number()?,
+                "screenshots" => map.screenshots = count()?,
+                other => return Err(invalid(&format!("unknown key {}", other))),
+            };
+        }
+        return Ok(stats);
+    }
+
+    /// Statistics saved at `path`. A missing file starts empty, an unreadable
+    /// or corrupt one starts empty with a warning and is replaced on the next save.
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> PlayStats {
+        let path: &Path = path.as_ref();
+        let text: String = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => return PlayStats::default(),
+            Err(error) => {
+                warn!(&crate::LOGGER, "Unable to read play statistics {}, starting afresh: {}", path.display(), error);
+                return PlayStats::default();
+            },
+        };
+        return match PlayStats::from_toml(&text) {
+            Ok(stats) => stats,
+            Err(error) => {
+                warn!(&crate::LOGGER, "Play statistics {} are corrupt, starting afresh: {}", path.display(), error);
+                PlayStats::default()
+            },
+        };
+    }
+
+}
+
+/// TOML basic string
+fn quote(value: &str) -> String {
+    return format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let inner: &str = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted: String = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.push(chars.next().filter(|c: &char| *c == '\\' || *c == '"')?),
+            '"' => return None,
+            c => unquoted.push(c),
+        };
+    }
+    return Some(unquoted);
+}
+
+/// Key of a map's statistics, FNV-1a over the BSP file, stable unlike the std hasher
+pub fn content_key(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return format!("{:016x}", hash);
+}
+
+/// Accumulates the statistics of the map being played from the simulation
+/// ticks, and saves them with the rest
+pub struct StatsTracker {
+    stats: PlayStats,
+    path: PathBuf,
+    current: Option<String>, // Key of the map being played
+    spawn: glm::Vec3,
+    last_position: Option<glm::Vec3>, // None until the first tick after a load or a pause
+}
+
+impl StatsTracker {
+
+    pub fn new(stats: PlayStats, path: PathBuf) -> Self {
+        return StatsTracker {
+            stats,
+            path,
+            current: None,
+            spawn: glm::vec3(0.0, 0.0, 0.0),
+            last_position: None,
+        };
+    }
+
+    /// Tracker for the statistics saved at `path`
+    pub fn load(path: PathBuf) -> Self {
+        return StatsTracker::new(PlayStats::load_or_default(&path), path);
+    }
+
+    pub fn stats(&self) -> &PlayStats {
+        return &self.stats;
+    }
+
+    /// Statistics of the map being played
+    pub fn current(&self) -> Option<&MapStats> {
+        return self.current.as_ref().and_then(|key: &String| self.stats.maps.get(key));
+    }
+
+    fn current_mut(&mut self) -> Option<&mut MapStats> {
+        return self.current.as_ref().and_then(|key: &String| self.stats.maps.get_mut(key));
+    }
+
+    /// Start counting for the map with contents key `key`, loaded from `name`
+    /// with the camera at `spawn`
+    pub fn map_loaded(&mut self, key: String, name: &str, spawn: glm::Vec3) {
+        let map: &mut MapStats = self.stats.maps.entry(key.clone()).or_default();
+        map.name = name.to_string();
+        map.loads += 1;
+        self.current = Some(key);
+        self.spawn = spawn;
+        self.last_position = None;
+    }
+
+    /// Count a simulation tick of `dt` seconds ending with the camera at
+    /// `position`. Paused ticks count nothing, and movement while paused is
+    /// not counted once play resumes.
+    pub fn tick(&mut self, position: glm::Vec3, dt: f32, paused: bool) {
+        if paused {
+            self.last_position = None;
+            return;
+        }
+        let step: f32 = self.last_position.map_or(0.0, |last: glm::Vec3| glm::distance(&last, &position));
+        let from_spawn: f32 = glm::distance(&self.spawn, &position);
+        self.last_position = Some(position);
+        let Some(map) = self.current_mut() else {
+            return;
+        };
+        map.time += dt as f64;
+        if step <= TELEPORT_SPEED * dt {
+            map.distance += step as f64;
+        }
+        map.furthest = map.furthest.max(from_spawn as f64);
+    }
+
+    pub fn screenshot_taken(&mut self) {
+        if let Some(map) = self.current_mut() {
+            map.screenshots += 1;
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        return fs::write(&self.path, self.stats.to_toml());
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 0.01;
+
+    fn tracker() -> StatsTracker {
+        let mut tracker: StatsTracker = StatsTracker::new(PlayStats::default(), PathBuf::from(STATS_PATH));
+        tracker.map_loaded("map".to_string(), "maps/test.bsp", glm::vec3(0.0, 0.0, 0.0));
+        return tracker;
+    }
+
+    #[test]
+    fn ticks_accumulate_time_and_distance() {
+        let mut tracker: StatsTracker = tracker();
+        for i in 0..=10 {
+            tracker.tick(glm::vec3(i as f32 * 3.0, 0.0, 0.0), TICK, false);
+        }
+        let map: &MapStats = tracker.current().unwrap();
+        assert!((map.time - 0.11).abs() < 1e-6);
+        assert!((map.distance - 30.0).abs() < 1e-4);
+        assert!((map.furthest - 30.0).abs() < 1e-4);
+        assert_eq!(map.loads, 1);
+    }
+
+    #[test]
+    fn teleports_are_not_travel() {
+        let mut tracker: StatsTracker = tracker();
+        tracker.tick(glm::vec3(0.0, 0.0, 0.0), TICK, false);
+        tracker.tick(glm::vec3(10.0, 0.0, 0.0), TICK, false);
+        tracker.tick(glm::vec3(5000.0, 0.0, 0.0), TICK, false);
+        tracker.tick(glm::vec3(5010.0, 0.0, 0.0), TICK, false);
+        let map: &MapStats = tracker.current().unwrap();
+        assert!((map.distance - 20.0).abs() < 1e-4);
+        assert!((map.furthest - 5010.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn paused_ticks_count_nothing() {
+        let mut tracker: StatsTracker = tracker();
+        tracker.tick(glm::vec3(0.0, 0.0, 0.0), TICK, false);
+        // Moved by a camera path while paused, then resumed where it left off
+        for i in 1..=100 {
+            tracker.tick(glm::vec3(i as f32, 0.0, 0.0), TICK, true);
+        }
+        tracker.tick(glm::vec3(100.0, 0.0, 0.0), TICK, false);
+        tracker.tick(glm::vec3(102.0, 0.0, 0.0), TICK, false);
+        let map: &MapStats = tracker.current().unwrap();
+        assert!((map.time - 0.03).abs() < 1e-6);
+        assert!((map.distance - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn reloading_counts_a_load_without_travel() {
+        let mut tracker: StatsTracker = tracker();
+        tracker.tick(glm::vec3(300.0, 0.0, 0.0), TICK, false);
+        tracker.map_loaded("map".to_string(), "maps/test.bsp", glm::vec3(0.0, 0.0, 0.0));
+        tracker.tick(glm::vec3(0.0, 0.0, 0.0), TICK, false);
+        tracker.screenshot_taken();
+        let map: &MapStats = tracker.current().unwrap();
+        assert_eq!((map.loads, map.screenshots), (2, 1));
+        assert_eq!(map.distance, 0.0);
+    }
+
+    #[test]
+    fn statistics_survive_a_round_trip() {
+        let mut stats: PlayStats = PlayStats::default();
+        stats.maps.insert(content_key(b"map one"), MapStats {
+            name: "maps/\"quoted\" \\ name.bsp".to_string(),
+            loads: 3,
+            time: 12.5,
+            distance: 1024.25,
+            furthest: 700.0,
+            screenshots: 2,
+        });
+        stats.maps.insert(content_key(b"map two"), MapStats {
+            name: "maps/two.bsp".to_string(),
+            loads: 1,
+            ..MapStats::default()
+        });
+        assert_eq!(PlayStats::from_toml(&stats.to_toml()).unwrap(), stats);
+        assert_eq!(stats.totals().loads, 4);
+    }
+
+    #[test]
+    fn corrupt_statistics_are_rejected() {
+        for text in [
+            "loads = 1",
+            "[maps.\"a\"]\nloads = many",
+            "[maps.\"a\"]\ntime = -1.0",
+            "[maps.\"a\"]\nunknown = 1",
+            "[maps.unquoted]",
+            "[maps.\"a\"]\nname = \"unterminated",
+            "garbage",
+        ] {
+            assert!(PlayStats::from_toml(text).is_err(), "accepted {:?}", text);
+        }
+    }
+
+    #[test]
+    fn content_keys_are_stable() {
+        assert_eq!(content_key(b""), "cbf29ce484222325");
+        assert_ne!(content_key(b"a"), content_key(b"b"));
+    }
+
+}
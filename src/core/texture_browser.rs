@@ -0,0 +1,191 @@
+use crate::map::bsp::{MissingTexture, TextureClass, BSP};
+use crate::map::wad::{MipmapTexture, TextureSource};
+use crate::map::bsp_renderable::BSPRenderable;
+use crate::rendering::renderable::RenderSettings;
+use crate::resource::image::Image;
+
+// Largest side of a thumbnail in pixels, larger textures are halved until they fit
+const THUMBNAIL_SIZE: usize = 64;
+const WINDOW_POSITION: [f32; 2] = [380.0, 10.0];
+const WINDOW_SIZE: [f32; 2] = [420.0, 520.0];
+// Height of the thumbnail grid, the selected texture's details go below it
+const GRID_HEIGHT: f32 = 360.0;
+
+/// What the browser shows about one of the map's textures
+struct TextureEntry {
+    name: String,
+    width: usize,
+    height: usize,
+    source: TextureSource,
+    masked: bool,
+    animated: bool,
+    tool: bool,
+    missing: bool,
+    thumbnail: Option<imgui::TextureId>, // None when the upload failed
+}
+
+impl TextureEntry {
+
+    fn matches(&self, search: &str, missing_only: bool) -> bool {
+        return (!missing_only || self.missing) && self.name.to_ascii_lowercase().contains(search);
+    }
+
+    /// Where the texture came from, the WAD by file name
+    fn source_label(&self) -> String {
+        return match &self.source {
+            TextureSource::Missing => "missing".to_string(),
+            TextureSource::Embedded => "BSP".to_string(),
+            TextureSource::Wad(path) => path.file_name()
+                .map_or(path.display().to_string(), |name: &std::ffi::OsStr| name.to_string_lossy().into_owned()),
+        };
+    }
+
+    fn flags(&self) -> String {
+        let flags: Vec<&str> = [(self.masked, "masked"), (self.animated, "animated"), (self.tool, "tool")].iter()
+            .filter(|(set, _): &&(bool, &str)| *set)
+            .map(|(_, name): &(bool, &str)| *name)
+            .collect();
+        return flags.join(", ");
+    }
+
+    fn describe(&self, ui: &imgui::Ui) {
+        ui.text(&self.name);
+        ui.text(format!("{}x{} from {}", self.width, self.height, self.source_label()));
+        let flags: String = self.flags();
+        if !flags.is_empty() {
+            ui.text(flags);
+        }
+    }
+
+}
+
+/// imgui window of thumbnails of every texture the map loaded, map textures
+/// then decal textures. Selecting one tints every face showing it.
+pub struct TextureBrowser {
+    search: String, // Case insensitive part of a texture name
+    missing_only: bool, // Limit the grid to textures in the missing texture report
+    selected: Option<usize>, // Index into the map's textures
+    entries: Option<Vec<TextureEntry>>, // Thumbnails are uploaded the first time the window is drawn
+}
+
+impl TextureBrowser {
+
+    pub fn new() -> Self {
+        return TextureBrowser {
+            search: String::new(),
+            missing_only: false,
+            selected: None,
+            entries: None,
+        };
+    }
+
+    /// Release the thumbnails and the selection, for when the map is replaced.
+    /// Thumbnails of the new map are uploaded when the window is next drawn.
+    pub fn clear(&mut self, renderable: &BSPRenderable) {
+        for entry in self.entries.take().unwrap_or_default() {
+            if let Some(thumbnail) = entry.thumbnail {
+                renderable.renderer().remove_imgui_texture(thumbnail);
+            }
+        }
+        self.selected = None;
+    }
+
+    /// Draw the window and set the texture to highlight in `settings`
+    pub fn draw(&mut self, ui: &imgui::Ui, renderable: &BSPRenderable, settings: &mut RenderSettings) {
+        let entries: &Vec<TextureEntry> = self.entries.get_or_insert_with(|| TextureBrowser::build_entries(renderable));
+        let selected: &mut Option<usize> = &mut self.selected;
+        let search: &mut String = &mut self.search;
+        let missing_only: &mut bool = &mut self.missing_only;
+        ui.window("Textures")
+            .position(WINDOW_POSITION, imgui::Condition::FirstUseEver)
+            .size(WINDOW_SIZE, imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.input_text("Search", search).build();
+                ui.checkbox("Missing only", missing_only);
+                let filter: String = search.to_ascii_lowercase();
+                let shown: Vec<usize> = (0..entries.len())
+                    .filter(|i: &usize| entries[*i].matches(&filter, *missing_only))
+                    .collect();
+                ui.text(format!("{} of {} textures", shown.len(), entries.len()));
+                ui.child_window("texture_grid").size([0.0, GRID_HEIGHT]).border(true).build(|| {
+                    let spacing: f32 = ui.clone_style().item_spacing[0];
+                    let right_edge: f32 = ui.window_pos()[0] + ui.window_content_region_max()[0];
+                    let mut row_started: bool = false;
+                    for i in shown.iter() {
+                        let entry: &TextureEntry = &entries[*i];
+                        let Some(thumbnail) = entry.thumbnail else {
+                            continue;
+                        };
+                        let size: [f32; 2] = TextureBrowser::thumbnail_size(entry);
+                        // Wrap to a new row when the thumbnail would overhang the grid
+                        if row_started && ui.item_rect_max()[0] + spacing + size[0] < right_edge {
+                            ui.same_line();
+                        }
+                        row_started = true;
+                        let tint: [f32; 4] = if *selected == Some(*i) { [1.0, 0.8, 0.0, 1.0] } else { [1.0, 1.0, 1.0, 1.0] };
+                        let clicked: bool = ui.image_button_config(format!("##texture{}", i), thumbnail, size)
+                            .tint_col(tint)
+                            .build();
+                        if ui.is_item_hovered() {
+                            ui.tooltip(|| entry.describe(ui));
+                        }
+                        if clicked {
+                            *selected = if *selected == Some(*i) { None } else { Some(*i) };
+                        }
+                    }
+                });
+                match selected.and_then(|i: usize| entries.get(i)) {
+                    Some(entry) => entry.describe(ui),
+                    None => ui.text_disabled("Click a texture to highlight the faces using it"),
+                };
+            });
+        settings.highlight_texture = self.selected;
+    }
+
+    /// Thumbnail size on screen, its pixel size so it is never stretched
+    fn thumbnail_size(entry: &TextureEntry) -> [f32; 2] {
+        let scale: f32 = (THUMBNAIL_SIZE as f32 / entry.width.max(entry.height).max(1) as f32).min(1.0);
+        return [(entry.width as f32 * scale).max(1.0), (entry.height as f32 * scale).max(1.0)];
+    }
+
+    fn build_entries(renderable: &BSPRenderable) -> Vec<TextureEntry> {
+        let bsp: &BSP = renderable.bsp();
+        let entries: Vec<TextureEntry> = bsp.m_textures.iter().enumerate().map(|(i, texture): (usize, &MipmapTexture)| {
+            let is_map_texture: bool = i < bsp.mip_textures.len();
+            TextureEntry {
+                name: if texture.name.is_empty() && is_map_texture { bsp.mip_texture_name(i) } else { texture.name.clone() },
+                width: texture.img[0].width,
+                height: texture.img[0].height,
+                source: texture.source.clone(),
+                masked: texture.masked,
+                animated: bsp.texture_animation(i).is_some(),
+                tool: is_map_texture && bsp.texture_classes.get(i).map_or(false, |class: &TextureClass| class.is_tool()),
+                missing: bsp.missing_textures().iter().any(|missing: &MissingTexture| missing.index == i),
+                thumbnail: TextureBrowser::upload_thumbnail(renderable, &texture.img[0]),
+            }
+        }).collect();
+        debug!(&crate::LOGGER, "Uploaded {} texture browser thumbnails", entries.len());
+        return entries;
+    }
+
+    fn upload_thumbnail(renderable: &BSPRenderable, image: &Image) -> Option<imgui::TextureId> {
+        let mut thumbnail: Image = image.clone();
+        while thumbnail.width.max(thumbnail.height) > THUMBNAIL_SIZE {
+            thumbnail = match thumbnail.resize_half() {
+                Ok(halved) => halved,
+                Err(error) => {
+                    warn!(&crate::LOGGER, "Unable to shrink texture thumbnail: {}", error);
+                    return None;
+                },
+            };
+        }
+        return match renderable.renderer().create_imgui_texture(&thumbnail) {
+            Ok(id) => Some(id),
+            Err(error) => {
+                warn!(&crate::LOGGER, "Unable to upload texture thumbnail: {}", error);
+                None
+            },
+        };
+    }
+
+}
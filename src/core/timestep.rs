@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+// Longest frame simulated in full. Anything past it, such as a breakpoint or
+// dragging the window, is dropped rather than run as a burst of ticks.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Splits the time between frames into fixed simulation ticks, as GoldSrc
+/// runs player movement. Time short of a whole tick is carried over to the
+/// next frame and says how far rendering is between the last two ticks.
+pub struct FixedTimestep {
+    tick: Duration,
+    accumulator: Duration, // Time not yet simulated, always less than a tick after advancing
+}
+
+impl FixedTimestep {
+
+    /// Timestep running `tick_rate` ticks per second
+    pub fn new(tick_rate: u32) -> Self {
+        return FixedTimestep {
+            tick: Duration::from_secs(1) / tick_rate.max(1),
+            accumulator: Duration::ZERO,
+        };
+    }
+
+    pub fn tick(&self) -> Duration {
+        return self.tick;
+    }
+
+    /// Ticks per second
+    pub fn tick_rate(&self) -> f32 {
+        return 1.0 / self.tick.as_secs_f32();
+    }
+
+    /// Time carried over to the next frame
+    pub fn remainder(&self) -> Duration {
+        return self.accumulator;
+    }
+
+    /// Add the time since the last frame and return how many ticks to run.
+    /// A 35 ms frame at 100 Hz runs 3 ticks and carries 5 ms over.
+    pub fn advance(&mut self, elapsed: Duration) -> usize {
+        self.accumulator += elapsed.min(MAX_FRAME_TIME);
+        let ticks: u32 = (self.accumulator.as_nanos() / self.tick.as_nanos()) as u32;
+        self.accumulator -= self.tick * ticks;
+        return ticks as usize;
+    }
+
+    /// Fraction of a tick carried over, how far to interpolate from the
+    /// state before the last tick to the state after it
+    pub fn alpha(&self) -> f32 {
+        return (self.accumulator.as_secs_f32() / self.tick.as_secs_f32()).clamp(0.0, 1.0);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_35_ms_frame_at_100_hz_runs_3_ticks_and_carries_5_ms() {
+        let mut timestep: FixedTimestep = FixedTimestep::new(100);
+        assert_eq!(timestep.tick(), Duration::from_millis(10));
+        assert_eq!(timestep.advance(Duration::from_millis(35)), 3);
+        assert_eq!(timestep.remainder(), Duration::from_millis(5));
+        assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn carried_time_adds_up_to_later_ticks() {
+        let mut timestep: FixedTimestep = FixedTimestep::new(100);
+        let ticks: Vec<usize> = [4, 4, 4, 4, 4].iter()
+            .map(|millis: &u64| timestep.advance(Duration::from_millis(*millis)))
+            .collect();
+        assert_eq!(ticks, vec![0, 0, 1, 0, 1]);
+        assert_eq!(timestep.remainder(), Duration::ZERO);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    fn long_frames_are_cut_short() {
+        let mut timestep: FixedTimestep = FixedTimestep::new(100);
+        assert_eq!(timestep.advance(Duration::from_secs(5)), 25);
+        assert_eq!(timestep.remainder(), Duration::ZERO);
+    }
+
+    #[test]
+    fn a_zero_tick_rate_runs_one_tick_a_second() {
+        let mut timestep: FixedTimestep = FixedTimestep::new(0);
+        assert_eq!(timestep.tick(), Duration::from_secs(1));
+        assert_eq!(timestep.tick_rate(), 1.0);
+        assert_eq!(timestep.advance(Duration::from_millis(200)), 0);
+    }
+
+}
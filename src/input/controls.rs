@@ -0,0 +1,102 @@
+use glium::glutin::event::{DeviceEvent, ElementState, MouseButton, VirtualKeyCode, WindowEvent};
+
+use crate::core::scale::ScaleDefaults;
+use crate::input::r#move::{UserCommand, IN_BACK, IN_DUCK, IN_FORWARD, IN_JUMP, IN_MOVE_LEFT, IN_MOVE_RIGHT};
+use crate::rendering::view::camera::Camera;
+// Degrees turned per count of mouse motion, m_yaw and m_pitch at sensitivity 3
+const MOUSE_SENSITIVITY: f32 = 0.022 * 3.0;
+// Speed multiplier while shift is held
+const RUN_SCALE: f32 = 2.0;
+
+/// Keyboard and mouse state turned into a user command each frame. WASD
+/// moves, space and control (jump and duck) rise and sink, shift runs, and
+/// dragging with the right mouse button held looks around.
+#[derive(Default)]
+pub struct CameraControls {
+    buttons: usize, // IN_ flags of the movement keys held
+    running: bool,
+    looking: bool, // Right mouse button held
+    look_delta: glm::Vec2, // Mouse motion since the last update, in counts
+    scale: ScaleDefaults, // Movement speeds for the world scale
+}
+
+impl CameraControls {
+
+    pub fn new() -> Self {
+        return CameraControls::default();
+    }
+
+    /// Move at the speeds of a world scale's tunables
+    pub fn apply_scale(&mut self, scale: &ScaleDefaults) {
+        self.scale = *scale;
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                let button: usize = match input.virtual_keycode {
+                    Some(VirtualKeyCode::W) => IN_FORWARD,
+                    Some(VirtualKeyCode::S) => IN_BACK,
+                    Some(VirtualKeyCode::A) => IN_MOVE_LEFT,
+                    Some(VirtualKeyCode::D) => IN_MOVE_RIGHT,
+                    Some(VirtualKeyCode::Space) => IN_JUMP,
+                    Some(VirtualKeyCode::LControl) => IN_DUCK,
+                    Some(VirtualKeyCode::LShift) => {
+                        self.running = input.state == ElementState::Pressed;
+                        return;
+                    },
+                    _ => return,
+                };
+                if input.state == ElementState::Pressed {
+                    self.buttons |= button;
+                } else {
+                    self.buttons &= !button;
+                }
+            },
+            WindowEvent::MouseInput { state, button: MouseButton::Right, .. } => {
+                self.looking = *state == ElementState::Pressed;
+            },
+            // Keys released while unfocused never report it
+            WindowEvent::Focused(false) => {
+                *self = CameraControls {
+                    scale: self.scale,
+                    ..CameraControls::default()
+                };
+            },
+            _ => (),
+        };
+    }
+
+    /// Raw mouse motion, used for looking as it is not limited by the window edges
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event {
+            if self.looking {
+                self.look_delta += glm::vec2(*x as f32, *y as f32);
+            }
+        }
+    }
+
+    /// Build the command for one simulation tick of `frame_time` seconds and
+    /// run it on the camera
+    pub fn update(&mut self, camera: &mut Camera, frame_time: f32) {
+        let held = |button: usize| -> f32 { if self.buttons & button != 0 { 1.0 } else { 0.0 } };
+        let scale: f32 = if self.running { RUN_SCALE } else { 1.0 };
+        // Turn from where the last command left the camera, not the interpolated view
+        let angles: glm::Vec3 = camera.latest_pose().angles;
+        let view_angles: glm::Vec3 = glm::vec3(
+            angles.x + self.look_delta.y * MOUSE_SENSITIVITY,
+            angles.y - self.look_delta.x * MOUSE_SENSITIVITY,
+            0.0,
+        );
+        self.look_delta = glm::vec2(0.0, 0.0);
+        camera.run_command(UserCommand {
+            forward_move: (held(IN_FORWARD) - held(IN_BACK)) * self.scale.forward_speed * scale,
+            side_mode: (held(IN_MOVE_RIGHT) - held(IN_MOVE_LEFT)) * self.scale.side_speed * scale,
+            up_move: (held(IN_JUMP) - held(IN_DUCK)) * self.scale.up_speed * scale,
+            buttons: self.buttons as isize,
+            framte_time: frame_time,
+            view_angles,
+        });
+    }
+
+}
@@ -1 +1,2 @@
 pub mod r#move;
+pub mod controls;
@@ -1,6 +1,7 @@
 use crate::map::bsp::Model;
 
 pub const IN_JUMP: usize = 1 << 1;
+pub const IN_DUCK: usize = 1 << 2;
 pub const IN_FORWARD: usize = 1 << 3;
 pub const IN_BACK: usize = 1 << 4;
 pub const IN_MOVE_LEFT: usize = 1 << 9;
@@ -8,6 +9,7 @@ pub const IN_MOVE_RIGHT: usize = 1 << 10;
 
 pub const FL_DUCKING: usize = 1 << 14;
 
+#[derive(Default)]
 pub struct UserCommand {
     pub forward_move: f32,
     pub side_mode: f32,
@@ -17,12 +19,15 @@ pub struct UserCommand {
     pub view_angles: glm::Vec3,
 }
 
+#[derive(Default)]
 pub enum MoveType {
+    #[default]
     Walk,
     Fly,
     Noclip,
 }
 
+#[derive(Default)]
 pub struct PlayerMove {
     pub angles: glm::Vec3,
     pub forward: glm::Vec3,
@@ -46,3 +51,44 @@ pub struct PlayerMove {
     pub phys_entities: Vec<Box<Model>>,
     pub ladders: Vec<Box<Model>>,
 }
+
+// Pitch stops short of straight up and down so the view never flips
+const MAX_PITCH: f32 = 89.0;
+
+impl PlayerMove {
+
+    /// Recompute the forward, right and up vectors from the view angles, as
+    /// the engine's AngleVectors. Positive pitch looks down.
+    pub fn angle_vectors(&mut self) {
+        let (pitch, yaw): (f32, f32) = (self.angles.x.to_radians(), self.angles.y.to_radians());
+        let roll: f32 = self.angles.z.to_radians();
+        let (sp, cp): (f32, f32) = pitch.sin_cos();
+        let (sy, cy): (f32, f32) = yaw.sin_cos();
+        let (sr, cr): (f32, f32) = roll.sin_cos();
+        self.forward = glm::vec3(cp * cy, cp * sy, -sp);
+        self.right = glm::vec3(-sr * sp * cy + cr * sy, -sr * sp * sy - cr * cy, -sr * cp);
+        self.up = glm::vec3(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp);
+    }
+
+    /// Run the current command: turn to its view angles and move
+    pub fn player_move(&mut self) {
+        self.frametime = self.cmd.framte_time;
+        self.angles = self.cmd.view_angles;
+        self.angles.x = self.angles.x.clamp(-MAX_PITCH, MAX_PITCH);
+        self.angle_vectors();
+        match self.move_type {
+            MoveType::Noclip => self.noclip(),
+            // Walking and flying need collision against the hulls, until then they move as noclip
+            MoveType::Walk | MoveType::Fly => self.noclip(),
+        };
+    }
+
+    /// Move along the view without collision at the commanded speeds
+    fn noclip(&mut self) {
+        self.velocity = self.forward * self.cmd.forward_move
+            + self.right * self.cmd.side_mode
+            + glm::vec3(0.0, 0.0, self.cmd.up_move);
+        self.origin += self.velocity * self.frametime;
+    }
+
+}
@@ -1,10 +1,11 @@
 use std::fs::{File, OpenOptions};
 use std::{fs, io, thread};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io::Write;
 
-use slog::{Drain, Duplicate, Fuse, Logger, Record};
-use slog_async::{Async, OverflowStrategy};
+use slog::{Drain, Duplicate, Filter, Fuse, IgnoreResult, Level, Logger, Record};
+use slog_async::{Async, AsyncGuard, OverflowStrategy};
 use slog_json::Json;
 use slog_term::{FullFormat, TermDecorator, ThreadSafeTimestampFn, RecordDecorator, CountingWriter};
 use regex::Regex;
@@ -18,8 +19,12 @@ macro_rules! get_current_thread_id {
 
 lazy_static! {
     static ref MODULE_SEPARATOR_REGEX: Regex = Regex::new(r"::").expect("Could not compile module separator regex");
+    static ref LOGGER_GUARD: Mutex<Option<AsyncGuard>> = Mutex::new(None);
 }
 
+// Least severe level logged, as `Level::as_usize`. Everything is logged until one is set.
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(usize::MAX);
+
 ///
 /// Format the message according to the following standard:
 /// `[YY-mm-dd HH:MM:SS.SSS] [MESSAGE] <LEVEL>: <MESSAGE>[, ...<KEY>: <VALUE>]`
@@ -100,6 +105,30 @@ pub fn timestamp_utc(io: &mut dyn io::Write) -> io::Result<()> {
     )
 }
 
+///
+/// Set the least severe level that is logged, records below it are dropped.
+/// Takes effect for loggers that already exist.
+///
+/// # Arguments
+/// * level: Least severe level to keep
+///
+pub fn set_log_level(level: Level) {
+    LOG_LEVEL.store(level.as_usize(), Ordering::Relaxed);
+}
+
+///
+/// Whether a record is at or above the level set with `set_log_level`
+///
+/// # Arguments
+/// * record: Record to check the level of
+///
+/// # Returns
+/// `bool`: `true` if the record should be logged
+///
+fn passes_log_level(record: &Record) -> bool {
+    return record.level().as_usize() <= LOG_LEVEL.load(Ordering::Relaxed);
+}
+
 ///
 /// Initialise a logger with a given prefix for the log file. Log file name will be
 /// in the following format:
@@ -145,13 +174,31 @@ pub fn initialize_logging(prefix: String) ->  Logger {
     let d2: FuseJF = Json::default(file).fuse();
     // Define mutex for drain access to assure thread safety
     let both: FuseMD = Mutex::new(Duplicate::new(d1, d2)).fuse();
-    // Create async access for for logging with Blocking strategy to queue up asynced methods
-    let both: Fuse<Async> = Async::new(both)
+    // Create async access for for logging with Blocking strategy to queue up asynced methods.
+    // Results are ignored so records emitted after flush_logging() are dropped rather than panicking
+    let (both, guard): (Async, AsyncGuard) = Async::new(both)
         .overflow_strategy(OverflowStrategy::Block)
-        .build()
-        .fuse();
+        .build_with_guard();
+    let both: IgnoreResult<Async> = both.ignore_res();
+    // Filter before the queue so dropped records are never formatted
+    let both: IgnoreResult<Filter<IgnoreResult<Async>, fn(&Record) -> bool>> = Filter::new(
+        both,
+        passes_log_level as fn(&Record) -> bool,
+    ).ignore_res();
+    *LOGGER_GUARD.lock().unwrap() = Some(guard);
     let log: Logger = Logger::root(both, o!());
 
     info!(log.new(get_current_thread_id!()), "{}", directory_creation_message);
     return log;
 }
+
+///
+/// Flush all queued log records and stop the async logging worker. Any records
+/// logged after this call are discarded, so it should be the last step of shutdown.
+///
+pub fn flush_logging() {
+    if let Ok(mut guard) = LOGGER_GUARD.lock() {
+        // Dropping the guard flushes the queue and joins the worker thread
+        std::mem::drop(guard.take());
+    }
+}
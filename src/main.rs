@@ -30,54 +30,640 @@ extern crate num_derive;
 extern crate num;
 
 use std::panic;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
-use glium::{
-    glutin,
-    Surface,
-};
+use glium::glutin;
 pub(crate) use lazy_static::lazy_static;
 use slog::Logger;
 
-use crate::logging::logging::initialize_logging;
+use crate::core::cli::{Options, USAGE};
+use crate::core::debug_overlay::DebugOverlay;
+use crate::core::frame_pacing::{FramePacing, SPIN_THRESHOLD};
+use crate::core::scale::{ScaleDefaults, ScaleSettings};
+use crate::core::shutdown::{ShutdownCoordinator, DEFAULT_HOOK_TIMEOUT};
+use crate::core::stats::{content_key, StatsTracker, STATS_PATH};
+use crate::core::timestep::FixedTimestep;
+use crate::input::controls::CameraControls;
+use crate::input::r#move::{MoveType, PlayerMove};
+use crate::map::bsp::BSP;
+use crate::map::bsp_renderable::BSPRenderable;
+use crate::map::export::ObjExportOptions;
+use crate::map::trace::TraceResult;
+use crate::rendering::glutin_platform::GlutinPlatform;
+use crate::rendering::fog::Fog;
+use crate::rendering::renderable::{RenderSettings, Renderable};
+use crate::rendering::renderer::{self, PassCapture, Platform, RenderStats, Renderer};
+use crate::rendering::view::camera::Camera;
+use crate::rendering::view::camera_path::{CameraPath, PathPlayback, PlaybackMode};
+use crate::resource::image::Image;
+use crate::resource::wad_manager::WadManager;
+use crate::logging::logging::{flush_logging, initialize_logging, set_log_level};
+
+const DEBUG_OVERLAY_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F1;
+// Toggles fullscreen with Alt held
+const FULLSCREEN_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::Return;
+const CAMERA_PATH_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F9;
+const SCREENSHOT_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F12;
+// Saves the colour buffer after each render pass of the next frame
+const PASS_CAPTURE_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F10;
+const RELOAD_MAP_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F5;
+const LIGHTING_MODE_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F4;
+const WIREFRAME_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F6;
+const OVERDRAW_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F7;
+// Logs the lightmap texel and atlas position under the crosshair
+const LIGHTMAP_PROBE_KEY: glutin::event::VirtualKeyCode = glutin::event::VirtualKeyCode::F3;
+// Furthest the lightmap probe looks, across the largest maps
+const LIGHTMAP_PROBE_DISTANCE: f32 = 8192.0;
 
 lazy_static! {
     static ref LOGGER: Logger = initialize_logging(String::from("Lambda"));
 }
 
-fn render(display: &glium::Display) {
-    let mut target = display.draw();
-    target.clear_color(0.0, 0.0, 1.0, 1.0);
-    target.finish().unwrap();
+/// Milliseconds since the Unix epoch
+fn unix_millis() -> u128 {
+    return std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed: std::time::Duration| elapsed.as_millis());
 }
 
-fn original_main() {
-    info!(&crate::LOGGER, "Configured logging");
-    let event_loop = glutin::event_loop::EventLoop::new();
-    let window_builder = glutin::window::WindowBuilder::new();
-    let context_builder = glutin::ContextBuilder::new();
-    let display: glium::Display = glium::Display::new(window_builder, context_builder, &event_loop).unwrap();
-    
-    event_loop.run(move |ev, _, control_flow| {
+/// `screenshots/<unix time in ms>.png`
+fn timestamped_screenshot_path() -> String {
+    return format!("screenshots/{}.png", unix_millis());
+}
 
-        render(&display);
+/// Save the passes of a frame captured with `PASS_CAPTURE_KEY` to
+/// `screenshots/passes-<unix time in ms>`
+fn save_pass_captures(renderable: &BSPRenderable) {
+    let captures: Vec<PassCapture> = renderable.renderer().take_captures();
+    if captures.is_empty() {
+        return;
+    }
+    let directory: PathBuf = PathBuf::from(format!("screenshots/passes-{}", unix_millis()));
+    match renderer::save_pass_captures(&captures, &directory) {
+        Ok(paths) => info!(&crate::LOGGER, "Saved {} passes to {}", paths.len(), directory.display()),
+        Err(error) => error!(&crate::LOGGER, "Failed to save pass captures: {}", error),
+    };
+}
 
-        let next_frame_time = std::time::Instant::now() +
-            std::time::Duration::from_nanos(16_666_667);
-        *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
-        match ev {
-            glutin::event::Event::WindowEvent { event, .. } => match event {
-                glutin::event::WindowEvent::CloseRequested => {
-                    *control_flow = glutin::event_loop::ControlFlow::Exit;
-                    return;
+/// Save the last presented frame to `path`
+fn save_screenshot(renderable: &BSPRenderable, path: String) {
+    match renderable.renderer().screenshot().and_then(|image: Image| image.save(path.clone())) {
+        Ok(()) => info!(&crate::LOGGER, "Saved screenshot to {}", path),
+        Err(error) => error!(&crate::LOGGER, "Failed to save screenshot: {}", error),
+    };
+}
+
+/// Log a debug view being switched along with the last frame's counters
+/// Trace along the view to the world face under the crosshair and log the
+/// lightmap texel lighting the point hit
+fn probe_lightmap(renderable: &BSPRenderable) {
+    let start: glm::Vec3 = renderable.camera().position();
+    let end: glm::Vec3 = start + renderable.camera().view_vector() * LIGHTMAP_PROBE_DISTANCE;
+    let trace: TraceResult = renderable.bsp().trace_line(start, end);
+    if !trace.hit() {
+        info!(&crate::LOGGER, "No surface under the crosshair to probe");
+        return;
+    }
+    match renderable.bsp().face_at(trace.end_pos) {
+        Some(face_index) => if renderable.probe_lightmap(face_index, trace.end_pos).is_none() {
+            info!(&crate::LOGGER, "Face {} has no lightmap", face_index);
+        },
+        None => info!(&crate::LOGGER, "No world face found at {:?}", trace.end_pos),
+    };
+}
+
+fn log_debug_view(renderable: &BSPRenderable, name: &str, enabled: bool) {
+    let stats: RenderStats = renderable.renderer().stats();
+    info!(
+        &crate::LOGGER,
+        "{} {}, last frame {} draw calls, {} faces, {} triangles",
+        name,
+        if enabled { "on" } else { "off" },
+        stats.draw_calls,
+        stats.faces_rendered,
+        stats.triangles,
+    );
+}
+
+/// Match the viewport and the camera's aspect ratio to the window
+fn resize(renderable: &mut BSPRenderable, size: glutin::dpi::PhysicalSize<u32>) {
+    renderable.renderer().resize_viewport(size.width as usize, size.height as usize);
+    let camera: &mut Camera = renderable.camera_mut();
+    camera.viewport_width = size.width as usize;
+    camera.viewport_height = size.height as usize;
+}
+
+fn render(
+    renderable: &mut BSPRenderable,
+    settings: &mut RenderSettings,
+    overlay: Option<&mut DebugOverlay>,
+    pacing: &mut FramePacing,
+    timestep: &FixedTimestep,
+    statistics: &StatsTracker,
+    scale: &mut ScaleSettings,
+    time: f32,
+    delta: std::time::Duration,
+) {
+    let camera: &Camera = renderable.camera();
+    settings.projection = camera.projection_matrix();
+    settings.view = camera.view_matrix();
+    settings.pitch = camera.pitch();
+    settings.yaw = camera.yaw();
+    settings.time = time;
+    renderable.renderer().begin_frame();
+    Renderable::render(renderable, settings);
+    if let Some(overlay) = overlay {
+        overlay.record_frame(delta);
+        overlay.render(renderable, settings, pacing, timestep, statistics, scale, delta);
+    }
+    if let Err(error) = renderable.renderer().end_frame() {
+        error!(&crate::LOGGER, "Failed to present frame: {}", error);
+    }
+}
+
+/// Give the camera, controls and fog the tunables of the world scale
+fn apply_scale(renderable: &mut BSPRenderable, settings: &mut RenderSettings, controls: &mut CameraControls, scale: &ScaleSettings) {
+    renderable.camera_mut().apply_scale(scale.effective());
+    controls.apply_scale(scale.effective());
+    settings.fog = Fog::from_entities(renderable.entities(), scale.effective());
+    info!(&crate::LOGGER, "World scale {} units per metre: {:?}", scale.world_scale(), scale.effective());
+}
+
+/// Camera at the first `info_player_start`, or the world origin when the
+/// map has none
+fn spawn_camera(bsp: &BSP, width: u32, height: u32) -> Camera {
+    let mut camera: Camera = Camera::new(Box::new(PlayerMove {
+        move_type: MoveType::Noclip,
+        ..PlayerMove::default()
+    }));
+    camera.viewport_width = width as usize;
+    camera.viewport_height = height as usize;
+    let Some(start) = BSP::find_entity(&bsp.entities, "info_player_start".to_string()) else {
+        warn!(&crate::LOGGER, "Map has no info_player_start, starting at the origin");
+        return camera;
+    };
+    let origin: Vec<f32> = start.find_property(&"origin".to_string())
+        .map_or(Vec::new(), |origin: &String| origin.split_whitespace()
+            .map_while(|component: &str| component.parse::<f32>().ok())
+            .collect());
+    let yaw: f32 = start.find_property(&"angle".to_string())
+        .and_then(|angle: &String| angle.trim().parse::<f32>().ok())
+        .unwrap_or(0.0);
+    if let [x, y, z] = origin[..] {
+        let yaw: f32 = yaw.to_radians();
+        camera.set_view(glm::vec3(x, y, z), glm::vec3(yaw.cos(), yaw.sin(), 0.0));
+    }
+    return camera;
+}
+
+/// Load the map again from its file and swap it in, with the camera back at
+/// the spawn point. WADs are closed first so textures edited since the last
+/// load are read again. The current map keeps running when loading or
+/// building the new one fails. True when the map was replaced.
+fn reload_map(
+    renderable: &mut BSPRenderable,
+    settings: &mut RenderSettings,
+    overlay: Option<&mut DebugOverlay>,
+    options: &Options,
+    scale: &ScaleDefaults,
+    wads: &mut WadManager,
+) -> bool {
+    let start: std::time::Instant = std::time::Instant::now();
+    info!(&crate::LOGGER, "Reloading map {}", options.map);
+    wads.close_all();
+    let result: Result<(), String> = match BSP::from_file_with_wads(&options.map, &options.load_options(), wads) {
+        Ok(bsp) => {
+            let mut camera: Camera = spawn_camera(
+                &bsp,
+                renderable.camera().viewport_width as u32,
+                renderable.camera().viewport_height as u32,
+            );
+            camera.fov_y = renderable.camera().fov_y;
+            camera.apply_scale(scale);
+            let fog: Fog = Fog::from_entities(&bsp.entities, scale);
+            match renderable.reload(Box::new(bsp), Box::new(camera)) {
+                Ok(()) => {
+                    settings.fog = fog;
+                    Ok(())
                 },
-                _ => return,
+                Err(error) => Err(format!("Failed to build map render resources: {}", error)),
+            }
+        },
+        Err(error) => Err(format!("Failed to load map {}: {}", options.map, error)),
+    };
+    match &result {
+        Ok(()) => {
+            settings.highlight_texture = None;
+            info!(&crate::LOGGER, "Reloaded map {} in {:?}", options.map, start.elapsed());
+            debug!(&crate::LOGGER, "{} WADs opened since startup", wads.open_count());
+            renderable.memory_report().log();
+        },
+        Err(error) => error!(&crate::LOGGER, "{}, keeping the current map", error),
+    };
+    let reloaded: bool = result.is_ok();
+    if let Some(overlay) = overlay {
+        overlay.map_reloaded(renderable, result);
+    }
+    return reloaded;
+}
+
+/// Start counting statistics for the map at `path`, keyed by its contents.
+/// Nothing is counted when the file cannot be read.
+fn track_map(statistics: &Mutex<StatsTracker>, path: &str, spawn: glm::Vec3) {
+    let bytes: Vec<u8> = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            warn!(&crate::LOGGER, "Unable to read {} for its play statistics: {}", path, error);
+            return;
+        },
+    };
+    if let Ok(mut tracker) = statistics.lock() {
+        tracker.map_loaded(content_key(&bytes), path, spawn);
+    }
+}
+
+/// Save the play statistics, logging a failure
+fn save_statistics(tracker: &StatsTracker) {
+    if let Err(error) = tracker.save() {
+        error!(&crate::LOGGER, "Failed to save play statistics to {}: {}", STATS_PATH, error);
+    }
+}
+
+/// Playback of `path`, stepping at exactly 1/60 s per frame when capturing
+fn start_playback(path: &CameraPath, capture: bool) -> PathPlayback {
+    info!(
+        &crate::LOGGER,
+        "Playing camera path of {} knots over {:.2}s{}",
+        path.knots.len(),
+        path.total_duration(),
+        if capture { ", capturing every frame" } else { "" },
+    );
+    return if capture {
+        PathPlayback::capture(path.clone())
+    } else {
+        PathPlayback::new(path.clone(), PlaybackMode::RealTime)
+    };
+}
+
+/// Open the window and draw the map until it is closed. The map is reloaded
+/// from `options.map` on request, through the WADs the map was loaded with.
+fn run(bsp: BSP, options: Options, mut wads: WadManager) {
+    let platform: GlutinPlatform = GlutinPlatform::new(options.fullscreen, options.frame_pacing.vsync());
+    let display: glium::Display = match platform.create_window_and_context(
+        options.width as usize,
+        options.height as usize,
+        "Lambda".to_string(),
+        options.monitor,
+    ) {
+        Ok(display) => display,
+        Err(error) => {
+            crit!(&crate::LOGGER, "{}", error);
+            flush_logging();
+            std::process::exit(1);
+        },
+    };
+    let (width, height): (u32, u32) = display.get_framebuffer_dimensions();
+    let scale_factor: f64 = display.gl_window().window().scale_factor();
+    let renderer: Rc<dyn Renderer> = match platform.create_renderer(display) {
+        Ok(renderer) => Rc::from(renderer),
+        Err(error) => {
+            crit!(&crate::LOGGER, "Failed to create renderer: {}", error);
+            flush_logging();
+            std::process::exit(1);
+        },
+    };
+    let mut scale: ScaleSettings = ScaleSettings::new(options.world_scale);
+    let mut camera: Camera = spawn_camera(&bsp, width, height);
+    if let Some(fov) = options.fov {
+        camera.fov_y = fov;
+    }
+    camera.apply_scale(scale.effective());
+    let fog: Fog = Fog::from_entities(&bsp.entities, scale.effective());
+    let mut renderable: BSPRenderable = match BSPRenderable::new(renderer, Box::new(bsp), Box::new(camera)) {
+        Ok(renderable) => renderable,
+        Err(error) => {
+            crit!(&crate::LOGGER, "Failed to build map render resources: {}", error);
+            flush_logging();
+            std::process::exit(1);
+        },
+    };
+    renderable.memory_report().log();
+    let mut overlay: Option<DebugOverlay> = match DebugOverlay::new(
+        renderable.renderer(),
+        glutin::dpi::PhysicalSize::new(width, height),
+        scale_factor,
+    ) {
+        Ok(overlay) => Some(overlay),
+        Err(error) => {
+            error!(&crate::LOGGER, "{}, running without the debug overlay", error);
+            None
+        },
+    };
+    let mut settings: RenderSettings = RenderSettings {
+        fog,
+        ..RenderSettings::default()
+    };
+    let start: std::time::Instant = std::time::Instant::now();
+    let mut last_frame: std::time::Instant = start;
+    let mut last_present: std::time::Instant = start;
+    let mut pacing: FramePacing = options.frame_pacing;
+    // The swap interval can only be chosen when the context is created
+    let vsync: bool = pacing.vsync();
+    info!(&crate::LOGGER, "Frame pacing {}", pacing);
+    let mut last_update: std::time::Instant = start;
+    let mut timestep: FixedTimestep = FixedTimestep::new(options.tick_rate);
+    info!(&crate::LOGGER, "Simulating at {} ticks per second", options.tick_rate);
+    let mut controls: CameraControls = CameraControls::new();
+    controls.apply_scale(scale.effective());
+    let camera_path: Option<CameraPath> = options.camera_path.as_ref().and_then(|path: &std::path::PathBuf| {
+        match CameraPath::from_file(path) {
+            Ok(camera_path) => Some(camera_path),
+            Err(error) => {
+                error!(&crate::LOGGER, "Failed to load camera path {}: {}", path.display(), error);
+                None
+            },
+        }
+    });
+    // Drives the camera instead of the controls while a path plays
+    let mut playback: Option<PathPlayback> = camera_path.as_ref()
+        .map(|path: &CameraPath| start_playback(path, options.capture));
+    // Shared with the shutdown hook that saves it
+    let statistics: Arc<Mutex<StatsTracker>> = Arc::new(Mutex::new(StatsTracker::load(PathBuf::from(STATS_PATH))));
+    track_map(&statistics, &options.map, renderable.camera().position());
+    // Statistics stop counting while the window is in the background
+    let mut focused: bool = true;
+    // Subsystems owning threads or persistent state register their hooks here
+    let mut shutdown: ShutdownCoordinator = ShutdownCoordinator::new();
+    let saved_statistics: Arc<Mutex<StatsTracker>> = Arc::clone(&statistics);
+    shutdown.register("statistics", DEFAULT_HOOK_TIMEOUT, move || {
+        if let Ok(tracker) = saved_statistics.lock() {
+            save_statistics(&tracker);
+        }
+    });
+    // Last, records logged after the flush are discarded
+    shutdown.register("logging", DEFAULT_HOOK_TIMEOUT, flush_logging);
+
+    let event_loop: glutin::event_loop::EventLoop<()> = platform.take_event_loop();
+    let mut modifiers: glutin::event::ModifiersState = glutin::event::ModifiersState::empty();
+    event_loop.run(move |ev, _, control_flow| {
+        match ev {
+            // Nothing more is drawn once a close has been requested
+            glutin::event::Event::MainEventsCleared | glutin::event::Event::RedrawRequested(_)
+                if shutdown.is_shutting_down() => (),
+            glutin::event::Event::MainEventsCleared => {
+                // The OS oversleeps short waits, so the last moments before a capped frame are spun out
+                let wait: std::time::Duration = pacing.wait_time(last_present.elapsed());
+                if !wait.is_zero() && wait <= SPIN_THRESHOLD {
+                    let due: std::time::Instant = std::time::Instant::now() + wait;
+                    while std::time::Instant::now() < due {
+                        std::hint::spin_loop();
+                    }
+                }
+                // Movement runs in fixed ticks for the time since the last pass, independent
+                // of the frame rate, and the view is drawn between the last two ticks
+                let now: std::time::Instant = std::time::Instant::now();
+                let ticks: usize = timestep.advance(now - last_update);
+                last_update = now;
+                // Camera paths and time in the background are not play
+                let paused: bool = playback.is_some() || !focused;
+                if let Ok(mut tracker) = statistics.lock() {
+                    for _ in 0..ticks {
+                        if playback.is_none() {
+                            controls.update(renderable.camera_mut(), timestep.tick().as_secs_f32());
+                        }
+                        tracker.tick(renderable.camera().latest_pose().origin, timestep.tick().as_secs_f32(), paused);
+                        renderable.update_events(timestep.tick().as_secs_f32());
+                    }
+                }
+                if let Some(overlay) = overlay.as_mut() {
+                    overlay.record_ticks(ticks, now.elapsed());
+                }
+                renderable.camera_mut().set_interpolation(timestep.alpha());
+                let wait: std::time::Duration = pacing.wait_time(now - last_present);
+                if wait.is_zero() {
+                    platform.request_redraw();
+                }
+                *control_flow = match pacing.interval() {
+                    // Wake just before the next frame is due, a frame from now if one was just requested
+                    Some(interval) => {
+                        let sleep: std::time::Duration = if wait.is_zero() { interval } else { wait };
+                        glutin::event_loop::ControlFlow::WaitUntil(now + sleep.saturating_sub(SPIN_THRESHOLD))
+                    },
+                    // Presenting blocks on the refresh with vsync, otherwise frames are drawn back to back
+                    None => glutin::event_loop::ControlFlow::Poll,
+                };
+            },
+            glutin::event::Event::RedrawRequested(_) => {
+                let now: std::time::Instant = std::time::Instant::now();
+                let delta: std::time::Duration = now - last_frame;
+                let previous_pacing: FramePacing = pacing;
+                let previous_scale: ScaleSettings = scale;
+                let mut time: f32 = (now - start).as_secs_f32();
+                let mut capture: Option<String> = None;
+                if let Some(active) = playback.as_mut() {
+                    match active.advance(renderable.camera_mut(), delta.as_secs_f32()) {
+                        Some(frame) => {
+                            // A capture runs on the path's clock so every frame is the same from run to run
+                            if active.mode() != PlaybackMode::RealTime {
+                                time = active.time();
+                            }
+                            if let Some(name) = frame.capture_name {
+                                debug!(&crate::LOGGER, "Capturing camera path frame {}", frame.frame_index);
+                                capture = Some(format!("screenshots/capture/{}", name));
+                            }
+                        },
+                        None => {
+                            info!(&crate::LOGGER, "Camera path finished");
+                            playback = None;
+                        },
+                    };
+                }
+                // The overlay is kept out of the footage while a path plays
+                let hide_overlay: bool = playback.as_ref().map_or(false, |active: &PathPlayback| active.hide_hud);
+                let frame_overlay: Option<&mut DebugOverlay> = if hide_overlay { None } else { overlay.as_mut() };
+                if let Ok(tracker) = statistics.lock() {
+                    render(&mut renderable, &mut settings, frame_overlay, &mut pacing, &timestep, &tracker, &mut scale, time, delta);
+                }
+                if let Some(path) = capture {
+                    save_screenshot(&renderable, path);
+                }
+                save_pass_captures(&renderable);
+                last_frame = now;
+                last_present = std::time::Instant::now();
+                if scale != previous_scale {
+                    apply_scale(&mut renderable, &mut settings, &mut controls, &scale);
+                }
+                if pacing != previous_pacing {
+                    info!(&crate::LOGGER, "Frame pacing {}", pacing);
+                    if pacing.vsync() != vsync {
+                        warn!(&crate::LOGGER, "VSync stays {} until the next start", if vsync { "on" } else { "off" });
+                    }
+                }
+            },
+            glutin::event::Event::DeviceEvent { event, .. } => controls.handle_device_event(&event),
+            glutin::event::Event::WindowEvent { event, .. } => {
+                // Input imgui wants for its widgets does not reach the camera or the bindings below
+                let captured: bool = overlay.as_mut().map_or(false, |overlay: &mut DebugOverlay| overlay.handle_event(&event));
+                if !captured {
+                    controls.handle_window_event(&event);
+                }
+                match event {
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(DEBUG_OVERLAY_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        if let Some(overlay) = overlay.as_mut() {
+                            overlay.toggle();
+                            info!(&crate::LOGGER, "Debug overlay {}", if overlay.visible() { "shown" } else { "hidden" });
+                        }
+                    },
+                    glutin::event::WindowEvent::ModifiersChanged(state) => modifiers = state,
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(FULLSCREEN_KEY),
+                            ..
+                        },
+                        ..
+                    } if modifiers.alt() => {
+                        // The Resized event the switch raises follows up with the settled size
+                        if let Some(size) = platform.toggle_fullscreen() {
+                            resize(&mut renderable, size);
+                        }
+                    },
+                    glutin::event::WindowEvent::Focused(state) => focused = state,
+                    _ if captured => (),
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(CAMERA_PATH_KEY),
+                            ..
+                        },
+                        ..
+                    } => match camera_path.as_ref() {
+                        Some(path) => playback = Some(start_playback(path, options.capture)),
+                        None => warn!(&crate::LOGGER, "No camera path loaded, give one with --camera-path"),
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(SCREENSHOT_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        save_screenshot(&renderable, timestamped_screenshot_path());
+                        if let Ok(mut tracker) = statistics.lock() {
+                            tracker.screenshot_taken();
+                        }
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(PASS_CAPTURE_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        info!(&crate::LOGGER, "Capturing the render passes of the next frame");
+                        renderable.renderer().request_capture();
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(RELOAD_MAP_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        if reload_map(&mut renderable, &mut settings, overlay.as_mut(), &options, scale.effective(), &mut wads) {
+                            if let Ok(tracker) = statistics.lock() {
+                                save_statistics(&tracker);
+                            }
+                            track_map(&statistics, &options.map, renderable.camera().position());
+                        }
+                        // Time spent reloading is not simulated
+                        last_update = std::time::Instant::now();
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(LIGHTING_MODE_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        settings.lighting_mode = settings.lighting_mode.next();
+                        info!(&crate::LOGGER, "Lighting mode {:?}", settings.lighting_mode);
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(WIREFRAME_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        settings.wireframe = !settings.wireframe;
+                        log_debug_view(&renderable, "Wireframe", settings.wireframe);
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(OVERDRAW_KEY),
+                            ..
+                        },
+                        ..
+                    } => {
+                        settings.overdraw = !settings.overdraw;
+                        log_debug_view(&renderable, "Overdraw", settings.overdraw);
+                    },
+                    glutin::event::WindowEvent::KeyboardInput {
+                        input: glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(LIGHTMAP_PROBE_KEY),
+                            ..
+                        },
+                        ..
+                    } => probe_lightmap(&renderable),
+                    glutin::event::WindowEvent::Resized(size) => resize(&mut renderable, size),
+                    glutin::event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => resize(&mut renderable, *new_inner_size),
+                    glutin::event::WindowEvent::CloseRequested => {
+                        info!(&crate::LOGGER, "Close requested, shutting down");
+                        shutdown.shutdown();
+                        *control_flow = glutin::event_loop::ControlFlow::Exit;
+                        return;
+                    },
+                    _ => return,
+                };
             },
             _ => (),
         }
     });
 }
 
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options: Options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            std::process::exit(2);
+        },
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    // Set before the first record so the level applies from the start
+    set_log_level(options.log_level);
     info!(&crate::LOGGER, "Configured Logging");
     // NOTE: Temporary debugging panic logger
     panic::set_hook(Box::new(|panic_info: &panic::PanicInfo| {
@@ -109,7 +695,38 @@ fn main() {
         crit!(&crate::LOGGER, "Panic at unknown location");
         std::thread::sleep(std::time::Duration::from_millis(1000));
     }));
-    let bsp = map::bsp::BSP::from_file(&"maps/crossfire.bsp".to_string()).unwrap();
-    std::thread::sleep(std::time::Duration::from_millis(1000));
-
+    if options.self_test {
+        let exit_code: i32 = crate::core::self_test::run_self_test();
+        flush_logging();
+        std::process::exit(exit_code);
+    }
+    if options.check {
+        let exit_code: i32 = crate::core::check::run_preflight_check(&options.map, options.asset_paths());
+        flush_logging();
+        std::process::exit(exit_code);
+    }
+    let map_path: &str = options.map.as_str();
+    let mut wads: WadManager = WadManager::from_asset_paths(&options.asset_paths());
+    let bsp: BSP = match BSP::from_file_with_wads(map_path, &options.load_options(), &mut wads) {
+        Ok(bsp) => bsp,
+        Err(error) => {
+            crit!(&crate::LOGGER, "Failed to load map {}: {}", map_path, error);
+            eprintln!("Failed to load map {}: {}", map_path, error);
+            flush_logging();
+            std::process::exit(1);
+        },
+    };
+    if let Some(obj_path) = &options.export_obj {
+        let exit_code: i32 = match bsp.export_obj(obj_path, ObjExportOptions::default()) {
+            Ok(()) => 0,
+            Err(error) => {
+                crit!(&crate::LOGGER, "Failed to export {} to {}: {}", map_path, obj_path.display(), error);
+                eprintln!("Failed to export {} to {}: {}", map_path, obj_path.display(), error);
+                1
+            },
+        };
+        flush_logging();
+        std::process::exit(exit_code);
+    }
+    run(bsp, options, wads);
 }
@@ -0,0 +1,412 @@
+use std::collections::BTreeMap;
+
+use crate::map::bsp::BSP;
+use crate::map::bsp30;
+use crate::scene::entity::Entity;
+
+// Frame rate of animated textures in the original engine
+pub const ANIMATION_FPS: f32 = 10.0;
+// Frames per sequence, +0 to +9 and +A to +J
+const MAX_ANIMATION_FRAMES: usize = 10;
+// Conveyor speed in units per second when the entity does not set one
+const DEFAULT_CONVEYOR_SPEED: f32 = 100.0;
+
+/// Frames of an animated texture, named `+0name` to `+9name`, and the
+/// alternate sequence `+Aname` to `+Jname` shown while a button is toggled.
+/// Both hold indices into `m_textures`.
+#[derive(Debug, Clone, Default)]
+pub struct TextureAnimation {
+    pub name: String, // Base name without the frame prefix
+    pub frames: Vec<usize>,
+    pub toggled: Vec<usize>,
+}
+
+impl TextureAnimation {
+
+    /// Texture to show `time` seconds in, falling back to whichever sequence
+    /// exists when the requested one is empty
+    pub fn frame_at(&self, time: f32, toggled: bool) -> usize {
+        let sequence: &Vec<usize> = if (toggled && !self.toggled.is_empty()) || self.frames.is_empty() {
+            &self.toggled
+        } else {
+            &self.frames
+        };
+        if sequence.is_empty() {
+            return 0;
+        }
+        let frame: usize = (time.max(0.0) * ANIMATION_FPS) as usize;
+        return sequence[frame % sequence.len()];
+    }
+
+    /// Split an animated texture name into its base name, whether it is part
+    /// of the toggled sequence, and its frame number
+    pub fn parse_frame_name(name: &str) -> Option<(String, bool, usize)> {
+        let mut chars: std::str::Chars = name.strip_prefix('+')?.chars();
+        let frame_char: char = chars.next()?.to_ascii_uppercase();
+        let base: String = chars.as_str().to_ascii_lowercase();
+        if base.is_empty() {
+            return None;
+        }
+        return match frame_char {
+            '0'..='9' => Some((base, false, frame_char as usize - '0' as usize)),
+            'A'..='J' => Some((base, true, frame_char as usize - 'A' as usize)),
+            _ => None,
+        };
+    }
+
+}
+
+/// Variants of a random tiling texture, named `-0name` to `-9name`, as
+/// indices into `m_textures`
+#[derive(Debug, Clone, Default)]
+pub struct TextureTiling {
+    pub name: String, // Base name without the variant prefix
+    pub variants: Vec<usize>,
+}
+
+impl TextureTiling {
+
+    /// Variant for a face, chosen by hashing a point on it so every face
+    /// keeps the same variant across runs
+    pub fn variant_for(&self, position: &glm::Vec3) -> usize {
+        if self.variants.is_empty() {
+            return 0;
+        }
+        // FNV-1a over the whole unit coordinates, stable unlike the std hasher
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for component in position.iter() {
+            for byte in (component.floor() as i32).to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        return self.variants[(hash % self.variants.len() as u64) as usize];
+    }
+
+    /// Split a random tiling texture name into its base name and variant number
+    pub fn parse_variant_name(name: &str) -> Option<(String, usize)> {
+        let mut chars: std::str::Chars = name.strip_prefix('-')?.chars();
+        let variant: usize = chars.next()?.to_digit(10)? as usize;
+        let base: String = chars.as_str().to_ascii_lowercase();
+        if base.is_empty() {
+            return None;
+        }
+        return Some((base, variant));
+    }
+
+}
+
+impl BSP {
+
+    /// Group the `+N` and `+A` frames of animated mip textures by base name.
+    /// A sequence stops at its first missing frame, as in the original engine.
+    pub (crate) fn group_texture_animations(&mut self) {
+        let mut sequences: BTreeMap<String, [[Option<usize>; MAX_ANIMATION_FRAMES]; 2]> = BTreeMap::new();
+        for i in 0..self.mip_textures.len() {
+            let Some((base, toggled, frame)) = TextureAnimation::parse_frame_name(&self.mip_texture_name(i)) else {
+                continue;
+            };
+            let slots: &mut [Option<usize>; MAX_ANIMATION_FRAMES] = &mut sequences.entry(base).or_default()[toggled as usize];
+            if slots[frame].is_some() {
+                self.load_issues.record("textures", format!("Texture {} repeats an animation frame, replacing the earlier one", i));
+            }
+            slots[frame] = Some(i);
+        }
+        self.texture_animations = Vec::with_capacity(sequences.len());
+        self.texture_animation_indices = vec![None; self.mip_textures.len()];
+        for (name, [frames, toggled]) in sequences.into_iter() {
+            let animation: TextureAnimation = TextureAnimation {
+                name,
+                frames: frames.iter().map_while(|frame: &Option<usize>| *frame).collect(),
+                toggled: toggled.iter().map_while(|frame: &Option<usize>| *frame).collect(),
+            };
+            let declared: usize = frames.iter().chain(toggled.iter()).flatten().count();
+            let kept: Vec<usize> = animation.frames.iter().chain(animation.toggled.iter()).copied().collect();
+            if kept.len() != declared {
+                self.load_issues.record(
+                    "textures",
+                    format!("Animated texture {} has gaps in its frames, ignoring frames after the first gap", animation.name),
+                );
+            }
+            if animation.frames.is_empty() && animation.toggled.is_empty() {
+                continue;
+            }
+            let index: usize = self.texture_animations.len();
+            for frame in kept {
+                self.texture_animation_indices[frame] = Some(index);
+            }
+            self.texture_animations.push(animation);
+        }
+        debug!(&crate::LOGGER, "Grouped {} animated textures", self.texture_animations.len());
+    }
+
+    /// Velocity in world units per second a `func_conveyor` scrolls its
+    /// textures at, from its `angles` and `speed`. Yaw -1 and -2 point
+    /// straight up and down, following the engine's move direction convention.
+    pub fn conveyor_velocity(entity: &Entity) -> Option<glm::Vec3> {
+        if entity.find_property(&"classname".to_string()).map(String::as_str) != Some("func_conveyor") {
+            return None;
+        }
+        let angles: Vec<f32> = entity.find_property(&"angles".to_string())
+            .map_or(Vec::new(), |angles: &String| angles.split_whitespace()
+                .map_while(|component: &str| component.parse::<f32>().ok())
+                .collect());
+        let (pitch, yaw): (f32, f32) = match angles[..] {
+            [pitch, yaw, _] => (pitch, yaw),
+            _ => (0.0, 0.0),
+        };
+        let direction: glm::Vec3 = if yaw == -1.0 {
+            glm::vec3(0.0, 0.0, 1.0)
+        } else if yaw == -2.0 {
+            glm::vec3(0.0, 0.0, -1.0)
+        } else {
+            let (pitch, yaw): (f32, f32) = (pitch.to_radians(), yaw.to_radians());
+            glm::vec3(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), -pitch.sin())
+        };
+        let speed: f32 = entity.find_property(&"speed".to_string())
+            .and_then(|speed: &String| speed.trim().parse::<f32>().ok())
+            .filter(|speed: &f32| *speed != 0.0)
+            .unwrap_or(DEFAULT_CONVEYOR_SPEED);
+        return Some(direction * speed);
+    }
+
+    /// Texture coordinate offset after `time` seconds of a surface scrolling
+    /// with `velocity`, in normalised units wrapped to [0, 1). Only `scroll`
+    /// textures move.
+    pub fn texture_uv_scroll(&self, texture_info: &bsp30::TextureInfo, velocity: &glm::Vec3, time: f32) -> Option<glm::Vec2> {
+        let mip_tex_index: usize = texture_info.mip_tex_index as usize;
+        if !self.mip_texture_name(mip_tex_index).to_ascii_lowercase().starts_with("scroll") {
+            return None;
+        }
+        // The offset is subtracted so the pattern moves along the velocity
+        let offset: glm::Vec2 = -glm::vec2(glm::dot(velocity, &texture_info.s), glm::dot(velocity, &texture_info.t)) * time;
+        return Some(glm::fract(&offset.component_div(&self.texture_normalisation_size(mip_tex_index))));
+    }
+
+    /// Group the `-N` variants of random tiling mip textures by base name
+    pub (crate) fn group_texture_tilings(&mut self) {
+        let mut tilings: BTreeMap<String, BTreeMap<usize, usize>> = BTreeMap::new();
+        for i in 0..self.mip_textures.len() {
+            let Some((base, variant)) = TextureTiling::parse_variant_name(&self.mip_texture_name(i)) else {
+                continue;
+            };
+            if tilings.entry(base).or_default().insert(variant, i).is_some() {
+                self.load_issues.record("textures", format!("Texture {} repeats a random tiling variant, replacing the earlier one", i));
+            }
+        }
+        self.texture_tilings = Vec::with_capacity(tilings.len());
+        self.texture_tiling_indices = vec![None; self.mip_textures.len()];
+        for (name, variants) in tilings.into_iter() {
+            let index: usize = self.texture_tilings.len();
+            for variant in variants.values() {
+                self.texture_tiling_indices[*variant] = Some(index);
+            }
+            self.texture_tilings.push(TextureTiling {
+                name,
+                variants: variants.into_values().collect(),
+            });
+        }
+        debug!(&crate::LOGGER, "Grouped {} random tiling textures", self.texture_tilings.len());
+    }
+
+    /// Random tiling group a mip texture is a variant of, if any
+    pub fn texture_tiling(&self, mip_tex_index: usize) -> Option<usize> {
+        return self.texture_tiling_indices.get(mip_tex_index).copied().flatten();
+    }
+
+    /// Animation a mip texture is a frame of, if any
+    pub fn texture_animation(&self, mip_tex_index: usize) -> Option<usize> {
+        return self.texture_animation_indices.get(mip_tex_index).copied().flatten();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map::load_micro_map;
+
+    /// Micro map with its mip textures replaced by blank ones with `names`
+    fn with_textures(names: &[&str]) -> BSP {
+        let mut bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        bsp.mip_textures = names.iter().map(|name: &&str| bsp30::MipTex {
+            name: bsp30::TextureName::from_name(name).unwrap(),
+            width: 16,
+            height: 16,
+            offsets: [0; bsp30::MIP_LEVELS],
+        }).collect();
+        bsp.load_issues = crate::map::load_options::LoadIssues::new();
+        return bsp;
+    }
+
+    fn entity(properties: &str) -> Entity {
+        return Entity::new(&properties.to_string());
+    }
+
+    #[test]
+    fn conveyor_velocity_follows_angles_and_speed() {
+        let velocity: glm::Vec3 = BSP::conveyor_velocity(&entity("\"classname\" \"func_conveyor\" \"angles\" \"0 90 0\" \"speed\" \"100\"")).unwrap();
+        assert!((velocity - glm::vec3(0.0, 100.0, 0.0)).norm() < 1e-3);
+        let up: glm::Vec3 = BSP::conveyor_velocity(&entity("\"classname\" \"func_conveyor\" \"angles\" \"0 -1 0\" \"speed\" \"50\"")).unwrap();
+        assert_eq!(up, glm::vec3(0.0, 0.0, 50.0));
+    }
+
+    #[test]
+    fn conveyor_speed_defaults_when_unset() {
+        let velocity: glm::Vec3 = BSP::conveyor_velocity(&entity("\"classname\" \"func_conveyor\"")).unwrap();
+        assert_eq!(velocity, glm::vec3(DEFAULT_CONVEYOR_SPEED, 0.0, 0.0));
+        assert_eq!(BSP::conveyor_velocity(&entity("\"classname\" \"func_wall\" \"angles\" \"0 90 0\"")), None);
+    }
+
+    #[test]
+    fn only_scroll_textures_move() {
+        let mut bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let velocity: glm::Vec3 = glm::vec3(0.0, 100.0, 0.0);
+        let texture_info: bsp30::TextureInfo = bsp.texture_infos[0];
+        assert_eq!(bsp.texture_uv_scroll(&texture_info, &velocity, 0.1), None);
+        bsp.mip_textures[0].name = bsp30::TextureName::from_name("scrollfloor").unwrap();
+        // The floor's t axis points down y, 10 units along a 16 texel texture
+        let offset: glm::Vec2 = bsp.texture_uv_scroll(&texture_info, &velocity, 0.1).unwrap();
+        assert!((offset - glm::vec2(0.0, 0.625)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn frame_names_split_into_base_sequence_and_frame() {
+        assert_eq!(TextureAnimation::parse_frame_name("+0lab1_w"), Some(("lab1_w".to_string(), false, 0)));
+        assert_eq!(TextureAnimation::parse_frame_name("+9LAB1_W"), Some(("lab1_w".to_string(), false, 9)));
+        assert_eq!(TextureAnimation::parse_frame_name("+a~light"), Some(("~light".to_string(), true, 0)));
+        assert_eq!(TextureAnimation::parse_frame_name("+Jbutton"), Some(("button".to_string(), true, 9)));
+        assert_eq!(TextureAnimation::parse_frame_name("+kbutton"), None);
+        assert_eq!(TextureAnimation::parse_frame_name("+0"), None);
+        assert_eq!(TextureAnimation::parse_frame_name("lab1_w"), None);
+        assert_eq!(TextureAnimation::parse_frame_name("-0lab1_w"), None);
+    }
+
+    #[test]
+    fn animation_frames_are_sorted_and_toggled_frames_kept_apart() {
+        let mut bsp: BSP = with_textures(&["+2screen", "floor", "+0screen", "+bscreen", "+1screen", "+ascreen", "+0water"]);
+        bsp.group_texture_animations();
+        assert!(bsp.load_issues.is_empty());
+        assert_eq!(bsp.texture_animations.len(), 2);
+        let screen: &TextureAnimation = &bsp.texture_animations[0];
+        assert_eq!(screen.name, "screen");
+        assert_eq!(screen.frames, vec![2, 4, 0]);
+        assert_eq!(screen.toggled, vec![5, 3]);
+        assert_eq!(bsp.texture_animations[1].frames, vec![6]);
+        for i in [0, 2, 3, 4, 5] {
+            assert_eq!(bsp.texture_animation(i), Some(0));
+        }
+        assert_eq!(bsp.texture_animation(1), None);
+        assert_eq!(bsp.texture_animation(6), Some(1));
+    }
+
+    #[test]
+    fn animation_sequences_stop_at_the_first_missing_frame() {
+        let mut bsp: BSP = with_textures(&["+0screen", "+1screen", "+3screen"]);
+        bsp.group_texture_animations();
+        assert_eq!(bsp.texture_animations[0].frames, vec![0, 1]);
+        assert_eq!(bsp.texture_animation(2), None);
+        assert_eq!(bsp.load_issues.len(), 1);
+    }
+
+    #[test]
+    fn frames_advance_at_the_animation_rate() {
+        let animation: TextureAnimation = TextureAnimation {
+            name: "screen".to_string(),
+            frames: vec![4, 5, 6],
+            toggled: vec![7],
+        };
+        let step: f32 = 1.0 / ANIMATION_FPS;
+        assert_eq!(animation.frame_at(0.0, false), 4);
+        assert_eq!(animation.frame_at(step * 0.5, false), 4);
+        assert_eq!(animation.frame_at(step * 1.5, false), 5);
+        assert_eq!(animation.frame_at(step * 2.5, false), 6);
+        // Wraps around after the last frame
+        assert_eq!(animation.frame_at(step * 3.5, false), 4);
+        assert_eq!(animation.frame_at(-1.0, false), 4);
+        assert_eq!(animation.frame_at(step * 1.5, true), 7);
+    }
+
+    #[test]
+    fn frame_selection_falls_back_to_the_sequence_that_exists() {
+        let frames_only: TextureAnimation = TextureAnimation {
+            name: "screen".to_string(),
+            frames: vec![1, 2],
+            toggled: Vec::new(),
+        };
+        assert_eq!(frames_only.frame_at(0.15, true), 2);
+        let toggled_only: TextureAnimation = TextureAnimation {
+            name: "button".to_string(),
+            frames: Vec::new(),
+            toggled: vec![3, 4],
+        };
+        assert_eq!(toggled_only.frame_at(0.15, false), 4);
+        assert_eq!(TextureAnimation::default().frame_at(1.0, false), 0);
+    }
+
+    #[test]
+    fn variant_names_split_into_base_and_variant() {
+        assert_eq!(TextureTiling::parse_variant_name("-0crete"), Some(("crete".to_string(), 0)));
+        assert_eq!(TextureTiling::parse_variant_name("-9CRETE"), Some(("crete".to_string(), 9)));
+        assert_eq!(TextureTiling::parse_variant_name("-acrete"), None);
+        assert_eq!(TextureTiling::parse_variant_name("-0"), None);
+        assert_eq!(TextureTiling::parse_variant_name("+0crete"), None);
+    }
+
+    #[test]
+    fn tiling_variants_are_grouped_by_base_name() {
+        let mut bsp: BSP = with_textures(&["-2crete", "-0crete", "floor", "-1brick", "-1crete", "+0crete"]);
+        bsp.group_texture_tilings();
+        assert!(bsp.load_issues.is_empty());
+        assert_eq!(bsp.texture_tilings.len(), 2);
+        assert_eq!(bsp.texture_tilings[0].name, "brick");
+        assert_eq!(bsp.texture_tilings[0].variants, vec![3]);
+        assert_eq!(bsp.texture_tilings[1].name, "crete");
+        assert_eq!(bsp.texture_tilings[1].variants, vec![1, 4, 0]);
+        for i in [0, 1, 4] {
+            assert_eq!(bsp.texture_tiling(i), Some(1));
+        }
+        assert_eq!(bsp.texture_tiling(2), None);
+        assert_eq!(bsp.texture_tiling(5), None);
+    }
+
+    #[test]
+    fn repeated_tiling_variants_replace_the_earlier_one() {
+        let mut bsp: BSP = with_textures(&["-0crete", "-0CRETE"]);
+        bsp.group_texture_tilings();
+        assert_eq!(bsp.texture_tilings[0].variants, vec![1]);
+        assert_eq!(bsp.load_issues.len(), 1);
+    }
+
+    #[test]
+    fn tiling_variants_are_stable_per_position() {
+        let tiling: TextureTiling = TextureTiling {
+            name: "crete".to_string(),
+            variants: vec![10, 11, 12, 13, 14],
+        };
+        let position: glm::Vec3 = glm::vec3(128.0, -64.0, 32.0);
+        let variant: usize = tiling.variant_for(&position);
+        assert!(tiling.variants.contains(&variant));
+        // Same answer every call, and for any point within the same unit cell
+        assert_eq!(tiling.variant_for(&position), variant);
+        assert_eq!(tiling.variant_for(&glm::vec3(128.9, -63.1, 32.5)), variant);
+        // Fixed hash, so a given face keeps its variant across runs
+        assert_eq!(variant, 12);
+        assert_eq!(TextureTiling::default().variant_for(&position), 0);
+    }
+
+    #[test]
+    fn tiling_variants_spread_across_positions() {
+        let tiling: TextureTiling = TextureTiling {
+            name: "crete".to_string(),
+            variants: vec![0, 1, 2, 3],
+        };
+        let mut seen: [bool; 4] = [false; 4];
+        for x in 0..16 {
+            seen[tiling.variant_for(&glm::vec3(x as f32 * 64.0, 0.0, 0.0))] = true;
+        }
+        assert!(seen.iter().all(|seen: &bool| *seen));
+    }
+
+}
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use crate::map::bsp::{DECAL_WADS, SKY_DIR, WAD_DIR};
+
+/// Directories the external assets a map references are looked up in
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetPaths {
+    pub wad_dirs: Vec<PathBuf>, // Searched in order for texture and decal WADs
+    pub sky_dir: PathBuf,
+    pub decal_wads: Vec<PathBuf>, // Relative to the WAD directories
+}
+
+impl Default for AssetPaths {
+    fn default() -> Self {
+        return AssetPaths {
+            wad_dirs: vec![PathBuf::from(WAD_DIR.as_str())],
+            sky_dir: PathBuf::from(SKY_DIR.as_str()),
+            decal_wads: DECAL_WADS.iter().map(PathBuf::from).collect(),
+        };
+    }
+}
+
+impl AssetPaths {
+
+    /// Every path a WAD reference such as `valve/halflife.wad` could be at,
+    /// in search order
+    pub fn wad_candidates(&self, name: &str) -> Vec<PathBuf> {
+        return self.wad_dirs.iter()
+            .map(|dir: &PathBuf| dir.join(name))
+            .collect();
+    }
+
+    /// Decal WAD references in the form the WAD providers expect
+    pub fn decal_wad_names(&self) -> Vec<String> {
+        return self.decal_wads.iter()
+            .map(|path: &PathBuf| path.to_string_lossy().replace("\\", "/"))
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::bsp::BSP;
+    use crate::map::error::BspError;
+    use crate::map::micro_map::MICRO_WAD;
+    use crate::map::wad::{FileWadProvider, Wad, WadProvider};
+
+    #[test]
+    fn worldspawn_references_resolve_against_each_directory() {
+        let asset_paths: AssetPaths = AssetPaths {
+            wad_dirs: vec![PathBuf::from("first"), PathBuf::from("second")],
+            ..AssetPaths::default()
+        };
+        let name: String = BSP::wad_reference_name(&"\\sierra\\half-life\\valve\\halflife.wad".replace("\\", "/"));
+        assert_eq!(name, "valve/halflife.wad");
+        assert_eq!(asset_paths.wad_candidates(&name), vec![
+            PathBuf::from("first/valve/halflife.wad"),
+            PathBuf::from("second/valve/halflife.wad"),
+        ]);
+    }
+
+    #[test]
+    fn the_first_directory_with_the_wad_wins() {
+        let root: PathBuf = std::env::temp_dir().join(format!("lambda-asset-paths-{}", std::process::id()));
+        let dirs: Vec<PathBuf> = vec![root.join("missing"), root.join("mod"), root.join("base")];
+        for dir in dirs[1..].iter() {
+            std::fs::create_dir_all(dir.join("valve")).unwrap();
+            std::fs::write(dir.join("valve/halflife.wad"), MICRO_WAD).unwrap();
+        }
+        let provider: FileWadProvider = FileWadProvider::with_dirs(dirs.clone());
+        let wad: Result<Wad, BspError> = provider.open_wad("valve/halflife.wad");
+        let missing: bool = provider.open_wad("valve/decals.wad").is_err();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(wad.unwrap().path, dirs[1].join("valve/halflife.wad"));
+        assert!(missing);
+    }
+
+    #[test]
+    fn decal_wad_names_use_forward_slashes() {
+        let asset_paths: AssetPaths = AssetPaths {
+            decal_wads: vec![PathBuf::from("valve\\decals.wad")],
+            ..AssetPaths::default()
+        };
+        assert_eq!(asset_paths.decal_wad_names(), vec![String::from("valve/decals.wad")]);
+    }
+
+}
@@ -1,17 +1,23 @@
 use std::collections::HashMap;
-use std::path::Path;
-use std::io::{Result, Error, ErrorKind, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::io::{self, Error, BufReader, Read, Seek, SeekFrom};
 use std::fs::{File, OpenOptions};
+use std::sync::Arc;
 use bit_set::BitSet;
 use lazy_static::lazy_static;
-use byteorder::ReadBytesExt;
 
+use crate::map::animation::{TextureAnimation, TextureTiling};
+use crate::map::AssetPaths;
 use crate::map::bsp30::{self, TextureInfo};
-use crate::map::wad::{Wad, MipmapTexture};
+use crate::map::error::BspError;
+use crate::map::load_options::{BspLoadOptions, LoadIssues, LoadStage, ParseStrictness};
+use crate::map::wad::{CacheStats, Wad, MipmapTexture, NoWads, TextureSource};
 use crate::resource::image::Image;
+use crate::resource::wad_manager::WadManager;
 use crate::resource::resource::Resource;
 use crate::scene::entity::Entity;
-use crate::util::mathutil::{point_in_plane, point_in_box};
+use crate::util::mathutil::point_in_plane;
+use crate::util::memory::{HeapBytes, vec_bytes};
 
 #[derive(Default, Clone)]
 pub struct FaceTexCoords {
@@ -19,10 +25,156 @@ pub struct FaceTexCoords {
     pub lightmap_coords: Vec<glm::Vec2>,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct LightmapExtents {
+    pub min_st: glm::Vec2, // Texture space bounds of the face vertices
+    pub max_st: glm::Vec2,
+    pub width: usize, // Lightmap size in texels
+    pub height: usize,
+}
+
+impl LightmapExtents {
+
+    fn mid_poly(&self) -> glm::Vec2 {
+        return (self.min_st + self.max_st) / 2.0;
+    }
+
+    fn mid_tex(&self) -> glm::Vec2 {
+        return glm::vec2(self.width as f32, self.height as f32) / 2.0;
+    }
+
+    /// Lightmap texel coordinate of a texture space position
+    pub fn texel(&self, st: glm::Vec2) -> glm::Vec2 {
+        return self.mid_tex() + (st - self.mid_poly()) / 16.0;
+    }
+
+    /// Texture space position of a lightmap texel coordinate
+    pub fn texture_space(&self, texel: glm::Vec2) -> glm::Vec2 {
+        return self.mid_poly() + (texel - self.mid_tex()) * 16.0;
+    }
+
+}
+
+// Lightmaps a face can blend, one per light style
+pub const MAX_LIGHTMAPS: usize = 4;
+pub const MAX_LIGHT_STYLES: usize = 64;
+pub const UNUSED_LIGHT_STYLE: u8 = 255;
+
+/// A face's lightmaps, one per light style it is lit by, stored in the
+/// lighting lump one after another
+#[derive(Clone, Default)]
+pub struct FaceLightmaps {
+    pub styles: [u8; MAX_LIGHTMAPS], // UNUSED_LIGHT_STYLE for empty slots
+    pub images: [Option<Image>; MAX_LIGHTMAPS],
+}
+
+impl FaceLightmaps {
+
+    pub fn is_empty(&self) -> bool {
+        return self.images.iter().all(Option::is_none);
+    }
+
+    /// Width and height shared by every style's lightmap
+    pub fn dimensions(&self) -> Option<(usize, usize)> {
+        return self.images.iter()
+            .flatten()
+            .next()
+            .map(|image: &Image| (image.width, image.height));
+    }
+
+    pub fn uses_style(&self, style: usize) -> bool {
+        return self.styles.iter()
+            .zip(self.images.iter())
+            .any(|(slot_style, image): (&u8, &Option<Image>)| image.is_some() && *slot_style as usize == style);
+    }
+
+    /// Sum of the style lightmaps, each scaled by its style's value and
+    /// saturated to the texel range
+    pub fn composite(&self, light_styles: &[f32; MAX_LIGHT_STYLES]) -> Image {
+        let Some((width, height)) = self.dimensions() else {
+            return Image::new();
+        };
+        let mut sum: Vec<f32> = vec![0.0; width * height * 3];
+        for (style, image) in self.styles.iter().zip(self.images.iter()) {
+            let Some(image) = image else {
+                continue;
+            };
+            let value: f32 = light_styles.get(*style as usize).copied().unwrap_or(0.0);
+            for (texel, byte) in sum.iter_mut().zip(image.data.iter()) {
+                *texel += *byte as f32 * value;
+            }
+        }
+        return Image {
+            channels: 3,
+            width,
+            height,
+            data: sum.iter().map(|texel: &f32| texel.round().clamp(0.0, 255.0) as u8).collect(),
+        };
+    }
+
+}
+
 pub struct Decal {
     pub tex_index: u32,
     pub normal: glm::Vec3,
-    pub vec: [glm::Vec3; 4],
+    pub vec: Vec<glm::Vec3>, // Convex polygon of the decal quad clipped to its face
+    pub tex_coords: Vec<glm::Vec2>, // Per vertex of vec
+}
+
+#[derive(Debug, Clone)]
+pub struct MissingTexture {
+    pub name: bsp30::TextureName,
+    pub index: usize, // Index into mip_textures and m_textures
+}
+
+/// Rendering behaviour implied by a texture name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextureClass {
+    #[default]
+    Normal,
+    Sky, // Drawn into the depth buffer only so the skybox shows through
+    Trigger,
+    Clip,
+    Origin,
+    Hint,
+    Skip,
+    Water, // `!` and `*` liquid surfaces, drawn translucent and warped
+}
+
+impl TextureClass {
+
+    /// Classify a mip texture name, ignoring case and the `{` (masked), `!`
+    /// and `*` (liquid) prefixes
+    pub fn from_name(name: &str) -> TextureClass {
+        let base: &str = name.trim_start_matches(|c: char| c == '{' || c == '!' || c == '*');
+        let liquid: bool = name.starts_with(|c: char| c == '!' || c == '*');
+        return match base.to_ascii_lowercase().as_str() {
+            "sky" => TextureClass::Sky,
+            "aaatrigger" | "trigger" => TextureClass::Trigger,
+            "clip" => TextureClass::Clip,
+            "origin" => TextureClass::Origin,
+            "hint" => TextureClass::Hint,
+            "skip" => TextureClass::Skip,
+            _ if liquid => TextureClass::Water,
+            _ => TextureClass::Normal,
+        };
+    }
+
+    /// Compiler tool textures that are never drawn
+    pub fn is_hidden(&self) -> bool {
+        return !matches!(self, TextureClass::Normal | TextureClass::Sky | TextureClass::Water);
+    }
+
+    pub fn is_tool(&self) -> bool {
+        return !matches!(self, TextureClass::Normal | TextureClass::Water);
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedWad {
+    pub name: String, // As referenced by the map, relative to the WAD directory
+    pub reason: String,
 }
 
 pub struct Hull {
@@ -47,6 +199,46 @@ impl Hull {
         };
     }
 
+    /// Signed distance of a point from a plane, axial planes skip the dot product
+    pub fn plane_distance(plane: &bsp30::Plane, point: &glm::Vec3) -> f32 {
+        if plane.r#type >= 0 && plane.r#type < bsp30::PlaneType::PlaneAnyX as i32 {
+            return point[plane.r#type as usize] - plane.dist;
+        }
+        return glm::dot(&plane.normal, point) - plane.dist;
+    }
+
+    /// Contents of the hull at a point, walking the clip nodes from `node`
+    /// until a leaf is reached. A point exactly on a plane goes to the front
+    /// child, as in SV_HullPointContents. Malformed trees and unknown contents
+    /// are treated as solid.
+    pub fn point_contents(&self, node: i32, point: glm::Vec3) -> bsp30::ContentType {
+        let mut current: i32 = node;
+        // A valid hull reaches a leaf in fewer steps than it has clip nodes
+        for _ in 0..=self.clip_nodes.len() {
+            if current < 0 {
+                return num::FromPrimitive::from_i32(current).unwrap_or(bsp30::ContentType::ContentsSolid);
+            }
+            let Some(clip_node) = self.clip_nodes.get(current as usize) else {
+                break;
+            };
+            let Some(plane) = self.planes.get(clip_node.plane_index as usize) else {
+                break;
+            };
+            let side: usize = if Hull::plane_distance(plane, &point) < 0.0 { 1 } else { 0 };
+            current = clip_node.child_index[side] as i32;
+        }
+        warn!(&crate::LOGGER, "Malformed clip node tree from node {}, treating as solid", node);
+        return bsp30::ContentType::ContentsSolid;
+    }
+
+}
+
+impl HeapBytes for Hull {
+
+    fn heap_bytes(&self) -> usize {
+        return vec_bytes(&self.clip_nodes) + vec_bytes(&self.planes);
+    }
+
 }
 
 impl Clone for Hull {
@@ -88,8 +280,17 @@ impl Model {
 
 }
 
+impl HeapBytes for Model {
+
+    fn heap_bytes(&self) -> usize {
+        return self.hulls.iter().map(|hull: &Hull| hull.heap_bytes()).sum();
+    }
+
+}
+
 pub struct BSP {
     pub header: bsp30::Header,
+    pub version: bsp30::BspVersion,
     pub vertices: Vec<bsp30::Vertex>,
     pub edges: Vec<bsp30::Edge>,
     pub surface_edges: Vec<bsp30::SurfaceEdge>,
@@ -107,20 +308,35 @@ pub struct BSP {
     pub entities: Vec<Entity>,
     pub brush_entities: Vec<usize>,
     pub special_entities: Vec<usize>,
-    pub wad_files: Vec<Wad>,
-    pub decal_wads: Vec<Wad>,
+    pub wad_files: Vec<String>, // Texture WADs open in the loading WadManager
+    pub decal_wads: Vec<String>,
     pub m_decals: Vec<Decal>,
     pub vis_lists: Vec<BitSet<u8>>,
     pub m_textures: Vec<MipmapTexture>,
-    pub m_lightmaps: Vec<Image>,
+    pub texture_classes: Vec<TextureClass>, // Per mip texture, decal textures are not classified
+    pub texture_animations: Vec<TextureAnimation>,
+    pub texture_animation_indices: Vec<Option<usize>>, // Per mip texture, index into texture_animations
+    pub texture_tilings: Vec<TextureTiling>,
+    pub texture_tiling_indices: Vec<Option<usize>>, // Per mip texture, index into texture_tilings
+    pub missing_textures: Vec<MissingTexture>,
+    pub skipped_wads: Vec<SkippedWad>,
+    pub asset_paths: AssetPaths,
+    pub m_lightmaps: Vec<FaceLightmaps>,
+    pub light_styles: [f32; MAX_LIGHT_STYLES], // Brightness of each light style, 1.0 is as compiled
     pub hull_0_clip_nodes: Vec<bsp30::ClipNode>,
     pub models: Vec<Model>,
+    pub load_issues: LoadIssues,
 }
 
 lazy_static!{
-    static ref WAD_DIR: String = String::from("data/wads");
-    static ref SKY_DIR: String = String::from("data/textures/sky");
-    static ref SKY_NAME_SUFFIXES: [String; 6] = [
+    pub (crate) static ref WAD_DIR: String = String::from("data/wads");
+    pub (crate) static ref SKY_DIR: String = String::from("data/textures/sky");
+    pub (crate) static ref DEFAULT_SKY_NAME: String = String::from("desert");
+    pub (crate) static ref DECAL_WADS: [String; 2] = [
+        String::from("valve/decals.wad"),
+        String::from("cstrike/decals.wad"),
+    ];
+    pub (crate) static ref SKY_NAME_SUFFIXES: [String; 6] = [
         String::from("ft"),
         String::from("bk"),
         String::from("up"),
@@ -132,27 +348,87 @@ lazy_static!{
 
 impl BSP {
 
-    pub fn from_file(path: &String) -> Result<Self> {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, BspError> {
+        return BSP::from_file_with_progress(path, |_, _| {});
+    }
+
+    /// Load a BSP from disk, calling `progress` with the current stage and the
+    /// fraction of it completed, from 0.0 to 1.0
+    pub fn from_file_with_progress<P: AsRef<Path>>(path: P, mut progress: impl FnMut(LoadStage, f32)) -> Result<Self, BspError> {
+        return BSP::load_file(path, &BspLoadOptions::default(), &mut progress);
+    }
+
+    /// Load a BSP from disk, resolving WADs from the options' WAD directories
+    /// unless they provide their own `wad_provider`
+    pub fn from_file_with_options<P: AsRef<Path>>(path: P, options: &BspLoadOptions) -> Result<Self, BspError> {
+        return BSP::load_file(path, options, &mut |_, _| {});
+    }
+
+    /// Load a BSP from disk taking texture and decal WADs from `wads`, which
+    /// keeps them open for the next map. The options' `wad_provider` is unused.
+    pub fn from_file_with_wads<P: AsRef<Path>>(path: P, options: &BspLoadOptions, wads: &mut WadManager) -> Result<Self, BspError> {
+        let file: File = BSP::open_file(path.as_ref())?;
+        return BSP::load_reader(file, options, wads, &mut |_, _| {});
+    }
+
+    fn load_file<P: AsRef<Path>>(path: P, options: &BspLoadOptions, progress: &mut dyn FnMut(LoadStage, f32)) -> Result<Self, BspError> {
+        let file: File = BSP::open_file(path.as_ref())?;
+        let mut wads: WadManager = match &options.wad_provider {
+            Some(provider) => WadManager::new(Arc::clone(provider)),
+            None => WadManager::from_asset_paths(&options.asset_paths),
+        };
+        wads.set_decal_wads(options.asset_paths.decal_wad_names());
+        return BSP::load_reader(file, options, &mut wads, progress);
+    }
+
+    fn open_file(path: &Path) -> Result<File, BspError> {
         let file: File = match OpenOptions::new()
             .read(true)
             .open(path) {
             Ok(f) => f,
-            Err(error) => return Err(Error::new(
+            Err(error) => return Err(BspError::Io(Error::new(
                 error.kind(),
                 format!("Failed to open BSP file for reading: {}", error.to_string())
-            ))
+            )))
         };
-        let mut reader: BufReader<File> = BufReader::new(file);
-        info!(&crate::LOGGER, "Loading BSP file: {}", path);
-        let header: bsp30::Header = bsp30::Header::from_reader(&mut reader)?;
-        if header.version != 30 {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("Invalid BSP version {}, expected 30", header.version)
-            ));
+        info!(&crate::LOGGER, "Loading BSP file: {}", path.display());
+        return Ok(file);
+    }
+
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self, BspError> {
+        return BSP::from_reader_with_options(reader, &BspLoadOptions::default());
+    }
+
+    /// Parse a BSP from any seekable source. WAD textures and decals are only
+    /// loaded when the options provide a `wad_provider`, nothing is read from disk otherwise.
+    pub fn from_reader_with_options<R: Read + Seek>(reader: R, options: &BspLoadOptions) -> Result<Self, BspError> {
+        let mut wads: WadManager = WadManager::new(match &options.wad_provider {
+            Some(provider) => Arc::clone(provider),
+            None => Arc::new(NoWads),
+        });
+        wads.set_decal_wads(options.asset_paths.decal_wad_names());
+        return BSP::load_reader(reader, options, &mut wads, &mut |_, _| {});
+    }
+
+    fn load_reader<R: Read + Seek>(reader: R,
+                                   options: &BspLoadOptions,
+                                   wads: &mut WadManager,
+                                   progress: &mut dyn FnMut(LoadStage, f32)) -> Result<Self, BspError> {
+        let mut reader: BufReader<R> = BufReader::new(reader);
+        let file_len: u64 = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header: bsp30::Header = bsp30::Header::from_reader(&mut reader)?;
+        if BSP::has_swapped_entity_lump(&mut reader, &header, file_len)? {
+            info!(&crate::LOGGER, "Entities and planes lumps are swapped, loading as a Blue Shift map");
+            header.lump.swap(bsp30::LumpType::LumpEntities as usize, bsp30::LumpType::LumpPlanes as usize);
         }
+        let Some(version) = bsp30::BspVersion::from_header(header.version) else {
+            return Err(BspError::InvalidVersion { found: header.version });
+        };
+        info!(&crate::LOGGER, "BSP version {} ({:?})", header.version, version);
         let mut bsp: BSP = BSP {
             header,
+            version,
             vertices: Vec::new(),
             edges: Vec::new(),
             surface_edges: Vec::new(),
@@ -175,43 +451,76 @@ impl BSP {
             m_decals: Vec::new(),
             vis_lists: Vec::new(),
             m_textures: Vec::new(),
+            texture_classes: Vec::new(),
+            texture_animations: Vec::new(),
+            texture_animation_indices: Vec::new(),
+            texture_tilings: Vec::new(),
+            texture_tiling_indices: Vec::new(),
+            missing_textures: Vec::new(),
+            skipped_wads: Vec::new(),
+            asset_paths: options.asset_paths.clone(),
             m_lightmaps: Vec::new(),
+            light_styles: [1.0; MAX_LIGHT_STYLES],
             hull_0_clip_nodes: Vec::new(),
             models: Vec::new(),
+            load_issues: LoadIssues::new(),
         };
-        // Init and read BSP component vectors
+        bsp.validate_lumps(file_len)?;
+        bsp.check_lump_overlaps(options.strictness)?;
+        bsp.check_limits()?;
+        progress(LoadStage::Lumps, 0.0);
+        // Init and read BSP component vectors. These lumps only depend on the
+        // header, so each is read into its own buffer and parsed independently
         macro_rules! bsp_comp_init {
-            ($name:ident,$lump_type:expr,$element_type:ty) => {
-                bsp.$name = Vec::with_capacity(
-                    bsp.header.lump[$lump_type as usize].length as usize / std::mem::size_of::<$element_type>()
-                );
-                reader.seek(SeekFrom::Start(bsp.header.lump[$lump_type as usize].offset as u64))?;
-                for _ in 0..bsp.$name.capacity() {
-                    bsp.$name.push(<$element_type>::from_reader(&mut reader)?);
+            ($($name:ident: $element_type:ty = $lump_type:expr),* $(,)?) => {
+                $(let $name: Vec<u8> = BSP::read_lump_bytes(&mut reader, &bsp.header.lump[$lump_type as usize])?;)*
+                let lumps_start: std::time::Instant = std::time::Instant::now();
+                if options.sequential_lumps {
+                    $(bsp.$name = BSP::parse_lump::<$element_type>(&$name)?;)*
+                } else {
+                    std::thread::scope(|scope| -> Result<(), BspError> {
+                        // Each handle shadows the buffer its thread borrows
+                        $(let $name = scope.spawn(|| BSP::parse_lump::<$element_type>(&$name));)*
+                        $(bsp.$name = BSP::join_lump($name)?;)*
+                        return Ok(());
+                    })?;
                 }
+                info!(
+                    &crate::LOGGER,
+                    "Parsed lumps {} in {:?}",
+                    if options.sequential_lumps { "sequentially" } else { "in parallel" },
+                    lumps_start.elapsed(),
+                );
             }
         }
-        bsp_comp_init!(nodes, bsp30::LumpType::LumpNodes, bsp30::Node);
-        bsp_comp_init!(leaves, bsp30::LumpType::LumpLeaves, bsp30::Leaf);
-        bsp_comp_init!(mark_surfaces, bsp30::LumpType::LumpMarkSurfaces, bsp30::MarkSurface);
-        bsp_comp_init!(faces, bsp30::LumpType::LumpFaces, bsp30::Face);
-        bsp_comp_init!(clip_nodes, bsp30::LumpType::LumpClipNodes, bsp30::ClipNode);
-        bsp_comp_init!(surface_edges, bsp30::LumpType::LumpSurfaceEdges, bsp30::SurfaceEdge);
-        bsp_comp_init!(edges, bsp30::LumpType::LumpEdges, bsp30::Edge);
-        bsp_comp_init!(vertices, bsp30::LumpType::LumpVertexes, bsp30::Vertex);
-        bsp_comp_init!(planes, bsp30::LumpType::LumpPlanes, bsp30::Plane);
-        bsp.load_models(&mut reader);
+        bsp_comp_init!(
+            nodes: bsp30::Node = bsp30::LumpType::LumpNodes,
+            leaves: bsp30::Leaf = bsp30::LumpType::LumpLeaves,
+            mark_surfaces: bsp30::MarkSurface = bsp30::LumpType::LumpMarkSurfaces,
+            faces: bsp30::Face = bsp30::LumpType::LumpFaces,
+            clip_nodes: bsp30::ClipNode = bsp30::LumpType::LumpClipNodes,
+            surface_edges: bsp30::SurfaceEdge = bsp30::LumpType::LumpSurfaceEdges,
+            edges: bsp30::Edge = bsp30::LumpType::LumpEdges,
+            vertices: bsp30::Vertex = bsp30::LumpType::LumpVertexes,
+            planes: bsp30::Plane = bsp30::LumpType::LumpPlanes,
+        );
+        progress(LoadStage::Lumps, 1.0);
+        progress(LoadStage::Models, 0.0);
+        bsp.load_models(&mut reader, options.strictness)?;
+        progress(LoadStage::Models, 1.0);
+        progress(LoadStage::Entities, 0.0);
         // Read and parse entities
-        let mut entity_buffer: Vec<u8> = Vec::with_capacity(bsp.header.lump[bsp30::LumpType::LumpEntities as usize].length as usize);
-        reader.seek(SeekFrom::Start(bsp.header.lump[bsp30::LumpType::LumpEntities as usize].offset as u64))?;
-        for _ in 0..entity_buffer.capacity() {
-            entity_buffer.push(reader.read_u8()?);
-        }
-        bsp.entities = BSP::parse_entities(&match String::from_utf8(entity_buffer) {
-            Ok(val) => val,
-            Err(error) => return Err(Error::new(ErrorKind::InvalidData, format!("Cannot parse entity buffer: {}", error))),
-        });
+        let entity_buffer: Vec<u8> = BSP::read_lump_bytes(&mut reader, &bsp.header.lump[bsp30::LumpType::LumpEntities as usize])?;
+        let entities_string: String = BSP::decode_entity_lump(&entity_buffer, &mut bsp.load_issues);
+        bsp.entities = BSP::parse_entities(&entities_string, options.strictness, &mut bsp.load_issues)?;
+        if BSP::find_entity(&bsp.entities, "worldspawn".to_string()).is_none() {
+            if options.strictness == ParseStrictness::Strict {
+                return Err(BspError::MissingWorldspawn);
+            }
+            bsp.load_issues.record("entities", BspError::MissingWorldspawn.to_string());
+        }
         debug!(&crate::LOGGER, "Parsed entities");
+        progress(LoadStage::Entities, 1.0);
         // Textures
         bsp.texture_infos = Vec::with_capacity(bsp.header.lump[bsp30::LumpType::LumpTexinfo as usize].length as usize / std::mem::size_of::<bsp30::TextureInfo>());
         reader.seek(SeekFrom::Start(bsp.header.lump[bsp30::LumpType::LumpTexinfo as usize].offset as u64))?;
@@ -223,6 +532,20 @@ impl BSP {
         bsp.texture_header = bsp30::TextureHeader::from_reader(&mut reader)?;
         println!("Texture header: {:?}", bsp.texture_header);
         debug!(&crate::LOGGER, "Read texture header");
+        let textures_lump: &bsp30::Lump = &bsp.header.lump[bsp30::LumpType::LumpTextures as usize];
+        let max_mip_textures: u32 = (textures_lump.length.max(4) as u32 - 4) / std::mem::size_of::<bsp30::MipTexOffset>() as u32;
+        if bsp.texture_header.mip_texture_count > max_mip_textures {
+            bsp.load_issues.violation(
+                options.strictness,
+                "textures",
+                format!(
+                    "Mip texture count {} exceeds the {} offsets that fit in the textures lump, clamping",
+                    bsp.texture_header.mip_texture_count,
+                    max_mip_textures,
+                ),
+            )?;
+            bsp.texture_header.mip_texture_count = max_mip_textures;
+        }
         bsp.mip_textures = Vec::with_capacity(bsp.texture_header.mip_texture_count as usize);
         bsp.mip_texture_offsets = Vec::with_capacity(bsp.texture_header.mip_texture_count as usize);
         for _ in 0..bsp.mip_texture_offsets.capacity() {
@@ -230,95 +553,113 @@ impl BSP {
         }
         debug!(&crate::LOGGER, "Read mip texture offsets");
         for i in 0..bsp.mip_textures.capacity() {
-            reader.seek(SeekFrom::Start(bsp.header.lump[bsp30::LumpType::LumpTextures as usize].offset as u64 + bsp.mip_texture_offsets[i] as u64))?;
+            let mip_tex_offset: u64 = bsp.header.lump[bsp30::LumpType::LumpTextures as usize].offset as u64 + bsp.mip_texture_offsets[i] as u64;
+            reader.seek(SeekFrom::Start(mip_tex_offset))?;
+            let mut raw_name: [u8; bsp30::MAX_TEXTURE_NAME] = [0; bsp30::MAX_TEXTURE_NAME];
+            reader.read_exact(&mut raw_name)?;
+            if let Some(nul) = raw_name.iter().position(|c: &u8| *c == 0) {
+                if raw_name[nul..].iter().any(|c: &u8| *c != 0) {
+                    bsp.load_issues.violation(
+                        options.strictness,
+                        "textures",
+                        format!(
+                            "Texture {} name '{}' has garbage after the NUL terminator, ignoring it",
+                            i,
+                            String::from_utf8_lossy(&raw_name[..nul]),
+                        ),
+                    )?;
+                }
+            }
+            reader.seek(SeekFrom::Start(mip_tex_offset))?;
             bsp.mip_textures.push(bsp30::MipTex::from_reader(&mut reader)?);
         }
         debug!(&crate::LOGGER, "Read mip textures");
-        bsp.load_textures(&mut reader);
+        bsp.check_texture_references(options.strictness)?;
+        if options.metadata_only {
+            info!(&crate::LOGGER, "Metadata only load, skipping textures, lightmaps, decals and visibility");
+            bsp.load_entity_models();
+            return Ok(bsp);
+        }
+        bsp.load_textures(&mut reader, wads, progress);
         debug!(&crate::LOGGER, "Loaded textures");
+        progress(LoadStage::Lightmaps, 0.0);
         // Lightmaps
         if bsp.header.lump[bsp30::LumpType::LumpLighting as usize].length == 0 {
             info!(&crate::LOGGER, "No lightmaps to load, skipping");
         } else {
-            let mut p_lightmap_data: Vec<u8> = Vec::with_capacity(bsp.header.lump[bsp30::LumpType::LumpLighting as usize].length as usize);
-            reader.seek(SeekFrom::Start(bsp.header.lump[bsp30::LumpType::LumpLighting as usize].offset as u64))?;
-            for _ in 0..p_lightmap_data.capacity() {
-                p_lightmap_data.push(reader.read_u8()?);
-            }
+            let p_lightmap_data: Vec<u8> = BSP::read_lump_bytes(&mut reader, &bsp.header.lump[bsp30::LumpType::LumpLighting as usize])?;
             bsp.load_light_maps(p_lightmap_data);
             debug!(&crate::LOGGER, "Loaded lightmaps")
         }
+        progress(LoadStage::Lightmaps, 1.0);
         // Decals
-        bsp.load_decals();
+        progress(LoadStage::Decals, 0.0);
+        bsp.load_decals(wads);
         debug!(&crate::LOGGER, "Loaded decals");
+        progress(LoadStage::Decals, 1.0);
+        progress(LoadStage::Visibility, 0.0);
         // Visibility list
-        if bsp.header.lump[bsp30::LumpType::LumpVisibility as usize].length <= 0 {
+        if options.skip_vis {
+            info!(&crate::LOGGER, "Visibility lists disabled, every leaf will be drawn");
+        } else if bsp.header.lump[bsp30::LumpType::LumpVisibility as usize].length <= 0 {
             info!(&crate::LOGGER, "No visibility lists to load, skipping");
         } else {
-            let mut compressed_vis: Vec<u8> = Vec::with_capacity(bsp.header.lump[bsp30::LumpType::LumpVisibility as usize].length as usize);
-            reader.seek(SeekFrom::Start(bsp.header.lump[bsp30::LumpType::LumpVisibility as usize].offset as u64))?;
-            for _ in 0..compressed_vis.capacity() {
-                compressed_vis.push(reader.read_u8()?);
-            }
-            let count: usize = bsp.count_vis_leaves(0);
-            info!(&crate::LOGGER, "Decompressing visibility list with {} leaves", count);
-            bsp.vis_lists = Vec::with_capacity(count);
-            for i in 0..count {
-                if bsp.leaves[i + 1].vis_offset >= 0 {
-                    bsp.vis_lists.push(bsp.decompress_vis(i + 1, &compressed_vis));
-                } else {
-                    bsp.vis_lists.push(BitSet::<u8>::default());
-                }
-            }
+            let compressed_vis: Vec<u8> = BSP::read_lump_bytes(&mut reader, &bsp.header.lump[bsp30::LumpType::LumpVisibility as usize])?;
+            // One list per leaf after the solid leaf 0, indexed by leaf - 1, so
+            // leaves without vis data keep an empty list rather than shifting the rest
+            let count: usize = bsp.leaves.len().saturating_sub(1);
+            info!(&crate::LOGGER, "Decompressing visibility lists for {} leaves, {} non-solid", count, bsp.count_vis_leaves(0)?);
+            let vis_start: std::time::Instant = std::time::Instant::now();
+            bsp.vis_lists = bsp.decompress_vis_lists(count, &compressed_vis, options.vis_threads);
+            info!(&crate::LOGGER, "Decompressed visibility lists in {:?}", vis_start.elapsed());
             debug!(&crate::LOGGER, "Loaded {} visibility lists", count);
         }
+        progress(LoadStage::Visibility, 1.0);
         // Close file through reader
         std::mem::drop(reader);
         debug!(&crate::LOGGER, "Dropped file");
-        for i in 0..bsp.entities.len() {
-            let entity: &Entity = &bsp.entities[i];
+        bsp.load_entity_models();
+        for dependency in bsp.dependencies() {
+            if dependency.required && !dependency.is_found() {
+                bsp.load_issues.record(
+                    "dependencies",
+                    format!("Missing {} {}", dependency.kind, dependency.name),
+                );
+            }
+        }
+        info!(&crate::LOGGER, "Finished loading BSP");
+        return Ok(bsp);
+    }
+
+    fn load_entity_models(&mut self) {
+        for i in 0..self.entities.len() {
+            let entity: &Entity = &self.entities[i];
             if BSP::is_brush_entity(entity) {
-                bsp.brush_entities.push(i);
+                self.brush_entities.push(i);
                 if let Some(sz_origin) = entity.find_property(&"origin".to_string()) {
-                    let i_model: usize = entity.find_property(&"model".to_string())
-                        .unwrap()
-                        .chars()
-                        .nth(1)
-                        .unwrap() as usize;
-                    let mut origin: glm::Vec3 = bsp.models[i_model].model.origin;
-                    macro_rules! scan {
-                        ($string:expr, $sep:expr, $($x:ty),+) => {{
-                            let mut iter = $string.split($sep);
-                            ($(iter.next().and_then(|word| word.parse::<$x>().ok()),)*)
-                        }}
+                    let i_model: usize = match BSP::brush_model_index(entity) {
+                        Some(i_model) if i_model < self.models.len() => i_model,
+                        Some(i_model) => {
+                            warn!(&crate::LOGGER, "Entity {} references model {} of {}, skipping origin", i, i_model, self.models.len());
+                            continue;
+                        },
+                        None => continue,
+                    };
+                    let components: Vec<f32> = sz_origin.split_whitespace()
+                        .map_while(|word: &str| word.parse::<f32>().ok())
+                        .collect();
+                    if components.len() < 3 {
+                        warn!(&crate::LOGGER, "Entity {} origin '{}' is not a 3D vector, skipping", i, sz_origin);
+                        continue;
                     }
-                    let origin_points: (Option<f32>, Option<f32>, Option<f32>) = scan!(
-                        sz_origin,
-                        char::is_whitespace,
-                        f32, f32, f32
-                    );
-                    origin.x = origin_points.0.unwrap();
-                    origin.y = origin_points.1.unwrap();
-                    origin.z = origin_points.2.unwrap();
+                    // Each inline model belongs to a single brush entity, so its origin can live on the model
+                    self.models[i_model].model.origin = glm::vec3(components[0], components[1], components[2]);
                 }
             } else {
-                bsp.special_entities.push(i);
+                self.special_entities.push(i);
             }
         }
         debug!(&crate::LOGGER, "Loaded brush and special entities");
-        std_tools::partition(
-            &mut bsp.brush_entities,
-            |i: &usize| -> bool {
-            if let Some(sz_render_mode_1) = bsp.entities[*i].find_property(&"rendermode".to_string()) {
-                if sz_render_mode_1.parse::<usize>().unwrap() == bsp30::RenderMode::RenderModeTexture as usize {
-                    return true;
-                }
-            }
-            return false;
-        });
-        info!(&crate::LOGGER, "Partitioned bush entities");
-        info!(&crate::LOGGER, "Finished loading BSP");
-        return Ok(bsp);
     }
 
     pub fn find_entity<'a>(entities: &'a Vec<Entity>, name: String) -> Option<&Entity> {
@@ -344,73 +685,115 @@ impl BSP {
         return result;
     }
 
-    pub fn load_skybox(&self) -> Option<[Image; 6]> {
-        let world_spawn: Option<&Entity> = BSP::find_entity(&self.entities, "world_spawn".to_string());
-        let skyname: Option<&String> = world_spawn?.find_property(&"skyname".to_string());
+    /// Sky name from worldspawn, defaulting to `desert` like the original
+    /// engine. `None` when the map has no worldspawn.
+    pub fn sky_name(&self) -> Option<String> {
+        let world_spawn: &Entity = BSP::find_entity(&self.entities, "worldspawn".to_string())?;
+        return Some(world_spawn.find_property(&"skyname".to_string())
+            .filter(|name: &&String| !name.trim().is_empty())
+            .map_or(DEFAULT_SKY_NAME.to_string(), |name: &String| name.trim().to_string()));
+    }
+
+    /// Paths of the six TGA sky face images in the sky directory, in
+    /// SKY_NAME_SUFFIXES order. Faces missing as TGA are looked for as BMP.
+    pub fn skybox_paths(&self) -> Option<[String; 6]> {
+        let sky_name: String = self.sky_name()?;
+        return Some(SKY_NAME_SUFFIXES.clone().map(|suffix: String| {
+            self.asset_paths.sky_dir
+                .join(format!("{}{}.tga", sky_name, suffix))
+                .to_string_lossy()
+                .to_string()
+        }));
+    }
+
+    pub fn load_skybox(&self) -> io::Result<Option<[Image; 6]>> {
+        let paths: [String; 6] = match self.skybox_paths() {
+            Some(paths) => paths,
+            None => return Ok(None),
+        };
         let mut result: Vec<Image> = Vec::with_capacity(6);
-        for i in 0..6 {
-            match Image::load(
-                SKY_DIR.clone()
-                + "/"
-                + skyname?.as_str()
-                + SKY_NAME_SUFFIXES[i].clone().as_str()
-                + ".tga"
-            ) {
+        for (i, path) in paths.iter().enumerate() {
+            // Half-Life ships sky sets as both TGA and BMP
+            let bmp_path: PathBuf = Path::new(path).with_extension("bmp");
+            let face_path: PathBuf = if !Path::new(path).is_file() && bmp_path.is_file() {
+                debug!(&crate::LOGGER, "No TGA sky face {}, using {}", path, bmp_path.display());
+                bmp_path
+            } else {
+                PathBuf::from(path)
+            };
+            match Image::from_path(&face_path) {
                 Ok(img) => result.push(img),
-                Err(error) => {
-                    error!(&crate::LOGGER, "Unable to load skybox: {}", error);
-                    return None;
-                },
+                Err(error) => return Err(Error::new(
+                    error.kind(),
+                    format!("Unable to load skybox face '{}' from {}: {}", SKY_NAME_SUFFIXES[i], face_path.display(), error),
+                )),
             };
         }
-        return result.try_into().ok();
+        return Ok(result.try_into().ok());
     }
 
-    pub (crate) fn load_wad_files(wad_str: &String) -> Vec<Wad> {
+    /// Open the WADs a worldspawn `wad` property lists, returning the names
+    /// of those that opened
+    pub (crate) fn load_wad_files(wad_str: &String, wads: &mut WadManager, skipped: &mut Vec<SkippedWad>) -> Vec<String> {
         let wad_string: String = wad_str.replace("\\", "/");
         let mut wad_count: usize = 0;
-        let mut wad_files: Vec<Wad> = Vec::new();
+        let mut wad_files: Vec<String> = Vec::new();
         for path_str in wad_string.split(";") {
             if path_str.is_empty() {
                 continue;
             }
-            let mut wad_path = Path::new(path_str);
-            if let Ok(stripped_path) = wad_path.strip_prefix("/") {
-                wad_path = stripped_path;
-            }
-            debug!(&crate::LOGGER, "WAD path: {:?}", wad_path);
-            let mut path: String = if let Some(parent_path) = wad_path.parent() {
-                Path::new(parent_path.file_name()
-                        .or_else(|| Some(std::ffi::OsStr::new("")))
-                        .unwrap()
-                    ).join(wad_path.file_name().unwrap())
-                    .as_path()
-                    .to_string_lossy()
-                    .to_string()
-            } else {
-                wad_path.to_string_lossy().to_string()
+            let name: String = BSP::wad_reference_name(path_str);
+            info!(&crate::LOGGER, "({}) Loading WAD {}", wad_count, name);
+            match wads.open(&name) {
+                Ok(_) => wad_files.push(name),
+                Err(error) => {
+                    warn!(&crate::LOGGER, "WAD {} could not be opened, skipping: {}", name, error);
+                    skipped.push(SkippedWad {
+                        name,
+                        reason: error.to_string(),
+                    });
+                    continue;
+                },
             };
-            path = Path::new(WAD_DIR.as_str())
-                .join(path)
-                .to_string_lossy()
-                .to_string();
-            info!(&crate::LOGGER, "({}) Loading WAD {}", wad_count, path);
-            wad_files.push(Wad::new(&path));
             wad_count += 1;
         }
         info!(&crate::LOGGER, "Loaded {} WADs", wad_count);
         return wad_files;
     }
 
+    /// Reduce a worldspawn WAD reference (usually an absolute path on the mapper's
+    /// machine) to the mod folder and file name.
+    pub (crate) fn wad_reference_name(path_str: &str) -> String {
+        let mut wad_path = Path::new(path_str);
+        if let Ok(stripped_path) = wad_path.strip_prefix("/") {
+            wad_path = stripped_path;
+        }
+        debug!(&crate::LOGGER, "WAD path: {:?}", wad_path);
+        if let Some(parent_path) = wad_path.parent() {
+            return Path::new(parent_path.file_name().unwrap_or_default())
+                .join(wad_path.file_name().unwrap_or_default())
+                .as_path()
+                .to_string_lossy()
+                .to_string();
+        }
+        return wad_path.to_string_lossy().to_string();
+    }
+
+    /// Forget the map's texture WADs, they stay open in the WadManager for
+    /// the next map that lists them
     pub (crate) fn unload_wad_files(&mut self) {
         self.wad_files.clear();
     }
 
-    pub (crate) fn load_textures(&mut self, reader: &mut BufReader<File>) {
+    pub (crate) fn load_textures<R: Read + Seek>(&mut self,
+                                                 reader: &mut BufReader<R>,
+                                                 wads: &mut WadManager,
+                                                 progress: &mut dyn FnMut(LoadStage, f32)) {
+        progress(LoadStage::Textures, 0.0);
         if let Some(world_spawn) = BSP::find_entity(&self.entities, "worldspawn".to_string()) {
             if let Some(wad) = world_spawn.find_property(&String::from("wad")) {
                 info!(&crate::LOGGER, "Loading texture WADs");
-                self.wad_files.append(&mut BSP::load_wad_files(wad));
+                self.wad_files.append(&mut BSP::load_wad_files(wad, wads, &mut self.skipped_wads));
             } else {
                 warn!(&crate::LOGGER, "No 'wad' property present on 'worldspawn' entity, skipping texture loading");
             }
@@ -419,87 +802,210 @@ impl BSP {
         }
         info!(&crate::LOGGER, "Loading textures...");
         self.m_textures.resize_with(self.texture_header.mip_texture_count as usize, || MipmapTexture::new());
-        let mut errors: usize = 0;
-        for i in 0..self.texture_header.mip_texture_count as usize {
-            debug!(&crate::LOGGER, "({}) Loading texture {}", i, String::from_utf8_lossy(&self.mip_textures[i].name));
+        self.texture_classes = (0..self.mip_textures.len())
+            .map(|i: usize| TextureClass::from_name(&self.mip_texture_name(i)))
+            .collect();
+        self.group_texture_animations();
+        self.group_texture_tilings();
+        let texture_count: usize = self.texture_header.mip_texture_count as usize;
+        for i in 0..texture_count {
+            if i > 0 {
+                progress(LoadStage::Textures, i as f32 / texture_count as f32);
+            }
+            debug!(&crate::LOGGER, "({}) Loading texture {}", i, self.mip_textures[i].name);
             if self.mip_textures[i].offsets[0] == 0 {
                 // External texture
-                if let Some(tex) = self.load_texture_from_wads(&String::from_utf8_lossy(&self.mip_textures[i].name).trim_matches(char::from(0)).to_string()) {
-                    self.m_textures[i] = tex;
-                } else {
-                    error!(&crate::LOGGER, "Failed to load external texture {}", String::from_utf8_lossy(&self.mip_textures[i].name));
-                    errors += 1;
+                let loaded: Result<Arc<MipmapTexture>, BspError> = self.load_texture_from_wads(wads, &self.mip_textures[i].name.to_string());
+                if let Ok(tex) = loaded {
+                    let declared: &bsp30::MipTex = &self.mip_textures[i];
+                    if tex.img[0].width as u32 != declared.width || tex.img[0].height as u32 != declared.height {
+                        let declared_aspect: f32 = declared.width as f32 / declared.height.max(1) as f32;
+                        let loaded_aspect: f32 = tex.img[0].width as f32 / tex.img[0].height.max(1) as f32;
+                        if (declared_aspect - loaded_aspect).abs() > 1.0e-3 {
+                            warn!(
+                                &crate::LOGGER,
+                                "Texture {} is {}x{} in WAD but declared {}x{} in BSP, aspect ratio differs",
+                                declared.name,
+                                tex.img[0].width,
+                                tex.img[0].height,
+                                declared.width,
+                                declared.height,
+                            );
+                        }
+                    }
+                    self.m_textures[i] = Arc::unwrap_or_clone(tex);
+                } else if let Err(error) = loaded {
+                    error!(&crate::LOGGER, "Failed to load external texture {}: {}", self.mip_textures[i].name, error);
+                    self.substitute_missing_texture(i);
                     continue;
                 }
             } else {
                 // Internal texture
                 let mip_tex: &bsp30::MipTex = &self.mip_textures[i];
-                let data_size: usize = std::mem::size_of::<u8>() * (mip_tex.offsets[3] + (mip_tex.height / 8) * (mip_tex.width / 8) + 2 + 768) as usize;
-                let mut img_data: Vec<u8> = Vec::with_capacity(data_size);
-                reader.seek(SeekFrom::Start(self.header.lump[bsp30::LumpType::LumpTextures as usize].offset as u64 + self.mip_texture_offsets[i] as u64))
-                    .expect("Unable to seek to textures lump offset for internal texture");
-                // TODO: Check header magic id, if not 30 then use Quake palette
-                for _ in 0..data_size {
-                    img_data.push(reader.read_u8().unwrap());
+                let mut data_size: usize = std::mem::size_of::<u8>() * (mip_tex.offsets[3] + (mip_tex.height / 8) * (mip_tex.width / 8)) as usize;
+                if self.version.has_embedded_palettes() {
+                    data_size += 2 + 768;
+                }
+                let mut img_data: Vec<u8> = vec![0u8; data_size];
+                let read: io::Result<()> = reader.seek(SeekFrom::Start(self.header.lump[bsp30::LumpType::LumpTextures as usize].offset as u64 + self.mip_texture_offsets[i] as u64))
+                    .and_then(|_| reader.read_exact(&mut img_data));
+                if let Err(error) = read {
+                    error!(&crate::LOGGER, "Failed to read internal texture {}: {}", self.mip_textures[i].name, error);
+                    self.substitute_missing_texture(i);
+                    continue;
                 }
-                self.m_textures[i] = Wad::create_mip_texture(&img_data);
+                let decoded: Result<MipmapTexture, BspError> = if self.version.has_embedded_palettes() {
+                    Wad::create_mip_texture(&img_data)
+                } else {
+                    Wad::create_quake_mip_texture(&img_data)
+                };
+                match decoded {
+                    Ok(tex) => self.m_textures[i] = MipmapTexture {
+                        source: TextureSource::Embedded,
+                        ..tex
+                    },
+                    Err(error) => {
+                        error!(&crate::LOGGER, "Failed to decode internal texture {}: {}", self.mip_textures[i].name, error);
+                        self.substitute_missing_texture(i);
+                    },
+                };
             }
         }
+        let mut cache_stats: CacheStats = CacheStats::default();
+        for wad in self.wad_files.iter().filter_map(|name: &String| wads.get(name)) {
+            cache_stats += wad.cache_stats();
+        }
+        info!(&crate::LOGGER, "WAD texture cache: {} hits, {} misses", cache_stats.hits, cache_stats.misses);
         self.unload_wad_files();
+        progress(LoadStage::Textures, 1.0);
+        let errors: usize = self.missing_textures.len();
         info!(&crate::LOGGER, "Loaded {} textures, {} failed", self.texture_header.mip_texture_count as usize - errors, errors);
         self.face_tex_coords.resize_with(self.faces.len(), Default::default);
+        let tex_sizes: Vec<glm::Vec2> = (0..self.mip_textures.len())
+            .map(|i: usize| self.texture_normalisation_size(i))
+            .collect();
         for i in 0..self.faces.len() {
             self.face_tex_coords[i].tex_coords.resize(self.faces[i].edge_count as usize, glm::vec2(0.0,0.0));
-            let cur_tex_info: &TextureInfo = &self.texture_infos[self.faces[i].texture_info as usize];
+            let cur_tex_info: TextureInfo = self.texture_infos[self.faces[i].texture_info as usize];
+            let tex_size: glm::Vec2 = tex_sizes[cur_tex_info.mip_tex_index as usize];
             for j in 0..self.faces[i].edge_count as usize {
-                let mut edge_index: i32 = self.surface_edges[self.faces[i].texture_info as usize + j];
-                if edge_index > 0 {
-                    self.face_tex_coords[i].tex_coords[j].x = (glm::dot(
-                        &self.vertices[self.edges[edge_index as usize].vertex_index[0] as usize],
-                        &cur_tex_info.s
-                    ) + cur_tex_info.s_shift) / self.mip_textures[cur_tex_info.mip_tex_index as usize].width as f32;
-                    self.face_tex_coords[i].tex_coords[j].y = (glm::dot(
-                        &self.vertices[self.edges[edge_index as usize].vertex_index[0] as usize],
-                        &cur_tex_info.t
-                    ) + cur_tex_info.t_shift) / self.mip_textures[cur_tex_info.mip_tex_index as usize].height as f32;
-                } else {
-                    edge_index *= -1;
-                    self.face_tex_coords[i].tex_coords[j].x = (glm::dot(
-                        &self.vertices[self.edges[edge_index as usize].vertex_index[1] as usize],
-                        &cur_tex_info.s
-                    ) + cur_tex_info.s_shift) / self.mip_textures[cur_tex_info.mip_tex_index as usize].width as f32;
-                    self.face_tex_coords[i].tex_coords[j].y = (glm::dot(
-                        &self.vertices[self.edges[edge_index as usize].vertex_index[1] as usize],
-                        &cur_tex_info.t
-                    ) + cur_tex_info.t_shift) / self.mip_textures[cur_tex_info.mip_tex_index as usize].height as f32;
-                }
+                let vertex: glm::Vec3 = self.face_vertex(i, j);
+                self.face_tex_coords[i].tex_coords[j] = BSP::texture_space(&cur_tex_info, &vertex)
+                    .component_div(&tex_size);
             }
         }
     }
 
-    pub (crate) fn load_texture_from_wads(&mut self, name: &String) -> Option<MipmapTexture> {
-        trace!(&crate::LOGGER, "Loading texture from WADs: {}", name);
-        for wad in self.wad_files.iter_mut() {
-            if let Some(p_mipmap_tex) = wad.load_texture(name) {
-                return Some(p_mipmap_tex);
-            }
+    /// Size to normalise texture coordinates of a mip texture by. A loaded texture
+    /// that is a uniform rescale of the declared one (e.g. a high resolution
+    /// replacement) keeps the declared size so surfaces cover the same world area,
+    /// whereas one with a different aspect ratio uses its real dimensions to avoid
+    /// stretching.
+    pub fn texture_normalisation_size(&self, mip_tex_index: usize) -> glm::Vec2 {
+        let mip_tex: &bsp30::MipTex = &self.mip_textures[mip_tex_index];
+        let declared: glm::Vec2 = glm::vec2(mip_tex.width as f32, mip_tex.height as f32);
+        let loaded: &Image = match self.m_textures.get(mip_tex_index) {
+            Some(tex) => &tex.img[0],
+            None => return declared,
+        };
+        if loaded.width == 0 || loaded.height == 0 || declared.x == 0.0 || declared.y == 0.0 {
+            return declared;
         }
-        return None;
+        let scale: glm::Vec2 = glm::vec2(loaded.width as f32 / declared.x, loaded.height as f32 / declared.y);
+        if (scale.x - scale.y).abs() < 1.0e-3 {
+            return declared;
+        }
+        return glm::vec2(loaded.width as f32, loaded.height as f32);
+    }
+
+    /// Record a texture that failed to load and stand in a placeholder of its declared size
+    fn substitute_missing_texture(&mut self, index: usize) {
+        let mip_tex: &bsp30::MipTex = &self.mip_textures[index];
+        self.m_textures[index] = MipmapTexture {
+            name: mip_tex.name.to_string(),
+            ..MipmapTexture::placeholder(mip_tex.width, mip_tex.height)
+        };
+        self.missing_textures.push(MissingTexture {
+            name: mip_tex.name,
+            index,
+        });
+    }
+
+    /// Class of the texture applied to a face
+    pub fn face_texture_class(&self, face: &bsp30::Face) -> TextureClass {
+        let mip_tex_index: usize = self.texture_infos[face.texture_info as usize].mip_tex_index as usize;
+        return self.texture_classes.get(mip_tex_index).copied().unwrap_or_default();
+    }
+
+    /// Textures that failed to load and were replaced by placeholders
+    pub fn missing_textures(&self) -> &Vec<MissingTexture> {
+        return &self.missing_textures;
+    }
+
+    /// Set the brightness of a light style, 0.0 switches its lights off.
+    /// Renderables pick up the change on their next frame.
+    pub fn set_light_style(&mut self, style: usize, value: f32) {
+        match self.light_styles.get_mut(style) {
+            Some(light_style) => *light_style = value.max(0.0),
+            None => warn!(&crate::LOGGER, "Light style {} is out of range, ignoring", style),
+        };
+    }
+
+    /// Offset into the lighting lump of a face's lightmap for a style slot,
+    /// each style's lightmap follows the previous one
+    pub fn lightmap_style_offset(face: &bsp30::Face, width: usize, height: usize, channels: usize, slot: usize) -> usize {
+        return face.lightmap_offset as usize + width * height * channels * slot;
+    }
+
+    /// Texture and decal WADs that were requested but could not be opened
+    pub fn skipped_wads(&self) -> &Vec<SkippedWad> {
+        return &self.skipped_wads;
+    }
+
+    pub (crate) fn load_texture_from_wads(&self, wads: &mut WadManager, name: &String) -> Result<Arc<MipmapTexture>, BspError> {
+        trace!(&crate::LOGGER, "Loading texture from WADs: {}", name);
+        return BSP::load_texture_from(wads, &self.wad_files, name, Wad::load_texture);
     }
 
-    pub (crate) fn load_decal_texture(decal_wads: &mut Vec<Wad>, name: &String) -> Option<MipmapTexture> {
+    pub (crate) fn load_decal_texture(&self, wads: &mut WadManager, name: &String) -> Result<Arc<MipmapTexture>, BspError> {
         trace!(&crate::LOGGER, "Loading decal texture: {}", name);
-        for decal_wad in decal_wads.iter_mut() {
-            if let Some(p_mipmap_tex) = decal_wad.load_texture(name) {
-                return Some(p_mipmap_tex);
+        return BSP::load_texture_from(wads, &self.decal_wads, name, Wad::load_decal_texture);
+    }
+
+    /// First WAD that has the texture wins, a WAD that has it but fails to
+    /// decode it with `load` is logged and the search continues
+    fn load_texture_from(wads: &mut WadManager, wad_names: &Vec<String>, name: &String, load: fn(&mut Wad, &String) -> Result<Arc<MipmapTexture>, BspError>) -> Result<Arc<MipmapTexture>, BspError> {
+        for wad_name in wad_names.iter() {
+            let Some(wad) = wads.get_mut(wad_name) else {
+                continue;
+            };
+            if !wad.contains(name) {
+                continue;
             }
+            match load(wad, name) {
+                Ok(p_mipmap_tex) => return Ok(p_mipmap_tex),
+                Err(BspError::TextureNotFound { .. }) => continue,
+                Err(error) => warn!(&crate::LOGGER, "Unable to load {} from {}: {}", name, wad.path.display(), error),
+            };
         }
-        return None;
+        return Err(BspError::TextureNotFound {
+            name: name.to_uppercase(),
+        });
     }
 
-    pub (crate) fn load_decals(&mut self) {
-        self.decal_wads.push(Wad::new(&Path::new(WAD_DIR.as_str()).join("valve/decals.wad").to_string_lossy().to_string()));
-        self.decal_wads.push(Wad::new(&Path::new(WAD_DIR.as_str()).join("cstrike/decals.wad").to_string_lossy().to_string()));
+    pub (crate) fn load_decals(&mut self, wads: &mut WadManager) {
+        for decal_wad in wads.decal_wads().clone() {
+            match wads.open(&decal_wad) {
+                Ok(_) => self.decal_wads.push(decal_wad),
+                Err(error) => {
+                    warn!(&crate::LOGGER, "Decal WAD {} could not be opened, skipping: {}", decal_wad, error);
+                    self.skipped_wads.push(SkippedWad {
+                        name: decal_wad,
+                        reason: error.to_string(),
+                    });
+                },
+            };
+        }
         let info_decals: Vec<&Entity> = BSP::find_entities(&self.entities, "infodecal".to_string()).clone();
         if info_decals.is_empty() {
             info!(&crate::LOGGER, "No decals to load, skipping");
@@ -509,159 +1015,198 @@ impl BSP {
         let mut new_m_textures: Vec<MipmapTexture> = Vec::new();
         let mut new_m_decals: Vec<Decal> = Vec::new();
         for info_decal in info_decals.iter().copied() {
-            let origin_str: Option<&String> = info_decal.find_property(&"origin".to_string());
-            if origin_str.is_none() {
+            let Some(origin_str) = info_decal.find_property(&"origin".to_string()) else {
                 continue;
-            }
-            let split_origin: Vec<&str> = origin_str.unwrap().split(" ").collect();
+            };
+            let split_origin: Vec<f32> = origin_str.split_whitespace()
+                .map_while(|component: &str| component.parse::<f32>().ok())
+                .collect();
             if split_origin.len() != 3 {
-                error!(&crate::LOGGER, "Expected 3D origin, got {}, skipping", split_origin.len());
+                error!(&crate::LOGGER, "Expected 3D origin, got '{}', skipping", origin_str);
                 continue;
             }
-            let origin: glm::Vec3 = glm::vec3(
-                split_origin[0].parse::<f32>().unwrap(),
-                split_origin[1].parse::<f32>().unwrap(),
-                split_origin[2].parse::<f32>().unwrap(),
-            );
-            let leaf: Option<i16> = self.find_leaf(origin, 0);
-            if leaf.is_none() {
+            let origin: glm::Vec3 = glm::vec3(split_origin[0], split_origin[1], split_origin[2]);
+            let Some(leaf) = self.find_leaf(origin, 0) else {
                 error!(&crate::LOGGER, "Cannot find decal leaf, skipping");
                 continue;
-            }
-            let current_leaf: Option<&bsp30::Leaf> = self.leaves.get(leaf.unwrap() as usize);
-            if current_leaf.is_none() {
-                error!(&crate::LOGGER, "Cannot find leaf, skipping");
-                continue;
-            }
-            let current_leaf_value: &bsp30::Leaf = current_leaf.unwrap();
-            for j in 0..current_leaf_value.mark_surface_count as usize {
-                let face: &bsp30::Face = &self.faces[self.mark_surfaces[current_leaf_value.first_mark_surface as usize + j] as usize];
+            };
+            // The decal sits on the first face of its leaf whose plane contains the origin
+            let Some((normal, texture_info, winding)) = self.leaf_faces(leaf).find_map(|face: &bsp30::Face| {
                 let normal: glm::Vec3 = self.planes[face.plane_index as usize].normal;
-                let vertex: glm::Vec3;
-                let edge_index: i32 = self.surface_edges[face.first_edge_index as usize];
-                if edge_index > 0 {
-                    vertex = self.vertices[self.edges[edge_index as usize].vertex_index[0] as usize];
-                } else {
-                    vertex = self.vertices[self.edges[(-edge_index) as usize].vertex_index[1] as usize];
-                }
-                if !point_in_plane(origin, normal, glm::dot(&normal, &vertex)) {
-                    continue;
-                }
-                let tex_name: Option<&String> = info_decal.find_property(&"texture".to_string());
-                if tex_name.is_none() {
-                    error!(&crate::LOGGER, "Unable to retrieve texture name from decal");
-                    break;
-                }
-                let it: Option<&usize> = loaded_tex.get(tex_name.unwrap());
-                let mut it_val: usize = 0;
-                if it.is_none() {
-                    let loaded_decal_texture: Option<MipmapTexture> = BSP::load_decal_texture(&mut self.decal_wads, &tex_name.unwrap());
-                    if loaded_decal_texture.is_none() {
-                        error!(&crate::LOGGER, "Unable to load mipmap texture for {}", &tex_name.unwrap());
-                        break;
-                    }
-                    it_val = self.m_textures.len();
-                    loaded_tex.insert(tex_name.unwrap().clone(), self.m_textures.len());
-                    new_m_textures.push(loaded_decal_texture.unwrap());
+                let winding: Vec<glm::Vec3> = self.face_vertices(face);
+                if !point_in_plane(origin, normal, glm::dot(&normal, winding.first()?)) {
+                    return None;
                 }
-                let img_0: &Image = &self.m_textures[it_val].img[0];
-                let h2: f32 = img_0.height as f32 / 2.0;
-                let w2: f32 = img_0.width as f32 / 2.0;
-                let s: glm::Vec3 = self.texture_infos[face.texture_info as usize].s;
-                let t: glm::Vec3 = self.texture_infos[face.texture_info as usize].t;
-                new_m_decals.push(Decal {
-                    normal,
-                    tex_index: it_val as u32,
-                    vec: [
-                        origin - t * h2 - s * w2,
-                        origin - t * h2 + s * w2,
-                        origin + t * h2 + s * w2,
-                        origin + t * h2 - s * w2,
-                    ],
-                });
-                break;
+                return Some((normal, face.texture_info as usize, winding));
+            }) else {
+                continue;
+            };
+            let Some(tex_name) = info_decal.find_property(&"texture".to_string()) else {
+                error!(&crate::LOGGER, "Unable to retrieve texture name from decal");
+                continue;
+            };
+            let it_val: usize = match loaded_tex.get(tex_name) {
+                Some(index) => *index,
+                None => {
+                    let loaded_decal_texture: MipmapTexture = match self.load_decal_texture(wads, tex_name) {
+                        Ok(tex) => Arc::unwrap_or_clone(tex),
+                        Err(error) => {
+                            error!(&crate::LOGGER, "Unable to load mipmap texture for {}: {}", tex_name, error);
+                            continue;
+                        },
+                    };
+                    // Decal textures are appended to m_textures once every decal is placed
+                    let index: usize = self.m_textures.len() + new_m_textures.len();
+                    loaded_tex.insert(tex_name.clone(), index);
+                    new_m_textures.push(loaded_decal_texture);
+                    index
+                },
+            };
+            let img_0: &Image = &new_m_textures[it_val - self.m_textures.len()].img[0];
+            let h2: f32 = img_0.height as f32 / 2.0;
+            let w2: f32 = img_0.width as f32 / 2.0;
+            let s: glm::Vec3 = self.texture_infos[texture_info].s;
+            let t: glm::Vec3 = self.texture_infos[texture_info].t;
+            let quad: Vec<(glm::Vec3, glm::Vec2)> = vec![
+                (origin - t * h2 - s * w2, glm::vec2(0.0, 0.0)),
+                (origin - t * h2 + s * w2, glm::vec2(1.0, 0.0)),
+                (origin + t * h2 + s * w2, glm::vec2(1.0, 1.0)),
+                (origin + t * h2 - s * w2, glm::vec2(0.0, 1.0)),
+            ];
+            // Keep decals near edges from overhanging into space or neighbouring walls
+            let clipped: Vec<(glm::Vec3, glm::Vec2)> = BSP::clip_to_winding(quad, &winding);
+            if clipped.len() < 3 {
+                debug!(&crate::LOGGER, "Decal {} at {:?} lies outside its face, dropping it", tex_name, origin);
+                continue;
             }
+            new_m_decals.push(Decal {
+                normal,
+                tex_index: it_val as u32,
+                vec: clipped.iter().map(|vertex: &(glm::Vec3, glm::Vec2)| vertex.0).collect(),
+                tex_coords: clipped.iter().map(|vertex: &(glm::Vec3, glm::Vec2)| vertex.1).collect(),
+            });
         }
         self.m_textures.append(&mut new_m_textures);
         self.m_decals.append(&mut new_m_decals);
         info!(&crate::LOGGER, "Loaded {} decals, {} decal textures", self.m_decals.len(), loaded_tex.len());
     }
 
+    /// Vertex at the given position in a face's edge loop, resolving the
+    /// surface edge direction
+    pub (crate) fn face_vertex(&self, face_index: usize, edge: usize) -> glm::Vec3 {
+        return self.surface_edge_vertex(self.surface_edges[self.faces[face_index].first_edge_index as usize + edge]);
+    }
+
+    /// Unscaled texture space s/t coordinates of a world position
+    pub (crate) fn texture_space(tex_info: &TextureInfo, point: &glm::Vec3) -> glm::Vec2 {
+        return glm::vec2(
+            glm::dot(&tex_info.s, point) + tex_info.s_shift,
+            glm::dot(&tex_info.t, point) + tex_info.t_shift,
+        );
+    }
+
+    /// QRAD lightmap extents of a face, one texel per 16 texture space units
+    pub fn lightmap_extents(&self, face_index: usize) -> LightmapExtents {
+        let tex_info: &TextureInfo = &self.texture_infos[self.faces[face_index].texture_info as usize];
+        let mut min_st: glm::Vec2 = glm::vec2(999999.0, 999999.0);
+        let mut max_st: glm::Vec2 = glm::vec2(-99999.0, -99999.0);
+        for j in 0..self.faces[face_index].edge_count as usize {
+            let st: glm::Vec2 = BSP::texture_space(tex_info, &self.face_vertex(face_index, j));
+            min_st = glm::min2(&min_st, &st);
+            max_st = glm::max2(&max_st, &st);
+        }
+        let width: usize = ((max_st.x / 16.0).ceil() - (min_st.x / 16.0).floor()) as usize + 1;
+        let height: usize = ((max_st.y / 16.0).ceil() - (min_st.y / 16.0).floor()) as usize + 1;
+        return LightmapExtents {
+            min_st,
+            max_st,
+            width,
+            height,
+        };
+    }
+
+    /// Lightmap texel of a face lighting the given world point on that face
+    pub fn world_to_lightmap_texel(&self, face_index: usize, point: glm::Vec3) -> (f32, f32) {
+        let tex_info: &TextureInfo = &self.texture_infos[self.faces[face_index].texture_info as usize];
+        let texel: glm::Vec2 = self.lightmap_extents(face_index).texel(BSP::texture_space(tex_info, &point));
+        return (texel.x, texel.y);
+    }
+
+    /// World position on the plane of a face lit by the given lightmap texel
+    pub fn lightmap_texel_to_world(&self, face_index: usize, u: f32, v: f32) -> glm::Vec3 {
+        let face: &bsp30::Face = &self.faces[face_index];
+        let tex_info: &TextureInfo = &self.texture_infos[face.texture_info as usize];
+        let plane: &bsp30::Plane = &self.planes[face.plane_index as usize];
+        let st: glm::Vec2 = self.lightmap_extents(face_index).texture_space(glm::vec2(u, v));
+        // Solve s.p = S, t.p = T and n.p = dist for p
+        let axes: glm::Mat3 = glm::mat3(
+            tex_info.s.x, tex_info.s.y, tex_info.s.z,
+            tex_info.t.x, tex_info.t.y, tex_info.t.z,
+            plane.normal.x, plane.normal.y, plane.normal.z,
+        );
+        let target: glm::Vec3 = glm::vec3(st.x - tex_info.s_shift, st.y - tex_info.t_shift, plane.dist);
+        return match axes.try_inverse() {
+            Some(inverse) => inverse * target,
+            // Texture axes parallel to the face normal, nothing to project onto
+            None => self.face_center(face_index),
+        };
+    }
+
     pub (crate) fn load_light_maps(&mut self, p_light_map_data: Vec<u8>) {
         let mut loaded_bytes: isize = 0;
         let mut loaded_lightmaps: usize = 0;
         for i in 0..self.faces.len() {
-            if self.faces[i].styles[0] != 0 || (self.faces[i].lightmap_offset as isize) < -1 {
-                self.m_lightmaps.push(Image::new());
+            if self.faces[i].styles[0] == UNUSED_LIGHT_STYLE || self.faces[i].lightmap_offset as i32 == -1 {
+                self.m_lightmaps.push(FaceLightmaps::default());
                 continue;
             }
             self.face_tex_coords[i].lightmap_coords.resize(self.faces[i].edge_count as usize, glm::vec2(0.0, 0.0));
-            // Start QRAD
-            let mut f_min_u: f32 = 999999.0;
-            let mut f_min_v: f32 = 999999.0;
-            let mut f_max_u: f32 = -99999.0;
-            let mut f_max_v: f32 = -99999.0;
-            let tex_info: &TextureInfo = &self.texture_infos[self.faces[i].texture_info as usize];
+            let extents: LightmapExtents = self.lightmap_extents(i);
+            let n_width: i32 = extents.width as i32;
+            let n_height: i32 = extents.height as i32;
+            let tex_info: TextureInfo = self.texture_infos[self.faces[i].texture_info as usize];
             for j in 0..self.faces[i].edge_count as usize {
-                let edge_index: i32 = self.surface_edges[self.faces[i].first_edge_index as usize + j];
-                let vertex: glm::Vec3 = if edge_index >= 0 {
-                    self.vertices[self.edges[edge_index as usize].vertex_index[0] as usize]
-                } else {
-                    self.vertices[self.edges[(-edge_index) as usize].vertex_index[1] as usize]
-                };
-                let f_u: f32 = glm::dot(&tex_info.s, &vertex) + tex_info.s_shift;
-                if f_u < f_min_u {
-                    f_min_u = f_u;
-                }
-                if f_u > f_max_u {
-                    f_max_u = f_u;
-                }
-                let f_v: f32 = glm::dot(&tex_info.t, &vertex) + tex_info.t_shift;
-                if f_v < f_min_v {
-                    f_min_v = f_v;
-                }
-                if f_v > f_max_v {
-                    f_max_v = f_v;
-                }
+                let vertex: glm::Vec3 = self.face_vertex(i, j);
+                let texel: glm::Vec2 = extents.texel(BSP::texture_space(&tex_info, &vertex));
+                self.face_tex_coords[i].lightmap_coords[j].x = texel.x / n_width as f32;
+                self.face_tex_coords[i].lightmap_coords[j].y = texel.y / n_height as f32;
             }
-            let f_tex_min_u: f32 = (f_min_u / 16.0).floor();
-            let f_tex_min_v: f32 = (f_min_v / 16.0).floor();
-            let f_tex_max_u: f32 = (f_max_u / 16.0).ceil();
-            let f_tex_max_v: f32 = (f_max_v / 16.0).ceil();
-            let n_width: i32 = (f_tex_max_u - f_tex_min_u) as i32 + 1;
-            let n_height: i32 = (f_tex_max_v - f_tex_min_v) as i32 + 1;
-            // End QRAD
-            let f_mid_poly_u: f32 = (f_min_u + f_max_u) / 2.0;
-            let f_mid_poly_v: f32 = (f_min_v + f_max_v) / 2.0;
-            let f_mid_tex_u: f32 = n_width as f32 / 2.0;
-            let f_mid_tex_v: f32 = n_height as f32 / 2.0;
-            for j in 0..self.faces[i].edge_count as usize {
-                let edge_index: i32 = self.surface_edges[self.faces[i].first_edge_index as usize + j];
-                let vertex: glm::Vec3 = if edge_index >= 0 {
-                    self.vertices[self.edges[edge_index as usize].vertex_index[0] as usize]
+            let channels: usize = self.version.lightmap_channels();
+            let lm_size: usize = n_width as usize * n_height as usize * channels;
+            let mut lightmaps: FaceLightmaps = FaceLightmaps {
+                styles: self.faces[i].styles,
+                ..FaceLightmaps::default()
+            };
+            for slot in 0..MAX_LIGHTMAPS {
+                if lightmaps.styles[slot] == UNUSED_LIGHT_STYLE {
+                    break;
+                }
+                let lm_offset: usize = BSP::lightmap_style_offset(&self.faces[i], n_width as usize, n_height as usize, channels, slot);
+                let Some(lm_data) = p_light_map_data.get(lm_offset..lm_offset.saturating_add(lm_size)) else {
+                    self.load_issues.record(
+                        "lightmaps",
+                        format!(
+                            "Face {} style {} lightmap of {} bytes at offset {} exceeds the {} byte lighting lump, skipping",
+                            i, lightmaps.styles[slot], lm_size, lm_offset, p_light_map_data.len(),
+                        ),
+                    );
+                    break;
+                };
+                let data: Vec<u8> = if channels == 1 {
+                    lm_data.iter().flat_map(|luminance: &u8| [*luminance; 3]).collect()
                 } else {
-                    self.vertices[self.edges[(-edge_index) as usize].vertex_index[1] as usize]
+                    lm_data.to_vec()
                 };
-                let f_u: f32 = glm::dot(&tex_info.s, &vertex) + tex_info.s_shift;
-                let f_v: f32 = glm::dot(&tex_info.t, &vertex) + tex_info.t_shift;
-                let f_lightmap_u: f32 = f_mid_tex_u + (f_u - f_mid_poly_u) / 16.0;
-                let f_lightmap_v: f32 = f_mid_tex_v + (f_v + f_mid_poly_v) / 16.0;
-                self.face_tex_coords[i].lightmap_coords[j].x = f_lightmap_u / n_width as f32;
-                self.face_tex_coords[i].lightmap_coords[j].y = f_lightmap_v / n_height as f32;
-            }
-            let lm_offset: usize = self.faces[i].lightmap_offset as usize;
-            let image: Image = Image {
-                channels: 3,
-                width: n_width as usize,
-                height: n_height as usize,
-                data: Vec::from_iter(p_light_map_data[
-                    lm_offset
-                    ..lm_offset + (n_width + n_height * 3 * std::mem::size_of::<u8>() as i32) as usize
-                ].iter().cloned()),
-            };
-            self.m_lightmaps.push(image);
-            loaded_lightmaps += 1;
-            loaded_bytes += (n_width + n_height * 3) as isize;
+                lightmaps.images[slot] = Some(Image {
+                    channels: 3,
+                    width: n_width as usize,
+                    height: n_height as usize,
+                    data,
+                });
+                loaded_lightmaps += 1;
+                loaded_bytes += lm_size as isize;
+            }
+            self.m_lightmaps.push(lightmaps);
         }
         info!(
             &crate::LOGGER,
@@ -671,26 +1216,45 @@ impl BSP {
         );
     }
 
-    pub (crate) fn load_models(&mut self, reader: &mut BufReader<File>) {
+    pub (crate) fn load_models<R: Read + Seek>(&mut self, reader: &mut BufReader<R>, strictness: ParseStrictness) -> Result<(), BspError> {
         let mut sub_models: Vec<bsp30::Model> = Vec::with_capacity(
             self.header.lump[bsp30::LumpType::LumpModels as usize].length as usize / std::mem::size_of::<bsp30::Model>()
         );
-        reader.seek(SeekFrom::Start(self.header.lump[bsp30::LumpType::LumpModels as usize].offset as u64)).expect("Unable to seek to models lump in BSP file");
+        reader.seek(SeekFrom::Start(self.header.lump[bsp30::LumpType::LumpModels as usize].offset as u64))?;
         for _ in 0..sub_models.capacity() {
-            sub_models.push(bsp30::Model::from_reader(reader).unwrap());
+            sub_models.push(bsp30::Model::from_reader(reader)?);
         }
-        self.hull_0_clip_nodes = self.nodes.iter().map(|node: &bsp30::Node| -> bsp30::ClipNode {
+        self.hull_0_clip_nodes = Vec::with_capacity(self.nodes.len());
+        for i in 0..self.nodes.len() {
+            let child_index: [i16; 2] = self.nodes[i].child_index;
             let mut clipnode: bsp30::ClipNode = Default::default();
-            clipnode.plane_index = node.plane_index as i32;
+            clipnode.plane_index = self.nodes[i].plane_index as i32;
             for j in 0..2 {
-                if node.child_index[j] < 0 {
-                    clipnode.child_index[j] = self.leaves[!node.child_index[j] as usize].content as i16;
-                } else {
-                    clipnode.child_index[j] = node.child_index[j];
+                if child_index[j] >= 0 {
+                    clipnode.child_index[j] = child_index[j];
+                    continue;
                 }
+                let leaf: usize = !child_index[j] as usize;
+                clipnode.child_index[j] = match self.leaves.get(leaf) {
+                    Some(leaf) => leaf.content as i16,
+                    None => {
+                        self.load_issues.violation(
+                            strictness,
+                            "nodes",
+                            format!(
+                                "Node {} child {} references leaf {} but there are only {} leaves, treating it as solid",
+                                i,
+                                j,
+                                leaf,
+                                self.leaves.len(),
+                            ),
+                        )?;
+                        bsp30::ContentType::ContentsSolid as i16
+                    },
+                };
             }
-            return clipnode;
-        }).collect();
+            self.hull_0_clip_nodes.push(clipnode);
+        }
         let mut model_0: Model = Model::new();
         let mut hull_0: &mut Hull = &mut model_0.hulls[0];
         hull_0.clip_nodes = self.hull_0_clip_nodes.iter()
@@ -725,7 +1289,7 @@ impl BSP {
         let hull_1: &mut Hull = &mut model_0.hulls[1];
         hull_1.clip_mins[0] = -16.0;
         hull_1.clip_mins[1] = -16.0;
-        hull_1.clip_mins[1] = -36.0;
+        hull_1.clip_mins[2] = -36.0;
         hull_1.clip_maxs[0] = 16.0;
         hull_1.clip_maxs[1] = 16.0;
         hull_1.clip_maxs[2] = 36.0;
@@ -741,23 +1305,43 @@ impl BSP {
         let hull_3: &mut Hull = &mut model_0.hulls[3];
         hull_3.clip_mins[0] = -16.0;
         hull_3.clip_mins[1] = -16.0;
-        hull_3.clip_mins[1] = -18.0;
+        hull_3.clip_mins[2] = -18.0;
         hull_3.clip_maxs[0] = 16.0;
         hull_3.clip_maxs[1] = 16.0;
         hull_3.clip_maxs[2] = 18.0;
-        self.models.push(model_0);
-        for i in 0..sub_models.capacity() {
-            if i != 0 {
-                self.models.push(self.models.last().unwrap().clone())
+        // Every model gets its own copy of the world's hulls, entered at its own head nodes
+        for sub_model in sub_models {
+            let mut model: Model = model_0.clone();
+            for (j, hull) in model.hulls.iter_mut().enumerate() {
+                hull.first_clip_node = sub_model.head_nodes_index[j] as isize;
             }
-            let index: usize = self.models.len() - 1;
-            let mut model: &mut Model = &mut self.models[index];
-            model.model = sub_models[i];
+            model.model = sub_model;
+            self.models.push(model);
+        }
+        if self.models.is_empty() {
+            self.load_issues.record("models", "Map has no models, using an empty world model".to_string());
+            self.models.push(model_0);
         }
+        debug!(&crate::LOGGER, "Loaded {} models", self.models.len());
+        return Ok(());
+    }
+
+    /// Index of the inline brush model referenced by a `"model" "*N"` property.
+    /// External models such as `models/barrel.mdl` and malformed indices give `None`.
+    pub fn brush_model_index(entity: &Entity) -> Option<usize> {
+        let model: &String = entity.find_property(&"model".to_string())?;
+        let index: &str = model.strip_prefix('*')?;
+        return match index.parse::<usize>() {
+            Ok(index) => Some(index),
+            Err(error) => {
+                warn!(&crate::LOGGER, "Malformed brush model reference '{}': {}", model, error);
+                None
+            },
+        };
     }
 
     fn is_brush_entity(entity: &Entity) -> bool {
-        if entity.find_property(&"model".to_string()).is_none() {
+        if BSP::brush_model_index(entity).is_none() {
             return false;
         }
         let classname: &String = match entity.find_property(&"classname".to_string()) {
@@ -775,88 +1359,1421 @@ impl BSP {
         };
     }
 
-    pub (crate) fn parse_entities(entities_string: &String) -> Vec<Entity> {
-        let mut entities: Vec<Entity> = Vec::new();
-        let mut pos: usize = 0;
-        loop {
-            pos += match entities_string[pos..].find('{') {
-                Some(new_pos) => new_pos,
-                None => break,
+    fn lump_count<T>(&self, lump_type: bsp30::LumpType) -> usize {
+        return self.header.lump[lump_type as usize].length.max(0) as usize / std::mem::size_of::<T>();
+    }
+
+    /// Compare lump sizes against the engine limits. Exceeding the soft MAX_MAP
+    /// limits is recorded and loading continues, exceeding what the on-disk
+    /// index types can address fails the load.
+    fn check_limits(&mut self) -> Result<(), BspError> {
+        use bsp30::LumpType::*;
+        // Name, element count, soft limit, hard limit and the index that imposes it
+        let limits: [(&str, usize, usize, Option<(usize, &str)>); 14] = [
+            ("planes", self.lump_count::<bsp30::Plane>(LumpPlanes), bsp30::MAX_MAP_PLANES, Some((u16::MAX as usize + 1, "face plane_index (u16)"))),
+            ("nodes", self.lump_count::<bsp30::Node>(LumpNodes), bsp30::MAX_MAP_NODES, Some((i16::MAX as usize + 1, "node child_index (i16)"))),
+            ("clip nodes", self.lump_count::<bsp30::ClipNode>(LumpClipNodes), bsp30::MAX_MAP_CLIPNODES, Some((i16::MAX as usize + 1, "clip node child_index (i16)"))),
+            ("leaves", self.lump_count::<bsp30::Leaf>(LumpLeaves), bsp30::MAX_MAP_LEAFS, Some((i16::MAX as usize + 1, "node child_index (i16)"))),
+            ("vertices", self.lump_count::<bsp30::Vertex>(LumpVertexes), bsp30::MAX_MAP_VERTS, Some((u16::MAX as usize + 1, "edge vertex_index (u16)"))),
+            ("faces", self.lump_count::<bsp30::Face>(LumpFaces), bsp30::MAX_MAP_FACES, Some((u16::MAX as usize + 1, "mark surface (u16)"))),
+            ("mark surfaces", self.lump_count::<bsp30::MarkSurface>(LumpMarkSurfaces), bsp30::MAX_MAP_MARKSURFACES, Some((u16::MAX as usize + 1, "leaf first_mark_surface (u16)"))),
+            ("texture infos", self.lump_count::<bsp30::TextureInfo>(LumpTexinfo), bsp30::MAX_MAP_TEXINFO, Some((u16::MAX as usize + 1, "face texture_info (u16)"))),
+            ("edges", self.lump_count::<bsp30::Edge>(LumpEdges), bsp30::MAX_MAP_EDGES, None),
+            ("surface edges", self.lump_count::<bsp30::SurfaceEdge>(LumpSurfaceEdges), bsp30::MAX_MAP_SURFEDGES, None),
+            ("models", self.lump_count::<bsp30::Model>(LumpModels), bsp30::MAX_MAP_MODELS, None),
+            ("lighting bytes", self.lump_count::<u8>(LumpLighting), bsp30::MAX_MAP_LIGHTING, None),
+            ("visibility bytes", self.lump_count::<u8>(LumpVisibility), bsp30::MAX_MAP_VISIBILITY, None),
+            ("entity bytes", self.lump_count::<u8>(LumpEntities), bsp30::MAX_MAP_ENTSTRING, None),
+        ];
+        for (name, count, soft, hard) in limits {
+            if let Some((hard, index)) = hard {
+                if count > hard {
+                    return Err(BspError::LimitExceeded {
+                        name,
+                        count,
+                        limit: hard,
+                        index,
+                    });
+                }
+            }
+            if count > soft {
+                self.load_issues.record(
+                    "limits",
+                    format!("Map has {} {}, exceeding the engine limit of {}, continuing", count, name, soft),
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    /// Parse a fixed size element lump from its raw bytes
+    fn parse_lump<T: Resource>(buffer: &[u8]) -> Result<Vec<T>, BspError> {
+        let mut lump_reader: BufReader<&[u8]> = BufReader::new(buffer);
+        let mut elements: Vec<T> = Vec::with_capacity(buffer.len() / std::mem::size_of::<T>());
+        for _ in 0..elements.capacity() {
+            elements.push(T::from_reader(&mut lump_reader)?);
+        }
+        return Ok(elements);
+    }
+
+    fn join_lump<T>(handle: std::thread::ScopedJoinHandle<'_, Result<Vec<T>, BspError>>) -> Result<Vec<T>, BspError> {
+        return match handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+    }
+
+    /// Read the raw bytes of a lump in one go
+    fn read_lump_bytes<R: Read + Seek>(reader: &mut BufReader<R>, lump: &bsp30::Lump) -> Result<Vec<u8>, BspError> {
+        let mut buffer: Vec<u8> = vec![0u8; lump.length.max(0) as usize];
+        reader.seek(SeekFrom::Start(lump.offset.max(0) as u64))?;
+        reader.read_exact(&mut buffer)?;
+        return Ok(buffer);
+    }
+
+    /// Blue Shift maps store the planes in lump 0 and the entities in lump 1.
+    /// Detected by entity text starting with `{` in lump 1 but not lump 0, with
+    /// lump 0 sized as a whole number of planes.
+    fn has_swapped_entity_lump<R: Read + Seek>(reader: &mut BufReader<R>, header: &bsp30::Header, file_len: u64) -> Result<bool, BspError> {
+        let mut first_non_whitespace = |lump: &bsp30::Lump| -> Result<Option<u8>, BspError> {
+            if lump.offset < 0 || lump.length <= 0 || lump.offset as u64 + lump.length as u64 > file_len {
+                // Left for validate_lumps to report
+                return Ok(None);
+            }
+            let mut buffer: Vec<u8> = vec![0u8; (lump.length as usize).min(256)];
+            reader.seek(SeekFrom::Start(lump.offset as u64))?;
+            reader.read_exact(&mut buffer)?;
+            return Ok(buffer.into_iter().find(|c: &u8| !c.is_ascii_whitespace()));
+        };
+        let lump_0: Option<u8> = first_non_whitespace(&header.lump[bsp30::LumpType::LumpEntities as usize])?;
+        let lump_1: Option<u8> = first_non_whitespace(&header.lump[bsp30::LumpType::LumpPlanes as usize])?;
+        let planes_sized: bool = header.lump[bsp30::LumpType::LumpEntities as usize].length as usize % std::mem::size_of::<bsp30::Plane>() == 0;
+        return Ok(lump_0 != Some(b'{') && lump_1 == Some(b'{') && planes_sized);
+    }
+
+    /// Check every lump lies within the file and that fixed size lumps hold a
+    /// whole number of elements, before anything is read from them
+    fn validate_lumps(&self, file_len: u64) -> Result<(), BspError> {
+        use bsp30::LumpType::*;
+        let element_sizes: [(bsp30::LumpType, usize); 11] = [
+            (LumpPlanes, std::mem::size_of::<bsp30::Plane>()),
+            (LumpVertexes, std::mem::size_of::<bsp30::Vertex>()),
+            (LumpNodes, std::mem::size_of::<bsp30::Node>()),
+            (LumpTexinfo, std::mem::size_of::<bsp30::TextureInfo>()),
+            (LumpFaces, std::mem::size_of::<bsp30::Face>()),
+            (LumpClipNodes, std::mem::size_of::<bsp30::ClipNode>()),
+            (LumpLeaves, std::mem::size_of::<bsp30::Leaf>()),
+            (LumpMarkSurfaces, std::mem::size_of::<bsp30::MarkSurface>()),
+            (LumpEdges, std::mem::size_of::<bsp30::Edge>()),
+            (LumpSurfaceEdges, std::mem::size_of::<bsp30::SurfaceEdge>()),
+            (LumpModels, std::mem::size_of::<bsp30::Model>()),
+        ];
+        for (index, lump) in self.header.lump.iter().enumerate() {
+            let name: &str = bsp30::LUMP_NAMES[index];
+            if lump.offset < 0 || lump.length < 0 || lump.offset as u64 + lump.length as u64 > file_len {
+                return Err(BspError::LumpOutOfBounds {
+                    lump: name,
+                    offset: lump.offset,
+                    length: lump.length,
+                    file_len,
+                });
+            }
+        }
+        for (lump_type, size) in element_sizes {
+            let index: usize = lump_type as usize;
+            let lump: &bsp30::Lump = &self.header.lump[index];
+            if lump.length as usize % size != 0 {
+                return Err(BspError::LumpMisaligned {
+                    lump: bsp30::LUMP_NAMES[index],
+                    length: lump.length,
+                    element_size: size,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Check that every face names an existing texture info and every texture
+    /// info an existing mip texture. Lenient parsing repoints bad references at
+    /// the first entry so later lookups can index directly.
+    fn check_texture_references(&mut self, strictness: ParseStrictness) -> Result<(), BspError> {
+        let texture_info_count: usize = self.texture_infos.len();
+        for i in 0..self.faces.len() {
+            let texture_info: usize = self.faces[i].texture_info as usize;
+            if texture_info < texture_info_count {
+                continue;
+            }
+            let message: String = format!(
+                "Face {} references texture info {} but there are only {} texture infos",
+                i,
+                texture_info,
+                texture_info_count,
+            );
+            if texture_info_count == 0 {
+                return Err(BspError::Violation { context: "faces".to_string(), message });
+            }
+            self.load_issues.violation(strictness, "faces", format!("{}, using texture info 0", message))?;
+            self.faces[i].texture_info = 0;
+        }
+        let mip_texture_count: usize = self.mip_textures.len();
+        for i in 0..self.texture_infos.len() {
+            let mip_tex_index: usize = self.texture_infos[i].mip_tex_index as usize;
+            if mip_tex_index < mip_texture_count {
+                continue;
+            }
+            let message: String = format!(
+                "Texture info {} references mip texture {} but there are only {} mip textures",
+                i,
+                mip_tex_index,
+                mip_texture_count,
+            );
+            if mip_texture_count == 0 {
+                return Err(BspError::Violation { context: "texture infos".to_string(), message });
+            }
+            self.load_issues.violation(strictness, "texture infos", format!("{}, using mip texture 0", message))?;
+            self.texture_infos[i].mip_tex_index = 0;
+        }
+        return Ok(());
+    }
+
+    fn check_lump_overlaps(&mut self, strictness: ParseStrictness) -> Result<(), BspError> {
+        let mut lumps: Vec<(usize, &bsp30::Lump)> = self.header.lump.iter()
+            .enumerate()
+            .filter(|(_, lump): &(usize, &bsp30::Lump)| lump.length > 0)
+            .collect();
+        lumps.sort_by_key(|(_, lump): &(usize, &bsp30::Lump)| lump.offset);
+        let mut overlaps: Vec<String> = Vec::new();
+        for pair in lumps.windows(2) {
+            let (prev_index, prev): (usize, &bsp30::Lump) = pair[0];
+            let (next_index, next): (usize, &bsp30::Lump) = pair[1];
+            if prev.offset as i64 + prev.length as i64 > next.offset as i64 {
+                overlaps.push(format!(
+                    "Lump {} [{}, {}) overlaps lump {} starting at {}, ignoring overlapping bytes",
+                    prev_index,
+                    prev.offset,
+                    prev.offset as i64 + prev.length as i64,
+                    next_index,
+                    next.offset,
+                ));
+            }
+        }
+        for overlap in overlaps {
+            self.load_issues.violation(strictness, "header", overlap)?;
+        }
+        return Ok(());
+    }
+
+    /// Convert the raw entities lump into text, dropping trailing NULs and
+    /// anything after the final closing brace, removing embedded NULs and
+    /// replacing invalid UTF-8.
+    pub (crate) fn decode_entity_lump(buffer: &[u8], issues: &mut LoadIssues) -> String {
+        let mut end: usize = buffer.len();
+        while end > 0 && buffer[end - 1] == 0 {
+            end -= 1;
+        }
+        if let Some(last_brace) = buffer[..end].iter().rposition(|c: &u8| *c == b'}') {
+            let trailing: &[u8] = &buffer[(last_brace + 1)..end];
+            if trailing.iter().any(|c: &u8| !c.is_ascii_whitespace() && *c != 0) {
+                issues.record(
+                    "entities",
+                    format!("Ignoring {} bytes of trailing data after the last entity", trailing.len()),
+                );
+            }
+            end = last_brace + 1;
+        }
+        let embedded_nuls: usize = buffer[..end].iter().filter(|c: &&u8| **c == 0).count();
+        let buffer: std::borrow::Cow<[u8]> = if embedded_nuls == 0 {
+            std::borrow::Cow::Borrowed(&buffer[..end])
+        } else {
+            issues.record(
+                "entities",
+                format!("Removing {} NUL bytes embedded in the entity lump", embedded_nuls),
+            );
+            std::borrow::Cow::Owned(buffer[..end].iter().copied().filter(|c: &u8| *c != 0).collect())
+        };
+        let text = String::from_utf8_lossy(&buffer);
+        if let std::borrow::Cow::Owned(_) = text {
+            issues.record(
+                "entities",
+                String::from("Entity lump contains invalid UTF-8, replaced offending bytes"),
+            );
+        }
+        return text.into_owned();
+    }
+
+    pub (crate) fn parse_entities(entities_string: &String,
+                                  strictness: ParseStrictness,
+                                  issues: &mut LoadIssues) -> Result<Vec<Entity>, BspError> {
+        let mut entities: Vec<Entity> = Vec::new();
+        let bytes: &[u8] = entities_string.as_bytes();
+        let mut pos: usize = 0;
+        while pos < bytes.len() {
+            // Everything between entity blocks should be whitespace
+            let start: usize = match bytes[pos..].iter().position(|c: &u8| !c.is_ascii_whitespace()) {
+                Some(offset) => pos + offset,
+                None => break,
             };
-            let end: usize = match entities_string[pos..].find('}') {
-                Some(end_pos) => end_pos,
-                None => {
-                    error!(&crate::LOGGER, "Cannot find ending brace for entity, skipping.");
-                    continue;
-                },
+            if bytes[start] != b'{' {
+                let skip_to: usize = bytes[start..].iter()
+                    .position(|c: &u8| *c == b'{')
+                    .map_or(bytes.len(), |offset: usize| start + offset);
+                issues.violation(
+                    strictness,
+                    "entities",
+                    format!("Skipping {} bytes of stray data at offset {} between entities", skip_to - start, start),
+                ).map_err(BspError::into_entity_parse)?;
+                pos = skip_to;
+                continue;
+            }
+            // Braces inside quoted values are part of the value, not block delimiters
+            let mut in_quotes: bool = false;
+            let mut end: Option<usize> = None;
+            for (offset, c) in bytes[start + 1..].iter().enumerate() {
+                match c {
+                    b'"' => in_quotes = !in_quotes,
+                    b'}' if !in_quotes => {
+                        end = Some(start + 1 + offset);
+                        break;
+                    },
+                    _ => {},
+                }
+            }
+            let Some(end) = end else {
+                issues.violation(
+                    strictness,
+                    "entities",
+                    format!("Entity at offset {} has no closing brace, ignoring {} trailing bytes", start, bytes.len() - start),
+                ).map_err(BspError::into_entity_parse)?;
+                break;
             };
-            entities.push(Entity::new(&entities_string[(pos + 1)..(pos + end - 1)].to_string()));
-            pos += end + 1;
+            let mut violations: Vec<String> = Vec::new();
+            let entity: Entity = Entity::parse(&entities_string[(start + 1)..end].to_string(), &mut violations);
+            let context: String = format!("entity {}", entities.len());
+            for violation in violations {
+                issues.violation(strictness, context.as_str(), violation).map_err(BspError::into_entity_parse)?;
+            }
+            entities.push(entity);
+            pos = end + 1;
         }
-        return entities;
+        return Ok(entities);
     }
 
-    pub (crate) fn count_vis_leaves(&self, i_node: i16) -> usize {
-        if i_node < 0 {
-            if i_node == -1 || self.leaves[!(i_node as usize)].content == bsp30::ContentType::ContentsSolid as i32 {
-                return 0;
+    /// Count the non-solid leaves under a head node, e.g. 0 for the world or a
+    /// submodel's hull 0 head node. Fails on child indices outside the node or
+    /// leaf arrays, or a node reachable twice.
+    pub (crate) fn count_vis_leaves(&self, head_node: usize) -> Result<usize, BspError> {
+        let mut count: usize = 0;
+        let mut visited: BitSet = BitSet::with_capacity(self.nodes.len());
+        let mut stack: Vec<usize> = vec![head_node];
+        while let Some(node) = stack.pop() {
+            if node >= self.nodes.len() {
+                return Err(BspError::CorruptTree {
+                    message: format!("Node index {} out of range for {} nodes", node, self.nodes.len()),
+                });
+            }
+            if !visited.insert(node) {
+                return Err(BspError::CorruptTree {
+                    message: format!("Node {} is reachable more than once", node),
+                });
+            }
+            for child_index in self.nodes[node].child_index {
+                if child_index >= 0 {
+                    stack.push(child_index as usize);
+                    continue;
+                }
+                let leaf: usize = !(child_index as i32) as usize;
+                let Some(leaf_data) = self.leaves.get(leaf) else {
+                    return Err(BspError::CorruptTree {
+                        message: format!("Leaf index {} in node {} out of range for {} leaves", leaf, node, self.leaves.len()),
+                    });
+                };
+                // Leaf 0 is the shared solid leaf and never has visibility data
+                if leaf != 0 && leaf_data.content != bsp30::ContentType::ContentsSolid as i32 {
+                    count += 1;
+                }
             }
-            return 1;
         }
-        let left_node_count: usize = self.count_vis_leaves(self.nodes[i_node as usize].child_index[0]);
-        let right_node_count: usize = self.count_vis_leaves(self.nodes[i_node as usize].child_index[1]);
-        return left_node_count + right_node_count;
+        return Ok(count);
     }
 
-    pub (crate) fn decompress_vis(&self, leaf: usize, compresed_vis: &Vec<u8>) -> BitSet<u8> {
+    fn decompress_vis_lists(&self, count: usize, compressed_vis: &Vec<u8>, threads: usize) -> Vec<BitSet<u8>> {
+        let leaf_count: usize = self.leaves.len().saturating_sub(1);
+        let vis_offsets: Vec<i32> = (0..count)
+            .map(|i: usize| self.leaves.get(i + 1).map_or(-1, |leaf: &bsp30::Leaf| leaf.vis_offset))
+            .collect();
+        // Empty lists for leaves with no vis data, so list i always belongs to leaf i + 1
+        let decompress = |i: usize| -> BitSet<u8> {
+            if vis_offsets[i] >= 0 {
+                return BSP::decompress_vis(vis_offsets[i] as usize, compressed_vis, leaf_count);
+            }
+            return BitSet::<u8>::default();
+        };
+        let threads: usize = if threads == 0 {
+            std::thread::available_parallelism().map_or(1, |n: std::num::NonZeroUsize| n.get())
+        } else {
+            threads
+        };
+        if threads <= 1 || count < threads * 2 {
+            return (0..count).map(decompress).collect();
+        }
+        let mut vis_lists: Vec<Option<BitSet<u8>>> = Vec::with_capacity(count);
+        vis_lists.resize_with(count, || None);
+        let chunk_size: usize = (count + threads - 1) / threads;
+        std::thread::scope(|scope| {
+            for (chunk_index, chunk) in vis_lists.chunks_mut(chunk_size).enumerate() {
+                let start: usize = chunk_index * chunk_size;
+                let decompress = &decompress;
+                scope.spawn(move || {
+                    for (i, slot) in chunk.iter_mut().enumerate() {
+                        *slot = Some(decompress(start + i));
+                    }
+                });
+            }
+        });
+        return vis_lists.into_iter()
+            .map(|vis: Option<BitSet<u8>>| vis.unwrap_or_default())
+            .collect();
+    }
+
+    /// Decode the run length encoded PVS row starting at `vis_offset`. Leaf N
+    /// (1-based, leaf 0 is never visible) maps to bit N - 1. A zero byte is
+    /// followed by a count of fully invisible bytes to skip.
+    pub (crate) fn decompress_vis(vis_offset: usize, compresed_vis: &[u8], leaf_count: usize) -> BitSet<u8> {
         let mut pvs: BitSet<u8> = BitSet::<u8>::default();
-        pvs.reserve_len(self.leaves.len() - 1);
-        let mut read: usize = self.leaves[leaf].vis_offset as usize;
-        let row: usize = (self.vis_lists.len() + 7) / 8;
-        while pvs.capacity() / 8 < row {
-            if compresed_vis[read] != 0 {
-                pvs.insert(compresed_vis[read] as usize);
-                continue;
-            } else {
+        pvs.reserve_len(leaf_count);
+        let row: usize = (leaf_count + 7) / 8;
+        let mut read: usize = vis_offset;
+        let mut out: usize = 0;
+        while out < row && read < compresed_vis.len() {
+            let byte: u8 = compresed_vis[read];
+            read += 1;
+            if byte == 0 {
+                // A zero byte is followed by the number of invisible bytes it stands for
+                let Some(run) = compresed_vis.get(read) else {
+                    // Truncated run, nothing further is visible
+                    break;
+                };
                 read += 1;
-                for _ in 0..compresed_vis[read] as usize {
-                    pvs.insert(0x00);
-                    if pvs.capacity() / 8 >= row {
-                        break;
-                    }
+                out += *run as usize;
+                continue;
+            }
+            for bit in 0..8 {
+                let leaf: usize = out * 8 + bit;
+                if byte & (1 << bit) != 0 && leaf < leaf_count {
+                    pvs.insert(leaf);
                 }
             }
-            read += 1;
+            out += 1;
         }
         return pvs;
     }
-   
-    #[inline(always)]
-    fn array_to_vec3(arr: [i16; 3]) -> glm::Vec3 {
-        return glm::vec3(
-            arr[0] as f32,
-            arr[1] as f32,
-            arr[2] as f32,
-        );
+
+    /// Contents of the world at a point for the given hull
+    pub fn point_contents(&self, hull_index: usize, point: glm::Vec3) -> bsp30::ContentType {
+        let Some(hull) = self.models.first().and_then(|world: &Model| world.hulls.get(hull_index)) else {
+            return bsp30::ContentType::ContentsSolid;
+        };
+        return hull.point_contents(hull.first_clip_node as i32, point);
     }
 
-    pub (crate) fn find_leaf(&self, pos: glm::Vec3, node: usize) -> Option<i16> {
-        for child_index in self.nodes[node].child_index {
-            if child_index >= 0 && point_in_box(
-                pos,
-                BSP::array_to_vec3(self.nodes[child_index as usize].lower),
-                BSP::array_to_vec3(self.nodes[child_index as usize].upper),
-            ) {
-                return self.find_leaf(pos, child_index as usize);
-            } else if (!child_index) != 0 && point_in_box(
-                pos,
-                BSP::array_to_vec3(self.leaves[!child_index as usize].lower),
-                BSP::array_to_vec3(self.leaves[!child_index as usize].upper),
-            ) {
-                return Some(!child_index);
+    /// Leaf containing a point, found by walking the node planes from `node`.
+    /// A point exactly on a plane goes to the back child, as in the original
+    /// engine. Only returns `None` when the tree itself is malformed.
+    pub (crate) fn find_leaf(&self, pos: glm::Vec3, node: usize) -> Option<usize> {
+        let mut current: usize = node;
+        // A valid tree reaches a leaf in fewer steps than it has nodes
+        for _ in 0..=self.nodes.len() {
+            let node: &bsp30::Node = self.nodes.get(current)?;
+            let plane: &bsp30::Plane = self.planes.get(node.plane_index as usize)?;
+            let side: usize = if glm::dot(&plane.normal, &pos) - plane.dist > 0.0 { 0 } else { 1 };
+            let child_index: i16 = node.child_index[side];
+            if child_index < 0 {
+                // Leaf indices are stored complemented, widen before use
+                return Some(!(child_index as i32) as usize);
             }
+            current = child_index as usize;
         }
+        error!(&crate::LOGGER, "Node tree from {} does not terminate in a leaf", node);
         return None;
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lightmap(texels: &[u8]) -> Image {
+        return Image {
+            channels: 3,
+            width: texels.len() / 3,
+            height: 1,
+            data: texels.to_vec(),
+        };
+    }
+
+    fn two_style_lightmaps() -> FaceLightmaps {
+        return FaceLightmaps {
+            styles: [0, 5, UNUSED_LIGHT_STYLE, UNUSED_LIGHT_STYLE],
+            images: [
+                Some(lightmap(&[100, 50, 0, 200, 200, 200])),
+                Some(lightmap(&[20, 40, 60, 100, 100, 100])),
+                None,
+                None,
+            ],
+        };
+    }
+
+    fn micro_map() -> BSP {
+        return crate::map::micro_map::load_micro_map(ParseStrictness::Strict).unwrap();
+    }
+
+    /// Micro map with an `infodecal` per texture and origin, decal textures
+    /// coming from the micro WAD
+    fn micro_map_with_decals(decals: &[(&str, &str)]) -> BSP {
+        let mut bsp: BSP = micro_map();
+        for (texture, origin) in decals {
+            bsp.entities.push(Entity::new(&format!("\"classname\" \"infodecal\" \"texture\" \"{}\" \"origin\" \"{}\"", texture, origin)));
+        }
+        let mut wads: WadManager = WadManager::new(Arc::new(crate::map::micro_map::EmbeddedWads));
+        wads.set_decal_wads(vec![crate::map::micro_map::MICRO_WAD_NAME.to_string()]);
+        bsp.load_decals(&mut wads);
+        return bsp;
+    }
+
+    /// Micro map bytes with `patch` written `offset` bytes into `lump`
+    fn patched_micro_map(lump: bsp30::LumpType, offset: usize, patch: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        let entry: usize = 4 + lump as usize * 8;
+        let lump_offset: usize = u32::from_le_bytes(bytes[entry..entry + 4].try_into().unwrap()) as usize;
+        bytes[lump_offset + offset..lump_offset + offset + patch.len()].copy_from_slice(patch);
+        return bytes;
+    }
+
+    fn load_bytes(bytes: Vec<u8>, strictness: ParseStrictness) -> Result<BSP, BspError> {
+        let options: BspLoadOptions = BspLoadOptions {
+            strictness,
+            wad_provider: Some(Arc::new(crate::map::micro_map::EmbeddedWads)),
+            ..BspLoadOptions::lenient()
+        };
+        return BSP::from_reader_with_options(std::io::Cursor::new(bytes), &options);
+    }
+
+    /// Micro map bytes with a second model holding the wall, used by a brush
+    /// entity of the given class and origin. The models lump is last in the
+    /// file, so the model is appended to it, and the entities move after it.
+    fn micro_map_with_brush_entity(classname: &str, origin: &str) -> Vec<u8> {
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        let lump_entry = |lump: bsp30::LumpType| -> usize { return 4 + lump as usize * 8; };
+        let read_i32 = |bytes: &Vec<u8>, at: usize| -> usize {
+            return i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        };
+        let models: usize = lump_entry(bsp30::LumpType::LumpModels);
+        assert_eq!(read_i32(&bytes, models) + read_i32(&bytes, models + 4), bytes.len());
+        for value in [0.0f32, 64.0, 0.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0i32, 0, 0, 0, 0, 1, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let models_length: i32 = (read_i32(&bytes, models + 4) + std::mem::size_of::<bsp30::Model>()) as i32;
+        bytes[models + 4..models + 8].copy_from_slice(&models_length.to_le_bytes());
+        let entities: usize = lump_entry(bsp30::LumpType::LumpEntities);
+        let (offset, length): (usize, usize) = (read_i32(&bytes, entities), read_i32(&bytes, entities + 4));
+        let mut text: String = String::from_utf8_lossy(&bytes[offset..offset + length]).trim_end_matches('\0').to_string();
+        text.push_str(&format!("{{\n\"classname\" \"{}\"\n\"model\" \"*1\"\n\"origin\" \"{}\"\n}}\n\0", classname, origin));
+        let entities_offset: i32 = bytes.len() as i32;
+        bytes[entities..entities + 4].copy_from_slice(&entities_offset.to_le_bytes());
+        bytes[entities + 4..entities + 8].copy_from_slice(&(text.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(text.as_bytes());
+        return bytes;
+    }
+
+    fn issue_count(issues: &LoadIssues, context: &str) -> usize {
+        return issues.iter()
+            .filter(|issue: &&crate::map::load_options::LoadIssue| issue.context == context)
+            .count();
+    }
+
+    fn polygon_area(polygon: &[glm::Vec3]) -> f32 {
+        return (1..polygon.len() - 1)
+            .map(|i: usize| glm::cross(&(polygon[i] - polygon[0]), &(polygon[i + 1] - polygon[0])).norm() / 2.0)
+            .sum();
+    }
+
+    fn leaf(content: bsp30::ContentType, vis_offset: i32) -> bsp30::Leaf {
+        return bsp30::Leaf {
+            content: content as i32,
+            vis_offset,
+            lower: [0; 3],
+            upper: [0; 3],
+            first_mark_surface: 0,
+            mark_surface_count: 0,
+            ambient_levels: [0; 4],
+        };
+    }
+
+    fn node(child_index: [i16; 2]) -> bsp30::Node {
+        return bsp30::Node {
+            plane_index: 0,
+            child_index,
+            lower: [0; 3],
+            upper: [0; 3],
+            first_face: 0,
+            last_face: 0,
+        };
+    }
+
+    /// The micro map with one lump's directory entry replaced
+    fn with_lump(lump_type: bsp30::LumpType, offset: i32, length: i32) -> Vec<u8> {
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        let entry: usize = 4 + lump_type as usize * 8;
+        bytes[entry..entry + 4].copy_from_slice(&offset.to_le_bytes());
+        bytes[entry + 4..entry + 8].copy_from_slice(&length.to_le_bytes());
+        return bytes;
+    }
+
+    fn plane(normal: glm::Vec3, dist: f32, r#type: bsp30::PlaneType) -> bsp30::Plane {
+        return bsp30::Plane {
+            normal,
+            dist,
+            r#type: r#type as i32,
+        };
+    }
+
+    /// Solid below z = 0 and beyond the diagonal plane x + y = 32 above it
+    fn two_node_hull() -> Hull {
+        let mut hull: Hull = Hull::new();
+        let diagonal: glm::Vec3 = glm::normalize(&glm::vec3(1.0, 1.0, 0.0));
+        hull.planes = vec![
+            plane(glm::vec3(0.0, 0.0, 1.0), 0.0, bsp30::PlaneType::PlaneZ),
+            plane(diagonal, 32.0 / 2.0f32.sqrt(), bsp30::PlaneType::PlaneAnyZ),
+        ];
+        hull.clip_nodes = vec![
+            bsp30::ClipNode { plane_index: 0, child_index: [1, bsp30::ContentType::ContentsSolid as i16] },
+            bsp30::ClipNode {
+                plane_index: 1,
+                child_index: [bsp30::ContentType::ContentsSolid as i16, bsp30::ContentType::ContentsEmpty as i16],
+            },
+        ];
+        hull.last_clip_node = 1;
+        return hull;
+    }
+
+    fn parse_entities(text: &str, strictness: ParseStrictness) -> (Result<Vec<Entity>, BspError>, LoadIssues) {
+        let mut issues: LoadIssues = LoadIssues::new();
+        let entities: Result<Vec<Entity>, BspError> = BSP::parse_entities(&text.to_string(), strictness, &mut issues);
+        return (entities, issues);
+    }
+
+    /// Strict parsing rejects `text` as an entity parse error, lenient parsing
+    /// loads it with one recorded issue
+    fn assert_violation(text: &str) -> Vec<Entity> {
+        let (strict, strict_issues): (Result<Vec<Entity>, BspError>, LoadIssues) = parse_entities(text, ParseStrictness::Strict);
+        assert!(matches!(strict, Err(BspError::EntityParse { .. })), "strict parsing accepted {:?}", text);
+        assert!(strict_issues.is_empty());
+        let (lenient, lenient_issues): (Result<Vec<Entity>, BspError>, LoadIssues) = parse_entities(text, ParseStrictness::Lenient);
+        assert_eq!(lenient_issues.len(), 1, "lenient parsing of {:?} recorded {:?}", text, lenient_issues);
+        return lenient.expect("lenient parsing failed");
+    }
+
+    #[test]
+    fn long_values_are_truncated_when_lenient() {
+        let text: String = format!("{{\n\"classname\" \"worldspawn\"\n\"message\" \"{}\"\n}}", "a".repeat(bsp30::MAX_VALUE + 10));
+        let entities: Vec<Entity> = assert_violation(&text);
+        assert_eq!(entities[0].find_property(&"message".to_string()).unwrap().len(), bsp30::MAX_VALUE - 1);
+    }
+
+    #[test]
+    fn duplicate_keys_keep_the_last_value_when_lenient() {
+        let entities: Vec<Entity> = assert_violation("{\n\"classname\" \"worldspawn\"\n\"wad\" \"a.wad\"\n\"wad\" \"b.wad\"\n}");
+        assert_eq!(entities[0].find_property(&"wad".to_string()), Some(&"b.wad".to_string()));
+    }
+
+    #[test]
+    fn stray_data_between_entities_is_skipped_when_lenient() {
+        let entities: Vec<Entity> = assert_violation("{\n\"classname\" \"worldspawn\"\n}\ngarbage\n{\n\"classname\" \"light\"\n}");
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[1].find_property(&"classname".to_string()), Some(&"light".to_string()));
+    }
+
+    #[test]
+    fn unclosed_entities_are_dropped_when_lenient() {
+        let entities: Vec<Entity> = assert_violation("{\n\"classname\" \"worldspawn\"\n}\n{\n\"classname\" \"light\"\n");
+        assert_eq!(entities.len(), 1);
+    }
+
+    #[test]
+    fn values_ending_right_before_the_closing_brace_are_kept() {
+        let (entities, issues): (Result<Vec<Entity>, BspError>, LoadIssues) =
+            parse_entities("{\"classname\" \"worldspawn\" \"wad\" \"a.wad\"}{\"classname\" \"light\"}", ParseStrictness::Strict);
+        let entities: Vec<Entity> = entities.unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].find_property(&"wad".to_string()), Some(&"a.wad".to_string()));
+        assert_eq!(entities[1].find_property(&"classname".to_string()), Some(&"light".to_string()));
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn well_formed_entities_record_no_issues() {
+        for strictness in [ParseStrictness::Strict, ParseStrictness::Lenient] {
+            let (entities, issues): (Result<Vec<Entity>, BspError>, LoadIssues) =
+                parse_entities("{\n\"classname\" \"worldspawn\"\n}\n", strictness);
+            assert_eq!(entities.unwrap().len(), 1);
+            assert!(issues.is_empty());
+        }
+    }
+
+    /// Decode `lump` and parse its entities leniently, returning the entities
+    /// and the issues decoding recorded
+    fn decode_and_parse(lump: &[u8]) -> (Vec<Entity>, LoadIssues) {
+        let mut issues: LoadIssues = LoadIssues::new();
+        let text: String = BSP::decode_entity_lump(lump, &mut issues);
+        let entities: Vec<Entity> = BSP::parse_entities(&text, ParseStrictness::Lenient, &mut LoadIssues::new()).unwrap();
+        return (entities, issues);
+    }
+
+    #[test]
+    fn trailing_nuls_are_trimmed_silently() {
+        let (entities, issues): (Vec<Entity>, LoadIssues) = decode_and_parse(b"{\n\"classname\" \"worldspawn\"\n}\n\0\0");
+        assert_eq!(entities.len(), 1);
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn embedded_nuls_are_removed() {
+        let (entities, issues): (Vec<Entity>, LoadIssues) =
+            decode_and_parse(b"{\n\"classname\" \"worldspawn\"\n}\n\0{\n\"classname\" \"li\0ght\"\n}\n");
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[1].find_property(&"classname".to_string()), Some(&"light".to_string()));
+        assert_eq!(issue_count(&issues, "entities"), 1);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let (entities, issues): (Vec<Entity>, LoadIssues) =
+            decode_and_parse(b"{\n\"classname\" \"worldspawn\"\n\"message\" \"caf\xff\"\n}\n");
+        assert_eq!(entities[0].find_property(&"message".to_string()), Some(&"caf\u{fffd}".to_string()));
+        assert_eq!(issue_count(&issues, "entities"), 1);
+    }
+
+    #[test]
+    fn trailing_garbage_is_dropped() {
+        let (entities, issues): (Vec<Entity>, LoadIssues) =
+            decode_and_parse(b"{\n\"classname\" \"worldspawn\"\n}\n\x01garbage\0");
+        assert_eq!(entities.len(), 1);
+        assert_eq!(issue_count(&issues, "entities"), 1);
+    }
+
+    #[test]
+    fn leaves_without_vis_data_keep_their_vis_list_slot() {
+        let mut bsp: BSP = micro_map();
+        bsp.leaves = vec![
+            leaf(bsp30::ContentType::ContentsSolid, -1),
+            leaf(bsp30::ContentType::ContentsEmpty, 0),
+            leaf(bsp30::ContentType::ContentsEmpty, -1),
+            leaf(bsp30::ContentType::ContentsEmpty, 1),
+        ];
+        // One byte rows for three leaves, leaf 1 sees leaves 2 and 3, leaf 3 sees leaves 1 and 2
+        let compressed_vis: Vec<u8> = vec![0b110, 0b011];
+        for threads in [1, 4] {
+            let vis_lists: Vec<BitSet<u8>> = bsp.decompress_vis_lists(bsp.leaves.len() - 1, &compressed_vis, threads);
+            assert_eq!(vis_lists.len(), bsp.leaves.len() - 1);
+            assert_eq!(vis_lists[0].iter().collect::<Vec<usize>>(), vec![1, 2]);
+            assert!(vis_lists[1].is_empty());
+            assert_eq!(vis_lists[2].iter().collect::<Vec<usize>>(), vec![0, 1]);
+        }
+    }
+
+    #[test]
+    fn count_vis_leaves_skips_solid_leaves() {
+        let mut bsp: BSP = micro_map();
+        bsp.leaves = vec![
+            leaf(bsp30::ContentType::ContentsSolid, -1),
+            leaf(bsp30::ContentType::ContentsEmpty, 0),
+            leaf(bsp30::ContentType::ContentsWater, 0),
+            leaf(bsp30::ContentType::ContentsSolid, -1),
+        ];
+        // Node 0 holds leaf 1 and node 1, which holds leaves 2 and 3
+        bsp.nodes = vec![node([1, -2]), node([-3, -4])];
+        assert_eq!(bsp.count_vis_leaves(0).unwrap(), 2);
+        assert_eq!(bsp.count_vis_leaves(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn count_vis_leaves_rejects_corrupt_trees() {
+        let mut bsp: BSP = micro_map();
+        bsp.leaves = vec![leaf(bsp30::ContentType::ContentsSolid, -1), leaf(bsp30::ContentType::ContentsEmpty, 0)];
+        bsp.nodes = vec![node([1, -2]), node([-2, 5])];
+        assert!(matches!(bsp.count_vis_leaves(0), Err(BspError::CorruptTree { .. })));
+        bsp.nodes = vec![node([-2, -9])];
+        assert!(matches!(bsp.count_vis_leaves(0), Err(BspError::CorruptTree { .. })));
+        bsp.nodes = vec![node([1, 1]), node([-2, -1])];
+        assert!(matches!(bsp.count_vis_leaves(0), Err(BspError::CorruptTree { .. })));
+        assert!(matches!(bsp.count_vis_leaves(3), Err(BspError::CorruptTree { .. })));
+    }
+
+    #[test]
+    fn count_vis_leaves_walks_deep_trees() {
+        let mut bsp: BSP = micro_map();
+        bsp.leaves = vec![leaf(bsp30::ContentType::ContentsSolid, -1), leaf(bsp30::ContentType::ContentsEmpty, 0)];
+        // A chain as deep as node indices allow, each node holding the next and leaf 1
+        let depth: usize = i16::MAX as usize;
+        bsp.nodes = (0..depth)
+            .map(|i: usize| node([if i + 1 < depth { (i + 1) as i16 } else { -1 }, -2]))
+            .collect();
+        assert_eq!(bsp.count_vis_leaves(0).unwrap(), depth);
+    }
+
+    #[test]
+    fn from_reader_loads_from_memory_without_a_wad_provider() {
+        let bsp: BSP = BSP::from_reader(std::io::Cursor::new(crate::map::micro_map::MICRO_MAP.to_vec())).unwrap();
+        assert_eq!(bsp.faces.len(), 2);
+        assert_eq!(bsp.entities.len(), 2);
+        assert_eq!(bsp.m_lightmaps.len(), 2);
+        // WAD textures stay missing rather than being looked for on disk
+        assert_eq!(bsp.missing_textures.len(), 2);
+        assert!(bsp.skipped_wads.iter().any(|wad: &SkippedWad| wad.name == crate::map::micro_map::MICRO_WAD_NAME));
+    }
+
+    #[test]
+    fn lumps_past_the_end_of_the_file_are_rejected() {
+        let file_len: i32 = crate::map::micro_map::MICRO_MAP.len() as i32;
+        let bytes: Vec<u8> = with_lump(bsp30::LumpType::LumpFaces, file_len - 20, 40);
+        assert!(matches!(
+            BSP::from_reader(std::io::Cursor::new(bytes)),
+            Err(BspError::LumpOutOfBounds { lump: "faces", length: 40, .. }),
+        ));
+    }
+
+    #[test]
+    fn negative_lump_lengths_are_rejected() {
+        let bytes: Vec<u8> = with_lump(bsp30::LumpType::LumpEdges, 124, -8);
+        assert!(matches!(
+            BSP::from_reader(std::io::Cursor::new(bytes)),
+            Err(BspError::LumpOutOfBounds { lump: "edges", length: -8, .. }),
+        ));
+    }
+
+    #[test]
+    fn misaligned_lumps_are_rejected() {
+        let bytes: Vec<u8> = with_lump(bsp30::LumpType::LumpFaces, 124, std::mem::size_of::<bsp30::Face>() as i32 + 3);
+        assert!(matches!(
+            BSP::from_reader(std::io::Cursor::new(bytes)),
+            Err(BspError::LumpMisaligned { lump: "faces", element_size: 20, .. }),
+        ));
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        bytes[..4].copy_from_slice(&31i32.to_le_bytes());
+        assert!(matches!(
+            BSP::from_reader(std::io::Cursor::new(bytes)),
+            Err(BspError::InvalidVersion { found: 31 }),
+        ));
+    }
+
+    #[test]
+    fn quake_lightmaps_expand_luminance_to_rgb() {
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        bytes[..4].copy_from_slice(&29i32.to_le_bytes());
+        let bsp: BSP = BSP::from_reader(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(bsp.version, bsp30::BspVersion::Quake);
+        let image: &Image = bsp.m_lightmaps[0].images[0].as_ref().unwrap();
+        assert_eq!((image.width, image.height, image.channels), (5, 5, 3));
+        assert!(image.data.chunks(3).all(|texel: &[u8]| texel[0] == texel[1] && texel[1] == texel[2]));
+        // The floor's first luxel is fully lit
+        assert_eq!(image.data[..6], [255, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn swapped_entity_and_plane_lumps_are_detected() {
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        // Swap the directory entries of lumps 0 and 1 as Blue Shift maps store them
+        let (entities, planes): (Vec<u8>, Vec<u8>) = (bytes[4..12].to_vec(), bytes[12..20].to_vec());
+        bytes[4..12].copy_from_slice(&planes);
+        bytes[12..20].copy_from_slice(&entities);
+        let bsp: BSP = BSP::from_reader(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(bsp.entities.len(), 2);
+        assert_eq!(bsp.planes.len(), 2);
+        assert_eq!(bsp.planes[0].normal, glm::vec3(0.0, 0.0, 1.0));
+        assert_eq!(bsp.planes[1].dist, 64.0);
+    }
+
+    #[test]
+    fn hull_point_contents_follow_the_clip_planes() {
+        let hull: Hull = two_node_hull();
+        assert_eq!(hull.point_contents(0, glm::vec3(0.0, 0.0, 8.0)), bsp30::ContentType::ContentsEmpty);
+        assert_eq!(hull.point_contents(0, glm::vec3(0.0, 0.0, -8.0)), bsp30::ContentType::ContentsSolid);
+        assert_eq!(hull.point_contents(0, glm::vec3(30.0, 30.0, 8.0)), bsp30::ContentType::ContentsSolid);
+        assert_eq!(hull.point_contents(1, glm::vec3(0.0, 0.0, -8.0)), bsp30::ContentType::ContentsEmpty);
+    }
+
+    #[test]
+    fn points_on_a_clip_plane_go_to_the_front() {
+        let hull: Hull = two_node_hull();
+        assert_eq!(hull.point_contents(0, glm::vec3(0.0, 0.0, 0.0)), bsp30::ContentType::ContentsEmpty);
+        assert_eq!(hull.point_contents(0, glm::vec3(0.0, 0.0, -0.01)), bsp30::ContentType::ContentsSolid);
+    }
+
+    #[test]
+    fn malformed_hulls_are_solid() {
+        let mut hull: Hull = two_node_hull();
+        hull.clip_nodes[1].child_index[1] = 7;
+        assert_eq!(hull.point_contents(0, glm::vec3(0.0, 0.0, 8.0)), bsp30::ContentType::ContentsSolid);
+        hull.clip_nodes[1].child_index[1] = 0;
+        assert_eq!(hull.point_contents(0, glm::vec3(0.0, 0.0, 8.0)), bsp30::ContentType::ContentsSolid);
+    }
+
+    #[test]
+    fn world_point_contents_start_at_the_world_head_node() {
+        let bsp: BSP = micro_map();
+        for hull in 0..bsp30::MAX_MAP_HULLS {
+            assert_eq!(bsp.point_contents(hull, glm::vec3(32.0, 8.0, 36.0)), bsp30::ContentType::ContentsEmpty);
+            assert_eq!(bsp.point_contents(hull, glm::vec3(32.0, 8.0, -36.0)), bsp30::ContentType::ContentsSolid);
+        }
+        assert_eq!(bsp.point_contents(bsp30::MAX_MAP_HULLS, glm::vec3(32.0, 8.0, 36.0)), bsp30::ContentType::ContentsSolid);
+    }
+
+    #[test]
+    fn missing_textures_are_reported_and_replaced_by_placeholders() {
+        let bsp: BSP = BSP::from_reader(std::io::Cursor::new(crate::map::micro_map::MICRO_MAP.to_vec())).unwrap();
+        let missing: Vec<(String, usize)> = bsp.missing_textures().iter()
+            .map(|missing: &MissingTexture| (missing.name.to_string(), missing.index))
+            .collect();
+        assert_eq!(missing, vec![("floor".to_string(), 0), ("wall".to_string(), 1)]);
+        for (texture, name) in bsp.m_textures.iter().zip(crate::map::micro_map::MICRO_TEXTURES) {
+            assert_eq!(texture.name, name);
+            assert_eq!(texture.source, TextureSource::Missing);
+            let sizes: Vec<(usize, usize)> = texture.img.iter().map(|img: &Image| (img.width, img.height)).collect();
+            assert_eq!(sizes, vec![(16, 16), (8, 8), (4, 4), (2, 2)]);
+            assert!(texture.img.iter().all(|img: &Image| img.data.len() == img.width * img.height * 4));
+        }
+    }
+
+    #[test]
+    fn parallel_and_sequential_lump_parsing_agree() {
+        let load = |sequential_lumps: bool| -> BSP {
+            let options: BspLoadOptions = BspLoadOptions {
+                sequential_lumps,
+                ..BspLoadOptions::strict()
+            };
+            return BSP::from_reader_with_options(std::io::Cursor::new(crate::map::micro_map::MICRO_MAP), &options).unwrap();
+        };
+        let (sequential, parallel): (BSP, BSP) = (load(true), load(false));
+        let counts = |bsp: &BSP| -> [usize; 9] {
+            return [
+                bsp.nodes.len(),
+                bsp.leaves.len(),
+                bsp.mark_surfaces.len(),
+                bsp.faces.len(),
+                bsp.clip_nodes.len(),
+                bsp.surface_edges.len(),
+                bsp.edges.len(),
+                bsp.vertices.len(),
+                bsp.planes.len(),
+            ];
+        };
+        assert_eq!(counts(&sequential), counts(&parallel));
+        assert_eq!(counts(&parallel), [1, 2, 2, 2, 1, 8, 8, 6, 2]);
+        assert_eq!(sequential.surface_edges, parallel.surface_edges);
+        assert_eq!(sequential.vertices[5], parallel.vertices[5]);
+        assert_eq!(sequential.faces[1].lightmap_offset, parallel.faces[1].lightmap_offset);
+        assert_eq!(sequential.leaves[1].content, parallel.leaves[1].content);
+        assert_eq!(sequential.planes[1].dist, parallel.planes[1].dist);
+    }
+
+    #[test]
+    fn progress_reports_each_stage_in_order() {
+        let path: PathBuf = std::env::temp_dir().join(format!("lambda-progress-{}.bsp", std::process::id()));
+        std::fs::write(&path, crate::map::micro_map::MICRO_MAP).unwrap();
+        let mut reports: Vec<(LoadStage, f32)> = Vec::new();
+        let loaded: Result<BSP, BspError> = BSP::from_file_with_progress(&path, |stage: LoadStage, fraction: f32| reports.push((stage, fraction)));
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+        assert!(reports.windows(2).all(|pair: &[(LoadStage, f32)]| {
+            pair[0].0 < pair[1].0 || (pair[0].0 == pair[1].0 && pair[0].1 <= pair[1].1)
+        }), "out of order: {:?}", reports);
+        let mut stages: Vec<LoadStage> = reports.iter().map(|(stage, _): &(LoadStage, f32)| *stage).collect();
+        stages.dedup();
+        assert_eq!(stages, vec![
+            LoadStage::Lumps,
+            LoadStage::Models,
+            LoadStage::Entities,
+            LoadStage::Textures,
+            LoadStage::Lightmaps,
+            LoadStage::Decals,
+            LoadStage::Visibility,
+        ]);
+        // Every stage runs from start to finish, textures in between as well
+        for stage in stages {
+            let fractions: Vec<f32> = reports.iter()
+                .filter(|(reported, _): &&(LoadStage, f32)| *reported == stage)
+                .map(|(_, fraction): &(LoadStage, f32)| *fraction)
+                .collect();
+            assert_eq!((fractions[0], fractions[fractions.len() - 1]), (0.0, 1.0), "{:?}", stage);
+        }
+        assert!(reports.contains(&(LoadStage::Textures, 0.5)));
+    }
+
+    #[test]
+    fn composite_is_the_weighted_sum_of_the_styles() {
+        let mut styles: [f32; MAX_LIGHT_STYLES] = [1.0; MAX_LIGHT_STYLES];
+        styles[5] = 0.5;
+        let image: Image = two_style_lightmaps().composite(&styles);
+        assert_eq!((image.width, image.height, image.channels), (2, 1, 3));
+        assert_eq!(image.data[..3], [110, 70, 30]);
+    }
+
+    #[test]
+    fn composite_saturates_to_the_texel_range() {
+        let styles: [f32; MAX_LIGHT_STYLES] = [1.0; MAX_LIGHT_STYLES];
+        let image: Image = two_style_lightmaps().composite(&styles);
+        assert_eq!(image.data[3..], [255, 255, 255]);
+    }
+
+    #[test]
+    fn composite_drops_styles_switched_off() {
+        let mut styles: [f32; MAX_LIGHT_STYLES] = [1.0; MAX_LIGHT_STYLES];
+        styles[0] = 0.0;
+        let image: Image = two_style_lightmaps().composite(&styles);
+        assert_eq!(image.data, vec![20, 40, 60, 100, 100, 100]);
+    }
+
+    #[test]
+    fn composite_of_no_lightmaps_is_empty() {
+        let lightmaps: FaceLightmaps = FaceLightmaps::default();
+        assert!(lightmaps.is_empty());
+        assert!(lightmaps.composite(&[1.0; MAX_LIGHT_STYLES]).data.is_empty());
+    }
+
+    #[test]
+    fn standard_tool_texture_names_are_classified() {
+        assert_eq!(TextureClass::from_name("sky"), TextureClass::Sky);
+        assert_eq!(TextureClass::from_name("SKY"), TextureClass::Sky);
+        assert_eq!(TextureClass::from_name("aaatrigger"), TextureClass::Trigger);
+        assert_eq!(TextureClass::from_name("CLIP"), TextureClass::Clip);
+        assert_eq!(TextureClass::from_name("origin"), TextureClass::Origin);
+        assert_eq!(TextureClass::from_name("hint"), TextureClass::Hint);
+        assert_eq!(TextureClass::from_name("skip"), TextureClass::Skip);
+        assert_eq!(TextureClass::from_name("c1a0_w1"), TextureClass::Normal);
+        assert_eq!(TextureClass::from_name("skybox_wall"), TextureClass::Normal);
+    }
+
+    #[test]
+    fn prefixed_texture_names_are_classified_by_their_base() {
+        assert_eq!(TextureClass::from_name("{grate"), TextureClass::Normal);
+        assert_eq!(TextureClass::from_name("{clip"), TextureClass::Clip);
+        assert_eq!(TextureClass::from_name("!water"), TextureClass::Water);
+        assert_eq!(TextureClass::from_name("*lava"), TextureClass::Water);
+        assert_eq!(TextureClass::from_name("!sky"), TextureClass::Sky);
+    }
+
+    #[test]
+    fn faces_with_liquid_textures_are_water() {
+        // Rename the wall's mip texture in the texture lump to a liquid one
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        let name: usize = bytes.windows(5).position(|window: &[u8]| window == b"wall\0").unwrap();
+        bytes[name..name + 5].copy_from_slice(b"!wal\0");
+        let options: BspLoadOptions = BspLoadOptions {
+            wad_provider: Some(Arc::new(crate::map::micro_map::EmbeddedWads)),
+            ..BspLoadOptions::lenient()
+        };
+        let bsp: BSP = BSP::from_reader_with_options(std::io::Cursor::new(bytes), &options).unwrap();
+        assert_eq!(bsp.texture_classes, vec![TextureClass::Normal, TextureClass::Water]);
+        assert_eq!(bsp.face_texture_class(&bsp.faces[0]), TextureClass::Normal);
+        assert_eq!(bsp.face_texture_class(&bsp.faces[1]), TextureClass::Water);
+    }
+
+    #[test]
+    fn only_compiler_tool_textures_are_hidden() {
+        assert!(TextureClass::Trigger.is_hidden() && TextureClass::Origin.is_hidden());
+        assert!(!TextureClass::Sky.is_hidden() && TextureClass::Sky.is_tool());
+        assert!(!TextureClass::Water.is_hidden() && !TextureClass::Water.is_tool());
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.texture_classes, vec![TextureClass::Normal, TextureClass::Normal]);
+        assert_eq!(bsp.face_texture_class(&bsp.faces[0]), TextureClass::Normal);
+    }
+
+    #[test]
+    fn repeated_decal_textures_share_an_index() {
+        let bsp: BSP = micro_map_with_decals(&[
+            ("floor", "32 32 0.01"),
+            ("floor", "16 40 0.01"),
+            ("wall", "32 63.99 32"),
+        ]);
+        let indices: Vec<u32> = bsp.m_decals.iter().map(|decal: &Decal| decal.tex_index).collect();
+        assert_eq!(indices, vec![2, 2, 3]);
+        assert_eq!(bsp.m_textures.len(), 4);
+        assert_eq!(bsp.m_textures[2].name, "floor");
+        assert_eq!(bsp.m_textures[3].name, "wall");
+        // Each quad is the 16x16 size of its texture
+        for decal in bsp.m_decals.iter() {
+            assert!((polygon_area(&decal.vec) - 256.0).abs() < 1e-2);
+        }
+        assert_eq!(bsp.m_decals[2].normal, glm::vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn style_lightmaps_follow_each_other_in_the_lighting_lump() {
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.faces[1].lightmap_offset, 75);
+        for slot in 0..MAX_LIGHTMAPS {
+            assert_eq!(BSP::lightmap_style_offset(&bsp.faces[1], 5, 5, 3, slot), 75 + 5 * 5 * 3 * slot);
+        }
+        assert_eq!(BSP::lightmap_style_offset(&bsp.faces[1], 5, 5, 1, 3), 75 + 5 * 5 * 3);
+    }
+
+    #[test]
+    fn every_style_of_a_face_is_loaded() {
+        // Give the floor a second style, whose lightmap is then the wall's
+        let mut bytes: Vec<u8> = crate::map::micro_map::MICRO_MAP.to_vec();
+        let entry: usize = 4 + bsp30::LumpType::LumpFaces as usize * 8;
+        let faces: usize = i32::from_le_bytes(bytes[entry..entry + 4].try_into().unwrap()) as usize;
+        bytes[faces + 12..faces + 16].copy_from_slice(&[0, 1, UNUSED_LIGHT_STYLE, UNUSED_LIGHT_STYLE]);
+        let bsp: BSP = BSP::from_reader(std::io::Cursor::new(bytes)).unwrap();
+        let floor: &FaceLightmaps = &bsp.m_lightmaps[0];
+        assert_eq!(floor.styles, [0, 1, UNUSED_LIGHT_STYLE, UNUSED_LIGHT_STYLE]);
+        assert_eq!(floor.images[1].as_ref().unwrap().data, bsp.m_lightmaps[1].images[0].as_ref().unwrap().data);
+        assert!(floor.images[2].is_none());
+    }
+
+    #[test]
+    fn light_styles_are_clamped_and_bounded() {
+        let mut bsp: BSP = micro_map();
+        bsp.set_light_style(1, 0.25);
+        bsp.set_light_style(2, -1.0);
+        bsp.set_light_style(MAX_LIGHT_STYLES, 0.5);
+        assert_eq!(bsp.light_styles[1], 0.25);
+        assert_eq!(bsp.light_styles[2], 0.0);
+        assert_eq!(bsp.light_styles[0], 1.0);
+    }
+
+    /// Micro map looking for the `test` sky set in `sky_dir`
+    fn micro_map_with_sky(sky_dir: &Path) -> BSP {
+        let mut bsp: BSP = micro_map();
+        bsp.asset_paths.sky_dir = sky_dir.to_path_buf();
+        bsp.entities[0].properties.insert("skyname".to_string(), "test".to_string());
+        return bsp;
+    }
+
+    #[test]
+    fn sky_faces_load_from_tga() {
+        let sky_dir: PathBuf = std::env::temp_dir().join(format!("lambda-sky-tga-{}", std::process::id()));
+        let face: Image = Image::from((2, 2, 4));
+        for suffix in SKY_NAME_SUFFIXES.iter() {
+            face.save(sky_dir.join(format!("test{}.tga", suffix)).to_string_lossy().to_string()).unwrap();
+        }
+        let skybox: io::Result<Option<[Image; 6]>> = micro_map_with_sky(&sky_dir).load_skybox();
+        std::fs::remove_dir_all(&sky_dir).unwrap();
+        let skybox: [Image; 6] = skybox.unwrap().unwrap();
+        assert!(skybox.iter().all(|image: &Image| (image.width, image.height, image.channels) == (2, 2, 4)));
+    }
+
+    #[test]
+    fn sky_faces_missing_as_tga_fall_back_to_bmp() {
+        let sky_dir: PathBuf = std::env::temp_dir().join(format!("lambda-sky-bmp-{}", std::process::id()));
+        for suffix in SKY_NAME_SUFFIXES.iter() {
+            let path: PathBuf = if suffix == "up" {
+                sky_dir.join(format!("test{}.bmp", suffix))
+            } else {
+                sky_dir.join(format!("test{}.tga", suffix))
+            };
+            Image::from((4, 2, 3)).save(path.to_string_lossy().to_string()).unwrap();
+        }
+        let skybox: io::Result<Option<[Image; 6]>> = micro_map_with_sky(&sky_dir).load_skybox();
+        std::fs::remove_file(sky_dir.join("testup.bmp")).unwrap();
+        let missing: io::Result<Option<[Image; 6]>> = micro_map_with_sky(&sky_dir).load_skybox();
+        std::fs::remove_dir_all(&sky_dir).unwrap();
+        assert_eq!(skybox.unwrap().unwrap()[2].width, 4);
+        let error: String = missing.err().unwrap().to_string();
+        assert!(error.contains("'up'") && error.contains("testup.tga"), "{}", error);
+    }
+
+    #[test]
+    fn maps_without_a_worldspawn_have_no_sky() {
+        let mut bsp: BSP = micro_map();
+        bsp.entities.clear();
+        assert!(bsp.load_skybox().unwrap().is_none());
+    }
+
+
+    #[test]
+    fn out_of_range_face_texture_info_is_repaired_when_lenient() {
+        // Point the wall face (20 bytes per face, texture info at byte 10) past the texture infos
+        let bytes: Vec<u8> = patched_micro_map(bsp30::LumpType::LumpFaces, 20 + 10, &7u16.to_le_bytes());
+        assert!(matches!(load_bytes(bytes.clone(), ParseStrictness::Strict), Err(BspError::Violation { .. })));
+        let bsp: BSP = load_bytes(bytes, ParseStrictness::Lenient).unwrap();
+        assert_eq!(bsp.faces[1].texture_info, 0);
+        assert_eq!(issue_count(&bsp.load_issues, "faces"), 1);
+    }
+
+    #[test]
+    fn out_of_range_mip_texture_index_is_repaired_when_lenient() {
+        // Texture info 1 (40 bytes each, mip texture index at byte 32) names a missing mip texture
+        let bytes: Vec<u8> = patched_micro_map(bsp30::LumpType::LumpTexinfo, 40 + 32, &9u32.to_le_bytes());
+        assert!(matches!(load_bytes(bytes.clone(), ParseStrictness::Strict), Err(BspError::Violation { .. })));
+        let bsp: BSP = load_bytes(bytes, ParseStrictness::Lenient).unwrap();
+        assert_eq!(bsp.texture_infos[1].mip_tex_index, 0);
+        assert_eq!(issue_count(&bsp.load_issues, "texture infos"), 1);
+    }
+
+    #[test]
+    fn out_of_range_node_leaf_is_solid_when_lenient() {
+        // The root node's back child (24 bytes per node, children at byte 4) names leaf 99
+        let bytes: Vec<u8> = patched_micro_map(bsp30::LumpType::LumpNodes, 6, &(!99i16).to_le_bytes());
+        assert!(matches!(load_bytes(bytes.clone(), ParseStrictness::Strict), Err(BspError::Violation { .. })));
+        let bsp: BSP = load_bytes(bytes, ParseStrictness::Lenient).unwrap();
+        assert_eq!(bsp.hull_0_clip_nodes[0].child_index[1], bsp30::ContentType::ContentsSolid as i16);
+        assert_eq!(issue_count(&bsp.load_issues, "nodes"), 1);
+    }
+
+
+    #[test]
+    fn uniformly_scaled_replacements_keep_the_declared_uv_scale() {
+        let mut bsp: BSP = micro_map();
+        let declared: glm::Vec2 = glm::vec2(bsp.mip_textures[0].width as f32, bsp.mip_textures[0].height as f32);
+        // A 2x replacement for the 16x16 floor texture
+        bsp.m_textures[0] = MipmapTexture::placeholder(32, 32);
+        assert_eq!(bsp.texture_normalisation_size(0), declared);
+        let tex_info: bsp30::TextureInfo = bsp.texture_infos[bsp.faces[0].texture_info as usize];
+        for j in 0..bsp.faces[0].edge_count as usize {
+            let tex_coord: glm::Vec2 = BSP::texture_space(&tex_info, &bsp.face_vertex(0, j))
+                .component_div(&bsp.texture_normalisation_size(0));
+            assert_eq!(tex_coord, bsp.face_tex_coords[0].tex_coords[j]);
+        }
+        // A replacement with a different aspect ratio uses its own size
+        bsp.m_textures[0] = MipmapTexture::placeholder(32, 16);
+        assert_eq!(bsp.texture_normalisation_size(0), glm::vec2(32.0, 16.0));
+    }
+
+    fn assert_lightmap_round_trip(bsp: &BSP, face_index: usize) {
+        let winding: Vec<glm::Vec3> = bsp.face_winding(face_index);
+        let center: glm::Vec3 = bsp.face_center(face_index);
+        for point in winding.iter().map(|corner: &glm::Vec3| glm::mix(&center, corner, 0.75)).chain([center]) {
+            let (u, v): (f32, f32) = bsp.world_to_lightmap_texel(face_index, point);
+            let world: glm::Vec3 = bsp.lightmap_texel_to_world(face_index, u, v);
+            assert!(glm::distance(&world, &point) < 1e-3, "face {} mapped {:?} back to {:?}", face_index, point, world);
+        }
+    }
+
+    #[test]
+    fn lightmap_texels_map_back_to_the_same_world_point() {
+        let mut bsp: BSP = micro_map();
+        for face_index in 0..bsp.faces.len() {
+            assert_lightmap_round_trip(&bsp, face_index);
+        }
+        // The floor's centre is the middle of its 5x5 lightmap
+        assert_eq!(bsp.world_to_lightmap_texel(0, glm::vec3(32.0, 32.0, 0.0)), (2.5, 2.5));
+        // Texture axes turned 30 degrees within the floor's plane
+        let (sin, cos): (f32, f32) = 30.0f32.to_radians().sin_cos();
+        bsp.texture_infos[bsp.faces[0].texture_info as usize].s = glm::vec3(cos, sin, 0.0);
+        bsp.texture_infos[bsp.faces[0].texture_info as usize].t = glm::vec3(-sin, cos, 0.0);
+        assert_lightmap_round_trip(&bsp, 0);
+    }
+
+    #[test]
+    fn exceeding_a_soft_limit_is_recorded() {
+        let mut bsp: BSP = micro_map();
+        let leaf_size: usize = std::mem::size_of::<bsp30::Leaf>();
+        bsp.header.lump[bsp30::LumpType::LumpLeaves as usize].length = ((bsp30::MAX_MAP_LEAFS + 1) * leaf_size) as i32;
+        assert!(bsp.check_limits().is_ok());
+        assert_eq!(issue_count(&bsp.load_issues, "limits"), 1);
+    }
+
+    #[test]
+    fn exceeding_what_an_index_can_address_fails() {
+        let mut bsp: BSP = micro_map();
+        let leaf_size: usize = std::mem::size_of::<bsp30::Leaf>();
+        bsp.header.lump[bsp30::LumpType::LumpLeaves as usize].length = ((i16::MAX as usize + 2) * leaf_size) as i32;
+        assert!(matches!(
+            bsp.check_limits(),
+            Err(BspError::LimitExceeded { name: "leaves", count, limit, .. }) if count == i16::MAX as usize + 2 && limit == i16::MAX as usize + 1,
+        ));
+    }
+
+    fn visible_leaves(pvs: &BitSet<u8>) -> Vec<usize> {
+        return pvs.iter().collect();
+    }
+
+    #[test]
+    fn zero_runs_skip_invisible_bytes() {
+        // Leaves 0 and 1, a run over the next two bytes, then leaf 31 of 32
+        let compressed: [u8; 4] = [0b0000_0011, 0, 2, 0b1000_0000];
+        assert_eq!(visible_leaves(&BSP::decompress_vis(0, &compressed, 32)), vec![0, 1, 31]);
+        // Reading starts at the leaf's offset
+        assert_eq!(visible_leaves(&BSP::decompress_vis(3, &compressed, 8)), vec![7]);
+    }
+
+    #[test]
+    fn visibility_stops_at_the_end_of_the_row() {
+        // A run past the row and bits past the leaf count are ignored
+        assert_eq!(visible_leaves(&BSP::decompress_vis(0, &[0, 9, 0xFF], 16)), Vec::<usize>::new());
+        assert_eq!(visible_leaves(&BSP::decompress_vis(0, &[0xFF], 3)), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn truncated_runs_end_the_visible_leaves() {
+        assert_eq!(visible_leaves(&BSP::decompress_vis(0, &[0b0000_0100, 0], 16)), vec![2]);
+    }
+
+    #[test]
+    fn face_texture_coordinates_follow_their_texture_info() {
+        let bsp: BSP = micro_map();
+        // The floor's s axis is +X and t axis -Y over a 16 texel texture
+        assert_eq!(bsp.face_tex_coords[0].tex_coords, vec![
+            glm::vec2(0.0, 0.0),
+            glm::vec2(0.0, -4.0),
+            glm::vec2(4.0, -4.0),
+            glm::vec2(4.0, 0.0),
+        ]);
+        // The wall's edges start at surface edge 4, not at its texture info, and its t axis is -Z
+        assert_eq!(bsp.face_tex_coords[1].tex_coords, vec![
+            glm::vec2(0.0, -4.0),
+            glm::vec2(4.0, -4.0),
+            glm::vec2(4.0, 0.0),
+            glm::vec2(0.0, 0.0),
+        ]);
+    }
+
+    /// Micro map whose floor has a 2x2 lightmap at `offset` and whose wall is unlit
+    fn micro_map_with_small_lightmap(offset: u32) -> BSP {
+        let mut bsp: BSP = micro_map();
+        // A quarter scale texture spans the 64 unit floor with 16 texture units, two luxels a side
+        bsp.texture_infos[0].s = glm::vec3(0.25, 0.0, 0.0);
+        bsp.texture_infos[0].t = glm::vec3(0.0, -0.25, 0.0);
+        bsp.faces[0].lightmap_offset = offset;
+        bsp.faces[0].styles = [0, UNUSED_LIGHT_STYLE, UNUSED_LIGHT_STYLE, UNUSED_LIGHT_STYLE];
+        bsp.faces[1].lightmap_offset = u32::MAX;
+        bsp.m_lightmaps.clear();
+        bsp.load_issues = LoadIssues::new();
+        return bsp;
+    }
+
+    #[test]
+    fn lightmaps_are_copied_from_their_offset() {
+        let mut bsp: BSP = micro_map_with_small_lightmap(3);
+        let lighting: Vec<u8> = (0..20).collect();
+        bsp.load_light_maps(lighting);
+        let image: &Image = bsp.m_lightmaps[0].images[0].as_ref().unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 2, 3));
+        assert_eq!(image.data, (3..15).collect::<Vec<u8>>());
+        assert!(bsp.m_lightmaps[0].images[1].is_none());
+        assert!(bsp.m_lightmaps[1].images.iter().all(|image: &Option<Image>| image.is_none()));
+        assert!(bsp.load_issues.is_empty());
+    }
+
+    #[test]
+    fn lightmaps_past_the_lighting_lump_are_skipped() {
+        let mut bsp: BSP = micro_map_with_small_lightmap(10);
+        bsp.load_light_maps((0..20).collect());
+        assert!(bsp.m_lightmaps[0].images[0].is_none());
+        assert_eq!(issue_count(&bsp.load_issues, "lightmaps"), 1);
+    }
+
+    #[test]
+    fn points_on_a_node_plane_go_to_the_back_child() {
+        let bsp: BSP = micro_map();
+        // The root node splits the empty leaf 1 above the floor from the solid leaf 0 below
+        assert_eq!(bsp.find_leaf(glm::vec3(32.0, 32.0, 1.0), 0), Some(1));
+        assert_eq!(bsp.find_leaf(glm::vec3(32.0, 32.0, -1.0), 0), Some(0));
+        assert_eq!(bsp.find_leaf(glm::vec3(32.0, 32.0, 0.0), 0), Some(0));
+    }
+
+    #[test]
+    fn func_walls_load_their_own_model() {
+        let bsp: BSP = load_bytes(micro_map_with_brush_entity("func_wall", "0 0 0"), ParseStrictness::Strict).unwrap();
+        assert_eq!(bsp.models.len(), 2);
+        assert_eq!((bsp.models[0].model.first_face, bsp.models[0].model.face_count), (0, 2));
+        // The func_wall's model is the wall face alone
+        assert_eq!((bsp.models[1].model.first_face, bsp.models[1].model.face_count), (1, 1));
+        assert_eq!(bsp.model_faces(1).count(), 1);
+        let walls: Vec<&Entity> = bsp.brush_entities.iter().map(|i: &usize| &bsp.entities[*i]).collect();
+        assert_eq!(walls.len(), 1);
+        assert_eq!(BSP::brush_model_index(walls[0]), Some(1));
+    }
+
+    #[test]
+    fn brush_model_references_parse_their_index() {
+        let model = |properties: &str| -> Option<usize> {
+            return BSP::brush_model_index(&Entity::new(&properties.to_string()));
+        };
+        assert_eq!(model("\"classname\" \"func_door\" \"model\" \"*3\""), Some(3));
+        assert_eq!(model("\"classname\" \"func_wall\" \"model\" \"*12\""), Some(12));
+        assert_eq!(model("\"classname\" \"func_wall\""), None);
+        // Studio models are files, not inline brush models
+        assert_eq!(model("\"classname\" \"cycler\" \"model\" \"models/barrel.mdl\""), None);
+    }
+
+    #[test]
+    fn brush_entity_origins_translate_their_model() {
+        let bsp: BSP = load_bytes(micro_map_with_brush_entity("func_door", "64 128 -32"), ParseStrictness::Strict).unwrap();
+        assert_eq!(bsp.models[1].model.origin, glm::vec3(64.0, 128.0, -32.0));
+        // The world keeps its own origin
+        assert_eq!(bsp.models[0].model.origin, glm::vec3(0.0, 0.0, 0.0));
+    }
+
+    fn micro_map_lump(lump_index: usize) -> bsp30::Lump {
+        let bsp: BSP = micro_map();
+        return bsp30::Lump {
+            offset: bsp.header.lump[lump_index].offset,
+            length: bsp.header.lump[lump_index].length,
+        };
+    }
+
+    /// Elements of a lump as the per element reader loaded them, seeking to
+    /// the lump in the whole file and reading one element at a time
+    fn read_per_element<T: Resource>(lump_index: usize) -> Vec<T> {
+        let lump: bsp30::Lump = micro_map_lump(lump_index);
+        let mut reader: BufReader<std::io::Cursor<&[u8]>> = BufReader::new(std::io::Cursor::new(crate::map::micro_map::MICRO_MAP));
+        reader.seek(SeekFrom::Start(lump.offset as u64)).unwrap();
+        return (0..lump.length as usize / std::mem::size_of::<T>())
+            .map(|_| T::from_reader(&mut reader).unwrap())
+            .collect();
+    }
+
+    /// Elements of a lump parsed from its bytes read in one go
+    fn read_bulk<T: Resource>(lump_index: usize) -> Vec<T> {
+        let lump: bsp30::Lump = micro_map_lump(lump_index);
+        let mut reader: BufReader<std::io::Cursor<&[u8]>> = BufReader::new(std::io::Cursor::new(crate::map::micro_map::MICRO_MAP));
+        return BSP::parse_lump::<T>(&BSP::read_lump_bytes(&mut reader, &lump).unwrap()).unwrap();
+    }
+
+    fn assert_bulk_matches<T: Resource, K: PartialEq + std::fmt::Debug>(lump_index: usize, key: impl Fn(&T) -> K) {
+        let per_element: Vec<K> = read_per_element::<T>(lump_index).iter().map(&key).collect();
+        let bulk: Vec<K> = read_bulk::<T>(lump_index).iter().map(&key).collect();
+        assert!(!bulk.is_empty(), "lump {} is empty", lump_index);
+        assert_eq!(per_element, bulk);
+    }
+
+    #[test]
+    fn bulk_lump_reads_match_the_per_element_reader() {
+        use bsp30::LumpType::*;
+        assert_bulk_matches(LumpNodes as usize, |node: &bsp30::Node| {
+            return (node.plane_index, node.child_index, node.lower, node.upper, node.first_face, node.last_face);
+        });
+        assert_bulk_matches(LumpLeaves as usize, |leaf: &bsp30::Leaf| {
+            return (leaf.content, leaf.vis_offset, leaf.lower, leaf.upper, leaf.first_mark_surface, leaf.mark_surface_count, leaf.ambient_levels);
+        });
+        assert_bulk_matches(LumpMarkSurfaces as usize, |mark_surface: &bsp30::MarkSurface| *mark_surface);
+        assert_bulk_matches(LumpFaces as usize, |face: &bsp30::Face| {
+            return (face.plane_index, face.plane_side, face.first_edge_index, face.edge_count, face.texture_info, face.styles, face.lightmap_offset);
+        });
+        assert_bulk_matches(LumpClipNodes as usize, |clip_node: &bsp30::ClipNode| (clip_node.plane_index, clip_node.child_index));
+        assert_bulk_matches(LumpSurfaceEdges as usize, |surface_edge: &bsp30::SurfaceEdge| *surface_edge);
+        assert_bulk_matches(LumpEdges as usize, |edge: &bsp30::Edge| edge.vertex_index);
+        assert_bulk_matches(LumpVertexes as usize, |vertex: &bsp30::Vertex| *vertex);
+        assert_bulk_matches(LumpPlanes as usize, |plane: &bsp30::Plane| (plane.normal, plane.dist, plane.r#type));
+        // Byte lumps match reading a byte at a time
+        for lump_index in [LumpEntities as usize, LumpLighting as usize] {
+            let lump: bsp30::Lump = micro_map_lump(lump_index);
+            let mut reader: BufReader<std::io::Cursor<&[u8]>> = BufReader::new(std::io::Cursor::new(crate::map::micro_map::MICRO_MAP));
+            let bulk: Vec<u8> = BSP::read_lump_bytes(&mut reader, &lump).unwrap();
+            reader.seek(SeekFrom::Start(lump.offset as u64)).unwrap();
+            let per_byte: Vec<u8> = (0..lump.length).map(|_| byteorder::ReadBytesExt::read_u8(&mut reader).unwrap()).collect();
+            assert_eq!(bulk, per_byte);
+        }
+    }
+
+}
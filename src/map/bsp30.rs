@@ -1,5 +1,8 @@
 use crate::resource::resource::{read_char_array, Resource};
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::{BufReader, Error, ErrorKind, Result};
 
 // ==== BSP FORMAT LAYOUT ====
@@ -50,6 +53,25 @@ pub enum LumpType {
     HeaderLumps = 15,
 }
 
+pub const LUMP_NAMES: [&str; LumpType::HeaderLumps as usize] = [
+    "entities",
+    "planes",
+    "textures",
+    "vertexes",
+    "visibility",
+    "nodes",
+    "texinfo",
+    "faces",
+    "lighting",
+    "clipnodes",
+    "leaves",
+    "marksurfaces",
+    "edges",
+    "surfedges",
+    "models",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum ContentType {
     ContentsEmpty = -1,
     ContentsSolid = -2,
@@ -77,7 +99,7 @@ pub enum PlaneType {
     PlaneAnyZ = 5,
 }
 
-#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum RenderMode {
     RenderModeNormal = 0,
     RenderModeColor = 1,
@@ -103,10 +125,42 @@ impl Resource for Lump {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BspVersion {
+    Quake = 29,
+    GoldSrc = 30,
+}
+
+impl BspVersion {
+
+    pub fn from_header(version: i32) -> Option<Self> {
+        return match version {
+            29 => Some(BspVersion::Quake),
+            30 => Some(BspVersion::GoldSrc),
+            _ => None,
+        };
+    }
+
+    /// Quake lightmaps are single channel luminance, GoldSrc lightmaps are RGB
+    pub fn lightmap_channels(&self) -> usize {
+        return match self {
+            BspVersion::Quake => 1,
+            BspVersion::GoldSrc => 3,
+        };
+    }
+
+    /// GoldSrc textures are followed by their own palette, Quake textures
+    /// index into the fixed Quake palette
+    pub fn has_embedded_palettes(&self) -> bool {
+        return *self == BspVersion::GoldSrc;
+    }
+
+}
+
 #[derive(Debug, Default)]
 pub struct Header {
     pub version: i32,
-    pub lump: [Lump; LumpType::HeaderLumps as usize + 1],
+    pub lump: [Lump; LumpType::HeaderLumps as usize],
 }
 
 impl Resource for Header {
@@ -114,8 +168,8 @@ impl Resource for Header {
 
     fn from_reader(reader: &mut BufReader<impl byteorder::ReadBytesExt>) -> Result<Self> {
         let version: i32 = reader.read_i32::<Self::T>()?;
-        let mut lump: Vec<Lump> = Vec::with_capacity(LumpType::HeaderLumps as usize + 1);
-        for _ in 0..(LumpType::HeaderLumps as usize + 1) {
+        let mut lump: Vec<Lump> = Vec::with_capacity(LumpType::HeaderLumps as usize);
+        for _ in 0..(LumpType::HeaderLumps as usize) {
             lump.push(Lump::from_reader(reader)?);
         }
         return Ok(Header {
@@ -220,7 +274,7 @@ impl Resource for MarkSurface {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct Plane {
     pub normal: glm::Vec3,
     pub dist: f32,
@@ -280,7 +334,7 @@ pub struct Face {
     pub first_edge_index: u32,
     pub edge_count: u16,
     pub texture_info: u16,
-    pub styles: [u8; 4], // 0: Lighting styles for the face, 1: Range from 0xFF (dark) to 0x00 (bright), 2: Additional model, 3: Additional model
+    pub styles: [u8; 4], // Light style of each of the face's lightmaps, 255 marks an unused slot
     pub lightmap_offset: u32,
 }
 
@@ -342,9 +396,69 @@ pub type MipTexOffset = i32;
 pub const MAX_TEXTURE_NAME: usize = 16;
 pub const MIP_LEVELS: usize = 4;
 
+/// Fixed size, NUL padded texture name as stored in BSP and WAD files.
+/// Compares and hashes ignoring ASCII case and anything after the first NUL.
+#[derive(Clone, Copy, Default)]
+pub struct TextureName(pub [u8; MAX_TEXTURE_NAME]);
+
+impl TextureName {
+
+    /// Name from a string, `None` when it does not fit in the 16 bytes
+    pub fn from_name(name: &str) -> Option<Self> {
+        let bytes: &[u8] = name.as_bytes();
+        if bytes.len() > MAX_TEXTURE_NAME {
+            return None;
+        }
+        let mut raw: [u8; MAX_TEXTURE_NAME] = [0; MAX_TEXTURE_NAME];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        return Some(TextureName(raw));
+    }
+
+    /// Bytes up to the first NUL, all 16 when there is none
+    pub fn as_bytes(&self) -> &[u8] {
+        let end: usize = self.0.iter().position(|c: &u8| *c == 0).unwrap_or(MAX_TEXTURE_NAME);
+        return &self.0[..end];
+    }
+
+    /// Name up to the first NUL, invalid UTF-8 replaced
+    pub fn as_str(&self) -> Cow<'_, str> {
+        return String::from_utf8_lossy(self.as_bytes());
+    }
+
+}
+
+impl PartialEq for TextureName {
+    fn eq(&self, other: &Self) -> bool {
+        return self.as_bytes().eq_ignore_ascii_case(other.as_bytes());
+    }
+}
+
+impl Eq for TextureName {}
+
+impl Hash for TextureName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for byte in self.as_bytes() {
+            state.write_u8(byte.to_ascii_uppercase());
+        }
+        state.write_usize(self.as_bytes().len());
+    }
+}
+
+impl fmt::Display for TextureName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(&self.as_str());
+    }
+}
+
+impl fmt::Debug for TextureName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "TextureName({:?})", self.as_str());
+    }
+}
+
 #[derive(Debug)]
 pub struct MipTex {
-    pub name: [u8; MAX_TEXTURE_NAME],
+    pub name: TextureName,
     pub width: u32,
     pub height: u32,
     pub offsets: [u32; MIP_LEVELS],
@@ -363,7 +477,7 @@ impl Resource for MipTex {
             offsets[i] = reader.read_u32::<Self::T>()?;
         }
         return Ok(MipTex {
-            name,
+            name: TextureName(name),
             width,
             height,
             offsets,
@@ -371,6 +485,7 @@ impl Resource for MipTex {
     }
 }
 
+#[derive(Copy, Clone)]
 pub struct TextureInfo {
     pub s: glm::Vec3,
     pub s_shift: f32,
@@ -498,3 +613,73 @@ impl Resource for ClipNode {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use std::collections::hash_map::DefaultHasher;
+    use std::io::{Cursor, Seek};
+
+    fn hash_of(name: &TextureName) -> u64 {
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        return hasher.finish();
+    }
+
+    #[test]
+    fn header_reads_exactly_the_lump_directory() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.write_i32::<LittleEndian>(30).unwrap();
+        for i in 0..LumpType::HeaderLumps as i32 {
+            bytes.write_i32::<LittleEndian>(1000 + i * 100).unwrap();
+            bytes.write_i32::<LittleEndian>(i * 8).unwrap();
+        }
+        // Data after the header must be left unread
+        bytes.extend_from_slice(&[0xAB; 16]);
+        let mut reader: BufReader<Cursor<Vec<u8>>> = BufReader::new(Cursor::new(bytes));
+        let header: Header = Header::from_reader(&mut reader).unwrap();
+        assert_eq!(header.version, 30);
+        for (i, lump) in header.lump.iter().enumerate() {
+            assert_eq!((lump.offset, lump.length), (1000 + i as i32 * 100, i as i32 * 8), "lump {}", LUMP_NAMES[i]);
+        }
+        assert_eq!(reader.stream_position().unwrap(), 4 + 15 * 8);
+    }
+
+    #[test]
+    fn full_length_names_have_no_terminator() {
+        let name: TextureName = TextureName(*b"+0~GENERIC65abcd");
+        assert_eq!(name.as_bytes().len(), MAX_TEXTURE_NAME);
+        assert_eq!(name.as_str(), "+0~GENERIC65abcd");
+        assert_eq!(Some(name), TextureName::from_name("+0~generic65ABCD"));
+        assert_eq!(TextureName::from_name("+0~GENERIC65abcde"), None);
+    }
+
+    #[test]
+    fn names_match_ignoring_case_and_padding() {
+        let mut padded: [u8; MAX_TEXTURE_NAME] = [0; MAX_TEXTURE_NAME];
+        padded[..12].copy_from_slice(b"+0~GENERIC65");
+        // Garbage after the terminator is ignored
+        padded[13..].copy_from_slice(b"xyz");
+        let padded: TextureName = TextureName(padded);
+        let lower: TextureName = TextureName::from_name("+0~generic65").unwrap();
+        assert_eq!(padded, lower);
+        assert_eq!(hash_of(&padded), hash_of(&lower));
+        assert_eq!(padded.to_string(), "+0~GENERIC65");
+        assert_ne!(lower, TextureName::from_name("+0~generic6").unwrap());
+    }
+
+    #[test]
+    fn non_ascii_bytes_compare_exactly() {
+        let mut raw: [u8; MAX_TEXTURE_NAME] = [0; MAX_TEXTURE_NAME];
+        raw[..4].copy_from_slice(&[b'w', 0xE9, b'l', b'l']);
+        let name: TextureName = TextureName(raw);
+        assert_eq!(name.as_bytes(), &[b'w', 0xE9, b'l', b'l']);
+        assert_eq!(name.as_str(), "w\u{FFFD}ll");
+        raw[0] = b'W';
+        assert_eq!(name, TextureName(raw));
+        raw[1] = 0xC9;
+        assert_ne!(name, TextureName(raw));
+    }
+
+}
@@ -1,32 +1,195 @@
 use bit_set::BitSet;
 use glium::texture::{SrgbCubemap, SrgbTexture2d};
-use glium::vertex::VertexBuffer;
+use glium::index::{IndexBuffer, PrimitiveType};
+use glium::vertex::{VertexBuffer, VertexBufferSlice};
 use std::boxed::Box;
+use std::collections::VecDeque;
 use std::io::{Error, ErrorKind, Result};
-use num::FromPrimitive;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use crate::map::bsp::{Decal, FaceTexCoords, BSP};
+use crate::map::bsp::{Decal, FaceLightmaps, FaceTexCoords, Model, TextureClass, BSP, MAX_LIGHT_STYLES};
 use crate::map::bsp30;
+use crate::map::load_options::LoadIssues;
 use crate::map::wad::MipmapTexture;
-use crate::rendering::renderable::{RenderSettings, Renderable};
-use crate::rendering::renderer::{EntityData, FaceRenderInfo, Renderer, Vertex, VertexWithLM};
+use crate::rendering::renderable::{LightstyleMode, PassMask, RenderSettings, Renderable};
+use crate::rendering::renderer::{EntityData, FaceRenderInfo, Renderer, RendererCapabilities, TextureHandle, Vertex, VertexWithLM};
 use crate::rendering::view::camera::Camera;
+use crate::resource::block_compression::BlockFormat;
 use crate::resource::image::Image;
+use crate::util::memory::{MemoryReport, vec_bytes};
 use crate::scene::entity::Entity;
+use crate::scene::events::{EventBus, UseMessage, UseType};
+use crate::scene::lights::{PointLight, MAX_POINT_LIGHTS};
+
+pub const MAX_FACE_EDGES: usize = 64;
+// Grid size of the map editors, the spacing of the axis ticks
+const GRID_SIZE: f32 = 64.0;
+// Opacity of the world's water when worldspawn sets no renderamt
+const DEFAULT_WATER_ALPHA: f32 = 0.5;
+// Wave height in world units when an entity sets no WaveHeight
+const DEFAULT_WAVE_HEIGHT: f32 = 2.0;
+
+// Screen ripple and field of view narrowing while the camera is in a liquid
+const UNDERWATER_WARP: f32 = 0.004;
+const UNDERWATER_FOV_SCALE: f32 = 0.94;
+
+/// Colour the view is tinted by inside a liquid, its alpha the strength of
+/// the tint. Currents are water flowing in a direction.
+fn liquid_tint(contents: bsp30::ContentType) -> Option<[f32; 4]> {
+    return match contents {
+        bsp30::ContentType::ContentsWater
+        | bsp30::ContentType::ContentsCurrent0
+        | bsp30::ContentType::ContentsCurrent90
+        | bsp30::ContentType::ContentsCurrent180
+        | bsp30::ContentType::ContentsCurrent270
+        | bsp30::ContentType::ContentsCurrentUp
+        | bsp30::ContentType::ContentsCurrentDown => Some([0.1, 0.3, 0.5, 0.35]),
+        bsp30::ContentType::ContentsSlime => Some([0.05, 0.4, 0.05, 0.5]),
+        bsp30::ContentType::ContentsLava => Some([1.0, 0.35, 0.0, 0.6]),
+        _ => None,
+    };
+}
+
+/// Render amount of an entity as an opacity, `None` when it has none or it does not parse
+fn entity_render_amount(entity: &Entity) -> Option<f32> {
+    return entity.find_property(&"renderamt".to_string())
+        .and_then(|amount: &String| amount.trim().parse::<f32>().ok())
+        .map(|amount: f32| (amount / 255.0).clamp(0.0, 1.0));
+}
+
+/// Render mode of an entity, Normal when it has none or it is not a known mode
+fn entity_render_mode(entity: &Entity) -> bsp30::RenderMode {
+    return entity.find_property(&"rendermode".to_string())
+        .and_then(|render_mode: &String| render_mode.trim().parse::<u64>().ok())
+        .map_or(Some(bsp30::RenderMode::RenderModeNormal), num::FromPrimitive::from_u64)
+        .unwrap_or(bsp30::RenderMode::RenderModeNormal);
+}
+
+/// Height of the waves on an entity's water surfaces, from its `WaveHeight`
+fn entity_wave_height(entity: &Entity) -> f32 {
+    return entity.find_property(&"WaveHeight".to_string())
+        .and_then(|height: &String| height.trim().parse::<f32>().ok())
+        .unwrap_or(DEFAULT_WAVE_HEIGHT);
+}
+pub const LIGHTMAP_ATLAS_SIZE: u32 = 1024; // Smallest atlas, grown in powers of two for larger maps
+pub const LIGHTMAP_GUTTER: usize = 1; // Texels of repeated edge around each lightmap, stops filtering bleeding between faces
+// Smallest change in a light style's value that recomposes the faces it lights
+const LIGHTSTYLE_THRESHOLD: f32 = 1.0 / 255.0;
+// Lightmap texels recomposed per frame when compositing on the CPU, the rest wait for later frames
+const LIGHTSTYLE_TEXEL_BUDGET: usize = 32 * 1024;
+const DEGENERATE_AREA_EPSILON: f32 = 1.0e-4;
+const BOX_EDGE_VERTICES: usize = 24; // 12 edges of a box as a line list
+
+/// Lookup table mapping lightmap texels through a gamma curve and an
+/// overbright scale. A gamma and scale of 1.0 map every texel to itself.
+pub fn lightmap_exposure_lut(gamma: f32, scale: f32) -> [u8; 256] {
+    let exponent: f32 = 1.0 / gamma.max(0.01);
+    let mut lut: [u8; 256] = [0; 256];
+    for (texel, value) in lut.iter_mut().enumerate() {
+        let exposed: f32 = (texel as f32 / 255.0).powf(exponent) * scale.max(0.0) * 255.0;
+        *value = exposed.round().clamp(0.0, 255.0) as u8;
+    }
+    return lut;
+}
+
+/// Region of the lightmap atlas changed since it was last uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize, // Row 0 at the top
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+
+    pub fn area(&self) -> usize {
+        return self.width * self.height;
+    }
+
+    /// Whether the rects overlap or share an edge or corner
+    pub fn touches(&self, other: &DirtyRect) -> bool {
+        return self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height;
+    }
+
+    /// Smallest rect covering both
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        let x: usize = self.x.min(other.x);
+        let y: usize = self.y.min(other.y);
+        return DirtyRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        };
+    }
+
+    /// Merge rects that touch into the rect covering them until none touch,
+    /// so neighbouring lightmaps in the atlas go up in a single upload
+    pub fn coalesce(rects: &[DirtyRect]) -> Vec<DirtyRect> {
+        let mut merged: Vec<DirtyRect> = Vec::with_capacity(rects.len());
+        for rect in rects.iter().filter(|rect: &&DirtyRect| rect.area() > 0) {
+            let mut rect: DirtyRect = *rect;
+            // A grown rect can reach ones it was apart from, check them again
+            while let Some(index) = merged.iter().position(|other: &DirtyRect| other.touches(&rect)) {
+                rect = rect.union(&merged.swap_remove(index));
+            }
+            merged.push(rect);
+        }
+        return merged;
+    }
+
+}
+
+/// What keeping the lightmaps in step with the light styles cost in the last frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LightstyleStats {
+    pub faces: usize, // Lightmaps recomposed
+    pub texels: usize,
+    pub uploads: usize, // Texture writes, one per coalesced dirty rect or one for the whole atlas
+    pub pending: usize, // Lightmaps left over the budget for the next frames
+    pub time: Duration, // Recomposing and uploading
+}
 
 pub struct TextureAtlas {
-    allocated: Vec<usize>,
+    allocated: Vec<usize>, // Skyline, height of the lowest free row in each column
+    used_area: usize, // Texels covered by stored images
+    gutter: usize, // Border around each stored image, filled by repeating its edge texels
     pub m_image: Image,
 }
 
 impl TextureAtlas {
-    pub fn new(width: usize, height: usize, channels: usize) -> Self {
+    pub fn new(width: usize, height: usize, channels: usize, gutter: usize) -> Self {
         return TextureAtlas {
-            allocated: Vec::new(),
+            allocated: vec![0; width],
+            used_area: 0,
+            gutter,
             m_image: Image::from((width, height, channels)),
         };
     }
 
+    /// Forget every stored image so the atlas can be packed again. The
+    /// pixel data is left as is and overwritten by later stores.
+    pub fn reset(&mut self) {
+        self.allocated.iter_mut().for_each(|column: &mut usize| *column = 0);
+        self.used_area = 0;
+    }
+
+    /// Fraction of the atlas covered by stored images
+    pub fn occupancy(&self) -> f32 {
+        let area: usize = self.m_image.width * self.m_image.height;
+        if area == 0 {
+            return 0.0;
+        }
+        return self.used_area as f32 / area as f32;
+    }
+
+    /// Copy an image into free space, returning the top left of its block.
+    /// The image itself starts `gutter` texels in from there.
     pub fn store(&mut self, image: &Image) -> Result<glm::UVec2> {
         if image.channels != self.m_image.channels {
             return Err(Error::new(
@@ -37,20 +200,51 @@ impl TextureAtlas {
                 ),
             ));
         }
-        let loc: Option<glm::UVec2> = self.alloc_lightmap(image.width, image.height);
+        let block_width: usize = image.width + self.gutter * 2;
+        let block_height: usize = image.height + self.gutter * 2;
+        let loc: Option<glm::UVec2> = self.alloc_lightmap(block_width, block_height);
         if loc.is_none() {
-            return Err(Error::new(ErrorKind::InvalidData, "Atlas is full"));
+            return Err(Error::new(ErrorKind::StorageFull, "Atlas is full"));
         }
         let coord: glm::UVec2 = loc.unwrap();
-        for y in 0..image.height {
-            let src: usize = (y * image.width) * image.channels;
-            let dst: usize =
-                ((coord.y as usize + y) * self.m_image.width + coord.x as usize) * image.channels;
-            for i in 0..(image.width * image.channels) {
-                self.m_image.data[dst + i] = image.data[src + i];
+        self.used_area += image.width * image.height;
+        self.write(coord, image)?;
+        return Ok(coord);
+    }
+
+    /// Overwrite the block of a stored image with new contents of the same size
+    pub fn write(&mut self, coord: glm::UVec2, image: &Image) -> Result<()> {
+        if image.width == 0 || image.height == 0 {
+            return Ok(());
+        }
+        let (x0, y0): (usize, usize) = (coord.x as usize, coord.y as usize);
+        if image.channels != self.m_image.channels
+            || x0 + image.width + self.gutter * 2 > self.m_image.width
+            || y0 + image.height + self.gutter * 2 > self.m_image.height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}x{}x{} image does not fit the atlas at ({}, {})", image.width, image.height, image.channels, x0, y0),
+            ));
+        }
+        let row_bytes: usize = image.width * image.channels;
+        for y in 0..image.height + self.gutter * 2 {
+            // Gutter rows repeat the nearest edge row of the image
+            let src_y: usize = y.saturating_sub(self.gutter).min(image.height - 1);
+            let dst_y: usize = y0 + y;
+            let Some(src_row) = image.data.get(src_y * row_bytes..(src_y + 1) * row_bytes) else {
+                return Err(Error::new(ErrorKind::InvalidData, "Image data is shorter than its dimensions"));
+            };
+            let dst: usize = (dst_y * self.m_image.width + x0 + self.gutter) * image.channels;
+            self.m_image.data[dst..dst + row_bytes].copy_from_slice(src_row);
+            // Gutter columns repeat the first and last texel of the row
+            let first: Vec<u8> = src_row[..image.channels].to_vec();
+            let last: Vec<u8> = src_row[row_bytes - image.channels..].to_vec();
+            for x in 0..self.gutter {
+                self.m_image.put_pixel(x0 + x, dst_y, &first)?;
+                self.m_image.put_pixel(x0 + self.gutter + image.width + x, dst_y, &last)?;
             }
         }
-        return Ok(coord);
+        return Ok(());
     }
 
     pub fn convert_coord(
@@ -59,7 +253,8 @@ impl TextureAtlas {
         stored_pos: glm::UVec2,
         coord: glm::Vec2,
     ) -> glm::Vec2 {
-        return (glm::vec2(stored_pos.x as f32, stored_pos.y as f32)
+        let gutter: f32 = self.gutter as f32;
+        return (glm::vec2(stored_pos.x as f32 + gutter, stored_pos.y as f32 + gutter)
             + glm::vec2(image.width as f32, image.height as f32).component_mul(&coord))
         .component_div(&glm::vec2(
             self.m_image.width as f32,
@@ -67,22 +262,27 @@ impl TextureAtlas {
         ));
     }
 
+    /// Skyline packing as in Quake's AllocBlock: place the image at the
+    /// column span whose highest allocated row is lowest, leftmost on ties
     fn alloc_lightmap(&mut self, lm_width: usize, lm_height: usize) -> Option<glm::UVec2> {
+        if lm_width > self.m_image.width {
+            return None;
+        }
         let mut pos: glm::UVec2 = glm::vec2(0u32, 0u32);
         let mut best: usize = self.m_image.height;
-        for i in 0..(self.m_image.width - lm_width) {
+        for i in 0..=(self.m_image.width - lm_width) {
             let mut best2: usize = 0;
-            let mut j_result: usize = 0;
+            let mut fits: bool = true;
             for j in 0..lm_width {
-                j_result = j;
                 if self.allocated[i + j] >= best {
+                    fits = false;
                     break;
                 }
                 if self.allocated[i + j] > best2 {
                     best2 = self.allocated[i + j];
                 }
             }
-            if j_result == lm_width {
+            if fits {
                 pos.x = i as u32;
                 best = best2;
                 pos.y = best as u32;
@@ -98,98 +298,486 @@ impl TextureAtlas {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct BuildOptions {
+    pub max_face_edges: usize,
+    pub compressed_textures: bool, // Upload world textures as BC1/BC3 when the renderer supports it
+    pub debug_dump_atlas: bool, // Write the packed lightmap atlas to lm_atlas.png
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        return Self {
+            max_face_edges: MAX_FACE_EDGES,
+            compressed_textures: false,
+            debug_dump_atlas: false,
+        };
+    }
+}
+
+/// Line list of the bounding box edges of every non-solid leaf
+struct LeafOutlines {
+    vertices: VertexBuffer<Vertex>,
+    first_vertex: Vec<Option<usize>>, // Per leaf, None for solid leaves
+}
+
 pub struct BSPRenderable {
-    m_renderer: Box<dyn Renderer>,
+    m_renderer: Rc<dyn Renderer>, // Shared with the map's replacement when it is reloaded
     m_bsp: Box<BSP>,
     m_camera: Box<Camera>,
     m_settings: RenderSettings,
     m_skybox_tex: Option<SrgbCubemap>,
-    m_textures: Vec<SrgbTexture2d>,
+    m_textures: Vec<TextureHandle>,
+    masked_textures: Vec<bool>, // Per texture, drawn with alpha test
+    point_lights: Vec<PointLight>, // From the map's light entities
     m_lightmap_atlas: SrgbTexture2d,
+    lightmap_atlas: TextureAtlas, // CPU copy of the atlas, recomposed when light styles change
+    applied_light_styles: [f32; MAX_LIGHT_STYLES], // Style values the atlas was composed with
+    lightmap_exposure: (f32, f32), // Gamma and scale set on the renderable, multiplied by the per frame settings
+    applied_exposure: (f32, f32), // Gamma and scale the atlas was composed with
+    pending_lightmaps: VecDeque<usize>, // Faces waiting to be recomposed with the applied styles, oldest first
+    lightmap_pending: Vec<bool>, // Per face, whether it is in pending_lightmaps
+    lightstyle_stats: LightstyleStats,
+    lm_positions: Vec<glm::UVec2>,
     m_static_geometry_vbo: VertexBuffer<VertexWithLM>,
+    m_static_geometry_ibo: IndexBuffer<u32>, // Triangle fans of each face, as triangle lists
     m_decal_vbo: VertexBuffer<Vertex>,
-    vertex_offsets: Vec<usize>,
+    first_indices: Vec<usize>, // Per face, into m_static_geometry_ibo
+    index_counts: Vec<usize>,
     faces_drawn: Vec<bool>,
+    leaf_outlines: Option<LeafOutlines>, // Built on first use
+    highlighted_entities: Vec<usize>, // Indices into the map's entities, brush entities among them have their bounds outlined
+    events: EventBus, // Use messages between the map's entities
 }
 
 impl BSPRenderable {
-    pub fn new(renderer: Box<dyn Renderer>, bsp: Box<BSP>, camera: Box<Camera>) -> Result<Self> {
-        let m_skybox_tex: Option<SrgbCubemap> = bsp
-            .load_skybox()
-            .map(|images: [Image; 6]| renderer.create_cube_texture(images).unwrap()); //FIXME:
-                                                                                      //Handle this
-                                                                                      //result
-                                                                                      //properly
-        let m_textures: Vec<SrgbTexture2d> =
-            BSPRenderable::load_textures(&renderer, &bsp.m_textures);
-        let (lm_coords, m_lightmap_atlas): (Vec<Vec<glm::Vec2>>, SrgbTexture2d) =
+    pub fn new(renderer: Rc<dyn Renderer>, bsp: Box<BSP>, camera: Box<Camera>) -> Result<Self> {
+        return BSPRenderable::with_max_face_edges(renderer, bsp, camera, MAX_FACE_EDGES);
+    }
+
+    pub fn with_max_face_edges(renderer: Rc<dyn Renderer>,
+                               bsp: Box<BSP>,
+                               camera: Box<Camera>,
+                               max_face_edges: usize) -> Result<Self> {
+        return BSPRenderable::with_options(renderer, bsp, camera, &BuildOptions {
+            max_face_edges,
+            ..BuildOptions::default()
+        });
+    }
+
+    pub fn with_options(renderer: Rc<dyn Renderer>,
+                        mut bsp: Box<BSP>,
+                        camera: Box<Camera>,
+                        options: &BuildOptions) -> Result<Self> {
+        let m_skybox_tex: Option<SrgbCubemap> = match bsp.load_skybox() {
+            Ok(images) => images.map(|images: [Image; 6]| renderer.create_cube_texture(images).unwrap()), //FIXME:
+                                                                                                           //Handle this
+                                                                                                           //result
+                                                                                                           //properly
+            Err(error) => {
+                error!(&crate::LOGGER, "{}, rendering without a skybox", error);
+                None
+            },
+        };
+        let m_textures: Vec<TextureHandle> =
+            BSPRenderable::load_textures(&renderer, &bsp.m_textures, options.compressed_textures);
+        let masked_textures: Vec<bool> = bsp.m_textures.iter().map(|texture: &MipmapTexture| texture.masked).collect();
+        let point_lights: Vec<PointLight> = PointLight::extract(&bsp.entities);
+        let unlit_styles: Vec<usize> = bsp.entities.iter()
+            .filter_map(PointLight::switchable_style)
+            .filter_map(|(style, lit): (usize, bool)| (!lit).then_some(style))
+            .collect();
+        for style in unlit_styles {
+            bsp.set_light_style(style, 0.0);
+        }
+        let events: EventBus = EventBus::from_entities(&bsp.entities);
+        let applied_light_styles: [f32; MAX_LIGHT_STYLES] = bsp.light_styles;
+        let lightmaps: Vec<Image> = bsp.m_lightmaps.iter()
+            .map(|lightmaps: &FaceLightmaps| lightmaps.composite(&applied_light_styles))
+            .collect();
+        let (lm_coords, lm_positions, lightmap_atlas, m_lightmap_atlas): (Vec<Vec<glm::Vec2>>, Vec<glm::UVec2>, TextureAtlas, SrgbTexture2d) =
             BSPRenderable::load_lightmaps(
-                &bsp.m_lightmaps,
+                &lightmaps,
                 bsp.faces.len(),
                 &bsp.face_tex_coords,
                 &renderer,
+                options.debug_dump_atlas,
             )?;
-        let (m_static_geometry_vbo, m_decal_vbo): (
+        let mut load_issues: LoadIssues = std::mem::take(&mut bsp.load_issues);
+        let (m_static_geometry_vbo, m_static_geometry_ibo, m_decal_vbo, first_indices, index_counts): (
             VertexBuffer<VertexWithLM>,
+            IndexBuffer<u32>,
             VertexBuffer<Vertex>,
+            Vec<usize>,
+            Vec<usize>,
         ) = BSPRenderable::build_buffers(
             &lm_coords,
             &renderer,
-            &bsp.faces,
-            &bsp.face_tex_coords,
-            &bsp.planes,
-            &bsp.surface_edges,
-            &bsp.vertices,
-            &bsp.edges,
-            &bsp.m_decals,
+            &bsp,
+            options.max_face_edges,
+            &mut load_issues,
         )?;
-        let faces_drawn: Vec<bool> = Vec::with_capacity(bsp.faces.len());
+        bsp.load_issues = load_issues;
+        let faces_drawn: Vec<bool> = vec![false; bsp.faces.len()];
+        let lightmap_pending: Vec<bool> = vec![false; bsp.faces.len()];
         return Ok(BSPRenderable {
-            m_renderer: renderer, // TODO: Change to Box<Rc<Renderer>> and create a new reference here
+            m_renderer: renderer,
             m_bsp: bsp,           // TODO: Same here with Box<Rc<BSP>>
             m_camera: camera,
-            m_settings: Box::new(RenderSettings::default()),
+            m_settings: RenderSettings::default(),
             m_skybox_tex,
             m_textures,
+            masked_textures,
+            point_lights,
             m_lightmap_atlas,
+            lightmap_atlas,
+            applied_light_styles,
+            lightmap_exposure: (1.0, 1.0),
+            applied_exposure: (1.0, 1.0),
+            pending_lightmaps: VecDeque::new(),
+            lightmap_pending,
+            lightstyle_stats: LightstyleStats::default(),
+            lm_positions,
             m_static_geometry_vbo,
+            m_static_geometry_ibo,
             m_decal_vbo,
-            vertex_offsets: Vec::new(),
+            first_indices,
+            index_counts,
             faces_drawn,
+            leaf_outlines: None,
+            highlighted_entities: Vec::new(),
+            events,
         });
     }
 
+    pub fn renderer(&self) -> &dyn Renderer {
+        return self.m_renderer.as_ref();
+    }
+
+    /// Swap in another map, building its textures, lightmap atlas and
+    /// buffers with the same renderer. Nothing changes when building fails,
+    /// otherwise the old map's GPU resources are released.
+    pub fn reload(&mut self, bsp: Box<BSP>, camera: Box<Camera>) -> Result<()> {
+        let mut reloaded: BSPRenderable = BSPRenderable::new(Rc::clone(&self.m_renderer), bsp, camera)?;
+        reloaded.lightmap_exposure = self.lightmap_exposure;
+        *self = reloaded;
+        return Ok(());
+    }
+
+    pub fn camera(&self) -> &Camera {
+        return &self.m_camera;
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        return &mut self.m_camera;
+    }
+
+    /// The map being drawn
+    pub fn bsp(&self) -> &BSP {
+        return &self.m_bsp;
+    }
+
+    pub fn entities(&self) -> &Vec<Entity> {
+        return &self.m_bsp.entities;
+    }
+
+    pub fn models(&self) -> &Vec<Model> {
+        return &self.m_bsp.models;
+    }
+
+    /// World space bounds of the brush model of an entity, None for point entities
+    pub fn entity_bounds(&self, entity_index: usize) -> Option<(glm::Vec3, glm::Vec3)> {
+        let model: &bsp30::Model = &self.m_bsp.models.get(BSP::brush_model_index(self.m_bsp.entities.get(entity_index)?)?)?.model;
+        return Some((model.origin + model.lower, model.origin + model.upper));
+    }
+
+    pub fn is_entity_highlighted(&self, entity_index: usize) -> bool {
+        return self.highlighted_entities.contains(&entity_index);
+    }
+
+    /// Outline the bounds of a brush entity every frame, drawn through walls
+    pub fn set_entity_highlighted(&mut self, entity_index: usize, highlighted: bool) {
+        self.highlighted_entities.retain(|index: &usize| *index != entity_index);
+        if highlighted {
+            self.highlighted_entities.push(entity_index);
+        }
+    }
+
+    /// Leaf the camera is in, None when it is outside the tree
+    pub fn camera_leaf(&self) -> Option<usize> {
+        return self.m_bsp.find_leaf(self.m_camera.position(), 0);
+    }
+
+    /// Contents of a leaf, None for an unknown leaf or contents value
+    pub fn leaf_contents(&self, leaf: usize) -> Option<bsp30::ContentType> {
+        return self.m_bsp.leaves.get(leaf)
+            .and_then(|leaf: &bsp30::Leaf| num::FromPrimitive::from_i32(leaf.content));
+    }
+
+    /// Number of leaves in a leaf's potentially visible set. Every leaf but
+    /// the shared solid one is counted when there is no visibility data for it,
+    /// matching what gets drawn.
+    pub fn visible_leaf_count(&self, leaf: Option<usize>) -> usize {
+        let all_leaves: usize = self.m_bsp.leaves.len().saturating_sub(1);
+        return match leaf {
+            Some(leaf) if leaf > 0 && leaf <= self.m_bsp.vis_lists.len() => {
+                let vis_list: &BitSet<u8> = &self.m_bsp.vis_lists[leaf - 1];
+                if vis_list.is_empty() { all_leaves } else { vis_list.len() }
+            },
+            _ => all_leaves,
+        };
+    }
+
+    /// Heap and GPU memory of the map and its render resources by category
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report: MemoryReport = self.m_bsp.memory_report();
+        report.add("vertex buffers", self.m_static_geometry_vbo.get_size() + self.m_decal_vbo.get_size());
+        report.add("index buffers", self.m_static_geometry_ibo.get_size());
+        report.add("gpu textures", self.m_textures.iter()
+            .map(|texture: &TextureHandle| texture.estimated_bytes())
+            .sum());
+        let atlas_size: usize = self.m_lightmap_atlas.width() as usize * self.m_lightmap_atlas.height() as usize * 4;
+        report.add("gpu lightmaps", atlas_size);
+        report.add("lightmap atlas", vec_bytes(&self.lightmap_atlas.m_image.data));
+        report.add("render state", vec_bytes(&self.first_indices)
+            + vec_bytes(&self.index_counts)
+            + vec_bytes(&self.lm_positions)
+            + vec_bytes(&self.faces_drawn));
+        return report;
+    }
+
+    /// Lightmap texel and atlas pixel lighting a world point on a face, logging
+    /// both for diagnosing lightmap seams
+    pub fn probe_lightmap(&self, face_index: usize, point: glm::Vec3) -> Option<(glm::Vec2, glm::Vec2)> {
+        let (width, height): (usize, usize) = self.m_bsp.m_lightmaps.get(face_index)?.dimensions()?;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let (u, v): (f32, f32) = self.m_bsp.world_to_lightmap_texel(face_index, point);
+        let position: glm::UVec2 = self.lm_positions[face_index];
+        let gutter: f32 = LIGHTMAP_GUTTER as f32;
+        let atlas: glm::Vec2 = glm::vec2(position.x as f32 + gutter + u, position.y as f32 + gutter + v);
+        info!(
+            &crate::LOGGER,
+            "Face {} lightmap texel ({:.2}, {:.2}) of {}x{}, atlas position ({:.2}, {:.2})",
+            face_index, u, v, width, height, atlas.x, atlas.y,
+        );
+        return Some((glm::vec2(u, v), atlas));
+    }
+
     fn load_textures(
-        renderer: &Box<dyn Renderer>,
+        renderer: &Rc<dyn Renderer>,
         bsp_m_textures: &Vec<MipmapTexture>,
-    ) -> Vec<SrgbTexture2d> {
-        let mut m_textures: Vec<SrgbTexture2d> = Vec::with_capacity(bsp_m_textures.len());
+        compressed: bool,
+    ) -> Vec<TextureHandle> {
+        let compressed: bool = compressed && renderer.capabilities().compressed_textures;
+        let mut m_textures: Vec<TextureHandle> = Vec::with_capacity(bsp_m_textures.len());
         for mip_tex in bsp_m_textures {
-            m_textures.push(
-                renderer
-                    .create_texture(&vec![&mip_tex.img[0], &mip_tex.img[4]])
-                    .unwrap(),
-            ); // FIXME: Handle this result type properly
+            let mipmaps: Vec<&Image> = mip_tex.img.iter().collect();
+            if compressed {
+                let format: BlockFormat = BlockFormat::for_image(&mip_tex.img[0]);
+                match renderer.create_compressed_texture(&mipmaps, format) {
+                    Ok(texture) => {
+                        m_textures.push(TextureHandle::Compressed(texture, format));
+                        continue;
+                    },
+                    Err(error) => warn!(&crate::LOGGER, "Falling back to uncompressed texture: {}", error),
+                }
+            }
+            m_textures.push(TextureHandle::Uncompressed(
+                renderer.create_texture(&mipmaps).unwrap(),
+            )); // FIXME: Handle this result type properly
         }
+        let texture_bytes: usize = m_textures.iter()
+            .map(|texture: &TextureHandle| texture.estimated_bytes())
+            .sum();
+        info!(&crate::LOGGER, "Uploaded {} textures, approximately {} KiB", m_textures.len(), texture_bytes / 1024);
         return m_textures;
     }
 
+    /// Set the brightness of a light style in the map, the lightmaps are
+    /// recomposed on the next frame
+    pub fn set_light_style(&mut self, style: usize, value: f32) {
+        self.m_bsp.set_light_style(style, value);
+    }
+
+    /// Advance the map's entity events by `elapsed` seconds and switch the
+    /// named lights they used
+    pub fn update_events(&mut self, elapsed: f32) {
+        self.events.tick(elapsed);
+        for (entity_index, use_type) in self.events.take_uses() {
+            let Some((style, _)) = self.m_bsp.entities.get(entity_index).and_then(PointLight::switchable_style) else {
+                continue;
+            };
+            let lit: bool = match use_type {
+                UseType::Toggle => self.m_bsp.light_styles[style] <= 0.0,
+                UseType::On => true,
+                UseType::Off => false,
+            };
+            debug!(&crate::LOGGER, "Switching light style {} {}", style, if lit { "on" } else { "off" });
+            self.set_light_style(style, if lit { 1.0 } else { 0.0 });
+        }
+    }
+
+    /// Use messages waiting for their delay
+    pub fn pending_events(&self) -> usize {
+        return self.events.pending();
+    }
+
+    /// Toggle every entity named `target_name`, as a trigger firing it would.
+    /// Delivered on the next update.
+    pub fn fire_target(&mut self, target_name: &str, activator: Option<usize>) {
+        self.events.send(UseMessage {
+            activator,
+            target_name: target_name.to_string(),
+            use_type: UseType::Toggle,
+            delay: 0.0,
+        });
+    }
+
+    /// Set the lightmap gamma and overbright scale and rebuild the atlas.
+    /// Both are multiplied by the per frame `lightmap_gamma` and `lightmap_scale`.
+    pub fn set_lightmap_exposure(&mut self, gamma: f32, scale: f32) {
+        self.lightmap_exposure = (gamma, scale);
+        let settings: RenderSettings = self.m_settings.clone();
+        self.update_lightmaps(&settings);
+    }
+
+    /// The map's light style values scaled by the per frame ones
+    fn light_style_values(&self, settings: &RenderSettings) -> [f32; MAX_LIGHT_STYLES] {
+        let mut styles: [f32; MAX_LIGHT_STYLES] = self.m_bsp.light_styles;
+        for (style, value) in styles.iter_mut().zip(settings.lightstyle_values.iter()) {
+            *style *= value.max(0.0);
+        }
+        return styles;
+    }
+
+    /// What the last frame spent keeping the lightmaps in step with the light styles
+    pub fn lightstyle_stats(&self) -> LightstyleStats {
+        return self.lightstyle_stats;
+    }
+
+    /// Queue the lightmaps of faces lit by styles whose value changed by more
+    /// than the threshold, or every lightmap when the exposure changed, then
+    /// recompose queued lightmaps into the atlas and upload them. The map's
+    /// style values and exposure are scaled by the per frame ones.
+    fn update_lightmaps(&mut self, settings: &RenderSettings) {
+        let start: Instant = Instant::now();
+        let mode: LightstyleMode = settings.lightstyle_mode;
+        let styles: [f32; MAX_LIGHT_STYLES] = match mode {
+            LightstyleMode::Off => self.applied_light_styles,
+            LightstyleMode::Gpu | LightstyleMode::Cpu => self.light_style_values(settings),
+        };
+        let exposure: (f32, f32) = (
+            self.lightmap_exposure.0 * settings.lightmap_gamma,
+            self.lightmap_exposure.1 * settings.lightmap_scale,
+        );
+        let exposure_changed: bool = exposure != self.applied_exposure;
+        let changed: Vec<usize> = (0..MAX_LIGHT_STYLES)
+            .filter(|style: &usize| (styles[*style] - self.applied_light_styles[*style]).abs() > LIGHTSTYLE_THRESHOLD)
+            .collect();
+        for style in changed.iter() {
+            self.applied_light_styles[*style] = styles[*style];
+        }
+        self.applied_exposure = exposure;
+        for (face_index, lightmaps) in self.m_bsp.m_lightmaps.iter().enumerate() {
+            if lightmaps.is_empty() || self.lightmap_pending[face_index] {
+                continue;
+            }
+            if exposure_changed || changed.iter().any(|style: &usize| lightmaps.uses_style(*style)) {
+                self.lightmap_pending[face_index] = true;
+                self.pending_lightmaps.push_back(face_index);
+            }
+        }
+        let mut stats: LightstyleStats = LightstyleStats::default();
+        if !self.pending_lightmaps.is_empty() {
+            let budget: usize = match mode {
+                LightstyleMode::Cpu => LIGHTSTYLE_TEXEL_BUDGET,
+                LightstyleMode::Gpu | LightstyleMode::Off => usize::MAX,
+            };
+            let dirty: Vec<DirtyRect> = self.recompose_pending(budget, &mut stats);
+            stats.uploads = self.upload_lightmaps(mode, &dirty);
+        }
+        stats.pending = self.pending_lightmaps.len();
+        stats.time = start.elapsed();
+        self.lightstyle_stats = stats;
+    }
+
+    /// Recompose queued lightmaps until `budget` texels have been written,
+    /// always at least one, and return the atlas blocks written
+    fn recompose_pending(&mut self, budget: usize, stats: &mut LightstyleStats) -> Vec<DirtyRect> {
+        let lut: [u8; 256] = lightmap_exposure_lut(self.applied_exposure.0, self.applied_exposure.1);
+        let mut dirty: Vec<DirtyRect> = Vec::new();
+        while let Some(face_index) = self.pending_lightmaps.front().copied() {
+            let lightmaps: &FaceLightmaps = &self.m_bsp.m_lightmaps[face_index];
+            let (width, height): (usize, usize) = lightmaps.dimensions().unwrap_or((0, 0));
+            if stats.texels > 0 && stats.texels + width * height > budget {
+                break;
+            }
+            self.pending_lightmaps.pop_front();
+            self.lightmap_pending[face_index] = false;
+            let mut image: Image = lightmaps.composite(&self.applied_light_styles);
+            image.data.iter_mut().for_each(|texel: &mut u8| *texel = lut[*texel as usize]);
+            let position: glm::UVec2 = self.lm_positions[face_index];
+            if let Err(error) = self.lightmap_atlas.write(position, &image) {
+                warn!(&crate::LOGGER, "Unable to recompose the lightmap of face {}: {}", face_index, error);
+                continue;
+            }
+            dirty.push(DirtyRect {
+                x: position.x as usize,
+                y: position.y as usize,
+                width: width + LIGHTMAP_GUTTER * 2,
+                height: height + LIGHTMAP_GUTTER * 2,
+            });
+            stats.faces += 1;
+            stats.texels += width * height;
+        }
+        return dirty;
+    }
+
+    /// Send recomposed atlas blocks to the GPU, as coalesced regions when
+    /// compositing on the CPU and as the whole atlas otherwise. Returns the
+    /// number of uploads made.
+    fn upload_lightmaps(&mut self, mode: LightstyleMode, dirty: &[DirtyRect]) -> usize {
+        if dirty.is_empty() {
+            return 0;
+        }
+        if mode != LightstyleMode::Cpu {
+            match self.m_renderer.create_texture(&vec![&self.lightmap_atlas.m_image]) {
+                Ok(texture) => self.m_lightmap_atlas = texture,
+                Err(error) => error!(&crate::LOGGER, "Unable to upload recomposed lightmaps: {}", error),
+            };
+            return 1;
+        }
+        let rects: Vec<DirtyRect> = DirtyRect::coalesce(dirty);
+        for rect in rects.iter() {
+            let region: Result<()> = self.lightmap_atlas.m_image.sub_image(rect.x, rect.y, rect.width, rect.height)
+                .and_then(|region: Image| self.m_renderer.update_texture(&self.m_lightmap_atlas, rect.x, rect.y, &region));
+            if let Err(error) = region {
+                error!(&crate::LOGGER, "Unable to upload recomposed lightmaps at ({}, {}): {}", rect.x, rect.y, error);
+            }
+        }
+        return rects.len();
+    }
+
     fn load_lightmaps(
         bsp_m_lightmaps: &Vec<Image>,
         bsp_faces_len: usize,
         bsp_face_tex_coords: &Vec<FaceTexCoords>,
-        renderer: &Box<dyn Renderer>,
-    ) -> Result<(Vec<Vec<glm::Vec2>>, SrgbTexture2d)> {
-        let mut atlas: TextureAtlas = TextureAtlas::new(1024, 1024, 3);
-        let mut lm_positions: Vec<glm::UVec2> = Vec::with_capacity(bsp_m_lightmaps.len());
-        for lm in bsp_m_lightmaps.iter() {
-            if lm.width == 0 || lm.height == 0 {
-                lm_positions.push(glm::vec2(0u32, 0u32));
-                continue;
+        renderer: &Rc<dyn Renderer>,
+        dump_atlas: bool,
+    ) -> Result<(Vec<Vec<glm::Vec2>>, Vec<glm::UVec2>, TextureAtlas, SrgbTexture2d)> {
+        let (atlas, lm_positions): (TextureAtlas, Vec<glm::UVec2>) = BSPRenderable::pack_lightmaps_to_fit(
+            bsp_m_lightmaps,
+            &renderer.capabilities(),
+        )?;
+        let atlas_size: usize = atlas.m_image.width;
+        info!(&crate::LOGGER, "Packed lightmaps into a {}x{} atlas, {:.1}% occupied", atlas_size, atlas_size, atlas.occupancy() * 100.0);
+        if dump_atlas {
+            if let Err(error) = atlas.m_image.save("lm_atlas.png".to_string()) {
+                warn!(&crate::LOGGER, "Failed to save lightmap atlas: {}", error);
             }
-            lm_positions.push(atlas.store(lm)?);
         }
-        atlas.m_image.save("lm_atlas.pmg".to_string());
         let mut lm_coords: Vec<Vec<glm::Vec2>> = Vec::with_capacity(bsp_faces_len);
         for i in 0..lm_coords.capacity() {
             let coords: &FaceTexCoords = &bsp_face_tex_coords[i];
@@ -203,23 +791,69 @@ impl BSPRenderable {
             lm_coords.push(sub_coords);
         }
         let m_lightmap_atlas: SrgbTexture2d = renderer.create_texture(&vec![&atlas.m_image])?;
-        return Ok((lm_coords, m_lightmap_atlas));
+        return Ok((lm_coords, lm_positions, atlas, m_lightmap_atlas));
+    }
+
+    /// Pack into the smallest power of two square that could hold every
+    /// lightmap, doubling whenever packing wastes too much to fit, up to the
+    /// largest texture the GPU takes
+    pub (crate) fn pack_lightmaps_to_fit(bsp_m_lightmaps: &Vec<Image>, capabilities: &RendererCapabilities) -> Result<(TextureAtlas, Vec<glm::UVec2>)> {
+        let total_area: usize = bsp_m_lightmaps.iter()
+            .map(|lm: &Image| (lm.width + LIGHTMAP_GUTTER * 2) * (lm.height + LIGHTMAP_GUTTER * 2))
+            .sum();
+        let max_size: u32 = capabilities.clamp_texture_size(u32::MAX);
+        let mut atlas_size: u32 = capabilities.clamp_texture_size(
+            ((total_area as f64).sqrt().ceil() as u32).next_power_of_two().max(LIGHTMAP_ATLAS_SIZE),
+        );
+        loop {
+            match BSPRenderable::pack_lightmaps(bsp_m_lightmaps, atlas_size as usize) {
+                Ok(packed) => return Ok(packed),
+                Err(error) if error.kind() == ErrorKind::StorageFull && atlas_size < max_size => {
+                    atlas_size = capabilities.clamp_texture_size(atlas_size.saturating_mul(2));
+                    debug!(&crate::LOGGER, "Lightmaps do not fit, growing atlas to {}x{}", atlas_size, atlas_size);
+                },
+                Err(error) => return Err(error),
+            };
+        }
+    }
+
+    pub (crate) fn pack_lightmaps(bsp_m_lightmaps: &Vec<Image>, atlas_size: usize) -> Result<(TextureAtlas, Vec<glm::UVec2>)> {
+        let mut atlas: TextureAtlas = TextureAtlas::new(atlas_size, atlas_size, 3, LIGHTMAP_GUTTER);
+        let mut lm_positions: Vec<glm::UVec2> = Vec::with_capacity(bsp_m_lightmaps.len());
+        for lm in bsp_m_lightmaps.iter() {
+            if lm.width == 0 || lm.height == 0 {
+                lm_positions.push(glm::vec2(0u32, 0u32));
+                continue;
+            }
+            lm_positions.push(atlas.store(lm)?);
+        }
+        return Ok((atlas, lm_positions));
     }
 
     fn render(
         &mut self,
         render_settings: &RenderSettings,
-        render_skybox: bool,
-        render_static_bsp: bool,
-        render_brush_entities: bool,
         render_leaf_outlines: bool,
         use_textures: bool,
     ) {
+        let camera_pos: glm::Vec3 = self.m_camera.position();
+        let camera_leaf: Option<usize> = self.m_bsp.find_leaf(camera_pos, 0);
+        let tint: Option<[f32; 4]> = camera_leaf
+            .and_then(|leaf: usize| self.leaf_contents(leaf))
+            .and_then(liquid_tint);
+        let mut submerged_settings: RenderSettings = render_settings.clone();
+        if tint.is_some() {
+            submerged_settings.projection = self.m_camera.projection_matrix_scaled(UNDERWATER_FOV_SCALE);
+        }
+        let render_settings: &RenderSettings = &submerged_settings;
         self.m_settings = render_settings.clone();
-        if self.m_skybox_tex.is_some() && render_skybox {
+        self.update_lightmaps(render_settings);
+        let render_static_bsp: bool = render_settings.draws(PassMask::WORLD);
+        let render_brush_entities: bool = render_settings.draws(PassMask::BRUSH_ENTITIES);
+        // The overdraw heatmap counts from a cleared frame
+        if self.m_skybox_tex.is_some() && render_settings.draws(PassMask::SKYBOX) && !render_settings.overdraw {
             self.render_skybox();
         }
-        let camera_pos: glm::Vec3 = self.m_camera.position();
         if render_static_bsp || render_brush_entities {
             self.faces_drawn = self
                 .faces_drawn
@@ -231,79 +865,227 @@ impl BSPRenderable {
         if render_static_bsp {
             // This take is black magic. Glorious stuff.
             let mut vis_list = std::mem::take(&mut self.m_bsp.vis_lists);
+            let (water, solid): (Vec<FaceRenderInfo>, Vec<FaceRenderInfo>) = self.render_static_geometry(
+                camera_pos.clone(),
+                camera_leaf,
+                &mut vis_list,
+            ).into_iter().partition(|face: &FaceRenderInfo| face.water);
+            self.m_bsp.vis_lists = vis_list;
+            let center: glm::Vec3 = self.m_bsp.models.first()
+                .map_or(glm::vec3(0.0, 0.0, 0.0), |world: &Model| (world.model.lower + world.model.upper) * 0.5);
+            let world_spawn: Option<&Entity> = BSP::find_entity(&self.m_bsp.entities, "worldspawn".to_string());
+            let wave_height: f32 = world_spawn.map_or(DEFAULT_WAVE_HEIGHT, entity_wave_height);
             entities.push(EntityData {
-                face_render_info: self.render_static_geometry(
-                    camera_pos.clone(),
-                    self.m_bsp.find_leaf(camera_pos, 0),
-                    &mut vis_list,
-                ),
+                face_render_info: solid,
+                model: None,
                 origin: glm::vec3(0.0, 0.0, 0.0),
+                center,
                 alpha: 1.0,
                 render_mode: bsp30::RenderMode::RenderModeNormal,
+                wave_height,
             });
-            self.m_bsp.vis_lists = vis_list;
+            // The world's water is drawn with the transparent entities
+            if !water.is_empty() {
+                entities.push(EntityData {
+                    face_render_info: water,
+                    model: None,
+                    origin: glm::vec3(0.0, 0.0, 0.0),
+                    center,
+                    alpha: world_spawn.and_then(entity_render_amount).unwrap_or(DEFAULT_WATER_ALPHA),
+                    render_mode: bsp30::RenderMode::RenderModeTexture,
+                    wave_height,
+                });
+            }
         }
         if render_brush_entities {
             for i in 0..self.m_bsp.brush_entities.len() {
                 let entity: &Entity = &self.m_bsp.entities[self.m_bsp.brush_entities[i]];
-                let model: isize = entity.find_property(&"model".to_string()).unwrap()[1..]
-                    .parse::<isize>()
-                    .unwrap();
-                let alpha: f32 =
-                    if let Some(render_amt) = entity.find_property(&"renderamt".to_string()) {
-                        render_amt.parse::<f32>().unwrap() / 255.0
-                    } else {
-                        1.0
-                    };
-                let render_mode: bsp30::RenderMode = if let Some(psz_render_mode) =
-                    entity.find_property(&"rendermode".to_string())
-                {
-                    num::FromPrimitive::from_u64(psz_render_mode.parse::<u64>().unwrap()).unwrap()
-                } else {
-                    bsp30::RenderMode::RenderModeNormal
+                let model: usize = match BSP::brush_model_index(entity) {
+                    Some(model) if model < self.m_bsp.models.len() => model,
+                    _ => continue,
                 };
+                let alpha: f32 = entity_render_amount(entity).unwrap_or(1.0);
+                let wave_height: f32 = entity_wave_height(entity);
+                let render_mode: bsp30::RenderMode = entity_render_mode(entity);
+                let conveyor_velocity: Option<glm::Vec3> = BSP::conveyor_velocity(entity);
                 let mut face_render_infos: Vec<FaceRenderInfo> = Vec::new();
                 self.render_bsp(
-                    self.m_bsp.models[model as usize].model.head_nodes_index[0] as isize,
+                    self.m_bsp.models[model].model.head_nodes_index[0] as isize,
                     &mut BitSet::<u8>::default(),
                     camera_pos.clone(),
                     use_textures,
+                    conveyor_velocity,
                     &mut face_render_infos,
                 );
+                let bounds: &bsp30::Model = &self.m_bsp.models[model].model;
                 entities.push(EntityData {
                     face_render_info: face_render_infos,
-                    origin: self.m_bsp.models[model as usize].model.origin.clone(),
+                    model: Some(model),
+                    origin: bounds.origin.clone(),
+                    center: bounds.origin + (bounds.lower + bounds.upper) * 0.5,
                     alpha,
                     render_mode,
+                    wave_height,
                 });
             }
         }
+        let no_decals: Vec<Decal> = Vec::new();
+        let lights: Vec<PointLight> = if render_settings.dynamic_lights {
+            PointLight::nearest(&self.point_lights, &camera_pos, MAX_POINT_LIGHTS, &self.light_style_values(render_settings))
+        } else {
+            Vec::new()
+        };
         self.m_renderer.render_static(
             &entities,
-            &self.m_bsp.m_decals,
+            if render_settings.draws(PassMask::DECALS) { &self.m_bsp.m_decals } else { &no_decals },
             &self.m_static_geometry_vbo,
+            &self.m_static_geometry_ibo,
             &self.m_decal_vbo,
             &self.m_textures,
+            &self.m_bsp.texture_animations,
             &self.m_lightmap_atlas,
+            &lights,
             render_settings,
         );
+        if let Some(tint) = tint {
+            self.m_renderer.render_fullscreen_effect(tint, UNDERWATER_WARP, render_settings.time);
+        }
+        if render_settings.show_axes {
+            self.m_renderer.render_coords(
+                &(render_settings.projection * render_settings.view),
+                render_settings.axes_length,
+                if render_settings.axes_ticks { Some(GRID_SIZE) } else { None },
+            );
+        }
         if render_leaf_outlines {
-            // TODO: Render outlines
+            self.render_leaf_outlines(camera_leaf);
+        }
+        if !self.highlighted_entities.is_empty() {
+            self.render_entity_outlines();
+        }
+    }
+
+    /// Bounds of highlighted brush entities, drawn through walls
+    fn render_entity_outlines(&self) {
+        const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+        let vertices: Vec<Vertex> = self.highlighted_entities.iter()
+            .filter_map(|entity_index: &usize| self.entity_bounds(*entity_index))
+            .flat_map(|(lower, upper): (glm::Vec3, glm::Vec3)| BSPRenderable::box_edges(&lower, &upper))
+            .map(|position: glm::Vec3| Vertex {
+                position: position.into(),
+                ..Vertex::default()
+            })
+            .collect();
+        if vertices.is_empty() {
+            return;
+        }
+        let vertices: VertexBuffer<Vertex> = match VertexBuffer::new(self.m_renderer.provide_facade(), &vertices[..]) {
+            Ok(buf) => buf,
+            Err(error) => {
+                warn!(&crate::LOGGER, "Cannot create entity outline buffer: {}", error);
+                return;
+            },
+        };
+        if let Some(lines) = vertices.slice(..) {
+            let matrix: glm::Mat4 = self.m_settings.projection * self.m_settings.view;
+            self.m_renderer.render_lines(lines, &matrix, HIGHLIGHT_COLOR, false);
+        }
+    }
+
+    fn render_leaf_outlines(&mut self, camera_leaf: Option<usize>) {
+        const OUTLINE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+        const CAMERA_LEAF_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+        if self.leaf_outlines.is_none() {
+            match self.build_leaf_outlines() {
+                Ok(outlines) => self.leaf_outlines = Some(outlines),
+                Err(error) => {
+                    warn!(&crate::LOGGER, "Unable to build leaf outlines: {}", error);
+                    return;
+                },
+            };
+        }
+        let Some(outlines) = self.leaf_outlines.as_ref() else {
+            return;
+        };
+        let matrix: glm::Mat4 = self.m_settings.projection * self.m_settings.view;
+        let depth_test: bool = self.m_settings.leaf_outlines_depth_test;
+        if let Some(lines) = outlines.vertices.slice(..) {
+            self.m_renderer.render_lines(lines, &matrix, OUTLINE_COLOR, depth_test);
+        }
+        let camera_lines: Option<VertexBufferSlice<Vertex>> = camera_leaf
+            .and_then(|leaf: usize| outlines.first_vertex.get(leaf).copied().flatten())
+            .and_then(|first: usize| outlines.vertices.slice(first..first + BOX_EDGE_VERTICES));
+        if let Some(lines) = camera_lines {
+            self.m_renderer.render_lines(lines, &matrix, CAMERA_LEAF_COLOR, depth_test);
+        }
+    }
+
+    fn build_leaf_outlines(&self) -> Result<LeafOutlines> {
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut first_vertex: Vec<Option<usize>> = Vec::with_capacity(self.m_bsp.leaves.len());
+        for leaf in self.m_bsp.leaves.iter() {
+            if leaf.content == bsp30::ContentType::ContentsSolid as i32 {
+                first_vertex.push(None);
+                continue;
+            }
+            first_vertex.push(Some(vertices.len()));
+            let lower: glm::Vec3 = glm::vec3(leaf.lower[0] as f32, leaf.lower[1] as f32, leaf.lower[2] as f32);
+            let upper: glm::Vec3 = glm::vec3(leaf.upper[0] as f32, leaf.upper[1] as f32, leaf.upper[2] as f32);
+            vertices.extend(BSPRenderable::box_edges(&lower, &upper).iter().map(|position: &glm::Vec3| Vertex {
+                position: (*position).into(),
+                ..Vertex::default()
+            }));
+        }
+        let vertices: VertexBuffer<Vertex> = match VertexBuffer::new(self.m_renderer.provide_facade(), &vertices[..]) {
+            Ok(buf) => buf,
+            Err(error) => return Err(Error::new(ErrorKind::InvalidData, format!("Cannot create leaf outline buffer: {}", error))),
+        };
+        debug!(&crate::LOGGER, "Built outlines of {} leaves", first_vertex.iter().flatten().count());
+        return Ok(LeafOutlines {
+            vertices,
+            first_vertex,
+        });
+    }
+
+    /// Line list of the 12 edges of an axis aligned box, joining each corner
+    /// to the corners one axis away
+    fn box_edges(lower: &glm::Vec3, upper: &glm::Vec3) -> [glm::Vec3; BOX_EDGE_VERTICES] {
+        let corner = |i: usize| glm::vec3(
+            if i & 1 == 0 { lower.x } else { upper.x },
+            if i & 2 == 0 { lower.y } else { upper.y },
+            if i & 4 == 0 { lower.z } else { upper.z },
+        );
+        let mut edges: [glm::Vec3; BOX_EDGE_VERTICES] = [glm::Vec3::zeros(); BOX_EDGE_VERTICES];
+        let mut count: usize = 0;
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    edges[count] = corner(i);
+                    edges[count + 1] = corner(i | axis);
+                    count += 2;
+                }
+            }
         }
+        return edges;
     }
 
+    /// The sky only turns with the camera. Pitch and yaw are applied as in
+    /// `Camera::view_matrix`, with the quarter turn about X last so the cube
+    /// is sampled with GoldSrc directions turned Y up as (x, z, -y).
     fn render_skybox(&self) {
         const DEG_90: f32 = 90.0f32;
         let matrix: glm::Mat4 = self.m_settings.projection
             * BSPRenderable::euler_angle_xzx(
-                (self.m_settings.pitch - 90.0).to_radians(),
-                (-self.m_settings.yaw).to_radians(),
+                (self.m_settings.pitch - DEG_90).to_radians(),
+                (DEG_90 - self.m_settings.yaw).to_radians(),
                 DEG_90.to_radians(),
             );
         self.m_renderer
-            .render_skybox(&self.m_skybox_tex.as_ref().unwrap(), &matrix);
+            .render_skybox(&self.m_skybox_tex.as_ref().unwrap(), &matrix, self.m_settings.fog.skybox_tint());
     }
 
+    /// Rotation about X by `t1`, then Z by `t2`, then X by `t3`, as
+    /// Rx(t1) * Rz(t2) * Rx(t3). `glm::mat4` takes its arguments row by row.
     #[inline(always)]
     fn euler_angle_xzx(t1: f32, t2: f32, t3: f32) -> glm::Mat4 {
         let c1: f32 = t1.cos();
@@ -314,15 +1096,15 @@ impl BSPRenderable {
         let s3: f32 = t3.sin();
         return glm::mat4(
             c2,
-            c1 * s2,
-            s1 * s2,
-            0.0,
             -c3 * s2,
-            c1 * c2 * c3 - s1 * s3,
-            c1 * s3 + c2 * c3 * s1,
-            0.0,
             s2 * s3,
+            0.0,
+            c1 * s2,
+            c1 * c2 * c3 - s1 * s3,
             -c3 * s1 - c1 * c2 * s3,
+            0.0,
+            s1 * s2,
+            c1 * s3 + c2 * c3 * s1,
             c1 * c3 - c2 * s1 * s3,
             0.0,
             0.0,
@@ -335,21 +1117,22 @@ impl BSPRenderable {
     fn render_static_geometry(
         &mut self,
         pos: glm::Vec3,
-        leaf: Option<i16>,
+        leaf: Option<usize>,
         bsp_vis_lists: &mut Vec<BitSet<u8>>,
     ) -> Vec<FaceRenderInfo> {
         let mut face_render_infos: Vec<FaceRenderInfo> = Vec::new();
         let mut bit_set: BitSet<u8> = BitSet::<u8>::default();
-        let mut vis_list: &mut BitSet<u8> = if leaf.is_none() || bsp_vis_lists.is_empty() {
-            &mut bit_set
-        } else {
-            &mut bsp_vis_lists[leaf.unwrap() as usize - 1]
+        // Leaf 0 is the shared solid leaf and has no vis list
+        let mut vis_list: &mut BitSet<u8> = match leaf {
+            Some(leaf) if leaf > 0 && leaf <= bsp_vis_lists.len() => &mut bsp_vis_lists[leaf - 1],
+            _ => &mut bit_set,
         };
         self.render_bsp(
             0,
             &mut vis_list,
             pos,
             true, // TODO: Make this into a method parameter
+            None,
             &mut face_render_infos,
         );
         return face_render_infos;
@@ -359,6 +1142,7 @@ impl BSPRenderable {
         &mut self,
         leaf_index: isize,
         use_textures: bool,
+        conveyor_velocity: Option<glm::Vec3>,
         face_render_infos: &mut Vec<FaceRenderInfo>,
         bsp_leaves: &Vec<bsp30::Leaf>,
         bsp_mark_surfaces: &Vec<bsp30::MarkSurface>,
@@ -378,16 +1162,47 @@ impl BSPRenderable {
             if face.styles[0] == 0xFF {
                 continue;
             }
+            if self.index_counts[face_index] == 0 {
+                // Degenerate face skipped while building buffers
+                continue;
+            }
+            let texture_class: TextureClass = self.m_bsp.texture_classes
+                .get(bsp_texture_infos[face.texture_info as usize].mip_tex_index as usize)
+                .copied()
+                .unwrap_or_default();
+            if texture_class.is_hidden() && !self.m_settings.show_tool_textures {
+                continue;
+            }
             let lightmap_available: bool = (face.lightmap_offset as isize) != -1
                 && bsp_header.lump[bsp30::LumpType::LumpLighting as usize].length > 0;
+            let mut mip_tex_index: usize = bsp_texture_infos[face.texture_info as usize].mip_tex_index as usize;
+            if !self.m_settings.disable_random_tiling {
+                if let Some(tiling) = self.m_bsp.texture_tiling(mip_tex_index) {
+                    let first_vertex: glm::Vec3 = self.m_bsp.surface_edge_vertex(self.m_bsp.surface_edges[face.first_edge_index as usize]);
+                    mip_tex_index = self.m_bsp.texture_tilings[tiling].variant_for(&first_vertex);
+                }
+            }
             let face_render_info: FaceRenderInfo = FaceRenderInfo {
                 tex: if use_textures {
-                    Some(bsp_texture_infos[face.texture_info as usize].mip_tex_index as usize)
+                    Some(mip_tex_index)
+                } else {
+                    None
+                },
+                animation: if use_textures {
+                    self.m_bsp.texture_animation(mip_tex_index)
                 } else {
                     None
                 },
-                offset: self.vertex_offsets[face_index],
-                count: (face.edge_count as usize - 2) * 3,
+                offset: self.first_indices[face_index],
+                count: self.index_counts[face_index],
+                depth_only: texture_class == TextureClass::Sky && !self.m_settings.show_tool_textures,
+                uv_scroll: conveyor_velocity.and_then(|velocity: glm::Vec3| self.m_bsp.texture_uv_scroll(
+                    &bsp_texture_infos[face.texture_info as usize],
+                    &velocity,
+                    self.m_settings.time,
+                )),
+                masked: use_textures && self.masked_textures.get(mip_tex_index).copied().unwrap_or(false),
+                water: texture_class == TextureClass::Water,
             };
             face_render_infos.push(face_render_info);
         }
@@ -399,6 +1214,7 @@ impl BSPRenderable {
         vis_list: &mut BitSet<u8>,
         pos: glm::Vec3,
         use_textures: bool,
+        conveyor_velocity: Option<glm::Vec3>,
         face_render_infos: &mut Vec<FaceRenderInfo>,
     ) {
         if node == -1 {
@@ -407,7 +1223,8 @@ impl BSPRenderable {
         if node < 0 {
             let leaf: isize = !node;
 
-            if vis_list.is_empty() && !vis_list.get_ref()[leaf as usize - 1] {
+            // An empty vis list means no visibility data, so every leaf is drawn
+            if !vis_list.is_empty() && !vis_list.contains(leaf as usize - 1) {
                 return;
             }
             // TODO: Create a macro that takes a sequence of fields to take and
@@ -423,6 +1240,7 @@ impl BSPRenderable {
             self.render_leaf(
                 leaf,
                 use_textures,
+                conveyor_velocity,
                 face_render_infos,
                 &leaves,
                 &mark_surfaces,
@@ -452,6 +1270,7 @@ impl BSPRenderable {
             vis_list,
             pos,
             use_textures,
+            conveyor_velocity,
             face_render_infos,
         );
         self.render_bsp(
@@ -459,31 +1278,47 @@ impl BSPRenderable {
             vis_list,
             pos,
             use_textures,
+            conveyor_velocity,
             face_render_infos,
         );
     }
 
-    fn build_buffers(
+    /// Vertices of every face once each, with triangle list indices fanning
+    /// across them. Returns the vertices, the indices, and each face's first
+    /// index and index count, zero for skipped faces.
+    fn static_geometry(
         lm_coords: &Vec<Vec<glm::Vec2>>,
-        renderer: &Box<dyn Renderer>,
-        bsp_faces: &Vec<bsp30::Face>,
-        bsp_face_tex_coords: &Vec<FaceTexCoords>,
-        bsp_planes: &Vec<bsp30::Plane>,
-        bsp_surface_edges: &Vec<bsp30::SurfaceEdge>,
-        bsp_vertices: &Vec<bsp30::Vertex>,
-        bsp_edges: &Vec<bsp30::Edge>,
-        bsp_decals: &Vec<Decal>,
-    ) -> Result<(VertexBuffer<VertexWithLM>, VertexBuffer<Vertex>)> {
+        bsp: &BSP,
+        max_face_edges: usize,
+        load_issues: &mut LoadIssues,
+    ) -> (Vec<VertexWithLM>, Vec<u32>, Vec<usize>, Vec<usize>) {
         let mut static_vertices: Vec<VertexWithLM> = Vec::new();
-        for (face_index, face) in bsp_faces.iter().enumerate() {
-            let coords: &FaceTexCoords = &bsp_face_tex_coords[face_index];
-            for i in 0..face.edge_count as usize {
-                if i > 2 {
-                    let first: VertexWithLM = static_vertices[i].clone();
-                    let prev: VertexWithLM = static_vertices.last().unwrap().clone();
-                    static_vertices.push(first);
-                    static_vertices.push(prev);
-                }
+        let mut static_indices: Vec<u32> = Vec::new();
+        let mut first_indices: Vec<usize> = Vec::with_capacity(bsp.faces.len());
+        let mut index_counts: Vec<usize> = Vec::with_capacity(bsp.faces.len());
+        for (face_index, face) in bsp.faces.iter().enumerate() {
+            let first_index: usize = static_indices.len();
+            first_indices.push(first_index);
+            if (face.edge_count as usize) < 3 {
+                load_issues.record(
+                    "geometry",
+                    format!("Face {} has {} edges, skipping degenerate face", face_index, face.edge_count),
+                );
+                index_counts.push(0);
+                continue;
+            }
+            if face.edge_count as usize > max_face_edges {
+                load_issues.record(
+                    "geometry",
+                    format!("Face {} has {} edges, exceeding the limit of {}, skipping", face_index, face.edge_count, max_face_edges),
+                );
+                index_counts.push(0);
+                continue;
+            }
+            let coords: &FaceTexCoords = &bsp.face_tex_coords[face_index];
+            let normal: glm::Vec3 = bsp.face_normal(face_index);
+            let base_vertex: usize = static_vertices.len();
+            for (i, position) in bsp.face_vertices(face).into_iter().enumerate() {
                 let mut v: VertexWithLM = VertexWithLM::default();
                 v.tex_coord = coords.tex_coords[i].clone().into();
                 v.lightmap_coord = if lm_coords[face_index].is_empty() {
@@ -491,24 +1326,35 @@ impl BSPRenderable {
                 } else {
                     lm_coords[face_index][i].clone().into()
                 };
-                v.normal = bsp_planes[face.plane_index as usize].normal.clone().into();
-                if face.plane_side != 0 {
-                    v.normal = [-v.normal[0], -v.normal[1], -v.normal[2]];
-                }
-                let edge: bsp30::SurfaceEdge =
-                    bsp_surface_edges[face.first_edge_index as usize + i];
-                if edge > 0 {
-                    v.position = bsp_vertices[bsp_edges[edge as usize].vertex_index[0] as usize]
-                        .clone()
-                        .into();
-                } else {
-                    v.position = bsp_vertices[bsp_edges[-edge as usize].vertex_index[1] as usize]
-                        .clone()
-                        .into();
-                }
+                v.normal = normal.into();
+                v.position = position.into();
                 static_vertices.push(v);
             }
+            static_indices.extend(BSPRenderable::fan_indices(base_vertex as u32, static_vertices.len() - base_vertex));
+            let dropped: usize = BSPRenderable::drop_degenerate_triangles(&static_vertices, &mut static_indices, first_index);
+            let count: usize = static_indices.len() - first_index;
+            if count == 0 {
+                load_issues.record(
+                    "geometry",
+                    format!("Face {} has zero area, skipping", face_index),
+                );
+            } else if dropped > 0 {
+                debug!(&crate::LOGGER, "Dropped {} zero area triangles from face {}", dropped, face_index);
+            }
+            index_counts.push(count);
         }
+        return (static_vertices, static_indices, first_indices, index_counts);
+    }
+
+    fn build_buffers(
+        lm_coords: &Vec<Vec<glm::Vec2>>,
+        renderer: &Rc<dyn Renderer>,
+        bsp: &BSP,
+        max_face_edges: usize,
+        load_issues: &mut LoadIssues,
+    ) -> Result<(VertexBuffer<VertexWithLM>, IndexBuffer<u32>, VertexBuffer<Vertex>, Vec<usize>, Vec<usize>)> {
+        let (static_vertices, static_indices, first_indices, index_counts): (Vec<VertexWithLM>, Vec<u32>, Vec<usize>, Vec<usize>) =
+            BSPRenderable::static_geometry(lm_coords, bsp, max_face_edges, load_issues);
         let m_static_geometry_vbo: VertexBuffer<VertexWithLM> =
             match VertexBuffer::new(renderer.provide_facade(), &static_vertices[..]) {
                 Ok(buf) => buf,
@@ -519,25 +1365,27 @@ impl BSPRenderable {
                     ))
                 }
             };
+        let m_static_geometry_ibo: IndexBuffer<u32> =
+            match IndexBuffer::new(renderer.provide_facade(), PrimitiveType::TrianglesList, &static_indices[..]) {
+                Ok(buf) => buf,
+                Err(error) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Cannot create static and brush geometry indices: {}", error),
+                    ))
+                }
+            };
         let mut decal_vertices: Vec<Vertex> = Vec::new();
-        for decal in bsp_decals.iter() {
-            for i in 0..6 {
-                let mut vertex: Vertex = Vertex::default();
-                vertex.normal = decal.normal.clone().into();
-                if i == 0 || i == 3 {
-                    vertex.position = decal.vec[0].clone().into();
-                    vertex.tex_coord = [0.0, 0.0];
-                } else if i == 1 {
-                    vertex.position = decal.vec[1].clone().into();
-                    vertex.tex_coord = [1.0, 0.0];
-                } else if i == 2 || i == 4 {
-                    vertex.position = decal.vec[2].clone().into();
-                    vertex.tex_coord = [1.0, 1.0];
-                } else if i == 5 {
-                    vertex.position = decal.vec[3].clone().into();
-                    vertex.tex_coord = [0.0, 1.0];
+        for decal in bsp.m_decals.iter() {
+            // Clipped decals are convex, so fan out from the first vertex
+            for i in 1..decal.vec.len().saturating_sub(1) {
+                for j in [0, i, i + 1] {
+                    let mut vertex: Vertex = Vertex::default();
+                    vertex.normal = decal.normal.clone().into();
+                    vertex.position = decal.vec[j].clone().into();
+                    vertex.tex_coord = decal.tex_coords[j].clone().into();
+                    decal_vertices.push(vertex);
                 }
-                decal_vertices.push(vertex);
             }
         }
         let m_decal_vbo: VertexBuffer<Vertex> =
@@ -550,64 +1398,394 @@ impl BSPRenderable {
                     ))
                 }
             };
-        return Ok((m_static_geometry_vbo, m_decal_vbo));
+        return Ok((m_static_geometry_vbo, m_static_geometry_ibo, m_decal_vbo, first_indices, index_counts));
+    }
+
+    /// Triangle list indices fanning out from the first of `count` vertices
+    /// starting at `base_vertex`, 3 * (count - 2) in all
+    fn fan_indices(base_vertex: u32, count: usize) -> Vec<u32> {
+        return (1..count.saturating_sub(1) as u32)
+            .flat_map(|i: u32| [base_vertex, base_vertex + i, base_vertex + i + 1])
+            .collect();
+    }
+
+    /// Remove triangles with (near) zero area from the indices emitted since
+    /// `start`, returning the number of triangles dropped.
+    fn drop_degenerate_triangles(vertices: &Vec<VertexWithLM>, indices: &mut Vec<u32>, start: usize) -> usize {
+        let triangles: Vec<u32> = indices.split_off(start);
+        let mut dropped: usize = 0;
+        for triangle in triangles.chunks(3) {
+            if triangle.len() < 3 {
+                dropped += 1;
+                continue;
+            }
+            let a: glm::Vec3 = glm::make_vec3(&vertices[triangle[0] as usize].position);
+            let b: glm::Vec3 = glm::make_vec3(&vertices[triangle[1] as usize].position);
+            let c: glm::Vec3 = glm::make_vec3(&vertices[triangle[2] as usize].position);
+            if glm::length(&glm::cross(&(b - a), &(c - a))) * 0.5 < DEGENERATE_AREA_EPSILON {
+                dropped += 1;
+                continue;
+            }
+            indices.extend_from_slice(triangle);
+        }
+        return dropped;
     }
 }
 
 impl Renderable for BSPRenderable {
-    fn render(&mut self, settings: &RenderSettings) -> Option<Error> {
-        const G_RENDER_SKYBOX: bool = true;
-        const G_RENDER_STATIC_BSP: bool = true;
-        const G_RENDER_BRUSH_ENTITIES: bool = true;
-        self.m_settings = settings.clone();
-        let camera_pos: glm::Vec3;
-        if self.m_skybox_tex.is_some() && G_RENDER_SKYBOX {
-            self.render_skybox();
+    fn render(&mut self, settings: &RenderSettings) {
+        BSPRenderable::render(self, settings, settings.leaf_outlines, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::r#move::PlayerMove;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map;
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> DirtyRect {
+        return DirtyRect { x, y, width, height };
+    }
+
+    /// Xorshift sequence so the packing tests are repeatable without a rand dependency
+    fn random_sizes(count: usize, max_size: usize) -> Vec<(usize, usize)> {
+        let mut state: u32 = 0x9E3779B9;
+        let mut next = || -> usize {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            return state as usize % max_size + 1;
+        };
+        return (0..count).map(|_| (next(), next())).collect();
+    }
+
+    fn overlaps(a: (glm::UVec2, (usize, usize)), b: (glm::UVec2, (usize, usize))) -> bool {
+        let (a_x, a_y): (usize, usize) = (a.0.x as usize, a.0.y as usize);
+        let (b_x, b_y): (usize, usize) = (b.0.x as usize, b.0.y as usize);
+        return a_x < b_x + b.1.0 && b_x < a_x + a.1.0 && a_y < b_y + b.1.1 && b_y < a_y + a.1.1;
+    }
+
+    #[test]
+    fn coalesce_merges_neighbouring_rects() {
+        let merged: Vec<DirtyRect> = DirtyRect::coalesce(&[rect(0, 0, 4, 4), rect(4, 0, 4, 4), rect(0, 4, 8, 2)]);
+        assert_eq!(merged, vec![rect(0, 0, 8, 6)]);
+    }
+
+    #[test]
+    fn coalesce_keeps_separate_rects_apart() {
+        let rects: [DirtyRect; 2] = [rect(0, 0, 4, 4), rect(10, 10, 2, 2)];
+        assert_eq!(DirtyRect::coalesce(&rects), rects.to_vec());
+    }
+
+    #[test]
+    fn coalesce_rechecks_rects_a_merge_grows_into() {
+        // The third rect bridges the first two, which then cover the fourth
+        let merged: Vec<DirtyRect> = DirtyRect::coalesce(&[
+            rect(0, 0, 2, 2),
+            rect(6, 0, 2, 2),
+            rect(2, 0, 4, 1),
+            rect(3, 1, 1, 1),
+        ]);
+        assert_eq!(merged, vec![rect(0, 0, 8, 2)]);
+    }
+
+    #[test]
+    fn coalesce_drops_empty_rects() {
+        assert!(DirtyRect::coalesce(&[rect(3, 3, 0, 5)]).is_empty());
+    }
+
+    #[test]
+    fn exposure_lut_is_identity_at_unit_gamma_and_scale() {
+        let lut: [u8; 256] = lightmap_exposure_lut(1.0, 1.0);
+        assert!(lut.iter().enumerate().all(|(texel, value): (usize, &u8)| *value as usize == texel));
+        assert_eq!(lightmap_exposure_lut(1.0, 2.0)[200], 255);
+    }
+
+    #[test]
+    fn exposure_lut_applies_gamma_then_scale() {
+        let brightened: [u8; 256] = lightmap_exposure_lut(2.0, 1.0);
+        assert_eq!((brightened[0], brightened[64], brightened[128], brightened[255]), (0, 128, 181, 255));
+        assert_eq!(lightmap_exposure_lut(0.5, 1.0)[200], 157);
+        let halved: [u8; 256] = lightmap_exposure_lut(1.0, 0.5);
+        assert_eq!((halved[128], halved[254]), (64, 127));
+        assert_eq!(lightmap_exposure_lut(2.0, 1.5)[100], 240);
+        // Degenerate settings stay within the texel range
+        assert!(lightmap_exposure_lut(0.0, -1.0).iter().all(|value: &u8| *value == 0));
+    }
+
+    #[test]
+    fn stored_images_never_overlap_and_stay_in_bounds() {
+        let mut atlas: TextureAtlas = TextureAtlas::new(256, 256, 3, 0);
+        let mut stored: Vec<(glm::UVec2, (usize, usize))> = Vec::new();
+        for (width, height) in random_sizes(300, 12) {
+            let image: Image = Image::from((width, height, 3));
+            let coord: glm::UVec2 = atlas.store(&image).unwrap();
+            assert!(coord.x as usize + width <= 256 && coord.y as usize + height <= 256);
+            stored.push((coord, (width, height)));
         }
-        if G_RENDER_STATIC_BSP || G_RENDER_BRUSH_ENTITIES {
-            self.faces_drawn.iter_mut()
-                .for_each(|f: &mut bool| *f = false);
+        for (i, a) in stored.iter().enumerate() {
+            for b in stored[i + 1..].iter() {
+                assert!(!overlaps(*a, *b), "{:?} overlaps {:?}", a, b);
+            }
         }
-        let mut ents: Vec<EntityData> = Vec::new();
-        if G_RENDER_STATIC_BSP {
-            ents.push(EntityData {
-                face_render_info: self.render_static_geometry(camera_pos, Option::None, &mut self.m_bsp.vis_lists),
-                origin: glm::Vec3::new(0.0, 0.0, 0.0),
-                alpha: 1.0,
-                render_mode: bsp30::RenderMode::RenderModeNormal,
-            });
+        let area: usize = stored.iter().map(|(_, (width, height)): &(glm::UVec2, (usize, usize))| width * height).sum();
+        assert_eq!(atlas.occupancy(), area as f32 / (256.0 * 256.0));
+    }
+
+    #[test]
+    fn a_full_atlas_reports_storage_full_until_reset() {
+        let mut atlas: TextureAtlas = TextureAtlas::new(16, 16, 3, 0);
+        let image: Image = Image::from((8, 8, 3));
+        for _ in 0..4 {
+            atlas.store(&image).unwrap();
         }
-        if G_RENDER_BRUSH_ENTITIES {
-            for i in self.m_bsp.brush_entities {
-                let entity: &Entity = &self.m_bsp.entities[i];
-                let model_index: u32;
-                if let Some(model_prop) = entity.find_property(&"model".to_string()) {
-                    model_index = model_prop[1..].parse::<u32>().unwrap();
-                } else {
-                    return Some(Error::new(ErrorKind::InvalidData, "expected model property to exist on entity"));
-                }
-                let mut alpha: f32 = 1.0;
-                if let Some(renderamt) = entity.find_property(&"renderamt".to_string()) {
-                    alpha = renderamt.parse::<f32>().unwrap() / 255.0;
-                }
-                let mut render_mode: bsp30::RenderMode = bsp30::RenderMode::RenderModeNormal;
-                if let Some(render_mode_prop) = entity.find_property(&"rendermode".to_string()) {
-                    render_mode = bsp30::RenderMode::from_u32(render_mode_prop.parse::<u32>().unwrap()).unwrap();
+        assert_eq!(atlas.occupancy(), 1.0);
+        assert_eq!(atlas.store(&image).unwrap_err().kind(), ErrorKind::StorageFull);
+        atlas.reset();
+        assert_eq!(atlas.occupancy(), 0.0);
+        assert_eq!(atlas.store(&image).unwrap(), glm::vec2(0u32, 0u32));
+    }
+
+    #[test]
+    fn lightmaps_larger_than_one_page_grow_the_atlas() {
+        let capabilities: RendererCapabilities = RendererCapabilities {
+            max_texture_size: 4096,
+            max_anisotropy: None,
+            srgb_framebuffer: true,
+            instancing: true,
+            compressed_textures: false,
+            wide_lines: false,
+        };
+        // 80 lightmaps of 126x126 with their gutter cover 1.3 million texels, more than a 1024 page
+        let lightmaps: Vec<Image> = (0..80).map(|_| Image::from((126, 126, 3))).collect();
+        let page: usize = LIGHTMAP_ATLAS_SIZE as usize;
+        assert_eq!(BSPRenderable::pack_lightmaps(&lightmaps, page).err().map(|error: Error| error.kind()), Some(ErrorKind::StorageFull));
+        let (atlas, positions): (TextureAtlas, Vec<glm::UVec2>) = BSPRenderable::pack_lightmaps_to_fit(&lightmaps, &capabilities).unwrap();
+        assert_eq!((atlas.m_image.width, atlas.m_image.height), (page * 2, page * 2));
+        assert_eq!(positions.len(), lightmaps.len());
+        let limited: RendererCapabilities = RendererCapabilities { max_texture_size: 1024, ..capabilities };
+        assert_eq!(
+            BSPRenderable::pack_lightmaps_to_fit(&lightmaps, &limited).err().map(|error: Error| error.kind()),
+            Some(ErrorKind::StorageFull),
+        );
+    }
+
+    /// 3x2 image whose texels count up from 10 in steps of 10
+    fn numbered_image() -> Image {
+        return Image {
+            channels: 3,
+            width: 3,
+            height: 2,
+            data: (1..=6).flat_map(|i: u8| [i * 10; 3]).collect(),
+        };
+    }
+
+    #[test]
+    fn gutters_repeat_the_adjacent_edge_texels() {
+        for gutter in [0usize, 2] {
+            let mut atlas: TextureAtlas = TextureAtlas::new(16, 16, 3, gutter);
+            let image: Image = numbered_image();
+            let coord: glm::UVec2 = atlas.store(&image).unwrap();
+            let (x0, y0): (usize, usize) = (coord.x as usize, coord.y as usize);
+            for y in 0..image.height + gutter * 2 {
+                for x in 0..image.width + gutter * 2 {
+                    let src_x: usize = x.saturating_sub(gutter).min(image.width - 1);
+                    let src_y: usize = y.saturating_sub(gutter).min(image.height - 1);
+                    assert_eq!(atlas.m_image.pixel(x0 + x, y0 + y), image.pixel(src_x, src_y), "gutter {} at ({}, {})", gutter, x, y);
                 }
-                // std::vector<render::FaceRenderInfo> fri;
-			    //renderBSP(m_bsp->models[model].headNodesIndex[0], boost::dynamic_bitset<uint8_t>{}, cameraPos, fri); // for some odd reason, VIS does not work for entities ...
-			    //ents.push_back(render::EntityData{ std::move(fri), m_bsp->models[model].origin, alpha, renderMode });
             }
         }
-        self.m_renderer.render_static(
-            &ents,
-            &self.m_bsp.m_decals,
-            &self.m_static_geometry_vbo,
-            &self.m_decal_vbo,
-            &self.m_textures,
-            &self.m_lightmap_atlas,
-            &self.m_settings
+    }
+
+    #[test]
+    fn converted_coordinates_land_inside_the_interior() {
+        for gutter in [0usize, 2] {
+            let mut atlas: TextureAtlas = TextureAtlas::new(16, 16, 3, gutter);
+            let image: Image = numbered_image();
+            atlas.store(&image).unwrap();
+            let coord: glm::UVec2 = atlas.store(&image).unwrap();
+            // Luxel centres, as the face lightmap coordinates sample them
+            for (s, t) in [(0.5, 0.5), (2.5, 1.5), (0.5, 1.5)] {
+                let uv: glm::Vec2 = atlas.convert_coord(&image, coord, glm::vec2(s / 3.0, t / 2.0));
+                let texel: glm::Vec2 = uv * 16.0;
+                assert!(texel.x > (coord.x as usize + gutter) as f32 && texel.x < (coord.x as usize + gutter + image.width) as f32);
+                assert!(texel.y > (coord.y as usize + gutter) as f32 && texel.y < (coord.y as usize + gutter + image.height) as f32);
+                let stored: Option<&[u8]> = atlas.m_image.pixel(texel.x as usize, texel.y as usize);
+                assert_eq!(stored, image.pixel(s as usize, t as usize));
+            }
+        }
+    }
+
+    #[test]
+    fn euler_angle_xzx_composes_three_rotations() {
+        let (t1, t2, t3): (f32, f32, f32) = (0.3, -1.1, 2.0);
+        let x: glm::Vec3 = glm::vec3(1.0, 0.0, 0.0);
+        let expected: glm::Mat4 = glm::rotation(t1, &x) * glm::rotation(t2, &glm::vec3(0.0, 0.0, 1.0)) * glm::rotation(t3, &x);
+        assert!((BSPRenderable::euler_angle_xzx(t1, t2, t3) - expected).norm() < 1e-5);
+    }
+
+    #[test]
+    fn the_sky_turns_with_the_camera() {
+        // Cubemap directions are GoldSrc directions as (x, z, -y), this takes them back
+        let cube_to_world: glm::Mat4 = glm::mat4(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, -1.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
         );
-        return None;
+        for (pitch, yaw) in [(0.0f32, 0.0f32), (0.0, 90.0), (30.0, 45.0), (-60.0, 200.0)] {
+            let mut camera: Camera = Camera::new(Box::new(PlayerMove::default()));
+            camera.set_view(glm::vec3(10.0, 20.0, 30.0), glm::vec3(
+                pitch.to_radians().cos() * yaw.to_radians().cos(),
+                pitch.to_radians().cos() * yaw.to_radians().sin(),
+                -pitch.to_radians().sin(),
+            ));
+            let view_rotation: glm::Mat3 = glm::mat4_to_mat3(&camera.view_matrix());
+            let sky: glm::Mat4 = BSPRenderable::euler_angle_xzx(
+                (camera.pitch() - 90.0).to_radians(),
+                (90.0 - camera.yaw()).to_radians(),
+                90.0f32.to_radians(),
+            );
+            // Sky directions must land where the view puts the world directions they stand for
+            let difference: f32 = (glm::mat4_to_mat3(&sky) - view_rotation * glm::mat4_to_mat3(&cube_to_world)).norm();
+            assert!(difference < 1e-5, "pitch {} yaw {} is off by {}", pitch, yaw, difference);
+        }
     }
+
+    #[test]
+    fn a_five_edge_face_fans_into_three_triangles() {
+        let indices: Vec<u32> = BSPRenderable::fan_indices(10, 5);
+        assert_eq!(indices, vec![10, 11, 12, 10, 12, 13, 10, 13, 14]);
+        let mut referenced: Vec<u32> = indices.clone();
+        referenced.sort();
+        referenced.dedup();
+        assert_eq!(referenced, vec![10, 11, 12, 13, 14]);
+        assert!(BSPRenderable::fan_indices(0, 2).is_empty());
+    }
+
+    #[test]
+    fn each_quad_fans_across_its_own_corners() {
+        let bsp: BSP = micro_map::load_micro_map(ParseStrictness::Strict).unwrap();
+        let lm_coords: Vec<Vec<glm::Vec2>> = vec![Vec::new(); bsp.faces.len()];
+        let (vertices, indices, first_indices, index_counts): (Vec<VertexWithLM>, Vec<u32>, Vec<usize>, Vec<usize>) =
+            BSPRenderable::static_geometry(&lm_coords, &bsp, MAX_FACE_EDGES, &mut LoadIssues::default());
+        assert_eq!(vertices.len(), 8);
+        assert_eq!((first_indices, index_counts), (vec![0, 6], vec![6, 6]));
+        let wall: &[u32] = &indices[6..12];
+        assert!(wall.iter().all(|index: &u32| (4..8).contains(index)));
+        // Every triangle of the wall closes on the wall's first corner
+        assert!(wall.chunks(3).all(|triangle: &[u32]| triangle[0] == 4));
+        let mut corners: Vec<[f32; 3]> = wall.iter().map(|index: &u32| vertices[*index as usize].position).collect();
+        corners.sort_by(|a: &[f32; 3], b: &[f32; 3]| a.partial_cmp(b).unwrap());
+        corners.dedup();
+        let mut expected: Vec<[f32; 3]> = bsp.face_vertices(&bsp.faces[1]).into_iter().map(|position: glm::Vec3| position.into()).collect();
+        expected.sort_by(|a: &[f32; 3], b: &[f32; 3]| a.partial_cmp(b).unwrap());
+        assert_eq!(corners, expected);
+    }
+
+    #[test]
+    fn faces_with_fewer_than_three_edges_are_skipped() {
+        let mut bsp: BSP = micro_map::load_micro_map(ParseStrictness::Strict).unwrap();
+        bsp.faces[1].edge_count = 2;
+        let lm_coords: Vec<Vec<glm::Vec2>> = vec![Vec::new(); bsp.faces.len()];
+        let mut load_issues: LoadIssues = LoadIssues::default();
+        let (vertices, _, first_indices, index_counts): (Vec<VertexWithLM>, Vec<u32>, Vec<usize>, Vec<usize>) =
+            BSPRenderable::static_geometry(&lm_coords, &bsp, MAX_FACE_EDGES, &mut load_issues);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!((first_indices, index_counts), (vec![0, 6], vec![6, 0]));
+        assert_eq!(load_issues.len(), 1);
+        assert!(load_issues.iter().next().unwrap().message.starts_with("Face 1 has 2 edges"));
+    }
+
+    #[test]
+    fn collinear_faces_are_skipped_as_zero_area() {
+        let mut bsp: BSP = micro_map::load_micro_map(ParseStrictness::Strict).unwrap();
+        // Line the floor's corners up along the x axis
+        let first: usize = bsp.faces[0].first_edge_index as usize;
+        for surface_edge in first..first + bsp.faces[0].edge_count as usize {
+            let edge: usize = bsp.surface_edges[surface_edge].unsigned_abs() as usize;
+            for vertex in bsp.edges[edge].vertex_index {
+                bsp.vertices[vertex as usize] = glm::vec3(vertex as f32 * 16.0, 0.0, 0.0);
+            }
+        }
+        let lm_coords: Vec<Vec<glm::Vec2>> = vec![Vec::new(); bsp.faces.len()];
+        let mut load_issues: LoadIssues = LoadIssues::default();
+        let (_, indices, _, index_counts): (Vec<VertexWithLM>, Vec<u32>, Vec<usize>, Vec<usize>) =
+            BSPRenderable::static_geometry(&lm_coords, &bsp, MAX_FACE_EDGES, &mut load_issues);
+        assert_eq!(index_counts[0], 0);
+        assert!(index_counts[1] > 0);
+        assert_eq!(indices.len(), index_counts[1]);
+        assert_eq!(load_issues.len(), 1);
+        assert_eq!(load_issues.iter().next().unwrap().message, "Face 0 has zero area, skipping");
+    }
+
+    #[test]
+    fn a_leaf_box_outlines_its_twelve_edges() {
+        let lower: glm::Vec3 = glm::vec3(-16.0, 0.0, 8.0);
+        let upper: glm::Vec3 = glm::vec3(16.0, 64.0, 40.0);
+        let edges: [glm::Vec3; BOX_EDGE_VERTICES] = BSPRenderable::box_edges(&lower, &upper);
+        assert_eq!(edges.len(), 24);
+        let mut lines: Vec<[i32; 6]> = Vec::new();
+        for line in edges.chunks(2) {
+            let delta: glm::Vec3 = line[1] - line[0];
+            // Each edge runs along one axis for the box's full extent
+            let axes: Vec<usize> = (0..3).filter(|axis: &usize| delta[*axis] != 0.0).collect();
+            assert_eq!(axes.len(), 1);
+            assert_eq!(delta[axes[0]], (upper - lower)[axes[0]]);
+            lines.push([line[0].x, line[0].y, line[0].z, line[1].x, line[1].y, line[1].z].map(|value: f32| value as i32));
+        }
+        lines.sort();
+        lines.dedup();
+        assert_eq!(lines.len(), 12);
+        // Every corner meets three edges
+        for corner in edges.iter() {
+            assert_eq!(edges.iter().filter(|other: &&glm::Vec3| *other == corner).count(), 3);
+            assert!((0..3).all(|axis: usize| corner[axis] == lower[axis] || corner[axis] == upper[axis]));
+        }
+    }
+
+    #[test]
+    fn unparseable_render_modes_draw_normally() {
+        let render_mode = |properties: &str| -> bsp30::RenderMode {
+            return entity_render_mode(&Entity::new(&properties.to_string()));
+        };
+        assert_eq!(render_mode("\"classname\" \"func_wall\" \"rendermode\" \"5\""), bsp30::RenderMode::RenderModeAdditive);
+        assert_eq!(render_mode("\"classname\" \"func_wall\" \"rendermode\" \" 2 \""), bsp30::RenderMode::RenderModeTexture);
+        assert_eq!(render_mode("\"classname\" \"func_wall\""), bsp30::RenderMode::RenderModeNormal);
+        assert_eq!(render_mode("\"classname\" \"func_wall\" \"rendermode\" \"glass\""), bsp30::RenderMode::RenderModeNormal);
+        assert_eq!(render_mode("\"classname\" \"func_wall\" \"rendermode\" \"-1\""), bsp30::RenderMode::RenderModeNormal);
+        assert_eq!(render_mode("\"classname\" \"func_wall\" \"rendermode\" \"99\""), bsp30::RenderMode::RenderModeNormal);
+    }
+
+    #[test]
+    fn liquids_tint_the_view_by_their_kind() {
+        let water: [f32; 4] = liquid_tint(bsp30::ContentType::ContentsWater).unwrap();
+        assert!(water[2] > water[0] && water[2] > water[1]);
+        let slime: [f32; 4] = liquid_tint(bsp30::ContentType::ContentsSlime).unwrap();
+        assert!(slime[1] > slime[0] && slime[1] > slime[2]);
+        let lava: [f32; 4] = liquid_tint(bsp30::ContentType::ContentsLava).unwrap();
+        assert!(lava[0] > lava[1] && lava[1] > lava[2]);
+        // Currents are flowing water
+        assert_eq!(liquid_tint(bsp30::ContentType::ContentsCurrentDown), Some(water));
+        assert!([water, slime, lava].iter().all(|tint: &[f32; 4]| tint[3] > 0.0 && tint[3] < 1.0));
+    }
+
+    #[test]
+    fn only_liquids_have_a_view_effect() {
+        let dry: Vec<bsp30::ContentType> = (-15..=-1)
+            .filter_map(num::FromPrimitive::from_i32)
+            .filter(|contents: &bsp30::ContentType| liquid_tint(*contents).is_none())
+            .collect();
+        assert_eq!(dry, vec![
+            bsp30::ContentType::ContentsTranslucent,
+            bsp30::ContentType::ContentsClip,
+            bsp30::ContentType::ContentsOrigin,
+            bsp30::ContentType::ContentsSky,
+            bsp30::ContentType::ContentsSolid,
+            bsp30::ContentType::ContentsEmpty,
+        ]);
+    }
+
 }
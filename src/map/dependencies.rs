@@ -0,0 +1,129 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::map::bsp::BSP;
+use crate::map::wad::Wad;
+use crate::scene::entity::Entity;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    Wad,
+    DecalWad,
+    SkyTexture,
+    Texture,
+}
+
+impl fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", match self {
+            DependencyKind::Wad => "WAD",
+            DependencyKind::DecalWad => "decal WAD",
+            DependencyKind::SkyTexture => "sky texture",
+            DependencyKind::Texture => "texture",
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub kind: DependencyKind,
+    pub name: String,
+    pub required: bool,
+    pub searched: Vec<PathBuf>,
+    pub resolved: Option<PathBuf>,
+    pub size: Option<u64>,
+}
+
+impl Dependency {
+
+    fn resolve(kind: DependencyKind, name: String, required: bool, candidates: Vec<PathBuf>) -> Self {
+        let resolved: Option<PathBuf> = candidates.iter()
+            .find(|path: &&PathBuf| path.is_file())
+            .cloned();
+        let size: Option<u64> = resolved.as_ref()
+            .and_then(|path: &PathBuf| path.metadata().ok())
+            .map(|metadata: std::fs::Metadata| metadata.len());
+        return Dependency {
+            kind,
+            name,
+            required,
+            searched: candidates,
+            resolved,
+            size,
+        };
+    }
+
+    pub fn is_found(&self) -> bool {
+        return self.resolved.is_some();
+    }
+
+}
+
+impl BSP {
+
+    /// Resolve every external asset the map references against the asset
+    /// directories without decoding any of them.
+    pub fn dependencies(&self) -> Vec<Dependency> {
+        let mut dependencies: Vec<Dependency> = Vec::new();
+        let world_spawn: Option<&Entity> = BSP::find_entity(&self.entities, "worldspawn".to_string());
+        let mut wads: Vec<Wad> = Vec::new();
+        if let Some(wad_str) = world_spawn.and_then(|entity: &Entity| entity.find_property(&"wad".to_string())) {
+            for path_str in wad_str.replace("\\", "/").split(";") {
+                if path_str.is_empty() {
+                    continue;
+                }
+                let dependency: Dependency = Dependency::resolve(
+                    DependencyKind::Wad,
+                    path_str.to_string(),
+                    true,
+                    self.asset_paths.wad_candidates(&BSP::wad_reference_name(path_str)),
+                );
+                if let Some(path) = &dependency.resolved {
+                    match Wad::open(path) {
+                        Ok(wad) => wads.push(wad),
+                        Err(error) => warn!(&crate::LOGGER, "Unable to open WAD {}: {}", path.display(), error),
+                    };
+                }
+                dependencies.push(dependency);
+            }
+        }
+        for mip_tex in self.mip_textures.iter() {
+            if mip_tex.offsets[0] != 0 {
+                // Embedded in the BSP
+                continue;
+            }
+            let name: String = mip_tex.name.to_string();
+            let containing: Option<&Wad> = wads.iter()
+                .find(|wad: &&Wad| wad.contains(&name));
+            dependencies.push(Dependency {
+                kind: DependencyKind::Texture,
+                name,
+                required: true,
+                searched: wads.iter().map(|wad: &Wad| wad.path.clone()).collect(),
+                resolved: containing.map(|wad: &Wad| wad.path.clone()),
+                size: None,
+            });
+        }
+        if let Some(paths) = self.skybox_paths() {
+            for path in paths {
+                let path: PathBuf = PathBuf::from(path);
+                dependencies.push(Dependency::resolve(
+                    DependencyKind::SkyTexture,
+                    path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string()),
+                    true,
+                    vec![path.clone(), path.with_extension("bmp")],
+                ));
+            }
+        }
+        for decal_wad in self.asset_paths.decal_wad_names() {
+            dependencies.push(Dependency::resolve(
+                DependencyKind::DecalWad,
+                decal_wad.clone(),
+                false,
+                self.asset_paths.wad_candidates(&decal_wad),
+            ));
+        }
+        return dependencies;
+    }
+
+}
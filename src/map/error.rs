@@ -0,0 +1,77 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum BspError {
+    Io(io::Error),
+    InvalidVersion { found: i32 },
+    LumpOutOfBounds { lump: &'static str, offset: i32, length: i32, file_len: u64 },
+    LumpMisaligned { lump: &'static str, length: i32, element_size: usize },
+    LimitExceeded { name: &'static str, count: usize, limit: usize, index: &'static str },
+    CorruptTree { message: String },
+    MissingWorldspawn,
+    InvalidWad { path: PathBuf, reason: String },
+    TextureNotFound { name: String },
+    InvalidTexture { name: String, reason: String },
+    EntityParse { context: String, message: String },
+    Violation { context: String, message: String }, // Spec violation rejected by strict parsing
+}
+
+impl BspError {
+
+    /// Reclassify a strict violation raised while parsing the entities lump
+    pub(crate) fn into_entity_parse(self) -> BspError {
+        if let BspError::Violation { context, message } = self {
+            return BspError::EntityParse { context, message };
+        }
+        return self;
+    }
+
+}
+
+impl fmt::Display for BspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            BspError::Io(error) => write!(f, "I/O error: {}", error),
+            BspError::InvalidVersion { found } => write!(f, "Invalid BSP version {}, expected 29 or 30", found),
+            BspError::LumpOutOfBounds { lump, offset, length, file_len } => write!(
+                f,
+                "Lump {} at offset {} with length {} lies outside the {} byte file",
+                lump, offset, length, file_len,
+            ),
+            BspError::LumpMisaligned { lump, length, element_size } => write!(
+                f,
+                "Lump {} length {} is not a multiple of its {} byte element size",
+                lump, length, element_size,
+            ),
+            BspError::LimitExceeded { name, count, limit, index } => write!(
+                f,
+                "Map has {} {}, more than the {} addressable by {}",
+                count, name, limit, index,
+            ),
+            BspError::CorruptTree { message } => write!(f, "Corrupt BSP tree: {}", message),
+            BspError::MissingWorldspawn => write!(f, "No 'worldspawn' entity present in BSP"),
+            BspError::InvalidWad { path, reason } => write!(f, "Invalid WAD {}: {}", path.display(), reason),
+            BspError::TextureNotFound { name } => write!(f, "No such texture found with name: {}", name),
+            BspError::InvalidTexture { name, reason } => write!(f, "Invalid texture {}: {}", name, reason),
+            BspError::EntityParse { context, message } => write!(f, "Failed to parse {}: {}", context, message),
+            BspError::Violation { context, message } => write!(f, "[{}] {}", context, message),
+        };
+    }
+}
+
+impl std::error::Error for BspError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            BspError::Io(error) => Some(error),
+            _ => None,
+        };
+    }
+}
+
+impl From<io::Error> for BspError {
+    fn from(error: io::Error) -> Self {
+        return BspError::Io(error);
+    }
+}
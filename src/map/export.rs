@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::map::bsp::{TextureClass, BSP};
+use crate::map::bsp30;
+use crate::map::error::BspError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjExportOptions {
+    pub include_submodels: bool, // Export brush entity models as well as the world
+    pub skip_tool_faces: bool, // Leave out sky, trigger, clip and other tool faces
+    pub scale: f32, // Multiplier applied to every vertex position
+}
+
+impl Default for ObjExportOptions {
+    fn default() -> Self {
+        return ObjExportOptions {
+            include_submodels: false,
+            skip_tool_faces: true,
+            scale: 1.0,
+        };
+    }
+}
+
+impl BSP {
+
+    /// Write the world geometry as a Wavefront OBJ, grouped by texture, with a
+    /// material library next to it and one PNG per texture in a `<name>_textures`
+    /// directory
+    pub fn export_obj(&self, path: &Path, options: ObjExportOptions) -> Result<(), BspError> {
+        let stem: String = path.file_stem()
+            .map_or(String::from("map"), |stem: &std::ffi::OsStr| stem.to_string_lossy().to_string());
+        let directory: &Path = path.parent().unwrap_or(Path::new(""));
+        let mtl_name: String = format!("{}.mtl", stem);
+        let texture_dir_name: String = format!("{}_textures", stem);
+        // Faces grouped by texture so each material is a single OBJ group
+        let model_count: usize = if options.include_submodels { self.models.len() } else { self.models.len().min(1) };
+        let mut groups: BTreeMap<usize, Vec<&bsp30::Face>> = BTreeMap::new();
+        for model in 0..model_count {
+            for face in self.model_faces(model) {
+                if face.edge_count < 3 {
+                    continue;
+                }
+                let mip_tex_index: usize = self.texture_infos[face.texture_info as usize].mip_tex_index as usize;
+                if options.skip_tool_faces && TextureClass::from_name(&self.mip_texture_name(mip_tex_index)).is_tool() {
+                    continue;
+                }
+                groups.entry(mip_tex_index).or_default().push(face);
+            }
+        }
+        let mut obj: BufWriter<File> = BufWriter::new(File::create(path)?);
+        writeln!(obj, "# Exported from {} faces in {} models", groups.values().map(Vec::len).sum::<usize>(), model_count)?;
+        writeln!(obj, "mtllib {}", mtl_name)?;
+        let mut vertex_count: usize = 0;
+        for (mip_tex_index, faces) in groups.iter() {
+            let name: String = self.mip_texture_name(*mip_tex_index);
+            writeln!(obj, "g {}", name)?;
+            writeln!(obj, "usemtl {}", name)?;
+            let tex_size: glm::Vec2 = self.texture_normalisation_size(*mip_tex_index);
+            for face in faces.iter().copied() {
+                let plane_normal: glm::Vec3 = self.planes[face.plane_index as usize].normal;
+                let normal: glm::Vec3 = if face.plane_side != 0 { -plane_normal } else { plane_normal };
+                let texture_info: &bsp30::TextureInfo = &self.texture_infos[face.texture_info as usize];
+                let vertices: Vec<glm::Vec3> = self.face_vertices(face);
+                for vertex in vertices.iter() {
+                    let position: glm::Vec3 = vertex * options.scale;
+                    let tex_coord: glm::Vec2 = BSP::texture_space(texture_info, vertex).component_div(&tex_size);
+                    writeln!(obj, "v {} {} {}", position.x, position.y, position.z)?;
+                    // OBJ texture coordinates start at the bottom left
+                    writeln!(obj, "vt {} {}", tex_coord.x, 1.0 - tex_coord.y)?;
+                    writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+                }
+                // Fan triangulation around the first vertex, indices are 1-based
+                let first: usize = vertex_count + 1;
+                for i in 1..vertices.len() - 1 {
+                    writeln!(
+                        obj,
+                        "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}",
+                        first,
+                        first + i,
+                        first + i + 1,
+                    )?;
+                }
+                vertex_count += vertices.len();
+            }
+        }
+        obj.flush()?;
+        let mut mtl: BufWriter<File> = BufWriter::new(File::create(directory.join(&mtl_name))?);
+        let texture_dir: PathBuf = directory.join(&texture_dir_name);
+        if !groups.is_empty() && !self.m_textures.is_empty() {
+            std::fs::create_dir_all(&texture_dir)?;
+        }
+        for mip_tex_index in groups.keys().copied() {
+            let name: String = self.mip_texture_name(mip_tex_index);
+            writeln!(mtl, "newmtl {}", name)?;
+            writeln!(mtl, "Kd 1.0 1.0 1.0")?;
+            let Some(texture) = self.m_textures.get(mip_tex_index) else {
+                // Textures are not decoded in metadata only loads
+                continue;
+            };
+            let file_name: String = format!("{}.png", BSP::texture_file_name(&name));
+            if let Err(error) = texture.img[0].save(texture_dir.join(&file_name).to_string_lossy().to_string()) {
+                warn!(&crate::LOGGER, "Failed to export texture {}: {}", name, error);
+                continue;
+            }
+            writeln!(mtl, "map_Kd {}/{}", texture_dir_name, file_name)?;
+        }
+        mtl.flush()?;
+        info!(&crate::LOGGER, "Exported {} vertices in {} groups to {}", vertex_count, groups.len(), path.display());
+        return Ok(());
+    }
+
+    /// Texture names may contain characters such as `*` that are not valid in file names
+    fn texture_file_name(name: &str) -> String {
+        return name.chars()
+            .map(|c: char| if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '{' || c == '!' { c } else { '_' })
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map::load_micro_map;
+
+    #[test]
+    fn exported_obj_matches_the_map_geometry() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let directory: PathBuf = std::env::temp_dir().join(format!("lambda-export-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path: PathBuf = directory.join("micro.obj");
+        bsp.export_obj(&path, ObjExportOptions::default()).unwrap();
+        let obj: String = std::fs::read_to_string(&path).unwrap();
+        let mtl: String = std::fs::read_to_string(directory.join("micro.mtl")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let count = |prefix: &str| -> usize {
+            return obj.lines().filter(|line: &&str| line.split_whitespace().next() == Some(prefix)).count();
+        };
+        let vertices: usize = bsp.faces.iter().map(|face: &bsp30::Face| face.edge_count as usize).sum();
+        let triangles: usize = bsp.faces.iter().map(|face: &bsp30::Face| face.edge_count as usize - 2).sum();
+        assert_eq!(count("v"), vertices);
+        assert_eq!(count("vt"), vertices);
+        assert_eq!(count("f"), triangles);
+        assert_eq!(count("g"), 2);
+        // Every face index refers to an exported vertex
+        for line in obj.lines().filter(|line: &&str| line.starts_with("f ")) {
+            for corner in line.split_whitespace().skip(1) {
+                let index: usize = corner.split('/').next().unwrap().parse::<usize>().unwrap();
+                assert!((1..=vertices).contains(&index));
+            }
+        }
+        assert!(mtl.contains("newmtl floor") && mtl.contains("map_Kd micro_textures/wall.png"));
+    }
+
+    #[test]
+    fn scale_multiplies_every_position() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let directory: PathBuf = std::env::temp_dir().join(format!("lambda-export-scale-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path: PathBuf = directory.join("micro.obj");
+        bsp.export_obj(&path, ObjExportOptions { scale: 0.5, ..ObjExportOptions::default() }).unwrap();
+        let obj: String = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let largest: f32 = obj.lines()
+            .filter(|line: &&str| line.starts_with("v "))
+            .flat_map(|line: &str| line.split_whitespace().skip(1).map(|value: &str| value.parse::<f32>().unwrap()).collect::<Vec<f32>>())
+            .fold(0.0, f32::max);
+        assert_eq!(largest, 32.0);
+    }
+
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+
+use crate::map::bsp::BSP;
+use crate::map::bsp30;
+use crate::util::memory::MemoryReport;
+
+// Consecutive winding points closer than this are treated as the same vertex
+const WINDING_EPSILON: f32 = 1.0e-4;
+// Distance off a face a point may be and still be on it, covering where traces stop short
+const FACE_PICK_EPSILON: f32 = 0.1;
+
+#[derive(Debug, Clone, Default)]
+pub struct BspStats {
+    pub world_face_count: usize,
+    pub total_world_area: f32,
+    pub largest_face: Option<(usize, f32)>, // Face index and area
+    pub average_face_area: f32,
+    pub faces_per_texture: HashMap<String, usize>,
+    pub memory: MemoryReport,
+}
+
+impl BSP {
+
+    /// Faces visible from a leaf, resolved through its mark surfaces
+    pub fn leaf_faces(&self, leaf: usize) -> impl Iterator<Item = &bsp30::Face> + '_ {
+        let mark_surfaces: &[bsp30::MarkSurface] = match self.leaves.get(leaf) {
+            Some(leaf) => {
+                let first: usize = leaf.first_mark_surface as usize;
+                let end: usize = (first + leaf.mark_surface_count as usize).min(self.mark_surfaces.len());
+                &self.mark_surfaces[first.min(end)..end]
+            },
+            None => &[],
+        };
+        return mark_surfaces.iter()
+            .filter_map(|mark_surface: &bsp30::MarkSurface| self.faces.get(*mark_surface as usize));
+    }
+
+    /// Faces of a model, the world being model 0
+    pub fn model_faces(&self, model: usize) -> impl Iterator<Item = &bsp30::Face> + '_ {
+        let faces: &[bsp30::Face] = match self.models.get(model) {
+            Some(model) => {
+                let first: usize = (model.model.first_face.max(0) as usize).min(self.faces.len());
+                let end: usize = (first + model.model.face_count.max(0) as usize).min(self.faces.len());
+                &self.faces[first..end]
+            },
+            None => &[],
+        };
+        return faces.iter();
+    }
+
+    /// Vertex loop of a face in edge order
+    pub fn face_vertices(&self, face: &bsp30::Face) -> Vec<glm::Vec3> {
+        let first: usize = face.first_edge_index as usize;
+        return (first..first + face.edge_count as usize)
+            .map(|surface_edge: usize| self.surface_edge_vertex(self.surface_edges[surface_edge]))
+            .collect();
+    }
+
+    /// Start vertex of a surface edge. A negative surface edge walks its edge
+    /// backwards, so it starts at the second vertex.
+    pub (crate) fn surface_edge_vertex(&self, surface_edge: bsp30::SurfaceEdge) -> glm::Vec3 {
+        if surface_edge >= 0 {
+            return self.vertices[self.edges[surface_edge as usize].vertex_index[0] as usize];
+        }
+        return self.vertices[self.edges[surface_edge.unsigned_abs() as usize].vertex_index[1] as usize];
+    }
+
+    /// Normal of the front side of a face, taking the plane side into account
+    pub fn face_normal(&self, face_index: usize) -> glm::Vec3 {
+        let face: &bsp30::Face = &self.faces[face_index];
+        let normal: glm::Vec3 = self.planes[face.plane_index as usize].normal;
+        if face.plane_side != 0 {
+            return -normal;
+        }
+        return normal;
+    }
+
+    /// Ordered vertex loop of a face, counter-clockwise when viewed from the front
+    pub fn face_winding(&self, face_index: usize) -> Vec<glm::Vec3> {
+        let face: &bsp30::Face = &self.faces[face_index];
+        let mut winding: Vec<glm::Vec3> = Vec::with_capacity(face.edge_count as usize);
+        for i in 0..face.edge_count as usize {
+            // Each edge contributes only its start vertex, its end is the start of the next
+            let vertex: glm::Vec3 = self.face_vertex(face_index, i);
+            if let Some(last) = winding.last() {
+                if glm::distance(last, &vertex) < WINDING_EPSILON {
+                    continue;
+                }
+            }
+            winding.push(vertex);
+        }
+        while winding.len() > 1 && glm::distance(&winding[0], &winding[winding.len() - 1]) < WINDING_EPSILON {
+            winding.pop();
+        }
+        if glm::dot(&BSP::winding_normal(&winding), &self.face_normal(face_index)) < 0.0 {
+            winding.reverse();
+        }
+        return winding;
+    }
+
+    pub fn face_area(&self, face_index: usize) -> f32 {
+        return glm::length(&BSP::winding_normal(&self.face_winding(face_index))) * 0.5;
+    }
+
+    pub fn face_perimeter(&self, face_index: usize) -> f32 {
+        let winding: Vec<glm::Vec3> = self.face_winding(face_index);
+        if winding.len() < 2 {
+            return 0.0;
+        }
+        return (0..winding.len())
+            .map(|i: usize| glm::distance(&winding[i], &winding[(i + 1) % winding.len()]))
+            .sum();
+    }
+
+    /// Area weighted centroid of a face, falling back to the vertex average
+    /// when the face has no area
+    pub fn face_center(&self, face_index: usize) -> glm::Vec3 {
+        let winding: Vec<glm::Vec3> = self.face_winding(face_index);
+        if winding.is_empty() {
+            return glm::Vec3::zeros();
+        }
+        let mut center: glm::Vec3 = glm::Vec3::zeros();
+        let mut total_area: f32 = 0.0;
+        for i in 1..winding.len().saturating_sub(1) {
+            let area: f32 = glm::length(&glm::cross(
+                &(winding[i] - winding[0]),
+                &(winding[i + 1] - winding[0]),
+            )) * 0.5;
+            center += (winding[0] + winding[i] + winding[i + 1]) * (area / 3.0);
+            total_area += area;
+        }
+        if total_area <= f32::EPSILON {
+            return winding.iter().fold(glm::Vec3::zeros(), |acc: glm::Vec3, v: &glm::Vec3| acc + v) / winding.len() as f32;
+        }
+        return center / total_area;
+    }
+
+    /// World face a point lies on, for picking the face a trace hit
+    pub fn face_at(&self, point: glm::Vec3) -> Option<usize> {
+        let world_faces: std::ops::Range<usize> = match self.models.first() {
+            Some(world) => {
+                let first: usize = (world.model.first_face.max(0) as usize).min(self.faces.len());
+                first..(first + world.model.face_count.max(0) as usize).min(self.faces.len())
+            },
+            None => 0..self.faces.len(),
+        };
+        return world_faces.into_iter().find(|face_index: &usize| {
+            let plane: &bsp30::Plane = &self.planes[self.faces[*face_index].plane_index as usize];
+            if (glm::dot(&plane.normal, &point) - plane.dist).abs() > FACE_PICK_EPSILON {
+                return false;
+            }
+            let winding: Vec<glm::Vec3> = self.face_winding(*face_index);
+            let normal: glm::Vec3 = self.face_normal(*face_index);
+            return winding.len() >= 3 && (0..winding.len()).all(|i: usize| {
+                let edge: glm::Vec3 = winding[(i + 1) % winding.len()] - winding[i];
+                let inward: glm::Vec3 = glm::normalize(&glm::cross(&normal, &edge));
+                return glm::dot(&inward, &(point - winding[i])) >= -FACE_PICK_EPSILON;
+            });
+        });
+    }
+
+    /// Name of the texture applied to a face, without NUL padding
+    pub fn face_texture_name(&self, face_index: usize) -> String {
+        let texture_info: &bsp30::TextureInfo = &self.texture_infos[self.faces[face_index].texture_info as usize];
+        return self.mip_texture_name(texture_info.mip_tex_index as usize);
+    }
+
+    /// Name of a mip texture, without NUL padding
+    pub fn mip_texture_name(&self, mip_tex_index: usize) -> String {
+        return self.mip_textures[mip_tex_index].name.to_string();
+    }
+
+    /// Aggregate surface statistics over the faces of the world model
+    pub fn stats(&self) -> BspStats {
+        let mut stats: BspStats = BspStats {
+            memory: self.memory_report(),
+            ..BspStats::default()
+        };
+        let world_faces: std::ops::Range<usize> = match self.models.first() {
+            Some(world) => {
+                let first: usize = world.model.first_face.max(0) as usize;
+                first..(first + world.model.face_count.max(0) as usize).min(self.faces.len())
+            },
+            None => 0..self.faces.len(),
+        };
+        for face_index in world_faces {
+            let area: f32 = self.face_area(face_index);
+            stats.world_face_count += 1;
+            stats.total_world_area += area;
+            if stats.largest_face.map_or(true, |(_, largest): (usize, f32)| area > largest) {
+                stats.largest_face = Some((face_index, area));
+            }
+            *stats.faces_per_texture.entry(self.face_texture_name(face_index)).or_insert(0) += 1;
+        }
+        if stats.world_face_count > 0 {
+            stats.average_face_area = stats.total_world_area / stats.world_face_count as f32;
+        }
+        return stats;
+    }
+
+    /// Clip a convex polygon lying in the plane of `winding` to the winding
+    /// with Sutherland-Hodgman, one pass per winding edge. The texture
+    /// coordinates paired with each position are interpolated along with it.
+    pub (crate) fn clip_to_winding(polygon: Vec<(glm::Vec3, glm::Vec2)>, winding: &Vec<glm::Vec3>) -> Vec<(glm::Vec3, glm::Vec2)> {
+        if winding.len() < 3 {
+            return polygon;
+        }
+        let normal: glm::Vec3 = BSP::winding_normal(winding);
+        let mut clipped: Vec<(glm::Vec3, glm::Vec2)> = polygon;
+        for i in 0..winding.len() {
+            if clipped.is_empty() {
+                break;
+            }
+            let start: glm::Vec3 = winding[i];
+            // Points into the winding whichever way round it is, as the normal follows the order
+            let inward: glm::Vec3 = glm::cross(&normal, &(winding[(i + 1) % winding.len()] - start));
+            let input: Vec<(glm::Vec3, glm::Vec2)> = std::mem::take(&mut clipped);
+            for j in 0..input.len() {
+                let current: (glm::Vec3, glm::Vec2) = input[j];
+                let next: (glm::Vec3, glm::Vec2) = input[(j + 1) % input.len()];
+                let current_dist: f32 = glm::dot(&inward, &(current.0 - start));
+                let next_dist: f32 = glm::dot(&inward, &(next.0 - start));
+                if current_dist >= 0.0 {
+                    clipped.push(current);
+                }
+                if (current_dist >= 0.0) != (next_dist >= 0.0) {
+                    let t: f32 = current_dist / (current_dist - next_dist);
+                    clipped.push((current.0 + (next.0 - current.0) * t, current.1 + (next.1 - current.1) * t));
+                }
+            }
+        }
+        return clipped;
+    }
+
+    /// Newell normal of a winding, its length is twice the enclosed area
+    fn winding_normal(winding: &Vec<glm::Vec3>) -> glm::Vec3 {
+        let mut normal: glm::Vec3 = glm::Vec3::zeros();
+        for i in 0..winding.len() {
+            let current: &glm::Vec3 = &winding[i];
+            let next: &glm::Vec3 = &winding[(i + 1) % winding.len()];
+            normal.x += (current.y - next.y) * (current.z + next.z);
+            normal.y += (current.z - next.z) * (current.x + next.x);
+            normal.z += (current.x - next.x) * (current.y + next.y);
+        }
+        return normal;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map::load_micro_map;
+
+    fn micro_map() -> BSP {
+        return load_micro_map(ParseStrictness::Strict).unwrap();
+    }
+
+    #[test]
+    fn negative_surface_edges_start_at_the_second_vertex() {
+        let bsp: BSP = micro_map();
+        // The floor's surface edges are all negative, walking its edges backwards
+        assert_eq!(bsp.face_vertices(&bsp.faces[0]), vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 64.0, 0.0),
+            glm::vec3(64.0, 64.0, 0.0),
+            glm::vec3(64.0, 0.0, 0.0),
+        ]);
+    }
+
+    #[test]
+    fn positive_surface_edges_start_at_the_first_vertex() {
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.face_vertices(&bsp.faces[1]), vec![
+            glm::vec3(0.0, 64.0, 64.0),
+            glm::vec3(64.0, 64.0, 64.0),
+            glm::vec3(64.0, 64.0, 0.0),
+            glm::vec3(0.0, 64.0, 0.0),
+        ]);
+        // Edge 3 runs from vertex 2 to vertex 3, either sign picks the other end
+        assert_eq!(bsp.surface_edge_vertex(3), glm::vec3(64.0, 64.0, 0.0));
+        assert_eq!(bsp.surface_edge_vertex(-3), glm::vec3(0.0, 64.0, 0.0));
+    }
+
+    #[test]
+    fn leaf_faces_resolve_mark_surfaces() {
+        let bsp: BSP = micro_map();
+        let faces: Vec<u16> = bsp.leaf_faces(1).map(|face: &bsp30::Face| face.texture_info).collect();
+        assert_eq!(faces, vec![0, 1]);
+        assert_eq!(bsp.leaf_faces(0).count(), 0);
+        assert_eq!(bsp.leaf_faces(7).count(), 0);
+    }
+
+    #[test]
+    fn model_faces_cover_the_model_range() {
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.model_faces(0).count(), 2);
+        assert_eq!(bsp.model_faces(1).count(), 0);
+    }
+
+    #[test]
+    fn overhanging_quads_are_clipped_to_the_face() {
+        let square: Vec<glm::Vec3> = vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 64.0, 0.0),
+            glm::vec3(64.0, 64.0, 0.0),
+            glm::vec3(64.0, 0.0, 0.0),
+        ];
+        // A 16x16 quad centred on the square's right edge, half of it outside
+        let quad: Vec<(glm::Vec3, glm::Vec2)> = vec![
+            (glm::vec3(56.0, 24.0, 0.0), glm::vec2(0.0, 0.0)),
+            (glm::vec3(72.0, 24.0, 0.0), glm::vec2(1.0, 0.0)),
+            (glm::vec3(72.0, 40.0, 0.0), glm::vec2(1.0, 1.0)),
+            (glm::vec3(56.0, 40.0, 0.0), glm::vec2(0.0, 1.0)),
+        ];
+        let clipped: Vec<(glm::Vec3, glm::Vec2)> = BSP::clip_to_winding(quad, &square);
+        let polygon: Vec<glm::Vec3> = clipped.iter().map(|vertex: &(glm::Vec3, glm::Vec2)| vertex.0).collect();
+        assert_eq!(polygon.len(), 4);
+        assert!((BSP::winding_normal(&polygon).norm() / 2.0 - 128.0).abs() < 1e-3);
+        assert!(polygon.iter().all(|vertex: &glm::Vec3| vertex.x <= 64.0));
+        // Texture coordinates are cut at the same place as the positions
+        assert!(clipped.iter().all(|vertex: &(glm::Vec3, glm::Vec2)| vertex.1.x <= 0.5 + 1e-5));
+    }
+
+    #[test]
+    fn polygons_outside_the_face_are_clipped_away() {
+        let square: Vec<glm::Vec3> = vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(64.0, 0.0, 0.0),
+            glm::vec3(64.0, 64.0, 0.0),
+            glm::vec3(0.0, 64.0, 0.0),
+        ];
+        let triangle: Vec<(glm::Vec3, glm::Vec2)> = vec![
+            (glm::vec3(80.0, 0.0, 0.0), glm::vec2(0.0, 0.0)),
+            (glm::vec3(96.0, 0.0, 0.0), glm::vec2(1.0, 0.0)),
+            (glm::vec3(96.0, 16.0, 0.0), glm::vec2(1.0, 1.0)),
+        ];
+        assert!(BSP::clip_to_winding(triangle, &square).is_empty());
+    }
+
+    #[test]
+    fn windings_turn_counter_clockwise_around_the_face_normal() {
+        let bsp: BSP = micro_map();
+        for face_index in 0..bsp.faces.len() {
+            let winding: Vec<glm::Vec3> = bsp.face_winding(face_index);
+            assert_eq!(winding.len(), 4);
+            let normal: glm::Vec3 = bsp.face_normal(face_index);
+            for i in 0..winding.len() {
+                let turn: glm::Vec3 = glm::cross(
+                    &(winding[(i + 1) % 4] - winding[i]),
+                    &(winding[(i + 2) % 4] - winding[(i + 1) % 4]),
+                );
+                assert!(glm::dot(&turn, &normal) > 0.0, "face {} turns clockwise at {}", face_index, i);
+            }
+        }
+        // The floor's edges run clockwise seen from above, so its winding is reversed
+        assert_eq!(bsp.face_winding(0), vec![
+            glm::vec3(64.0, 0.0, 0.0),
+            glm::vec3(64.0, 64.0, 0.0),
+            glm::vec3(0.0, 64.0, 0.0),
+            glm::vec3(0.0, 0.0, 0.0),
+        ]);
+    }
+
+    #[test]
+    fn coincident_winding_points_are_merged() {
+        let mut bsp: BSP = micro_map();
+        // Pull the floor's corner at the origin onto its neighbour, within the epsilon
+        let corner: usize = bsp.vertices.iter().position(|vertex: &glm::Vec3| *vertex == glm::vec3(0.0, 0.0, 0.0)).unwrap();
+        bsp.vertices[corner] = glm::vec3(64.0, WINDING_EPSILON / 2.0, 0.0);
+        let winding: Vec<glm::Vec3> = bsp.face_winding(0);
+        assert_eq!(winding.len(), 3);
+        assert!((bsp.face_area(0) - 2048.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn axis_aligned_quads_have_their_side_lengths_multiplied() {
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.face_area(0), 4096.0);
+        assert_eq!(bsp.face_area(1), 4096.0);
+        assert_eq!(bsp.face_perimeter(0), 256.0);
+    }
+
+    #[test]
+    fn face_centers_are_the_middle_of_each_quad() {
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.face_center(0), glm::vec3(32.0, 32.0, 0.0));
+        assert_eq!(bsp.face_center(1), glm::vec3(32.0, 64.0, 32.0));
+    }
+
+    #[test]
+    fn points_pick_the_face_they_lie_on() {
+        let bsp: BSP = micro_map();
+        assert_eq!(bsp.face_at(glm::vec3(16.0, 48.0, 0.0)), Some(0));
+        // Traces stop just short of the surface they hit
+        assert_eq!(bsp.face_at(glm::vec3(16.0, 48.0, 0.03125)), Some(0));
+        assert_eq!(bsp.face_at(glm::vec3(40.0, 64.0, 8.0)), Some(1));
+        // On the floor's plane but past its edge, and off every plane
+        assert_eq!(bsp.face_at(glm::vec3(96.0, 32.0, 0.0)), None);
+        assert_eq!(bsp.face_at(glm::vec3(32.0, 32.0, 32.0)), None);
+    }
+
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use crate::map::AssetPaths;
+use crate::map::error::BspError;
+use crate::map::wad::WadProvider;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseStrictness {
+    Strict, // Fail on any spec violation
+    #[default]
+    Lenient, // Log, repair where safely possible and continue
+}
+
+/// Stages reported to a loading progress callback, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoadStage {
+    Lumps,
+    Models,
+    Entities,
+    Textures,
+    Lightmaps,
+    Decals,
+    Visibility,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BspLoadOptions {
+    pub strictness: ParseStrictness,
+    pub vis_threads: usize, // Threads used to decompress vis lists, 0 uses all available cores
+    pub sequential_lumps: bool, // Parse the geometry lumps on the loading thread rather than one thread each
+    pub metadata_only: bool, // Only parse lumps, entities and the texture directory, decoding no pixel data
+    pub skip_vis: bool, // Leave the vis lists empty so no leaf is culled, for maps with corrupt vis data
+    pub wad_provider: Option<Arc<dyn WadProvider>>, // Resolves texture and decal WADs, none loads only embedded textures
+    pub asset_paths: AssetPaths, // WAD and sky directories, and the decal WADs to load
+}
+
+impl BspLoadOptions {
+
+    pub fn strict() -> Self {
+        return BspLoadOptions {
+            strictness: ParseStrictness::Strict,
+            ..Self::default()
+        };
+    }
+
+    pub fn lenient() -> Self {
+        return BspLoadOptions {
+            strictness: ParseStrictness::Lenient,
+            ..Self::default()
+        };
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadIssue {
+    pub context: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoadIssues {
+    issues: Vec<LoadIssue>,
+}
+
+impl LoadIssues {
+
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn record(&mut self, context: &str, message: String) {
+        warn!(&crate::LOGGER, "[{}] {}", context, message);
+        self.issues.push(LoadIssue {
+            context: context.to_string(),
+            message,
+        });
+    }
+
+    /// Fail with the violation when parsing strictly, otherwise record it as
+    /// a tolerated issue so the caller can repair and continue.
+    pub fn violation(&mut self, strictness: ParseStrictness, context: &str, message: String) -> Result<(), BspError> {
+        if strictness == ParseStrictness::Strict {
+            return Err(BspError::Violation {
+                context: context.to_string(),
+                message,
+            });
+        }
+        self.record(context, message);
+        return Ok(());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.issues.is_empty();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.issues.len();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LoadIssue> {
+        return self.issues.iter();
+    }
+
+}
@@ -0,0 +1,116 @@
+use bit_set::BitSet;
+
+use crate::map::animation::{TextureAnimation, TextureTiling};
+use crate::map::bsp::{Decal, FaceLightmaps, FaceTexCoords, BSP};
+use crate::scene::entity::Entity;
+use crate::util::memory::{HeapBytes, MemoryReport, nested_vec_bytes, vec_bytes};
+
+impl HeapBytes for FaceTexCoords {
+    fn heap_bytes(&self) -> usize {
+        return vec_bytes(&self.tex_coords) + vec_bytes(&self.lightmap_coords);
+    }
+}
+
+impl HeapBytes for FaceLightmaps {
+    fn heap_bytes(&self) -> usize {
+        return self.images.iter().flatten().map(|image: &crate::resource::image::Image| image.heap_bytes()).sum();
+    }
+}
+
+impl HeapBytes for Decal {
+    fn heap_bytes(&self) -> usize {
+        return vec_bytes(&self.vec) + vec_bytes(&self.tex_coords);
+    }
+}
+
+impl HeapBytes for Entity {
+    fn heap_bytes(&self) -> usize {
+        // Approximate, ignores the hash table overhead
+        return self.properties.capacity() * std::mem::size_of::<(String, String)>()
+            + self.properties.iter()
+                .map(|(key, value): (&String, &String)| key.capacity() + value.capacity())
+                .sum::<usize>();
+    }
+}
+
+impl HeapBytes for TextureAnimation {
+    fn heap_bytes(&self) -> usize {
+        return self.name.capacity() + vec_bytes(&self.frames) + vec_bytes(&self.toggled);
+    }
+}
+
+impl HeapBytes for TextureTiling {
+    fn heap_bytes(&self) -> usize {
+        return self.name.capacity() + vec_bytes(&self.variants);
+    }
+}
+
+impl HeapBytes for BitSet<u8> {
+    fn heap_bytes(&self) -> usize {
+        return self.get_ref().storage().len();
+    }
+}
+
+impl HeapBytes for BSP {
+    fn heap_bytes(&self) -> usize {
+        return self.memory_report().total();
+    }
+}
+
+impl BSP {
+
+    /// Heap usage of the loaded map data grouped by category
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report: MemoryReport = MemoryReport::new();
+        report.add("geometry", vec_bytes(&self.vertices)
+            + vec_bytes(&self.edges)
+            + vec_bytes(&self.surface_edges)
+            + vec_bytes(&self.faces)
+            + vec_bytes(&self.planes)
+            + vec_bytes(&self.texture_infos));
+        report.add("bsp tree", vec_bytes(&self.nodes)
+            + vec_bytes(&self.leaves)
+            + vec_bytes(&self.mark_surfaces));
+        report.add("clip hulls", vec_bytes(&self.clip_nodes)
+            + vec_bytes(&self.hull_0_clip_nodes)
+            + nested_vec_bytes(&self.models));
+        report.add("textures", nested_vec_bytes(&self.m_textures)
+            + vec_bytes(&self.mip_textures)
+            + vec_bytes(&self.mip_texture_offsets)
+            + vec_bytes(&self.texture_classes)
+            + nested_vec_bytes(&self.texture_animations)
+            + vec_bytes(&self.texture_animation_indices)
+            + nested_vec_bytes(&self.texture_tilings)
+            + vec_bytes(&self.texture_tiling_indices));
+        report.add("texture coords", nested_vec_bytes(&self.face_tex_coords));
+        report.add("lightmaps", nested_vec_bytes(&self.m_lightmaps));
+        report.add("vis", nested_vec_bytes(&self.vis_lists));
+        report.add("entities", nested_vec_bytes(&self.entities)
+            + vec_bytes(&self.brush_entities)
+            + vec_bytes(&self.special_entities)
+            + nested_vec_bytes(&self.m_decals));
+        return report;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map::load_micro_map;
+
+    #[test]
+    fn micro_map_memory_matches_its_lumps() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let report: MemoryReport = bsp.memory_report();
+        // 6 vertices of 12 bytes, 8 edges and 8 surface edges of 4, and 2 each
+        // of 20 byte faces, 20 byte planes and 40 byte texture infos
+        assert_eq!(report.bytes("geometry"), 6 * 12 + 8 * 4 + 8 * 4 + 2 * 20 + 2 * 20 + 2 * 40);
+        // A 5x5 RGB lightmap per face on top of the per face slots
+        assert_eq!(report.bytes("lightmaps"), vec_bytes(&bsp.m_lightmaps) + 2 * 5 * 5 * 3);
+        assert_eq!(report.bytes("vis"), 0);
+        assert_eq!(bsp.heap_bytes(), report.total());
+    }
+
+}
@@ -0,0 +1,297 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::map::bsp::BSP;
+use crate::map::error::BspError;
+use crate::map::load_options::{BspLoadOptions, ParseStrictness};
+use crate::map::wad::{Wad, WadProvider};
+
+// A two face room and the WAD with its two textures, built by the tests below
+// so the map loads anywhere without game assets
+pub const MICRO_MAP_NAME: &str = "micro.bsp";
+pub const MICRO_WAD_NAME: &str = "micro.wad";
+pub const MICRO_MAP: &[u8] = include_bytes!("fixtures/micro.bsp");
+pub const MICRO_WAD: &[u8] = include_bytes!("fixtures/micro.wad");
+// Textures the micro map's faces use, all from the embedded WAD
+pub const MICRO_TEXTURES: [&str; 2] = ["floor", "wall"];
+
+/// Serves the embedded micro WAD, every other WAD is missing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbeddedWads;
+
+impl WadProvider for EmbeddedWads {
+    fn open_wad(&self, name: &str) -> Result<Wad, BspError> {
+        if !name.eq_ignore_ascii_case(MICRO_WAD_NAME) {
+            return Err(BspError::InvalidWad {
+                path: PathBuf::from(name),
+                reason: String::from("only the micro map's WAD is embedded"),
+            });
+        }
+        return Wad::from_reader(PathBuf::from(MICRO_WAD_NAME), Cursor::new(MICRO_WAD));
+    }
+}
+
+/// Load the embedded micro map with its textures from the embedded WAD
+pub fn load_micro_map(strictness: ParseStrictness) -> Result<BSP, BspError> {
+    let options: BspLoadOptions = BspLoadOptions {
+        strictness,
+        wad_provider: Some(Arc::new(EmbeddedWads)),
+        ..BspLoadOptions::default()
+    };
+    return BSP::from_reader_with_options(Cursor::new(MICRO_MAP), &options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use crate::map::bsp30;
+    use crate::map::wad::TextureSource;
+
+    const TEXTURE_SIZE: u32 = 16;
+    const ROOM_SIZE: f32 = 64.0;
+    // Luxels per side of each face's lightmap, 64 units at one per 16 plus one
+    const LIGHTMAP_SIZE: usize = 5;
+
+    fn write_name(bytes: &mut Vec<u8>, name: &str) {
+        let mut raw: [u8; bsp30::MAX_TEXTURE_NAME] = [0; bsp30::MAX_TEXTURE_NAME];
+        raw[..name.len()].copy_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&raw);
+    }
+
+    fn write_vec3(bytes: &mut Vec<u8>, v: [f32; 3]) {
+        for component in v {
+            bytes.write_f32::<LittleEndian>(component).unwrap();
+        }
+    }
+
+    /// WAD3 mip texture entry of `pattern` indices with a two colour palette
+    fn mip_texture(name: &str, colours: [[u8; 3]; 2], pattern: impl Fn(u32, u32) -> u8) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        write_name(&mut bytes, name);
+        bytes.write_u32::<LittleEndian>(TEXTURE_SIZE).unwrap();
+        bytes.write_u32::<LittleEndian>(TEXTURE_SIZE).unwrap();
+        let mut offset: u32 = 40;
+        for level in 0..bsp30::MIP_LEVELS as u32 {
+            bytes.write_u32::<LittleEndian>(offset).unwrap();
+            offset += (TEXTURE_SIZE >> level) * (TEXTURE_SIZE >> level);
+        }
+        for level in 0..bsp30::MIP_LEVELS as u32 {
+            let size: u32 = TEXTURE_SIZE >> level;
+            for y in 0..size {
+                for x in 0..size {
+                    bytes.push(pattern(x << level, y << level));
+                }
+            }
+        }
+        bytes.write_u16::<LittleEndian>(256).unwrap();
+        for index in 0..256 {
+            bytes.extend_from_slice(&colours.get(index).copied().unwrap_or([0, 0, 0]));
+        }
+        return bytes;
+    }
+
+    fn build_wad() -> Vec<u8> {
+        let textures: [(&str, Vec<u8>); 2] = [
+            ("floor", mip_texture("floor", [[96, 96, 96], [160, 140, 100]], |x: u32, y: u32| ((x / 4 + y / 4) % 2) as u8)),
+            ("wall", mip_texture("wall", [[120, 40, 30], [200, 180, 160]], |_: u32, y: u32| (y % 4 == 0) as u8)),
+        ];
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(b"WAD3");
+        bytes.write_i32::<LittleEndian>(textures.len() as i32).unwrap();
+        let dir_offset: usize = 12 + textures.iter().map(|(_, data): &(&str, Vec<u8>)| data.len()).sum::<usize>();
+        bytes.write_i32::<LittleEndian>(dir_offset as i32).unwrap();
+        let mut positions: Vec<usize> = Vec::new();
+        for (_, data) in textures.iter() {
+            positions.push(bytes.len());
+            bytes.extend_from_slice(data);
+        }
+        for ((name, data), position) in textures.iter().zip(positions) {
+            bytes.write_i32::<LittleEndian>(position as i32).unwrap();
+            bytes.write_i32::<LittleEndian>(data.len() as i32).unwrap();
+            bytes.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+            bytes.write_u8(0x43).unwrap();
+            bytes.write_u8(0).unwrap();
+            bytes.write_i16::<LittleEndian>(0).unwrap();
+            write_name(&mut bytes, name);
+        }
+        return bytes;
+    }
+
+    /// A floor and the wall at its far edge, both lit and textured from the
+    /// micro WAD, in one empty leaf with no visibility data
+    fn build_bsp() -> Vec<u8> {
+        let s: f32 = ROOM_SIZE;
+        let mut lumps: [Vec<u8>; bsp30::LumpType::HeaderLumps as usize] = Default::default();
+        let entities: String = format!(
+            "{{\n\"classname\" \"worldspawn\"\n\"wad\" \"{}\"\n}}\n{{\n\"classname\" \"info_player_start\"\n\"origin\" \"{} 8 36\"\n\"angle\" \"90\"\n}}\n\0",
+            MICRO_WAD_NAME,
+            s / 2.0,
+        );
+        lumps[bsp30::LumpType::LumpEntities as usize] = entities.into_bytes();
+        // Planes, the floor facing up and the wall facing away from the room
+        let planes: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpPlanes as usize];
+        for (normal, dist, r#type) in [([0.0, 0.0, 1.0], 0.0, 2), ([0.0, 1.0, 0.0], s, 1)] {
+            write_vec3(planes, normal);
+            planes.write_f32::<LittleEndian>(dist).unwrap();
+            planes.write_i32::<LittleEndian>(r#type).unwrap();
+        }
+        // Texture directory of two WAD textures, mip offsets of 0 mean external
+        let textures: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpTextures as usize];
+        textures.write_u32::<LittleEndian>(MICRO_TEXTURES.len() as u32).unwrap();
+        for i in 0..MICRO_TEXTURES.len() {
+            textures.write_i32::<LittleEndian>(4 + 4 * MICRO_TEXTURES.len() as i32 + 40 * i as i32).unwrap();
+        }
+        for name in MICRO_TEXTURES {
+            write_name(textures, name);
+            textures.write_u32::<LittleEndian>(TEXTURE_SIZE).unwrap();
+            textures.write_u32::<LittleEndian>(TEXTURE_SIZE).unwrap();
+            textures.extend_from_slice(&[0u8; 16]);
+        }
+        let vertices: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpVertexes as usize];
+        for vertex in [[0.0, 0.0, 0.0], [s, 0.0, 0.0], [s, s, 0.0], [0.0, s, 0.0], [0.0, s, s], [s, s, s]] {
+            write_vec3(vertices, vertex);
+        }
+        // One node splitting the empty leaf 1 above the floor from the solid leaf 0
+        let nodes: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpNodes as usize];
+        nodes.write_u32::<LittleEndian>(0).unwrap();
+        for value in [-2i16, -1, 0, 0, 0, s as i16, s as i16, s as i16] {
+            nodes.write_i16::<LittleEndian>(value).unwrap();
+        }
+        nodes.write_u16::<LittleEndian>(0).unwrap();
+        nodes.write_u16::<LittleEndian>(2).unwrap();
+        let texinfo: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpTexinfo as usize];
+        for (s_axis, t_axis, mip_tex) in [([1.0, 0.0, 0.0], [0.0, -1.0, 0.0], 0u32), ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], 1)] {
+            write_vec3(texinfo, s_axis);
+            texinfo.write_f32::<LittleEndian>(0.0).unwrap();
+            write_vec3(texinfo, t_axis);
+            texinfo.write_f32::<LittleEndian>(0.0).unwrap();
+            texinfo.write_u32::<LittleEndian>(mip_tex).unwrap();
+            texinfo.write_u32::<LittleEndian>(0).unwrap();
+        }
+        // Faces wound clockwise seen from the room, each with one lightmap style
+        let lightmap_bytes: usize = LIGHTMAP_SIZE * LIGHTMAP_SIZE * 3;
+        let faces: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpFaces as usize];
+        for (plane, side, first_edge, texture_info, lightmap_offset) in [(0u16, 0u16, 0u32, 0u16, 0u32), (1, 1, 4, 1, lightmap_bytes as u32)] {
+            faces.write_u16::<LittleEndian>(plane).unwrap();
+            faces.write_u16::<LittleEndian>(side).unwrap();
+            faces.write_u32::<LittleEndian>(first_edge).unwrap();
+            faces.write_u16::<LittleEndian>(4).unwrap();
+            faces.write_u16::<LittleEndian>(texture_info).unwrap();
+            faces.extend_from_slice(&[0, 255, 255, 255]);
+            faces.write_u32::<LittleEndian>(lightmap_offset).unwrap();
+        }
+        // Light falling off across the floor and up the wall
+        let lighting: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpLighting as usize];
+        for face in 0..2 {
+            for y in 0..LIGHTMAP_SIZE {
+                for x in 0..LIGHTMAP_SIZE {
+                    let level: u8 = (255 - (x + y) * 16 - face * 40) as u8;
+                    lighting.extend_from_slice(&[level, level, (level as f32 * 0.9) as u8]);
+                }
+            }
+        }
+        // Hulls 1 to 3 share one clip node with the same split as the node
+        let clip_nodes: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpClipNodes as usize];
+        clip_nodes.write_i32::<LittleEndian>(0).unwrap();
+        clip_nodes.write_i16::<LittleEndian>(bsp30::ContentType::ContentsEmpty as i16).unwrap();
+        clip_nodes.write_i16::<LittleEndian>(bsp30::ContentType::ContentsSolid as i16).unwrap();
+        let leaves: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpLeaves as usize];
+        for (content, mark_surface_count) in [(bsp30::ContentType::ContentsSolid as i32, 0u16), (bsp30::ContentType::ContentsEmpty as i32, 2)] {
+            leaves.write_i32::<LittleEndian>(content).unwrap();
+            leaves.write_i32::<LittleEndian>(-1).unwrap();
+            for value in [0i16, 0, 0, s as i16, s as i16, s as i16] {
+                leaves.write_i16::<LittleEndian>(value).unwrap();
+            }
+            leaves.write_u16::<LittleEndian>(0).unwrap();
+            leaves.write_u16::<LittleEndian>(mark_surface_count).unwrap();
+            leaves.extend_from_slice(&[0; 4]);
+        }
+        let mark_surfaces: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpMarkSurfaces as usize];
+        for face in [0u16, 1] {
+            mark_surfaces.write_u16::<LittleEndian>(face).unwrap();
+        }
+        // Edge 0 is never referenced, its negation would be itself
+        let edges: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpEdges as usize];
+        for edge in [[0u16, 0], [0, 1], [1, 2], [2, 3], [3, 0], [4, 5], [5, 2], [3, 4]] {
+            edges.write_u16::<LittleEndian>(edge[0]).unwrap();
+            edges.write_u16::<LittleEndian>(edge[1]).unwrap();
+        }
+        let surface_edges: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpSurfaceEdges as usize];
+        for surface_edge in [-4i32, -3, -2, -1, 5, 6, 3, 7] {
+            surface_edges.write_i32::<LittleEndian>(surface_edge).unwrap();
+        }
+        let models: &mut Vec<u8> = &mut lumps[bsp30::LumpType::LumpModels as usize];
+        write_vec3(models, [0.0, 0.0, 0.0]);
+        write_vec3(models, [s, s, s]);
+        write_vec3(models, [0.0, 0.0, 0.0]);
+        for value in [0i32, 0, 0, 0, 1, 0, 2] {
+            models.write_i32::<LittleEndian>(value).unwrap();
+        }
+        // Header, then every lump in order at four byte alignment
+        let header_size: usize = 4 + lumps.len() * 8;
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.write_i32::<LittleEndian>(30).unwrap();
+        let mut offset: usize = header_size;
+        for lump in lumps.iter() {
+            bytes.write_i32::<LittleEndian>(offset as i32).unwrap();
+            bytes.write_i32::<LittleEndian>(lump.len() as i32).unwrap();
+            offset += (lump.len() + 3) & !3;
+        }
+        for lump in lumps.iter() {
+            bytes.extend_from_slice(lump);
+            bytes.resize((bytes.len() + 3) & !3, 0);
+        }
+        return bytes;
+    }
+
+    /// Set LAMBDA_WRITE_FIXTURES to regenerate the embedded files after changing the builders
+    #[test]
+    fn embedded_fixtures_match_the_builders() {
+        let fixtures: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/map/fixtures");
+        let (bsp, wad): (Vec<u8>, Vec<u8>) = (build_bsp(), build_wad());
+        if std::env::var_os("LAMBDA_WRITE_FIXTURES").is_some() {
+            std::fs::create_dir_all(&fixtures).unwrap();
+            std::fs::write(fixtures.join(MICRO_MAP_NAME), &bsp).unwrap();
+            std::fs::write(fixtures.join(MICRO_WAD_NAME), &wad).unwrap();
+            return;
+        }
+        assert!(MICRO_MAP == bsp.as_slice(), "micro.bsp is stale, regenerate it with LAMBDA_WRITE_FIXTURES=1");
+        assert!(MICRO_WAD == wad.as_slice(), "micro.wad is stale, regenerate it with LAMBDA_WRITE_FIXTURES=1");
+    }
+
+    #[test]
+    fn micro_map_loads_strictly_with_its_textures_and_lightmaps() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        assert_eq!(bsp.faces.len(), 2);
+        assert_eq!(bsp.leaves.len(), 2);
+        assert_eq!(bsp.models.len(), 1);
+        assert!(bsp.missing_textures.is_empty());
+        for (texture, name) in bsp.m_textures.iter().zip(MICRO_TEXTURES) {
+            assert_eq!(texture.name, name);
+            assert_eq!(texture.source, TextureSource::Wad(PathBuf::from(MICRO_WAD_NAME)));
+            assert_eq!((texture.img[0].width, texture.img[0].height), (16, 16));
+        }
+        for lightmaps in bsp.m_lightmaps.iter() {
+            let image = lightmaps.images[0].as_ref().expect("face has no lightmap");
+            assert_eq!((image.width, image.height), (LIGHTMAP_SIZE, LIGHTMAP_SIZE));
+        }
+    }
+
+    #[test]
+    fn micro_map_spawn_is_inside_the_empty_leaf() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        assert_eq!(bsp.find_leaf(glm::vec3(32.0, 8.0, 36.0), 0), Some(1));
+        assert_eq!(bsp.find_leaf(glm::vec3(32.0, 8.0, -4.0), 0), Some(0));
+        assert_eq!(bsp.point_contents(0, glm::vec3(32.0, 8.0, 36.0)), bsp30::ContentType::ContentsEmpty);
+    }
+
+    #[test]
+    fn other_wads_are_not_embedded() {
+        assert!(EmbeddedWads.open_wad("halflife.wad").is_err());
+        assert!(EmbeddedWads.open_wad("MICRO.WAD").is_ok());
+    }
+
+}
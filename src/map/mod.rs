@@ -1,4 +1,18 @@
 pub mod bsp30;
+pub mod quake_palette;
 pub mod bsp;
 pub mod wad;
 pub mod bsp_renderable;
+pub mod load_options;
+pub mod error;
+pub mod dependencies;
+pub mod geometry;
+pub mod animation;
+pub mod visibility;
+pub mod trace;
+pub mod memory;
+pub mod export;
+pub mod asset_paths;
+pub mod micro_map;
+
+pub use asset_paths::AssetPaths;
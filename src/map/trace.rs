@@ -0,0 +1,192 @@
+use crate::map::bsp::{BSP, Hull, Model};
+use crate::map::bsp30;
+
+// Keeps impact points off the plane so the next trace does not start inside it
+const DIST_EPSILON: f32 = 0.03125;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TraceResult {
+    pub all_solid: bool, // The whole trace was inside solid
+    pub start_solid: bool, // The trace started inside solid
+    pub fraction: f32, // Portion of the segment travelled, 1.0 when nothing was hit
+    pub end_pos: glm::Vec3,
+    pub plane: Option<bsp30::Plane>, // Plane of the impact, facing the start of the trace
+    pub contents: bsp30::ContentType, // Contents at the end position
+}
+
+impl TraceResult {
+
+    fn new(end: glm::Vec3) -> Self {
+        return TraceResult {
+            all_solid: true,
+            start_solid: false,
+            fraction: 1.0,
+            end_pos: end,
+            plane: None,
+            contents: bsp30::ContentType::ContentsSolid,
+        };
+    }
+
+    pub fn hit(&self) -> bool {
+        return self.fraction < 1.0;
+    }
+
+}
+
+impl BSP {
+
+    /// Trace a segment through one of the world's clip hulls, stopping at the
+    /// first solid surface
+    pub fn trace_hull(&self, hull_index: usize, start: glm::Vec3, end: glm::Vec3) -> TraceResult {
+        let mut trace: TraceResult = TraceResult::new(end);
+        let Some(hull) = self.models.first().and_then(|world: &Model| world.hulls.get(hull_index)) else {
+            trace.start_solid = true;
+            trace.fraction = 0.0;
+            trace.end_pos = start;
+            return trace;
+        };
+        let head_node: i32 = hull.first_clip_node as i32;
+        hull.recursive_hull_check(head_node, 0.0, 1.0, start, end, &mut trace, 0);
+        if trace.all_solid {
+            trace.start_solid = true;
+            trace.fraction = 0.0;
+            trace.end_pos = start;
+        }
+        trace.contents = hull.point_contents(head_node, trace.end_pos);
+        return trace;
+    }
+
+    /// Trace a point sized segment through hull 0
+    pub fn trace_line(&self, start: glm::Vec3, end: glm::Vec3) -> TraceResult {
+        return self.trace_hull(0, start, end);
+    }
+
+}
+
+impl Hull {
+
+    /// Clip the part of the trace from `p1` to `p2`, at fractions `p1f` and
+    /// `p2f` of the whole trace, against the subtree at `node`. Returns false
+    /// once an impact has been found.
+    fn recursive_hull_check(&self,
+                            node: i32,
+                            p1f: f32,
+                            p2f: f32,
+                            p1: glm::Vec3,
+                            p2: glm::Vec3,
+                            trace: &mut TraceResult,
+                            depth: usize) -> bool {
+        if node < 0 {
+            if node != bsp30::ContentType::ContentsSolid as i32 {
+                trace.all_solid = false;
+            } else {
+                trace.start_solid = true;
+            }
+            return true;
+        }
+        let clip_node: Option<&bsp30::ClipNode> = self.clip_nodes.get(node as usize);
+        let plane: Option<&bsp30::Plane> = clip_node.and_then(|clip_node: &bsp30::ClipNode| self.planes.get(clip_node.plane_index as usize));
+        let (Some(clip_node), Some(plane)) = (clip_node, plane) else {
+            warn!(&crate::LOGGER, "Clip node {} or its plane is out of range, stopping trace", node);
+            return false;
+        };
+        if depth > self.clip_nodes.len() {
+            warn!(&crate::LOGGER, "Clip node tree does not terminate at node {}, stopping trace", node);
+            return false;
+        }
+        let t1: f32 = Hull::plane_distance(plane, &p1);
+        let t2: f32 = Hull::plane_distance(plane, &p2);
+        if t1 >= 0.0 && t2 >= 0.0 {
+            return self.recursive_hull_check(clip_node.child_index[0] as i32, p1f, p2f, p1, p2, trace, depth + 1);
+        }
+        if t1 < 0.0 && t2 < 0.0 {
+            return self.recursive_hull_check(clip_node.child_index[1] as i32, p1f, p2f, p1, p2, trace, depth + 1);
+        }
+        // Split at the crossing, nudged back towards the near side
+        let mut frac: f32 = if t1 < 0.0 {
+            (t1 + DIST_EPSILON) / (t1 - t2)
+        } else {
+            (t1 - DIST_EPSILON) / (t1 - t2)
+        }.clamp(0.0, 1.0);
+        let mut midf: f32 = p1f + (p2f - p1f) * frac;
+        let mut mid: glm::Vec3 = p1 + (p2 - p1) * frac;
+        let side: usize = if t1 < 0.0 { 1 } else { 0 };
+        if !self.recursive_hull_check(clip_node.child_index[side] as i32, p1f, midf, p1, mid, trace, depth + 1) {
+            return false;
+        }
+        let far_node: i32 = clip_node.child_index[side ^ 1] as i32;
+        if self.point_contents(far_node, mid) != bsp30::ContentType::ContentsSolid {
+            return self.recursive_hull_check(far_node, midf, p2f, mid, p2, trace, depth + 1);
+        }
+        if trace.all_solid {
+            // Never got out of the solid area
+            return false;
+        }
+        // The far side is solid, this is the impact point
+        trace.plane = Some(if side == 0 {
+            *plane
+        } else {
+            bsp30::Plane {
+                normal: -plane.normal,
+                dist: -plane.dist,
+                r#type: plane.r#type,
+            }
+        });
+        // Float error can leave the midpoint inside solid, back off until it is not
+        while self.point_contents(self.first_clip_node as i32, mid) == bsp30::ContentType::ContentsSolid {
+            frac -= 0.1;
+            if frac < 0.0 {
+                trace.fraction = midf;
+                trace.end_pos = mid;
+                debug!(&crate::LOGGER, "Trace backed up past the start of the split");
+                return false;
+            }
+            midf = p1f + (p2f - p1f) * frac;
+            mid = p1 + (p2 - p1) * frac;
+        }
+        trace.fraction = midf;
+        trace.end_pos = mid;
+        return false;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map::load_micro_map;
+
+    #[test]
+    fn traces_stop_just_above_the_floor() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let trace: TraceResult = bsp.trace_line(glm::vec3(32.0, 8.0, 64.0), glm::vec3(32.0, 8.0, -64.0));
+        assert!(trace.hit());
+        assert!(!trace.start_solid && !trace.all_solid);
+        assert!((trace.fraction - (64.0 - DIST_EPSILON) / 128.0).abs() < 1e-5, "fraction {}", trace.fraction);
+        assert!((trace.end_pos.z - DIST_EPSILON).abs() < 1e-4);
+        assert_eq!(trace.plane.map(|plane: bsp30::Plane| plane.normal), Some(glm::vec3(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn traces_through_empty_space_travel_the_whole_segment() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let end: glm::Vec3 = glm::vec3(40.0, 20.0, 4.0);
+        let trace: TraceResult = bsp.trace_line(glm::vec3(32.0, 8.0, 64.0), end);
+        assert!(!trace.hit());
+        assert_eq!(trace.end_pos, end);
+        assert!(trace.plane.is_none());
+        assert_eq!(trace.contents, bsp30::ContentType::ContentsEmpty);
+    }
+
+    #[test]
+    fn traces_starting_in_solid_are_flagged() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        let trace: TraceResult = bsp.trace_line(glm::vec3(32.0, 8.0, -16.0), glm::vec3(32.0, 8.0, 16.0));
+        assert!(trace.start_solid);
+        assert!(!trace.all_solid);
+        let buried: TraceResult = bsp.trace_line(glm::vec3(32.0, 8.0, -16.0), glm::vec3(32.0, 8.0, -8.0));
+        assert!(buried.start_solid && buried.all_solid);
+    }
+
+}
@@ -0,0 +1,115 @@
+use bit_set::BitSet;
+
+use crate::map::bsp::BSP;
+use crate::map::bsp30;
+
+impl BSP {
+
+    /// Leaf of the world containing a point
+    pub fn leaf_at(&self, pos: glm::Vec3) -> Option<usize> {
+        return self.find_leaf(pos, 0);
+    }
+
+    /// Decompressed PVS of a leaf, `None` when the leaf has no vis data
+    fn leaf_pvs(&self, leaf: usize) -> Option<&BitSet<u8>> {
+        if leaf == 0 || self.leaves.get(leaf)?.vis_offset < 0 {
+            return None;
+        }
+        return self.vis_lists.get(leaf - 1);
+    }
+
+    fn is_solid_leaf(&self, leaf: usize) -> bool {
+        return self.leaves.get(leaf)
+            .map_or(true, |data: &bsp30::Leaf| data.content == bsp30::ContentType::ContentsSolid as i32);
+    }
+
+    /// Leaves potentially visible from `leaf`. A leaf without vis data sees
+    /// every non-solid leaf, as maps compiled without vis do in the engine.
+    pub fn visible_leaves(&self, leaf: usize) -> impl Iterator<Item = usize> + '_ {
+        let pvs: Option<&BitSet<u8>> = self.leaf_pvs(leaf);
+        let novis: Option<std::ops::Range<usize>> = if pvs.is_none() && leaf < self.leaves.len() {
+            Some(1..self.leaves.len())
+        } else {
+            None
+        };
+        // Bit N - 1 of a PVS row is leaf N
+        return pvs.into_iter()
+            .flat_map(|pvs: &BitSet<u8>| pvs.iter().map(|bit: usize| bit + 1))
+            .chain(novis.into_iter().flatten().filter(move |other: &usize| !self.is_solid_leaf(*other)));
+    }
+
+    pub fn is_leaf_visible_from(&self, from: usize, to: usize) -> bool {
+        if to == 0 || to >= self.leaves.len() || from >= self.leaves.len() {
+            return false;
+        }
+        return match self.leaf_pvs(from) {
+            Some(pvs) => from == to || pvs.contains(to - 1),
+            None => !self.is_solid_leaf(to),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::load_options::ParseStrictness;
+    use crate::map::micro_map::load_micro_map;
+
+    fn leaf(content: bsp30::ContentType, vis_offset: i32) -> bsp30::Leaf {
+        return bsp30::Leaf {
+            content: content as i32,
+            vis_offset,
+            lower: [0; 3],
+            upper: [0; 3],
+            first_mark_surface: 0,
+            mark_surface_count: 0,
+            ambient_levels: [0; 4],
+        };
+    }
+
+    /// Micro map with leaf 1 seeing leaf 2, leaf 2 seeing leaves 1 and 3,
+    /// leaf 3 without vis data and a solid leaf 4
+    fn vis_map() -> BSP {
+        let mut bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        bsp.leaves = vec![
+            leaf(bsp30::ContentType::ContentsSolid, -1),
+            leaf(bsp30::ContentType::ContentsEmpty, 0),
+            leaf(bsp30::ContentType::ContentsEmpty, 1),
+            leaf(bsp30::ContentType::ContentsWater, -1),
+            leaf(bsp30::ContentType::ContentsSolid, -1),
+        ];
+        bsp.vis_lists = [vec![1], vec![0, 2], vec![], vec![]].into_iter()
+            .map(|bits: Vec<usize>| bits.into_iter().collect::<BitSet<u8>>())
+            .collect();
+        return bsp;
+    }
+
+    #[test]
+    fn leaf_at_finds_the_leaf_containing_a_point() {
+        let bsp: BSP = load_micro_map(ParseStrictness::Strict).unwrap();
+        assert_eq!(bsp.leaf_at(glm::vec3(32.0, 8.0, 36.0)), Some(1));
+        assert_eq!(bsp.leaf_at(glm::vec3(32.0, 8.0, -36.0)), Some(0));
+    }
+
+    #[test]
+    fn visible_leaves_follow_the_pvs() {
+        let bsp: BSP = vis_map();
+        assert_eq!(bsp.visible_leaves(1).collect::<Vec<usize>>(), vec![2]);
+        assert_eq!(bsp.visible_leaves(2).collect::<Vec<usize>>(), vec![1, 3]);
+        assert!(bsp.is_leaf_visible_from(2, 3));
+        assert!(!bsp.is_leaf_visible_from(1, 3));
+        assert!(bsp.is_leaf_visible_from(1, 1));
+    }
+
+    #[test]
+    fn leaves_without_vis_data_see_every_non_solid_leaf() {
+        let bsp: BSP = vis_map();
+        assert_eq!(bsp.visible_leaves(3).collect::<Vec<usize>>(), vec![1, 2, 3]);
+        assert!(bsp.is_leaf_visible_from(3, 1));
+        assert!(!bsp.is_leaf_visible_from(3, 4));
+        assert!(!bsp.is_leaf_visible_from(3, 0));
+        assert_eq!(bsp.visible_leaves(9).count(), 0);
+    }
+
+}
@@ -1,10 +1,17 @@
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
+use std::fmt::Debug;
 use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::map::bsp30;
+use crate::map::error::BspError;
+use crate::map::quake_palette::QUAKE_PALETTE;
 use crate::resource::image::Image;
+use crate::util::memory::HeapBytes;
 use crate::resource::resource::{read_char_array, Resource};
 
 #[derive(Debug)]
@@ -34,6 +41,27 @@ impl Resource for WadHeader {
     }
 }
 
+/// Lump types found in WAD directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum WadEntryType {
+    Palette = 0x40,
+    QPic = 0x42,
+    MipTex = 0x43, // WAD3, followed by its own palette
+    QuakeMipTex = 0x44, // WAD2, uses the Quake palette
+    Font = 0x46,
+}
+
+#[derive(Debug, Clone)]
+pub struct WadEntryInfo {
+    pub name: String,
+    pub entry_type: Option<WadEntryType>, // None for types this loader does not know
+    pub raw_type: u8,
+    pub compressed: bool,
+    pub disk_size: usize,
+    pub size: usize, // Uncompressed size
+    pub dimensions: Option<(u32, u32)>, // Width and height of mip texture entries
+}
+
 #[derive(Debug, Default)]
 pub struct WadDirEntry {
     pub n_file_pos: i32,
@@ -42,7 +70,7 @@ pub struct WadDirEntry {
     pub r#type: u8,
     pub compressed: bool,
     pub n_dummy: i16,
-    pub name: [u8; bsp30::MAX_TEXTURE_NAME],
+    pub name: bsp30::TextureName,
 }
 
 impl Resource for WadDirEntry {
@@ -64,121 +92,314 @@ impl Resource for WadDirEntry {
             r#type,
             compressed,
             n_dummy,
-            name,
+            name: bsp30::TextureName(name),
         });
     }
 }
 
+/// Where the texels of a loaded texture came from
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TextureSource {
+    #[default]
+    Missing, // Placeholder standing in for a texture that failed to load
+    Embedded, // Stored in the BSP's texture lump
+    Wad(PathBuf), // Path of the WAD it was read from
+}
+
+#[derive(Clone)]
 pub struct MipmapTexture {
     pub img: [Image; bsp30::MIP_LEVELS],
+    pub masked: bool, // Named with a `{` prefix, palette index 255 is transparent
+    pub name: String, // As stored in the texture's header
+    pub source: TextureSource,
 }
 
 impl MipmapTexture {
     pub fn new() -> MipmapTexture {
         return MipmapTexture {
             img: [(); bsp30::MIP_LEVELS].map(|_| Image::new()),
+            masked: false,
+            name: String::new(),
+            source: TextureSource::Missing,
         };
     }
 }
 
+// Size used for placeholders when the declared size is unusable
+const PLACEHOLDER_SIZE: u32 = 64;
+const PLACEHOLDER_CHECKS: usize = 8; // Checks along each side of the top mip level
+
+impl MipmapTexture {
+
+    /// Magenta and black checkerboard with valid mip levels, standing in for
+    /// a texture that failed to load
+    pub fn placeholder(width: u32, height: u32) -> MipmapTexture {
+        let mut width: usize = if width == 0 { PLACEHOLDER_SIZE } else { width } as usize;
+        let mut height: usize = if height == 0 { PLACEHOLDER_SIZE } else { height } as usize;
+        let check: usize = (width.min(height) / PLACEHOLDER_CHECKS).max(1);
+        let mut mip_tex: MipmapTexture = MipmapTexture::new();
+        for level in 0..bsp30::MIP_LEVELS {
+            let level_check: usize = (check >> level).max(1);
+            let img: &mut Image = &mut mip_tex.img[level];
+            img.channels = 4;
+            img.width = width;
+            img.height = height;
+            img.data = Vec::with_capacity(width * height * 4);
+            for y in 0..height {
+                for x in 0..width {
+                    let magenta: bool = (x / level_check + y / level_check) % 2 == 0;
+                    img.data.extend_from_slice(if magenta { &[255, 0, 255, 255] } else { &[0, 0, 0, 255] });
+                }
+            }
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+        }
+        return mip_tex;
+    }
+
+}
+
+impl HeapBytes for MipmapTexture {
+    fn heap_bytes(&self) -> usize {
+        return self.img.iter().map(|img: &Image| img.heap_bytes()).sum::<usize>() + self.name.capacity();
+    }
+}
+
+pub trait WadSource: Read + Seek {}
+
+impl<R: Read + Seek> WadSource for R {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl std::ops::AddAssign for CacheStats {
+    fn add_assign(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
+}
+
 pub struct Wad {
-    pub(crate) wad_file: BufReader<File>,
-    pub(crate) dir_entries: HashMap<String, WadDirEntry>,
+    pub(crate) path: PathBuf,
+    pub(crate) wad_file: RefCell<BufReader<Box<dyn WadSource>>>, // Shared so entries can be inspected through &self
+    pub(crate) dir_entries: HashMap<bsp30::TextureName, WadDirEntry>,
+    texture_cache: HashMap<bsp30::TextureName, Arc<MipmapTexture>>, // Decoded textures, keyed case insensitively
+    decal_cache: HashMap<bsp30::TextureName, Arc<MipmapTexture>>, // Decals decode differently, so are cached apart
+    cache_stats: CacheStats,
 }
 
 impl Wad {
-    pub fn new(path: &String) -> Wad {
-        let wad_file: File = match OpenOptions::new().read(true).open(&path) {
-            Ok(file) => {
-                if file.metadata().unwrap().is_dir() {
-                    panic!("Cannot read WAD from path pointing to directory: {}", path);
-                }
-                file
-            }
-            Err(error) => panic!("Unable to read WAD file at {}: {}", path, error,),
-        };
+    pub fn open(path: impl AsRef<Path>) -> Result<Wad, BspError> {
+        let path: &Path = path.as_ref();
+        let wad_file: File = OpenOptions::new().read(true).open(path)?;
+        if wad_file.metadata()?.is_dir() {
+            return Err(BspError::InvalidWad {
+                path: path.to_path_buf(),
+                reason: String::from("path points to a directory"),
+            });
+        }
+        return Wad::from_reader(path.to_path_buf(), wad_file);
+    }
+
+    /// Read a WAD from any seekable source, `path` is only used to identify it
+    pub fn from_reader(path: PathBuf, reader: impl Read + Seek + 'static) -> Result<Wad, BspError> {
         let mut wad: Wad = Wad {
-            wad_file: BufReader::new(wad_file),
+            path,
+            wad_file: RefCell::new(BufReader::new(Box::new(reader))),
             dir_entries: HashMap::new(),
+            texture_cache: HashMap::new(),
+            decal_cache: HashMap::new(),
+            cache_stats: CacheStats::default(),
         };
-        wad.load_directory();
-        return wad;
+        wad.load_directory()?;
+        return Ok(wad);
     }
 
-    pub fn load_texture(&mut self, name: &String) -> Option<MipmapTexture> {
-        let raw_texture: Vec<u8> = self.get_texture(name);
-        if raw_texture.is_empty() {
-            return None;
+    /// Decode a texture, reading it from the WAD only the first time it is requested
+    pub fn load_texture(&mut self, name: &String) -> Result<Arc<MipmapTexture>, BspError> {
+        let key: bsp30::TextureName = Self::texture_key(name)?;
+        if let Some(texture) = self.texture_cache.get(&key) {
+            self.cache_stats.hits += 1;
+            return Ok(Arc::clone(texture));
         }
-        return Some(Self::create_mip_texture(&raw_texture));
+        let (entry_type, raw_texture): (u8, Vec<u8>) = self.get_texture(name)?;
+        let mut decoded: MipmapTexture = match num::FromPrimitive::from_u8(entry_type) {
+            Some(WadEntryType::MipTex) => Self::create_mip_texture(&raw_texture)?,
+            Some(WadEntryType::QuakeMipTex) => Self::create_quake_mip_texture(&raw_texture)?,
+            other => return Err(BspError::InvalidTexture {
+                name: name.clone(),
+                reason: format!("entry type {:#04x} ({:?}) is not a mip texture", entry_type, other),
+            }),
+        };
+        decoded.source = TextureSource::Wad(self.path.clone());
+        let texture: Arc<MipmapTexture> = Arc::new(decoded);
+        self.cache_stats.misses += 1;
+        self.texture_cache.insert(key, Arc::clone(&texture));
+        return Ok(texture);
     }
 
-    pub fn load_decal_texture(&mut self, name: &String) -> Option<MipmapTexture> {
-        let raw_texture: Vec<u8> = self.get_texture(name);
-        if raw_texture.is_empty() {
-            return None;
+    pub fn load_decal_texture(&mut self, name: &String) -> Result<Arc<MipmapTexture>, BspError> {
+        let key: bsp30::TextureName = Self::texture_key(name)?;
+        if let Some(texture) = self.decal_cache.get(&key) {
+            self.cache_stats.hits += 1;
+            return Ok(Arc::clone(texture));
         }
-        return Some(self.create_decal_texture(&raw_texture));
+        let (_, raw_texture): (u8, Vec<u8>) = self.get_texture(name)?;
+        let texture: Arc<MipmapTexture> = Arc::new(self.create_decal_texture(&raw_texture)?);
+        self.cache_stats.misses += 1;
+        self.decal_cache.insert(key, Arc::clone(&texture));
+        return Ok(texture);
+    }
+
+    /// Whether the WAD has an entry with this name, ignoring case
+    pub fn contains(&self, name: &str) -> bool {
+        return self.entry(name).is_some();
+    }
+
+    /// Directory entries in no particular order. Mip texture dimensions are
+    /// read from each entry's header as the iterator reaches it.
+    pub fn entries(&self) -> impl Iterator<Item = WadEntryInfo> + '_ {
+        return self.dir_entries.values().map(|entry: &WadDirEntry| WadEntryInfo {
+            name: entry.name.to_string(),
+            entry_type: num::FromPrimitive::from_u8(entry.r#type),
+            raw_type: entry.r#type,
+            compressed: entry.compressed,
+            disk_size: entry.n_disk_size.max(0) as usize,
+            size: entry.n_size as usize,
+            dimensions: self.read_dimensions(entry),
+        });
+    }
+
+    /// Look up an entry case insensitively, names longer than an entry name
+    /// can hold match nothing
+    fn entry(&self, name: &str) -> Option<&WadDirEntry> {
+        return self.dir_entries.get(&bsp30::TextureName::from_name(name)?);
     }
 
-    fn load_directory(&mut self) {
-        let header: WadHeader = match WadHeader::from_reader(&mut self.wad_file) {
+    fn texture_key(name: &String) -> Result<bsp30::TextureName, BspError> {
+        return bsp30::TextureName::from_name(name).ok_or_else(|| BspError::TextureNotFound {
+            name: name.to_uppercase(),
+        });
+    }
+
+    fn read_dimensions(&self, entry: &WadDirEntry) -> Option<(u32, u32)> {
+        match num::FromPrimitive::from_u8(entry.r#type) {
+            Some(WadEntryType::MipTex | WadEntryType::QuakeMipTex) if !entry.compressed => {},
+            _ => return None,
+        };
+        let mut wad_file: std::cell::RefMut<BufReader<Box<dyn WadSource>>> = self.wad_file.try_borrow_mut().ok()?;
+        wad_file.seek(SeekFrom::Start(entry.n_file_pos as u64 + bsp30::MAX_TEXTURE_NAME as u64)).ok()?;
+        let width: u32 = wad_file.read_u32::<LittleEndian>().ok()?;
+        let height: u32 = wad_file.read_u32::<LittleEndian>().ok()?;
+        return Some((width, height));
+    }
+
+    /// Texture cache hits and misses, a miss being a texture read from the WAD
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.cache_stats;
+    }
+
+    fn load_directory(&mut self) -> Result<(), BspError> {
+        let wad_file: &mut BufReader<Box<dyn WadSource>> = self.wad_file.get_mut();
+        let header: WadHeader = match WadHeader::from_reader(wad_file) {
             Ok(header) => header,
-            Err(error) => panic!("Unable to read WAD header: {}", error),
+            Err(error) => return Err(BspError::InvalidWad {
+                path: self.path.clone(),
+                reason: format!("unable to read header: {}", error),
+            }),
         };
         match header.magic {
             [b'W', b'A', b'D', b'2' | b'3'] => {}
-            other => panic!("Invalid WAD magic string: {:?}", other),
+            other => return Err(BspError::InvalidWad {
+                path: self.path.clone(),
+                reason: format!("invalid magic string {:?}", other),
+            }),
         };
         // self.dir_entries.resize_with(header.n_dir as usize, Default::default);
-        self.wad_file
-            .seek(SeekFrom::Start(header.dir_offset as u64))
-            .unwrap();
-        for i in 0..header.n_dir as usize {
-            match WadDirEntry::from_reader(&mut self.wad_file) {
-                Ok(entry) => self.dir_entries.insert(
-                    String::from_utf8_lossy(&entry.name)
-                        .trim_matches(char::from(0))
-                        .to_string(),
-                    entry,
-                ),
-                Err(error) => panic!("Unable to parse WadDirEntry {}: {}", i, error),
-            };
+        wad_file.seek(SeekFrom::Start(header.dir_offset as u64))?;
+        for _ in 0..header.n_dir.max(0) as usize {
+            let entry: WadDirEntry = WadDirEntry::from_reader(wad_file)?;
+            self.dir_entries.insert(entry.name, entry);
         }
+        return Ok(());
     }
 
-    fn get_texture(&mut self, name: &String) -> Vec<u8> {
-        let option_entry: Option<&WadDirEntry> = self.dir_entries.get(&name.to_uppercase());
-        if let Some(entry) = option_entry {
-            if entry.compressed {
-                panic!("Cannot load compressed WAD texture {}", name);
-            }
-            self.wad_file
-                .seek(SeekFrom::Start(entry.n_file_pos as u64))
-                .unwrap();
-            let mut texture_bytes: Vec<u8> = Vec::with_capacity(entry.n_size as usize);
-            for _ in 0..entry.n_size as usize {
-                texture_bytes.push(self.wad_file.read_u8().unwrap());
+    /// Raw bytes of a directory entry along with its type
+    fn get_texture(&mut self, name: &String) -> Result<(u8, Vec<u8>), BspError> {
+        let Some(entry) = self.entry(name) else {
+            return Err(BspError::TextureNotFound {
+                name: name.to_uppercase(),
+            });
+        };
+        if entry.compressed {
+            return Err(BspError::InvalidTexture {
+                name: name.clone(),
+                reason: String::from("compressed WAD textures are not supported"),
+            });
+        }
+        let entry_type: u8 = entry.r#type;
+        let file_pos: u64 = entry.n_file_pos as u64;
+        let mut texture_bytes: Vec<u8> = vec![0u8; entry.n_size as usize];
+        let wad_file: &mut BufReader<Box<dyn WadSource>> = self.wad_file.get_mut();
+        wad_file.seek(SeekFrom::Start(file_pos))?;
+        wad_file.read_exact(&mut texture_bytes)?;
+        return Ok((entry_type, texture_bytes));
+    }
+
+    /// Check the mip levels and, when embedded, the palette that follows them
+    /// lie within the raw texture, returning the palette offset
+    fn check_mip_bounds(raw_texture: &Vec<u8>, raw_mip_tex: &bsp30::MipTex, embedded_palette: bool) -> Result<usize, BspError> {
+        let name: String = raw_mip_tex.name.to_string();
+        let mut width: usize = raw_mip_tex.width as usize;
+        let mut height: usize = raw_mip_tex.height as usize;
+        let palette_offset: usize = raw_mip_tex.offsets[3] as usize + (width / 8) * (height / 8) + 2;
+        if embedded_palette && palette_offset + 256 * 3 > raw_texture.len() {
+            return Err(BspError::InvalidTexture {
+                name,
+                reason: format!("palette at {} extends past the {} byte texture", palette_offset, raw_texture.len()),
+            });
+        }
+        for level in 0..bsp30::MIP_LEVELS {
+            if raw_mip_tex.offsets[level] as usize + width * height > raw_texture.len() {
+                return Err(BspError::InvalidTexture {
+                    name,
+                    reason: format!("mip level {} extends past the {} byte texture", level, raw_texture.len()),
+                });
             }
-            return texture_bytes;
-        } else {
-            error!(
-                &crate::LOGGER,
-                "No such texture found with name: {}",
-                name.to_uppercase()
-            );
-            return Vec::with_capacity(0);
+            width /= 2;
+            height /= 2;
         }
+        return Ok(palette_offset);
+    }
+
+    pub fn create_mip_texture(raw_texture: &Vec<u8>) -> Result<MipmapTexture, BspError> {
+        return Wad::decode_mip_texture(raw_texture, None);
+    }
+
+    /// Decode a Quake texture, which has no palette after the mip data
+    pub fn create_quake_mip_texture(raw_texture: &Vec<u8>) -> Result<MipmapTexture, BspError> {
+        return Wad::decode_mip_texture(raw_texture, Some(&QUAKE_PALETTE));
     }
 
-    pub fn create_mip_texture(raw_texture: &Vec<u8>) -> MipmapTexture {
+    /// Decode the mip levels against the given palette, or the one embedded
+    /// after them. Textures named with a `{` prefix are masked, their palette
+    /// index 255 decodes to a transparent texel.
+    fn decode_mip_texture(raw_texture: &Vec<u8>, palette: Option<&[u8]>) -> Result<MipmapTexture, BspError> {
         let mut reader: BufReader<&[u8]> = BufReader::new(raw_texture.as_slice());
-        let raw_mip_tex: bsp30::MipTex = bsp30::MipTex::from_reader(&mut reader).unwrap();
+        let raw_mip_tex: bsp30::MipTex = bsp30::MipTex::from_reader(&mut reader)?;
+        let palette_offset: usize = Wad::check_mip_bounds(raw_texture, &raw_mip_tex, palette.is_none())?;
+        let palette: &[u8] = match palette {
+            Some(palette) => palette,
+            None => &raw_texture[palette_offset..palette_offset + 256 * 3],
+        };
         let mut width: u32 = raw_mip_tex.width;
         let mut height: u32 = raw_mip_tex.height;
-        let palette_offset: usize =
-            raw_mip_tex.offsets[3] as usize + (width / 8) as usize * (height / 8) as usize + 2;
         let mut mip_tex: MipmapTexture = MipmapTexture::new();
+        mip_tex.name = raw_mip_tex.name.to_string();
+        mip_tex.masked = raw_mip_tex.name.as_bytes().first() == Some(&b'{');
         for level in 0..bsp30::MIP_LEVELS {
             let pixel_index: usize = raw_mip_tex.offsets[level] as usize;
             let mut img: &mut Image = &mut mip_tex.img[level];
@@ -187,27 +408,31 @@ impl Wad {
             img.height = height as usize;
             img.data.resize(width as usize * height as usize * 4, 0);
             for i in 0..(height * width) as usize {
-                let palette_index: usize = raw_texture[pixel_index + i] as usize * 3;
-                img.data[i * 4 + 0] = raw_texture[palette_offset + palette_index + 0];
-                img.data[i * 4 + 1] = raw_texture[palette_offset + palette_index + 1];
-                img.data[i * 4 + 2] = raw_texture[palette_offset + palette_index + 2];
-                img.data[i * 4 + 3] = 255u8;
+                let index: u8 = raw_texture[pixel_index + i];
+                let palette_index: usize = index as usize * 3;
+                img.data[i * 4 + 0] = palette[palette_index + 0];
+                img.data[i * 4 + 1] = palette[palette_index + 1];
+                img.data[i * 4 + 2] = palette[palette_index + 2];
+                img.data[i * 4 + 3] = if mip_tex.masked && index == MASK_INDEX { 0 } else { 255 };
+            }
+            if mip_tex.masked {
+                apply_alpha_sections(&mut mip_tex.img[level]);
             }
-            apply_alpha_sections(&mut mip_tex.img[level]);
             width /= 2;
             height /= 2;
         }
-        return mip_tex;
+        return Ok(mip_tex);
     }
 
-    fn create_decal_texture(&self, raw_texture: &Vec<u8>) -> MipmapTexture {
+    fn create_decal_texture(&self, raw_texture: &Vec<u8>) -> Result<MipmapTexture, BspError> {
         let mut reader: BufReader<&[u8]> = BufReader::new(raw_texture.as_slice());
-        let raw_mip_tex: bsp30::MipTex = bsp30::MipTex::from_reader(&mut reader).unwrap();
+        let raw_mip_tex: bsp30::MipTex = bsp30::MipTex::from_reader(&mut reader)?;
+        let palette_offset: usize = Wad::check_mip_bounds(raw_texture, &raw_mip_tex, true)?;
         let mut width: u32 = raw_mip_tex.width;
         let mut height: u32 = raw_mip_tex.height;
-        let palette_offset: usize =
-            raw_mip_tex.offsets[3] as usize + (width / 8) as usize * (height / 8) as usize + 2;
         let mut mip_tex: MipmapTexture = MipmapTexture::new();
+        mip_tex.name = raw_mip_tex.name.to_string();
+        mip_tex.source = TextureSource::Wad(self.path.clone());
         let colour: usize = palette_offset + 255 * 3;
         for level in 0..bsp30::MIP_LEVELS {
             let pixel_index: usize = raw_mip_tex.offsets[level] as usize;
@@ -227,120 +452,370 @@ impl Wad {
             width /= 2;
             height /= 2;
         }
-        return mip_tex;
+        return Ok(mip_tex);
+    }
+}
+
+/// Resolves WAD references from a BSP (worldspawn `wad` entries and the decal
+/// WADs) to opened WADs. References are relative, e.g. `valve/halflife.wad`.
+pub trait WadProvider: Debug + Send + Sync {
+    fn open_wad(&self, name: &str) -> Result<Wad, BspError>;
+}
+
+/// Opens WADs from directories on disk, taking the first directory that has
+/// the WAD
+#[derive(Debug, Clone)]
+pub struct FileWadProvider {
+    pub roots: Vec<PathBuf>,
+}
+
+impl FileWadProvider {
+
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        return FileWadProvider::with_dirs(vec![root.as_ref().to_path_buf()]);
+    }
+
+    pub fn with_dirs(roots: Vec<PathBuf>) -> Self {
+        return FileWadProvider {
+            roots,
+        };
     }
+
 }
 
+impl WadProvider for FileWadProvider {
+    fn open_wad(&self, name: &str) -> Result<Wad, BspError> {
+        for root in self.roots.iter() {
+            let candidate: PathBuf = root.join(name);
+            if candidate.is_file() {
+                debug!(&crate::LOGGER, "Resolved WAD {} to {}", name, candidate.display());
+                return Wad::open(candidate);
+            }
+            trace!(&crate::LOGGER, "WAD {} is not at {}", name, candidate.display());
+        }
+        return Err(BspError::InvalidWad {
+            path: PathBuf::from(name),
+            reason: format!("not found in any of {} WAD directories", self.roots.len()),
+        });
+    }
+}
+
+/// Resolves no WADs, leaving only the textures embedded in the BSP
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoWads;
+
+impl WadProvider for NoWads {
+    fn open_wad(&self, name: &str) -> Result<Wad, BspError> {
+        return Err(BspError::InvalidWad {
+            path: PathBuf::from(name),
+            reason: String::from("no WAD provider configured"),
+        });
+    }
+}
+
+// Palette index of the transparent texels in `{` textures
+const MASK_INDEX: u8 = 255;
+// Neighbours blended into a transparent texel, diagonals are weighted by sqrt(2)
+const ALPHA_NEIGHBOURS: [(isize, isize); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+/// Give fully transparent texels the average colour of their visible
+/// neighbours, so filtering does not bleed the mask colour into the edges
 fn apply_alpha_sections(p_tex: &mut Image) {
-    let mut p_rgb_texture: Vec<u8> = Vec::with_capacity(p_tex.width * p_tex.height * 4);
-    for _ in 0..(p_tex.width * p_tex.height) {
-        p_rgb_texture.push(0);
-        p_rgb_texture.push(0);
-        p_rgb_texture.push(255);
-        p_rgb_texture.push(0);
+    if p_tex.channels != 4 {
+        warn!(&crate::LOGGER, "Cannot apply alpha sections to a {} channel texture", p_tex.channels);
+        return;
     }
+    let unset: [u8; 4] = [0, 0, 0, 0];
+    let mut p_rgb_texture: Image = Image {
+        channels: 4,
+        width: p_tex.width,
+        height: p_tex.height,
+        data: unset.repeat(p_tex.width * p_tex.height),
+    };
     for y in 0..p_tex.height {
         for x in 0..p_tex.width {
-            let index: usize = y * p_tex.width + x;
-            if !(p_tex.data[index * 4] == 0
-                && p_tex.data[index * 4 + 1] == 0
-                && p_tex.data[index * 4 + 2] == 255) {
-                continue;
-            }
-            p_tex.data[index * 4 + 2] = 0;
-            p_tex.data[index * 4 + 3] = 0;
-            let mut count: usize = 0;
-            let mut rgb_colour_sum: (usize, usize, usize) = (0, 0, 0);
-
-            macro_rules! corner_pixel {
-                ($pixel_index_expr:expr) => {
-                    let pixel_index: usize = $pixel_index_expr;
-                    if !(p_tex.data[pixel_index] == 0
-                        && p_tex.data[pixel_index + 1] == 0
-                        && p_tex.data[pixel_index + 2] == 255)
-                    {
-                        rgb_colour_sum.0 += (p_tex.data[pixel_index + 0] as f32
-                            * std::f32::consts::SQRT_2) as usize;
-                        rgb_colour_sum.1 += (p_tex.data[pixel_index + 1] as f32
-                            * std::f32::consts::SQRT_2) as usize;
-                        rgb_colour_sum.2 += (p_tex.data[pixel_index + 2] as f32
-                            * std::f32::consts::SQRT_2) as usize;
-                        count += 1;
-                    }
+            match p_tex.pixel_mut(x, y) {
+                Some(pixel) if pixel[3] == 0 => {
+                    pixel[0] = 0;
+                    pixel[1] = 0;
+                    pixel[2] = 0;
+                },
+                _ => continue,
+            };
+            let mut total_weight: f32 = 0.0;
+            let mut rgb_colour_sum: (f32, f32, f32) = (0.0, 0.0, 0.0);
+            for (dx, dy) in ALPHA_NEIGHBOURS {
+                let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                    continue;
                 };
-            }
-
-            macro_rules! absolute_pixel {
-                ($pixel_index_expr:expr) => {
-                    let pixel_index: usize = $pixel_index_expr;
-                    if !(p_tex.data[pixel_index] == 0
-                        && p_tex.data[pixel_index + 1] == 0
-                        && p_tex.data[pixel_index + 2] == 255)
-                    {
-                        rgb_colour_sum.0 += p_tex.data[pixel_index] as usize;
-                        rgb_colour_sum.1 += p_tex.data[pixel_index + 1] as usize;
-                        rgb_colour_sum.2 += p_tex.data[pixel_index + 2] as usize;
-                        count += 1;
-                    }
+                let Some(pixel) = p_tex.pixel(nx, ny) else {
+                    continue;
                 };
+                if pixel[3] == 0 {
+                    continue;
+                }
+                let weight: f32 = if dx != 0 && dy != 0 { std::f32::consts::SQRT_2 } else { 1.0 };
+                rgb_colour_sum.0 += pixel[0] as f32 * weight;
+                rgb_colour_sum.1 += pixel[1] as f32 * weight;
+                rgb_colour_sum.2 += pixel[2] as f32 * weight;
+                total_weight += weight;
             }
-
-            // Top left
-            if x > 0 && y > 0 {
-                corner_pixel!(((y - 1) * p_tex.width + (x - 1)) * 4);
-            }
-            // Top
-            if x >= 0 && y > 0 {
-                absolute_pixel!(((y - 1) * p_tex.width + x) * 4);
-            }
-            // Top right
-            if x < p_tex.width - 1 && y > 0 {
-                corner_pixel!(((y - 1) * p_tex.width + (x + 1)) * 4);
-            }
-            // Left
-            if x > 0 {
-                absolute_pixel!((y * p_tex.width + (x - 1)) * 4);
-            }
-            // Right
-            if x < p_tex.width - 1 {
-                absolute_pixel!((y * p_tex.width + (x + 1)) * 4);
-            }
-            // Bottom left
-            if x > 0 && y < p_tex.height - 1 {
-                corner_pixel!(((y + 1) * p_tex.width + (x - 1)) * 4);
-            }
-            // Bottom
-            if x >= 0 && y < p_tex.height - 1 {
-                absolute_pixel!(((y + 1) * p_tex.width + x) * 4);
-            }
-            // Bottom right
-            if x < p_tex.width - 1 && y < p_tex.height - 1 {
-                corner_pixel!(((y + 1) * p_tex.width + (x + 1)) * 4);
-            }
-            if count > 0 {
-                rgb_colour_sum.0 /= count;
-                rgb_colour_sum.1 /= count;
-                rgb_colour_sum.2 /= count;
-
-                p_rgb_texture[index * 4 + 0] = rgb_colour_sum.0 as u8;
-                p_rgb_texture[index * 4 + 1] = rgb_colour_sum.1 as u8;
-                p_rgb_texture[index * 4 + 2] = rgb_colour_sum.2 as u8;
+            if total_weight > 0.0 {
+                let averaged: [u8; 4] = [
+                    (rgb_colour_sum.0 / total_weight).round() as u8,
+                    (rgb_colour_sum.1 / total_weight).round() as u8,
+                    (rgb_colour_sum.2 / total_weight).round() as u8,
+                    0,
+                ];
+                if let Err(error) = p_rgb_texture.put_pixel(x, y, &averaged) {
+                    warn!(&crate::LOGGER, "Unable to blend alpha section texel: {}", error);
+                }
             }
         }
     }
     for y in 0..p_tex.height {
         for x in 0..p_tex.width {
-            let index: usize = y * p_tex.width + x;
-            if p_rgb_texture[index * 4] != 0
-                || p_rgb_texture[index * 4 + 1] != 0
-                || p_rgb_texture[index * 4 + 2] != 255
-                || p_rgb_texture[index * 4 + 3] != 0
-            {
-                p_tex.data[index * 4 + 0] = p_rgb_texture[index * 4 + 0];
-                p_tex.data[index * 4 + 1] = p_rgb_texture[index * 4 + 1];
-                p_tex.data[index * 4 + 2] = p_rgb_texture[index * 4 + 2];
-                p_tex.data[index * 4 + 3] = p_rgb_texture[index * 4 + 3];
+            let Some(blended) = p_rgb_texture.pixel(x, y) else {
+                continue;
+            };
+            if blended != unset {
+                if let Err(error) = p_tex.put_pixel(x, y, blended) {
+                    warn!(&crate::LOGGER, "Unable to write alpha section texel: {}", error);
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use crate::map::micro_map::{EmbeddedWads, MICRO_WAD, MICRO_WAD_NAME};
+
+    /// Reader that counts the bytes read through it
+    struct CountingReader {
+        inner: Cursor<&'static [u8]>,
+        read: Rc<Cell<usize>>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read: usize = self.inner.read(buf)?;
+            self.read.set(self.read.get() + read);
+            return Ok(read);
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            return self.inner.seek(pos);
+        }
+    }
+
+    /// 8x8 mip texture of palette indices 0 to 84, with a palette where index
+    /// i is (i, 2i, 3i) when `embedded_palette` is set
+    fn raw_mip_texture(name: &str, embedded_palette: bool) -> Vec<u8> {
+        let mut raw_texture: Vec<u8> = vec![0; bsp30::MAX_TEXTURE_NAME];
+        raw_texture[..name.len()].copy_from_slice(name.as_bytes());
+        for value in [8u32, 8, 40, 104, 120, 124] {
+            raw_texture.extend_from_slice(&value.to_le_bytes());
+        }
+        raw_texture.extend((0..85).map(|i: u8| i));
+        if embedded_palette {
+            raw_texture.extend_from_slice(&256u16.to_le_bytes());
+            raw_texture.extend((0..=255).flat_map(|i: u8| [i, i.wrapping_mul(2), i.wrapping_mul(3)]));
+        }
+        return raw_texture;
+    }
+
+    /// WAD of the given entries, each a name, entry type and raw bytes
+    fn build_wad(magic: &[u8; 4], entries: &[(&str, u8, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = magic.to_vec();
+        bytes.extend_from_slice(&(entries.len() as i32).to_le_bytes());
+        let dir_offset: usize = 12 + entries.iter().map(|(_, _, data): &(&str, u8, Vec<u8>)| data.len()).sum::<usize>();
+        bytes.extend_from_slice(&(dir_offset as i32).to_le_bytes());
+        let mut positions: Vec<usize> = Vec::new();
+        for (_, _, data) in entries.iter() {
+            positions.push(bytes.len());
+            bytes.extend_from_slice(data);
+        }
+        for ((name, r#type, data), position) in entries.iter().zip(positions) {
+            bytes.extend_from_slice(&(position as i32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&[*r#type, 0, 0, 0]);
+            let mut raw_name: [u8; bsp30::MAX_TEXTURE_NAME] = [0; bsp30::MAX_TEXTURE_NAME];
+            raw_name[..name.len()].copy_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&raw_name);
+        }
+        return bytes;
+    }
+
+    #[test]
+    fn missing_textures_are_not_found() {
+        let mut wad: Wad = EmbeddedWads.open_wad(MICRO_WAD_NAME).unwrap();
+        assert!(matches!(
+            wad.load_texture(&"missing".to_string()),
+            Err(BspError::TextureNotFound { .. }),
+        ));
+        assert!(wad.load_texture(&"FLOOR".to_string()).is_ok());
+    }
+
+    #[test]
+    fn placeholders_without_a_size_use_the_default() {
+        let texture: MipmapTexture = MipmapTexture::placeholder(0, 0);
+        let size: usize = PLACEHOLDER_SIZE as usize;
+        assert_eq!((texture.img[0].width, texture.img[0].height), (size, size));
+        assert_eq!(texture.img[0].data[..4], [255, 0, 255, 255]);
+        assert_eq!(texture.img[bsp30::MIP_LEVELS - 1].width, size >> (bsp30::MIP_LEVELS - 1));
+    }
+
+    #[test]
+    fn quake_textures_use_the_quake_palette() {
+        // An 8x8 texture's header and mip levels with no palette after them
+        let mut raw_texture: Vec<u8> = b"quake\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+        for value in [8u32, 8, 40, 104, 120, 124] {
+            raw_texture.extend_from_slice(&value.to_le_bytes());
+        }
+        raw_texture.extend((0..85).map(|i: u8| i));
+        let texture: MipmapTexture = Wad::create_quake_mip_texture(&raw_texture).unwrap();
+        assert_eq!((texture.img[0].width, texture.img[0].height), (8, 8));
+        for (i, texel) in texture.img[0].data.chunks(4).enumerate() {
+            assert_eq!(texel[..3], QUAKE_PALETTE[i * 3..i * 3 + 3]);
+            assert_eq!(texel[3], 255);
+        }
+    }
+
+    #[test]
+    fn cached_textures_are_not_read_again() {
+        let read: Rc<Cell<usize>> = Rc::new(Cell::new(0));
+        let reader: CountingReader = CountingReader {
+            inner: Cursor::new(MICRO_WAD),
+            read: Rc::clone(&read),
+        };
+        let mut wad: Wad = Wad::from_reader(PathBuf::from(MICRO_WAD_NAME), reader).unwrap();
+        let first: Arc<MipmapTexture> = wad.load_texture(&"floor".to_string()).unwrap();
+        let read_once: usize = read.get();
+        let second: Arc<MipmapTexture> = wad.load_texture(&"FLOOR".to_string()).unwrap();
+        assert_eq!(read.get(), read_once);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(wad.cache_stats(), CacheStats { hits: 1, misses: 1 });
+    }
+
+    #[test]
+    fn opening_a_nonexistent_wad_fails() {
+        let path: PathBuf = std::env::temp_dir().join(format!("lambda-missing-{}.wad", std::process::id()));
+        assert!(matches!(Wad::open(&path), Err(BspError::Io(_))));
+    }
+
+    #[test]
+    fn opening_an_empty_or_foreign_file_is_an_invalid_wad() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("lambda-wad-open-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let empty: PathBuf = directory.join("empty.wad");
+        let bad_magic: PathBuf = directory.join("bad_magic.wad");
+        std::fs::write(&empty, b"").unwrap();
+        std::fs::write(&bad_magic, b"PACK\0\0\0\0\0\0\0\0").unwrap();
+        let empty_result: Result<Wad, BspError> = Wad::open(&empty);
+        let bad_magic_result: Result<Wad, BspError> = Wad::open(&bad_magic);
+        let directory_result: Result<Wad, BspError> = Wad::open(&directory);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(matches!(empty_result, Err(BspError::InvalidWad { .. })));
+        assert!(matches!(bad_magic_result, Err(BspError::InvalidWad { reason, .. }) if reason.contains("magic")));
+        assert!(matches!(directory_result, Err(BspError::InvalidWad { .. })));
+    }
+
+    #[test]
+    fn opening_a_valid_wad_reads_its_directory() {
+        let path: PathBuf = std::env::temp_dir().join(format!("lambda-valid-{}.wad", std::process::id()));
+        std::fs::write(&path, MICRO_WAD).unwrap();
+        let wad: Result<Wad, BspError> = Wad::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        let wad: Wad = wad.unwrap();
+        assert!(wad.contains("floor") && wad.contains("WALL"));
+        assert_eq!(wad.dir_entries.len(), 2);
+    }
+
+    #[test]
+    fn textures_decode_by_their_entry_type() {
+        let bytes: Vec<u8> = build_wad(b"WAD2", &[
+            ("half", WadEntryType::MipTex as u8, raw_mip_texture("half", true)),
+            ("quake", WadEntryType::QuakeMipTex as u8, raw_mip_texture("quake", false)),
+            ("conchars", WadEntryType::QPic as u8, vec![0; 64]),
+        ]);
+        let mut wad: Wad = Wad::from_reader(PathBuf::from("entries.wad"), Cursor::new(bytes)).unwrap();
+        let half: Arc<MipmapTexture> = wad.load_texture(&"half".to_string()).unwrap();
+        assert_eq!(half.img[0].data[5 * 4..6 * 4], [5, 10, 15, 255]);
+        assert_eq!(half.img[1].data[..4], [64, 128, 192, 255]);
+        let quake: Arc<MipmapTexture> = wad.load_texture(&"quake".to_string()).unwrap();
+        assert_eq!(quake.img[0].data[5 * 4..5 * 4 + 3], QUAKE_PALETTE[15..18]);
+        assert!(matches!(
+            wad.load_texture(&"conchars".to_string()),
+            Err(BspError::InvalidTexture { .. }),
+        ));
+    }
+
+    #[test]
+    fn decals_take_their_colour_from_the_last_palette_entry() {
+        let bytes: Vec<u8> = build_wad(b"WAD3", &[("{blood1", WadEntryType::MipTex as u8, raw_mip_texture("{blood1", true))]);
+        let mut wad: Wad = Wad::from_reader(PathBuf::from("decals.wad"), Cursor::new(bytes)).unwrap();
+        let decal: Arc<MipmapTexture> = wad.load_decal_texture(&"{BLOOD1".to_string()).unwrap();
+        // Alpha is the inverse of the palette entry's red, the colour always entry 255
+        assert_eq!(decal.img[0].data[5 * 4..6 * 4], [255, 254, 253, 250]);
+        assert_eq!(decal.img[1].data[..4], [255, 254, 253, 191]);
+        // Decoded apart from the same entry loaded as a texture, each cached once
+        let texture: Arc<MipmapTexture> = wad.load_texture(&"{blood1".to_string()).unwrap();
+        assert!(!Arc::ptr_eq(&decal, &texture));
+        assert!(Arc::ptr_eq(&decal, &wad.load_decal_texture(&"{blood1".to_string()).unwrap()));
+        assert_eq!(wad.cache_stats(), CacheStats { hits: 1, misses: 2 });
+    }
+
+    #[test]
+    fn entries_list_names_types_and_sizes() {
+        let wad: Wad = EmbeddedWads.open_wad(MICRO_WAD_NAME).unwrap();
+        let mut entries: Vec<WadEntryInfo> = wad.entries().collect();
+        entries.sort_by(|a: &WadEntryInfo, b: &WadEntryInfo| a.name.cmp(&b.name));
+        let names: Vec<&str> = entries.iter().map(|entry: &WadEntryInfo| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["floor", "wall"]);
+        for entry in entries.iter() {
+            assert_eq!(entry.entry_type, Some(WadEntryType::MipTex));
+            assert!(!entry.compressed);
+            assert_eq!(entry.dimensions, Some((16, 16)));
+            assert_eq!(entry.disk_size, entry.size);
+        }
+    }
+
+    #[test]
+    fn contains_ignores_case() {
+        let wad: Wad = EmbeddedWads.open_wad(MICRO_WAD_NAME).unwrap();
+        assert!(wad.contains("floor") && wad.contains("FLOOR") && wad.contains("Wall"));
+        assert!(!wad.contains("sky"));
+        assert!(!wad.contains("floor_with_a_very_long_name"));
+    }
+
+    #[test]
+    fn masked_textures_decode_index_255_as_transparent() {
+        let mut raw_texture: Vec<u8> = raw_mip_texture("{fence", true);
+        // Top left and a middle texel of the base level
+        raw_texture[40] = 255;
+        raw_texture[40 + 3 * 8 + 3] = 255;
+        let texture: MipmapTexture = Wad::decode_mip_texture(&raw_texture, None).unwrap();
+        assert!(texture.masked);
+        let base: &Image = &texture.img[0];
+        let transparent: Vec<usize> = (0..64).filter(|i: &usize| base.data[i * 4 + 3] == 0).collect();
+        assert_eq!(transparent, vec![0, 27]);
+        assert_eq!(base.data.chunks(4).filter(|texel: &&[u8]| texel[3] != 255).count(), 2);
+        // Transparent texels take their neighbours' colour rather than the palette's
+        assert_ne!(base.pixel(3, 3).unwrap()[..3], [255, 254, 253]);
+        let mut unmasked: Vec<u8> = raw_texture.clone();
+        unmasked[0] = b'f';
+        let texture: MipmapTexture = Wad::decode_mip_texture(&unmasked, None).unwrap();
+        assert!(!texture.masked);
+        assert!(texture.img[0].data.chunks(4).all(|texel: &[u8]| texel[3] == 255));
+    }
+
+}
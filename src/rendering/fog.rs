@@ -0,0 +1,173 @@
+use crate::core::scale::ScaleDefaults;
+use crate::scene::entity::Entity;
+
+// Distance the skybox is treated as being at when it takes on the fog
+const SKYBOX_DISTANCE: f32 = 16384.0;
+// Keeps linear fog defined when start and end meet
+const MIN_LINEAR_RANGE: f32 = 0.001;
+
+/// How fog thickens with distance from the eye
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FogMode {
+    #[default]
+    Off,
+    Linear, // Clear before start, solid past end
+    Exp2, // Visibility falls off as exp(-(density * distance)^2)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: [f32; 3],
+    pub start: f32, // Linear fog only, in world units
+    pub end: f32,
+    pub density: f32, // Exp2 fog only, per world unit
+    pub skybox: bool, // Fade the skybox into the fog as if it were at the far plane, otherwise leave it clear
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        return Fog {
+            mode: FogMode::Off,
+            color: [0.5, 0.5, 0.5],
+            start: ScaleDefaults::default().fog_start,
+            end: ScaleDefaults::default().fog_end,
+            density: 0.0,
+            skybox: false,
+        };
+    }
+}
+
+impl Fog {
+
+    /// Fog of the first `env_fog` entity, disabled when there is none. A
+    /// `density` gives exp2 fog, otherwise `fogstartdistance` and
+    /// `fogenddistance` give linear fog, each falling back to the world
+    /// scale's fog range. `rendercolor` is 0 to 255 per channel.
+    pub fn from_entities(entities: &[Entity], scale: &ScaleDefaults) -> Fog {
+        let Some(env_fog) = entities.iter().find(|entity: &&Entity| {
+            entity.find_property(&"classname".to_string()).map(String::as_str) == Some("env_fog")
+        }) else {
+            return Fog::default();
+        };
+        let number = |key: &str| -> Option<f32> {
+            env_fog.find_property(&key.to_string()).and_then(|value: &String| value.trim().parse::<f32>().ok())
+        };
+        let color: Vec<f32> = env_fog.find_property(&"rendercolor".to_string())
+            .map_or(Vec::new(), |color: &String| color.split_whitespace()
+                .map_while(|component: &str| component.parse::<f32>().ok())
+                .collect());
+        let mut fog: Fog = Fog::default();
+        if let [r, g, b] = color[..] {
+            fog.color = [r / 255.0, g / 255.0, b / 255.0].map(|channel: f32| channel.clamp(0.0, 1.0));
+        }
+        match number("density").filter(|density: &f32| *density > 0.0) {
+            Some(density) => {
+                fog.mode = FogMode::Exp2;
+                fog.density = density;
+            },
+            None => {
+                fog.mode = FogMode::Linear;
+                fog.start = number("fogstartdistance").unwrap_or(scale.fog_start).max(0.0);
+                fog.end = number("fogenddistance").unwrap_or(scale.fog_end).max(fog.start);
+            },
+        };
+        info!(&crate::LOGGER, "Fog from env_fog: {:?}", fog);
+        return fog;
+    }
+
+    /// Share of a surface's own colour left at `distance` from the eye, 1.0
+    /// where there is no fog. Matches `fog_visibility` in the shaders.
+    pub fn visibility(&self, distance: f32) -> f32 {
+        return match self.mode {
+            FogMode::Off => 1.0,
+            FogMode::Linear => ((self.end - distance) / (self.end - self.start).max(MIN_LINEAR_RANGE)).clamp(0.0, 1.0),
+            FogMode::Exp2 => (-(self.density * distance).powi(2)).exp().clamp(0.0, 1.0),
+        };
+    }
+
+    /// Fog colour with how much of it covers the skybox in alpha
+    pub fn skybox_tint(&self) -> [f32; 4] {
+        let amount: f32 = if self.skybox { 1.0 - self.visibility(SKYBOX_DISTANCE) } else { 0.0 };
+        return [self.color[0], self.color[1], self.color[2], amount];
+    }
+
+    /// Value of the shaders' `fog_mode` uniform
+    pub fn shader_mode(&self) -> i32 {
+        return match self.mode {
+            FogMode::Off => 0,
+            FogMode::Linear => 1,
+            FogMode::Exp2 => 2,
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fog_of(properties: &str) -> Fog {
+        return Fog::from_entities(&[Entity::new(&properties.to_string())], &ScaleDefaults::default());
+    }
+
+    #[test]
+    fn maps_without_env_fog_have_none() {
+        let entities: Vec<Entity> = vec![Entity::new(&"\"classname\" \"worldspawn\"".to_string())];
+        let fog: Fog = Fog::from_entities(&entities, &ScaleDefaults::default());
+        assert_eq!(fog.mode, FogMode::Off);
+        assert_eq!(fog.visibility(100000.0), 1.0);
+        assert_eq!(fog.shader_mode(), 0);
+    }
+
+    #[test]
+    fn env_fog_distances_give_linear_fog() {
+        let fog: Fog = fog_of("\"classname\" \"env_fog\" \"rendercolor\" \"255 128 0\" \"fogstartdistance\" \"100\" \"fogenddistance\" \"500\"");
+        assert_eq!(fog.mode, FogMode::Linear);
+        assert_eq!((fog.start, fog.end), (100.0, 500.0));
+        assert_eq!(fog.color, [1.0, 128.0 / 255.0, 0.0]);
+        // Without distances the world scale's range applies
+        let fog: Fog = fog_of("\"classname\" \"env_fog\"");
+        assert_eq!((fog.start, fog.end), (512.0, 4096.0));
+        assert_eq!(fog.color, Fog::default().color);
+    }
+
+    #[test]
+    fn env_fog_density_gives_exp2_fog() {
+        let fog: Fog = fog_of("\"classname\" \"env_fog\" \"density\" \"0.002\" \"rendercolor\" \"300 -5 x\"");
+        assert_eq!(fog.mode, FogMode::Exp2);
+        assert_eq!(fog.density, 0.002);
+        assert_eq!(fog.shader_mode(), 2);
+        // A colour missing a channel keeps the default
+        assert_eq!(fog.color, Fog::default().color);
+        assert_eq!(fog_of("\"classname\" \"env_fog\" \"density\" \"0\"").mode, FogMode::Linear);
+    }
+
+    #[test]
+    fn linear_fog_fades_between_start_and_end() {
+        let fog: Fog = fog_of("\"classname\" \"env_fog\" \"fogstartdistance\" \"100\" \"fogenddistance\" \"500\"");
+        assert_eq!(fog.visibility(0.0), 1.0);
+        assert_eq!(fog.visibility(100.0), 1.0);
+        assert_eq!(fog.visibility(300.0), 0.5);
+        assert_eq!(fog.visibility(500.0), 0.0);
+        assert_eq!(fog.visibility(1000.0), 0.0);
+    }
+
+    #[test]
+    fn exp2_fog_thickens_with_distance() {
+        let fog: Fog = fog_of("\"classname\" \"env_fog\" \"density\" \"0.01\"");
+        assert_eq!(fog.visibility(0.0), 1.0);
+        assert!((fog.visibility(100.0) - (-1.0f32).exp()).abs() < 1e-6);
+        assert!((fog.visibility(200.0) - (-4.0f32).exp()).abs() < 1e-6);
+        assert!(fog.visibility(1000.0) < 1e-6);
+    }
+
+    #[test]
+    fn the_skybox_takes_on_the_fog_only_when_asked() {
+        let mut fog: Fog = fog_of("\"classname\" \"env_fog\" \"density\" \"0.001\" \"rendercolor\" \"0 0 255\"");
+        assert_eq!(fog.skybox_tint(), [0.0, 0.0, 1.0, 0.0]);
+        fog.skybox = true;
+        assert!((fog.skybox_tint()[3] - 1.0).abs() < 1e-6);
+    }
+
+}
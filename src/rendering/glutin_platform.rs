@@ -0,0 +1,141 @@
+use glium::glutin;
+use glium::glutin::event_loop::EventLoop;
+use glium::glutin::monitor::MonitorHandle;
+use glium::glutin::window::{Fullscreen, Window, WindowBuilder};
+use std::cell::RefCell;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::rendering::opengl_renderer::OpenGLRenderer;
+use crate::rendering::renderer::{Platform, Renderer};
+
+/// Windows and GL contexts from glutin. The platform owns the event loop
+/// until it is taken to run, windows can only be created before then.
+pub struct GlutinPlatform {
+    event_loop: RefCell<Option<EventLoop<()>>>,
+    display: RefCell<Option<glium::Display>>, // The created window, for toggling fullscreen and presenting
+    fullscreen: bool, // Create the window borderless fullscreen rather than windowed
+    vsync: bool,
+}
+
+impl GlutinPlatform {
+
+    pub fn new(fullscreen: bool, vsync: bool) -> Self {
+        return GlutinPlatform {
+            event_loop: RefCell::new(Some(EventLoop::new())),
+            display: RefCell::new(None),
+            fullscreen,
+            vsync,
+        };
+    }
+
+    /// Hand over the event loop to run. Panics if it has already been taken.
+    pub fn take_event_loop(&self) -> EventLoop<()> {
+        return self.event_loop.borrow_mut().take().expect("Event loop has already been taken");
+    }
+
+    /// Monitor at `index` in the event loop's list, the primary monitor when
+    /// none is given or the index is out of range
+    fn select_monitor(event_loop: &EventLoop<()>, index: Option<usize>) -> Option<MonitorHandle> {
+        let monitors: Vec<MonitorHandle> = event_loop.available_monitors().collect();
+        for (i, monitor) in monitors.iter().enumerate() {
+            let size: glutin::dpi::PhysicalSize<u32> = monitor.size();
+            debug!(
+                &crate::LOGGER,
+                "Monitor {}: {} {}x{} at scale {}",
+                i,
+                monitor.name().unwrap_or("unnamed".to_string()),
+                size.width,
+                size.height,
+                monitor.scale_factor(),
+            );
+        }
+        let primary: Option<MonitorHandle> = event_loop.primary_monitor().or(monitors.first().cloned());
+        let Some(index) = index else {
+            return primary;
+        };
+        if let Some(monitor) = monitors.get(index) {
+            return Some(monitor.clone());
+        }
+        warn!(&crate::LOGGER, "No monitor {}, {} available, using the primary monitor", index, monitors.len());
+        return primary;
+    }
+
+    /// Position that centres a window of `width` by `height` logical pixels on `monitor`
+    fn centred_on(monitor: &MonitorHandle, width: usize, height: usize) -> glutin::dpi::PhysicalPosition<i32> {
+        let origin: glutin::dpi::PhysicalPosition<i32> = monitor.position();
+        let size: glutin::dpi::PhysicalSize<u32> = monitor.size();
+        let window: glutin::dpi::PhysicalSize<u32> = glutin::dpi::LogicalSize::new(width as u32, height as u32)
+            .to_physical(monitor.scale_factor());
+        return glutin::dpi::PhysicalPosition::new(
+            origin.x + (size.width.saturating_sub(window.width) / 2) as i32,
+            origin.y + (size.height.saturating_sub(window.height) / 2) as i32,
+        );
+    }
+
+    pub fn request_redraw(&self) {
+        if let Some(display) = self.display.borrow().as_ref() {
+            display.gl_window().window().request_redraw();
+        }
+    }
+
+    /// Switch the window between borderless fullscreen on the monitor it is
+    /// on and windowed, returning its size after the switch. None when no
+    /// window has been created.
+    pub fn toggle_fullscreen(&self) -> Option<glutin::dpi::PhysicalSize<u32>> {
+        let display: std::cell::Ref<Option<glium::Display>> = self.display.borrow();
+        let gl_window = display.as_ref()?.gl_window();
+        let window: &Window = gl_window.window();
+        let fullscreen: Option<Fullscreen> = match window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(window.current_monitor())),
+        };
+        info!(&crate::LOGGER, "Switching to {}", if fullscreen.is_some() { "fullscreen" } else { "windowed" });
+        window.set_fullscreen(fullscreen);
+        return Some(window.inner_size());
+    }
+
+}
+
+impl Platform for GlutinPlatform {
+
+    fn create_window_and_context(&self, width: usize, height: usize, title: String, monitor: Option<usize>) -> Result<glium::Display> {
+        let event_loop: std::cell::Ref<Option<EventLoop<()>>> = self.event_loop.borrow();
+        let Some(event_loop) = event_loop.as_ref() else {
+            return Err(Error::new(ErrorKind::Other, "Windows must be created before the event loop runs"));
+        };
+        let monitor: Option<MonitorHandle> = GlutinPlatform::select_monitor(event_loop, monitor);
+        let mut window_builder: WindowBuilder = WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(glutin::dpi::LogicalSize::new(width as u32, height as u32));
+        if self.fullscreen {
+            window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        } else if let Some(monitor) = &monitor {
+            window_builder = window_builder.with_position(GlutinPlatform::centred_on(monitor, width, height));
+        }
+        let context_builder = glutin::ContextBuilder::new()
+            .with_depth_buffer(24)
+            .with_vsync(self.vsync);
+        let display: glium::Display = match glium::Display::new(window_builder, context_builder, event_loop) {
+            Ok(display) => display,
+            Err(error) => return Err(Error::new(
+                ErrorKind::Other,
+                format!("Failed to create window and context: {}", error),
+            )),
+        };
+        *self.display.borrow_mut() = Some(display.clone());
+        return Ok(display);
+    }
+
+    fn create_renderer(&self, display: glium::Display) -> Result<Box<dyn Renderer>> {
+        return Ok(Box::new(OpenGLRenderer::new(display)?));
+    }
+
+    fn swap_buffers(&self) {
+        if let Some(display) = self.display.borrow().as_ref() {
+            if let Err(error) = display.swap_buffers() {
+                error!(&crate::LOGGER, "Failed to swap buffers: {}", error);
+            }
+        }
+    }
+
+}
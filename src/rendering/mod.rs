@@ -1,5 +1,7 @@
+pub mod fog;
 pub mod renderer;
 pub mod renderable;
 pub mod view;
 
 pub mod opengl_renderer;
+pub mod glutin_platform;
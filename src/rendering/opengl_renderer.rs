@@ -1,34 +1,1046 @@
+use std::borrow::Cow;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
 use std::io::{Result, Error, ErrorKind};
 
-use glium::texture::{SrgbTexture2d, SrgbCubemap, RawImage2d, MipmapsOption};
-use glium::Rect;
+use glium::texture::{
+    SrgbTexture2d, SrgbCubemap, RawImage2d, MipmapsOption, CubeLayer,
+    CompressedSrgbTexture2d, CompressedSrgbFormat, CompressedMipmapsOption, Texture2d, UncompressedFloatFormat,
+};
+use glium::backend::Context;
+use glium::draw_parameters::{BackfaceCullingMode, Depth, DepthTest, PolygonMode, PolygonOffset};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::index::{IndexBuffer, IndexBufferSlice, NoIndices, PrimitiveType};
+use glium::uniforms::{
+    AsUniformValue, MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerWrapFunction, UniformValue, Uniforms,
+};
+use glium::program::ProgramCreationInput;
+use glium::vertex::{EmptyVertexAttributes, VertexBufferSlice};
+use glium::{
+    Api, Blend, BlendingFunction, BlitTarget, CapabilitiesSource, DrawError, DrawParameters, Frame,
+    LinearBlendingFactor, Program, Rect, Surface, Version, VertexBuffer,
+};
 
-use crate::rendering::renderer::Renderer;
+use crate::map::animation::TextureAnimation;
+use crate::map::bsp::{Decal, SKY_NAME_SUFFIXES};
+use crate::map::bsp30::RenderMode;
+use crate::rendering::fog::Fog;
+use crate::rendering::renderable::{LightingMode, RenderSettings};
+use crate::rendering::renderer::{
+    axis_lines, EntityData, FaceRenderInfo, GammaEncoding, PassCapture, PassScope, Renderer, RendererCapabilities, RenderStats, TextureHandle,
+    Vertex, VertexWithLM,
+};
+use crate::resource::block_compression::{self, BlockFormat};
+use crate::resource::image::Image;
+use crate::scene::lights::{PointLight, MAX_POINT_LIGHTS};
 
-struct OpenGLRenderer {
+// Flat colours of the debug views, overdraw adds up per layer
+const WIREFRAME_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const OVERDRAW_COLOR: [f32; 4] = [0.12, 0.05, 0.02, 1.0];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.8, 0.0, 0.5];
+// Colours of the X, Y and Z axes of the coordinate gizmo
+const AXIS_COLORS: [[f32; 4]; 3] = [[1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]];
+// Used where the driver draws wide lines, thin lines otherwise
+const AXIS_LINE_WIDTH: f32 = 2.0;
+// Angular speed of the water's standing wave in radians per second
+const WAVE_SPEED: f32 = 2.0;
+// Fewest entities sharing a model worth an instanced draw, fewer are drawn one by one
+const MIN_INSTANCES: usize = 2;
+
+/// Per instance attributes of the instanced static program
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct InstanceAttributes {
+    model: [[f32; 4]; 4], // Entity transform
+    instance_alpha: f32,
+}
+
+implement_vertex!(InstanceAttributes, model, instance_alpha);
+
+/// Instances of a group of entities and the buffer they were uploaded to
+struct InstanceBuffer {
+    instances: Vec<InstanceAttributes>,
+    buffer: VertexBuffer<InstanceAttributes>,
+}
+
+/// How a GoldSrc sky image is rearranged to become a cubemap face
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkyFaceOrientation {
+    FlipHorizontal, // Mirrored left to right
+    Transpose, // Rows become columns, mirroring across the top left to bottom right diagonal
+    AntiTranspose, // Mirrored across the top right to bottom left diagonal
+}
+
+/// Cubemap face and orientation for each sky image, in SKY_NAME_SUFFIXES
+/// order (ft, bk, up, dn, rt, lf).
+///
+/// The cubemap is sampled with GoldSrc directions converted to Y up as
+/// (x, z, -y), so rt looks along +X, lf along +Y and up along +Z in the
+/// world. Sky images are drawn as seen from inside the box with Z up, but
+/// cubemap faces follow the left handed RenderMan convention, so the four
+/// sides come out mirrored and the up and down images are turned a quarter
+/// from where their edges meet the sides.
+pub fn skybox_face_mapping() -> [(CubeLayer, SkyFaceOrientation); 6] {
+    return [
+        (CubeLayer::PositiveZ, SkyFaceOrientation::FlipHorizontal), // ft, world -Y
+        (CubeLayer::NegativeX, SkyFaceOrientation::FlipHorizontal), // bk, world -X
+        (CubeLayer::PositiveY, SkyFaceOrientation::Transpose), // up, world +Z
+        (CubeLayer::NegativeY, SkyFaceOrientation::AntiTranspose), // dn, world -Z
+        (CubeLayer::PositiveX, SkyFaceOrientation::FlipHorizontal), // rt, world +X
+        (CubeLayer::NegativeZ, SkyFaceOrientation::FlipHorizontal), // lf, world +Y
+    ];
+}
+
+/// Blending and shading an entity's faces are drawn with
+#[derive(Debug, Clone, Copy)]
+struct EntityDrawState {
+    blend: Option<Blend>, // None draws opaque and writes depth
+    alpha: f32, // Multiplies the texel alpha
+    alpha_test: bool, // Discard texels below half opacity
+    lit: bool, // Modulate by the lightmap
+    additive: bool, // Blended by adding to the frame
+    water: bool, // Warped liquid surface, written to depth even when blended
+    wave_height: f32, // Displacement of water vertices in world units
+}
+
+/// Static shader inputs that stay the same for every face of a frame
+struct StaticFrameUniforms {
+    lights: PointLightUniforms,
+    lighting_mode: LightingMode,
+    time: f32,
+    fog: Fog,
+    camera: glm::Vec3,
+}
+
+/// Point light arrays of the static shader. glium sets arrays one element
+/// at a time, so each element's uniform name is built once per frame.
+struct PointLightUniforms {
+    count: i32,
+    positions: Vec<(String, [f32; 3])>,
+    colors: Vec<(String, [f32; 3])>,
+    intensities: Vec<(String, f32)>,
+}
+
+impl PointLightUniforms {
+
+    fn new(lights: &[PointLight]) -> Self {
+        let lights: &[PointLight] = &lights[..lights.len().min(MAX_POINT_LIGHTS)];
+        return PointLightUniforms {
+            count: lights.len() as i32,
+            positions: lights.iter().enumerate()
+                .map(|(i, light): (usize, &PointLight)| (format!("light_positions[{}]", i), light.origin.into()))
+                .collect(),
+            colors: lights.iter().enumerate()
+                .map(|(i, light): (usize, &PointLight)| (format!("light_colors[{}]", i), light.color.into()))
+                .collect(),
+            intensities: lights.iter().enumerate()
+                .map(|(i, light): (usize, &PointLight)| (format!("light_intensities[{}]", i), light.intensity))
+                .collect(),
+        };
+    }
+
+}
+
+/// Uniforms with the point light arrays added
+struct WithPointLights<'l, U: Uniforms> {
+    uniforms: U,
+    lights: &'l PointLightUniforms,
+}
+
+impl<'l, U: Uniforms> Uniforms for WithPointLights<'l, U> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut output: F) {
+        self.uniforms.visit_values(&mut output);
+        output("light_count", UniformValue::SignedInt(self.lights.count));
+        for (name, position) in self.lights.positions.iter() {
+            output(name, UniformValue::Vec3(*position));
+        }
+        for (name, color) in self.lights.colors.iter() {
+            output(name, UniformValue::Vec3(*color));
+        }
+        for (name, intensity) in self.lights.intensities.iter() {
+            output(name, UniformValue::Float(*intensity));
+        }
+    }
+}
+
+pub struct OpenGLRenderer {
     display: glium::Display,
+    capabilities: RendererCapabilities,
+    static_program: Program, // Diffuse modulated by the lightmap atlas
+    static_instanced_program: Option<Program>, // The static program with transforms per instance, when instancing is supported
+    instance_buffers: RefCell<HashMap<(usize, usize), InstanceBuffer>>, // By model and render mode, kept while the instances are unchanged
+    decal_program: Program,
+    skybox_program: Program,
+    lines_program: Program,
+    post_program: Program, // Fullscreen effects over a copy of the frame, passing its colours through unconverted
+    post_texture: RefCell<Option<Texture2d>>, // Copy of the frame for post effects, resized with the frame
+    skybox_cube: VertexBuffer<Vertex>, // Unit cube around the eye, its positions double as sample directions
+    frame: RefCell<Option<Frame>>, // Frame being drawn between begin_frame and end_frame
+    viewport: Cell<Option<Rect>>, // Area every draw call is limited to, the whole frame when None
+    clear_color: Cell<(f32, f32, f32, f32)>,
+    stats: Cell<RenderStats>,
+    imgui_renderer: RefCell<Option<imgui_glium_renderer::Renderer>>, // Created by init_imgui
+    capture_requested: Cell<bool>, // Capture the passes of the next frame
+    capturing: Cell<bool>, // The frame being drawn is captured
+    captures: RefCell<Vec<PassCapture>>, // Passes of the captured frame, in drawing order
+}
+
+impl OpenGLRenderer {
+
+    pub fn new(display: glium::Display) -> Result<Self> {
+        let capabilities: RendererCapabilities = OpenGLRenderer::query_capabilities(&display);
+        info!(&crate::LOGGER, "Renderer capabilities: {:?}", capabilities);
+        let static_program: Program = OpenGLRenderer::compile_program(
+            &display,
+            capabilities.gamma_encoding(),
+            "static",
+            include_str!("shaders/static.vert"),
+            include_str!("shaders/static.frag"),
+        )?;
+        let static_instanced_program: Option<Program> = if capabilities.instancing {
+            Some(OpenGLRenderer::compile_program(
+                &display,
+                capabilities.gamma_encoding(),
+                "instanced static",
+                include_str!("shaders/static_instanced.vert"),
+                include_str!("shaders/static.frag"),
+            )?)
+        } else {
+            None
+        };
+        let decal_program: Program = OpenGLRenderer::compile_program(
+            &display,
+            capabilities.gamma_encoding(),
+            "decal",
+            include_str!("shaders/decal.vert"),
+            include_str!("shaders/decal.frag"),
+        )?;
+        let skybox_program: Program = OpenGLRenderer::compile_program(
+            &display,
+            capabilities.gamma_encoding(),
+            "skybox",
+            include_str!("shaders/skybox.vert"),
+            include_str!("shaders/skybox.frag"),
+        )?;
+        let lines_program: Program = OpenGLRenderer::compile_program(
+            &display,
+            capabilities.gamma_encoding(),
+            "lines",
+            include_str!("shaders/lines.vert"),
+            include_str!("shaders/lines.frag"),
+        )?;
+        let post_program: Program = OpenGLRenderer::compile_post_program(
+            &display,
+            include_str!("shaders/post.vert"),
+            include_str!("shaders/post.frag"),
+        )?;
+        let skybox_cube: VertexBuffer<Vertex> = OpenGLRenderer::skybox_cube(&display)?;
+        return Ok(OpenGLRenderer {
+            display,
+            capabilities,
+            static_program,
+            static_instanced_program,
+            instance_buffers: RefCell::new(HashMap::new()),
+            decal_program,
+            skybox_program,
+            lines_program,
+            post_program,
+            post_texture: RefCell::new(None),
+            skybox_cube,
+            frame: RefCell::new(None),
+            viewport: Cell::new(None),
+            clear_color: Cell::new((0.0, 0.0, 0.0, 1.0)),
+            stats: Cell::new(RenderStats::default()),
+            imgui_renderer: RefCell::new(None),
+            capture_requested: Cell::new(false),
+            capturing: Cell::new(false),
+            captures: RefCell::new(Vec::new()),
+        });
+    }
+
+    /// Triangle list of the six faces of a cube spanning -1 to 1
+    fn skybox_cube(display: &glium::Display) -> Result<VertexBuffer<Vertex>> {
+        const CORNERS: [[f32; 3]; 8] = [
+            [-1.0, -1.0, -1.0], [1.0, -1.0, -1.0], [1.0, 1.0, -1.0], [-1.0, 1.0, -1.0],
+            [-1.0, -1.0, 1.0], [1.0, -1.0, 1.0], [1.0, 1.0, 1.0], [-1.0, 1.0, 1.0],
+        ];
+        const FACES: [[usize; 4]; 6] = [
+            [1, 2, 6, 5], [0, 4, 7, 3], // +X, -X
+            [3, 7, 6, 2], [0, 1, 5, 4], // +Y, -Y
+            [4, 5, 6, 7], [0, 3, 2, 1], // +Z, -Z
+        ];
+        let vertices: Vec<Vertex> = FACES.iter()
+            .flat_map(|face: &[usize; 4]| [face[0], face[1], face[2], face[0], face[2], face[3]])
+            .map(|corner: usize| Vertex {
+                position: CORNERS[corner],
+                ..Vertex::default()
+            })
+            .collect();
+        return match VertexBuffer::new(display, &vertices) {
+            Ok(buffer) => Ok(buffer),
+            Err(error) => Err(Error::new(ErrorKind::InvalidData, format!("Unable to create skybox cube: {}", error))),
+        };
+    }
+
+    /// The post program reads the frame back as it was written, so its
+    /// output must not be encoded to sRGB a second time
+    fn compile_post_program(display: &glium::Display, vertex: &str, fragment: &str) -> Result<Program> {
+        let input: ProgramCreationInput = ProgramCreationInput::SourceCode {
+            vertex_shader: vertex,
+            tessellation_control_shader: None,
+            tessellation_evaluation_shader: None,
+            geometry_shader: None,
+            fragment_shader: fragment,
+            transform_feedback_varyings: None,
+            outputs_srgb: true,
+            uses_point_size: false,
+        };
+        return match Program::new(display, input) {
+            Ok(program) => Ok(program),
+            Err(error) => Err(Error::new(ErrorKind::InvalidData, format!("Unable to compile post shader program: {}", error))),
+        };
+    }
+
+    fn compile_program(display: &glium::Display, gamma: GammaEncoding, name: &str, vertex: &str, fragment: &str) -> Result<Program> {
+        let fragment: String = OpenGLRenderer::fragment_source(fragment, gamma);
+        let input: ProgramCreationInput = ProgramCreationInput::SourceCode {
+            vertex_shader: vertex,
+            tessellation_control_shader: None,
+            tessellation_evaluation_shader: None,
+            geometry_shader: None,
+            fragment_shader: &fragment,
+            transform_feedback_varyings: None,
+            outputs_srgb: gamma == GammaEncoding::Shader,
+            uses_point_size: false,
+        };
+        return match Program::new(display, input) {
+            Ok(program) => Ok(program),
+            Err(error) => Err(Error::new(ErrorKind::InvalidData, format!("Unable to compile {} shader program: {}", name, error))),
+        };
+    }
+
+    /// Fragment shader source defining ENCODE_SRGB after its version line when
+    /// the shader has to encode its output itself
+    fn fragment_source(fragment: &str, gamma: GammaEncoding) -> String {
+        if gamma == GammaEncoding::Framebuffer {
+            return fragment.to_string();
+        }
+        return match fragment.split_once('\n') {
+            Some((version, body)) => format!("{}\n#define ENCODE_SRGB\n{}", version, body),
+            None => format!("{}\n#define ENCODE_SRGB\n", fragment),
+        };
+    }
+
+    /// Displacement along the normal of water at the crests of its standing
+    /// wave `time` seconds in. Zero at time zero, so still frames are flat.
+    fn wave_amplitude(wave_height: f32, time: f32) -> f32 {
+        return wave_height * (time * WAVE_SPEED).sin();
+    }
+
+    /// GL state for an entity's render mode. Normal entities are opaque and
+    /// Solid ones cut out their `{` texels, neither is blended nor uses its
+    /// render amount. Glow has no sprite to turn towards the camera on a brush
+    /// model, so it is drawn like Additive but without lighting.
+    fn entity_draw_state(entity: &EntityData) -> EntityDrawState {
+        let additive: Blend = Blend {
+            color: BlendingFunction::Addition {
+                source: LinearBlendingFactor::SourceAlpha,
+                destination: LinearBlendingFactor::One,
+            },
+            alpha: BlendingFunction::Addition {
+                source: LinearBlendingFactor::Zero,
+                destination: LinearBlendingFactor::One,
+            },
+            constant_value: (0.0, 0.0, 0.0, 0.0),
+        };
+        let opaque: EntityDrawState = EntityDrawState {
+            blend: None,
+            alpha: 1.0,
+            alpha_test: false,
+            lit: true,
+            additive: false,
+            water: false,
+            wave_height: entity.wave_height,
+        };
+        return match entity.render_mode {
+            RenderMode::RenderModeNormal => opaque,
+            RenderMode::RenderModeSolid => EntityDrawState { alpha_test: true, ..opaque },
+            RenderMode::RenderModeTexture | RenderMode::RenderModeColor => EntityDrawState {
+                blend: Some(Blend::alpha_blending()),
+                alpha: entity.alpha,
+                ..opaque
+            },
+            RenderMode::RenderModeAdditive => EntityDrawState {
+                blend: Some(additive),
+                alpha: entity.alpha,
+                additive: true,
+                ..opaque
+            },
+            RenderMode::RenderModeGlow => EntityDrawState {
+                blend: Some(additive),
+                alpha: entity.alpha,
+                additive: true,
+                lit: false,
+                ..opaque
+            },
+        };
+    }
+
+    /// BSP faces are wound clockwise seen from the front, and the view keeps
+    /// that handedness, so counter clockwise triangles are back faces
+    fn static_draw_parameters(&self, blend: Option<Blend>, depth_only: bool, depth_write: bool) -> DrawParameters<'static> {
+        return DrawParameters {
+            viewport: self.viewport.get(),
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: depth_write,
+                ..Default::default()
+            },
+            blend: blend.unwrap_or_default(),
+            color_mask: if depth_only { (false, false, false, false) } else { (true, true, true, true) },
+            backface_culling: BackfaceCullingMode::CullCounterClockwise,
+            ..Default::default()
+        };
+    }
+
+    /// Wires over the filled faces, with the same culling so faces dropped by
+    /// it leave gaps in the wireframe
+    fn wireframe_draw_parameters(viewport: Option<Rect>) -> DrawParameters<'static> {
+        return DrawParameters {
+            viewport,
+            depth: Depth {
+                test: DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            polygon_mode: PolygonMode::Line,
+            polygon_offset: PolygonOffset {
+                factor: -1.0,
+                units: -1.0,
+                line: true,
+                ..Default::default()
+            },
+            backface_culling: BackfaceCullingMode::CullCounterClockwise,
+            ..Default::default()
+        };
+    }
+
+    /// Translucent fill over faces already drawn, only where they are visible
+    fn highlight_draw_parameters(&self) -> DrawParameters<'static> {
+        return DrawParameters {
+            viewport: self.viewport.get(),
+            depth: Depth {
+                test: DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            polygon_offset: PolygonOffset {
+                factor: -1.0,
+                units: -1.0,
+                fill: true,
+                ..Default::default()
+            },
+            blend: Blend::alpha_blending(),
+            backface_culling: BackfaceCullingMode::CullCounterClockwise,
+            ..Default::default()
+        };
+    }
+
+    /// Every rasterised fragment adds the overdraw colour, hidden or not
+    fn overdraw_draw_parameters(viewport: Option<Rect>) -> DrawParameters<'static> {
+        return DrawParameters {
+            viewport,
+            depth: Depth {
+                test: DepthTest::Overwrite,
+                write: false,
+                ..Default::default()
+            },
+            blend: Blend {
+                color: BlendingFunction::Addition {
+                    source: LinearBlendingFactor::One,
+                    destination: LinearBlendingFactor::One,
+                },
+                alpha: BlendingFunction::Addition {
+                    source: LinearBlendingFactor::One,
+                    destination: LinearBlendingFactor::One,
+                },
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            backface_culling: BackfaceCullingMode::CullCounterClockwise,
+            ..Default::default()
+        };
+    }
+
+    /// Draw faces in a single colour with the line program, which only reads
+    /// positions, so the static vertex buffer is reused as it is
+    fn draw_flat_faces(&self,
+                       frame: &mut Frame,
+                       vertices: &VertexBuffer<VertexWithLM>,
+                       indices: &IndexBuffer<u32>,
+                       faces: &Vec<FaceRenderInfo>,
+                       matrix: &glm::Mat4,
+                       color: [f32; 4],
+                       params: &DrawParameters,
+                       stats: &mut RenderStats) {
+        let matrix: [[f32; 4]; 4] = (*matrix).into();
+        for face in faces.iter() {
+            let Some(face_indices) = indices.slice(face.offset..face.offset + face.count) else {
+                continue;
+            };
+            let drawn: std::result::Result<(), DrawError> = frame.draw(
+                vertices,
+                face_indices,
+                &self.lines_program,
+                &uniform! {
+                    matrix: matrix,
+                    line_color: color,
+                },
+                params,
+            );
+            match drawn {
+                Ok(()) => stats.draw_calls += 1,
+                Err(error) => warn!(&crate::LOGGER, "Unable to draw flat face at vertex {}: {}", face.offset, error),
+            };
+        }
+    }
+
+    fn diffuse_sampler<T>(sampler: Sampler<'_, T>, anisotropy: u16) -> Sampler<'_, T> {
+        return sampler
+            .wrap_function(SamplerWrapFunction::Repeat)
+            .minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+            .magnify_filter(MagnifySamplerFilter::Linear)
+            .anisotropy(anisotropy);
+    }
+
+    fn draw_static_face<D: AsUniformValue>(&self,
+                                           frame: &mut Frame,
+                                           vertices: &VertexBuffer<VertexWithLM>,
+                                           instances: Option<&VertexBuffer<InstanceAttributes>>, // Draws every instance with the instanced program
+                                           indices: IndexBufferSlice<u32>,
+                                           matrix: &glm::Mat4,
+                                           origin: &glm::Vec3,
+                                           diffuse: D,
+                                           textured: bool,
+                                           lightmap: Sampler<SrgbTexture2d>,
+                                           state: &EntityDrawState,
+                                           uv_scroll: glm::Vec2,
+                                           shared: &StaticFrameUniforms,
+                                           params: &DrawParameters) -> std::result::Result<(), DrawError> {
+        let matrix: [[f32; 4]; 4] = (*matrix).into();
+        let origin: [f32; 3] = (*origin).into();
+        let uv_scroll: [f32; 2] = uv_scroll.into();
+        let (show_diffuse, show_lighting): (bool, bool) = shared.lighting_mode.shader_terms();
+        let camera_position: [f32; 3] = shared.camera.into();
+        // Added light fades out with distance rather than turning the fog's colour
+        let fog_color: [f32; 3] = if state.additive { [0.0, 0.0, 0.0] } else { shared.fog.color };
+        let uniforms = WithPointLights {
+            uniforms: uniform! {
+                matrix: matrix,
+                origin: origin,
+                uv_scroll: uv_scroll,
+                diffuse: diffuse,
+                textured: textured,
+                lightmap: lightmap,
+                alpha: state.alpha,
+                alpha_test: state.alpha_test,
+                lit: state.lit,
+                show_diffuse: show_diffuse,
+                show_lighting: show_lighting,
+                water: state.water,
+                wave_amplitude: OpenGLRenderer::wave_amplitude(state.wave_height, shared.time),
+                time: shared.time,
+                fog_mode: shared.fog.shader_mode(),
+                fog_color: fog_color,
+                fog_start: shared.fog.start,
+                fog_end: shared.fog.end,
+                fog_density: shared.fog.density,
+                camera_position: camera_position,
+            },
+            lights: &shared.lights,
+        };
+        let (Some(instances), Some(program)) = (instances, self.static_instanced_program.as_ref()) else {
+            return frame.draw(vertices, indices, &self.static_program, &uniforms, params);
+        };
+        // Buffers only get here after per_instance succeeded in update_instance_buffer
+        return frame.draw((vertices, instances.per_instance().unwrap()), indices, program, &uniforms, params);
+    }
+
+    /// Key of the instance buffer of a group of entities drawn together
+    fn instance_key(entity: &EntityData) -> (usize, usize) {
+        return (entity.model.unwrap_or(usize::MAX), entity.render_mode as usize);
+    }
+
+    /// Make the instance buffer of a group of entities match their current
+    /// transforms and alpha, uploading it only when they changed. False when
+    /// the group cannot be drawn instanced and must be drawn one by one.
+    fn update_instance_buffer(&self, entities: &[EntityData], group: &[usize]) -> bool {
+        if self.static_instanced_program.is_none() {
+            return false;
+        }
+        let instances: Vec<InstanceAttributes> = group.iter()
+            .map(|index: &usize| InstanceAttributes {
+                model: glm::translation(&entities[*index].origin).into(),
+                instance_alpha: OpenGLRenderer::entity_draw_state(&entities[*index]).alpha,
+            })
+            .collect();
+        let key: (usize, usize) = OpenGLRenderer::instance_key(&entities[group[0]]);
+        let mut buffers: RefMut<HashMap<(usize, usize), InstanceBuffer>> = self.instance_buffers.borrow_mut();
+        if buffers.get(&key).is_some_and(|cached: &InstanceBuffer| cached.instances == instances) {
+            return true;
+        }
+        let buffer: VertexBuffer<InstanceAttributes> = match VertexBuffer::new(&self.display, &instances) {
+            Ok(buffer) => buffer,
+            Err(error) => {
+                warn!(&crate::LOGGER, "Unable to create an instance buffer, drawing {} entities one by one: {}", group.len(), error);
+                return false;
+            },
+        };
+        if buffer.per_instance().is_err() {
+            return false;
+        }
+        buffers.insert(key, InstanceBuffer { instances, buffer });
+        return true;
+    }
+
+
+    fn query_capabilities(display: &glium::Display) -> RendererCapabilities {
+        let context: &Context = display;
+        let version: &Version = context.get_opengl_version();
+        return RendererCapabilities {
+            max_texture_size: context.get_capabilities().max_texture_size.max(0) as u32,
+            max_anisotropy: context.get_max_anisotropy_support(),
+            srgb_framebuffer: context.get_capabilities().srgb,
+            instancing: *version >= Version(Api::Gl, 3, 3)
+                || *version >= Version(Api::GlEs, 3, 0)
+                || context.get_extensions().gl_arb_instanced_arrays,
+            compressed_textures: CompressedSrgbFormat::S3tcDxt1NoAlpha.is_supported(context)
+                && CompressedSrgbFormat::S3tcDxt5Alpha.is_supported(context),
+            wide_lines: !context.is_forward_compatible(),
+        };
+    }
+
+    /// Check each mip level is half the size of the one before, rounding
+    /// down and stopping at 1
+    fn check_mip_chain(mipmaps: &Vec<&Image>) -> Result<()> {
+        for i in 1..mipmaps.len() {
+            let (previous, level): (&Image, &Image) = (mipmaps[i - 1], mipmaps[i]);
+            let expected: (usize, usize) = ((previous.width / 2).max(1), (previous.height / 2).max(1));
+            if (level.width, level.height) != expected {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Mipmap level {} is {}x{} but level {} is {}x{}, expected {}x{}",
+                        i, level.width, level.height, i - 1, previous.width, previous.height, expected.0, expected.1,
+                    ),
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    /// RGBA image of pixels read back from GL, which reads rows from the
+    /// bottom up, flipped so row 0 is the top
+    fn image_from_gl_rows(raw: RawImage2d<u8>) -> Result<Image> {
+        let mut image: Image = Image {
+            channels: 4,
+            width: raw.width as usize,
+            height: raw.height as usize,
+            data: raw.data.into_owned(),
+        };
+        image.flip_vertical()?;
+        return Ok(image);
+    }
+
+    /// Check every sky face is a square of the same non-zero size, returning that size
+    fn check_sky_faces(sides: &[Image; 6]) -> Result<usize> {
+        let size: usize = sides[0].width;
+        for (i, side) in sides.iter().enumerate() {
+            if side.width != side.height || side.width != size || size == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Skybox face '{}' is {}x{}, every face must be the same non-empty square size as '{}' ({}x{})",
+                        SKY_NAME_SUFFIXES[i], side.width, side.height, SKY_NAME_SUFFIXES[0], size, size,
+                    ),
+                ));
+            }
+        }
+        return Ok(size);
+    }
+
+    /// Four channel view of an image for the RGBA uploads, converting 1 to 3
+    /// channel images such as the lightmap atlas
+    fn rgba(image: &Image) -> Result<Cow<'_, Image>> {
+        if image.channels == 4 {
+            return Ok(Cow::Borrowed(image));
+        }
+        return Ok(Cow::Owned(image.to_rgba()?));
+    }
+
+    /// Rearrange a sky image into the layout of the cubemap face it is
+    /// uploaded to, see `skybox_face_mapping`
+    fn orient_sky_face(image: &Image, orientation: SkyFaceOrientation) -> Result<Image> {
+        let rgba: Cow<Image> = OpenGLRenderer::rgba(image)?;
+        let size: usize = rgba.width;
+        let mut face: Image = Image::from((size, size, 4));
+        for t in 0..size {
+            for s in 0..size {
+                let (u, v): (usize, usize) = match orientation {
+                    SkyFaceOrientation::FlipHorizontal => (size - 1 - s, t),
+                    SkyFaceOrientation::Transpose => (t, s),
+                    SkyFaceOrientation::AntiTranspose => (size - 1 - t, size - 1 - s),
+                };
+                face.put_pixel(s, t, rgba.pixel(u, v).unwrap())?;
+            }
+        }
+        return Ok(face);
+    }
+
+    /// Block compress an image with rows reversed to match the bottom-up
+    /// uploads done by `create_texture`
+    fn compress_reversed(image: &Image, format: BlockFormat) -> Vec<u8> {
+        let row: usize = image.width * image.channels;
+        let mut reversed: Image = image.clone();
+        for y in 0..image.height {
+            let src: usize = (image.height - 1 - y) * row;
+            reversed.data[y * row..(y + 1) * row].copy_from_slice(&image.data[src..src + row]);
+        }
+        return block_compression::compress_image(&reversed, format);
+    }
+
+    /// The world and brush entities of `render_static`, with the debug views over them
+    fn render_entities(&self,
+                       entities: &Vec<EntityData>,
+                       static_layout: &VertexBuffer<VertexWithLM>,
+                       static_indices: &IndexBuffer<u32>,
+                       textures: &Vec<TextureHandle>,
+                       animations: &Vec<TextureAnimation>,
+                       lightmaps_atlas: &SrgbTexture2d,
+                       lights: &[PointLight],
+                       settings: &RenderSettings) {
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_static called outside of a frame, skipping");
+            return;
+        };
+        let lightmap: Sampler<SrgbTexture2d> = lightmaps_atlas.sampled()
+            .wrap_function(SamplerWrapFunction::Clamp)
+            .minify_filter(MinifySamplerFilter::Linear)
+            .magnify_filter(MagnifySamplerFilter::Linear);
+        let anisotropy: u16 = self.capabilities.clamp_anisotropy(settings.anisotropy);
+        let view_projection: glm::Mat4 = settings.projection * settings.view;
+        let mut stats: RenderStats = self.stats.get();
+        let camera: glm::Vec3 = glm::inverse(&settings.view).column(3).xyz();
+        let shared: StaticFrameUniforms = StaticFrameUniforms {
+            lights: PointLightUniforms::new(if settings.dynamic_lights { lights } else { &[] }),
+            lighting_mode: settings.lighting_mode,
+            time: settings.time,
+            fog: settings.fog,
+            camera,
+        };
+        let wireframe_params: DrawParameters = OpenGLRenderer::wireframe_draw_parameters(self.viewport.get());
+        let overdraw_params: DrawParameters = OpenGLRenderer::overdraw_draw_parameters(self.viewport.get());
+        let highlight_params: DrawParameters = self.highlight_draw_parameters();
+        let order: Vec<usize> = EntityData::draw_order(entities, &camera);
+        // Entities sharing a model are drawn in one go, except in the
+        // overdraw view which counts each entity's layers
+        let mut draws: Vec<Vec<usize>> = Vec::with_capacity(order.len());
+        let groups: Vec<Vec<usize>> = if settings.overdraw {
+            order.into_iter().map(|index: usize| vec![index]).collect()
+        } else {
+            EntityData::instance_groups(entities, &order)
+        };
+        // A buffer serves one group a frame, later groups with the same key are drawn one by one
+        let mut instanced_keys: Vec<(usize, usize)> = Vec::new();
+        for group in groups {
+            let key: (usize, usize) = OpenGLRenderer::instance_key(&entities[group[0]]);
+            if group.len() < MIN_INSTANCES {
+                draws.push(group);
+            } else if !instanced_keys.contains(&key) && self.update_instance_buffer(entities, &group) {
+                instanced_keys.push(key);
+                draws.push(group);
+            } else {
+                draws.extend(group.into_iter().map(|index: usize| vec![index]));
+            }
+        }
+        let instance_buffers: Ref<HashMap<(usize, usize), InstanceBuffer>> = self.instance_buffers.borrow();
+        for group in draws.iter() {
+            let entity: &EntityData = &entities[group[0]];
+            let instances: Option<&VertexBuffer<InstanceAttributes>> = if group.len() >= MIN_INSTANCES {
+                instance_buffers.get(&OpenGLRenderer::instance_key(entity)).map(|instances: &InstanceBuffer| &instances.buffer)
+            } else {
+                None
+            };
+            // Instances carry their own transforms
+            let matrix: glm::Mat4 = match instances {
+                Some(_) => view_projection,
+                None => view_projection * glm::translation(&entity.origin),
+            };
+            let state: EntityDrawState = OpenGLRenderer::entity_draw_state(entity);
+            let batched: Vec<FaceRenderInfo>;
+            let faces: &Vec<FaceRenderInfo> = if settings.batch_draws {
+                batched = FaceRenderInfo::batch(&entity.face_render_info, animations, settings.time);
+                &batched
+            } else {
+                &entity.face_render_info
+            };
+            stats.faces_rendered += entity.face_render_info.len() * group.len();
+            if settings.overdraw {
+                self.draw_flat_faces(frame, static_layout, static_indices, faces, &matrix, OVERDRAW_COLOR, &overdraw_params, &mut stats);
+                stats.triangles += faces.iter().map(|face: &FaceRenderInfo| face.count / 3).sum::<usize>();
+                continue;
+            }
+            for face in faces.iter() {
+                let Some(indices) = static_indices.slice(face.offset..face.offset + face.count) else {
+                    warn!(&crate::LOGGER, "Face indices {}..{} are outside the static index buffer", face.offset, face.offset + face.count);
+                    continue;
+                };
+                // Water is blended but still hides what is under it from later transparent entities
+                let params: DrawParameters = self.static_draw_parameters(state.blend, face.depth_only, state.blend.is_none() || face.water);
+                let face_state: EntityDrawState = EntityDrawState {
+                    alpha_test: state.alpha_test || face.masked,
+                    water: face.water,
+                    wave_height: state.wave_height * settings.water_wave_scale,
+                    ..state
+                };
+                let texture_index: Option<usize> = face.texture_at(animations, settings.time);
+                let uv_scroll: glm::Vec2 = face.uv_scroll.unwrap_or(glm::vec2(0.0, 0.0));
+                let drawn: std::result::Result<(), DrawError> = match texture_index.and_then(|index: usize| textures.get(index)) {
+                    Some(TextureHandle::Uncompressed(texture)) => self.draw_static_face(
+                        frame, static_layout, instances, indices, &matrix, &entity.origin,
+                        OpenGLRenderer::diffuse_sampler(texture.sampled(), anisotropy), true, lightmap,
+                        &face_state, uv_scroll, &shared, &params,
+                    ),
+                    Some(TextureHandle::Compressed(texture, _)) => self.draw_static_face(
+                        frame, static_layout, instances, indices, &matrix, &entity.origin,
+                        OpenGLRenderer::diffuse_sampler(texture.sampled(), anisotropy), true, lightmap,
+                        &face_state, uv_scroll, &shared, &params,
+                    ),
+                    // Untextured faces show the lighting alone, the lightmap stands in for the unused diffuse sampler
+                    None => self.draw_static_face(
+                        frame, static_layout, instances, indices, &matrix, &entity.origin,
+                        lightmap, false, lightmap,
+                        &face_state, uv_scroll, &shared, &params,
+                    ),
+                };
+                match drawn {
+                    Ok(()) => {
+                        stats.draw_calls += 1;
+                        stats.triangles += face.count / 3 * group.len();
+                        if instances.is_some() {
+                            stats.instanced_draw_calls += 1;
+                        }
+                    },
+                    Err(error) => warn!(&crate::LOGGER, "Unable to draw face at vertex {}: {}", face.offset, error),
+                };
+            }
+            if instances.is_some() {
+                stats.instances += group.len();
+            }
+            // The debug views go over each entity of the group on its own
+            for member in group.iter().map(|index: &usize| &entities[*index]) {
+                let matrix: glm::Mat4 = view_projection * glm::translation(&member.origin);
+                if settings.wireframe {
+                    self.draw_flat_faces(frame, static_layout, static_indices, faces, &matrix, WIREFRAME_COLOR, &wireframe_params, &mut stats);
+                }
+                if let Some(texture) = settings.highlight_texture {
+                    let highlighted: Vec<FaceRenderInfo> = member.face_render_info.iter()
+                        .filter(|face: &&FaceRenderInfo| face.uses_texture(texture, animations))
+                        .cloned()
+                        .collect();
+                    self.draw_flat_faces(frame, static_layout, static_indices, &highlighted, &matrix, HIGHLIGHT_COLOR, &highlight_params, &mut stats);
+                }
+            }
+        }
+        self.stats.set(stats);
+    }
+
+    /// Decals of `render_static`, over the finished world
+    fn render_decals(&self, decals: &Vec<Decal>, decal_layout: &VertexBuffer<Vertex>, textures: &Vec<TextureHandle>, settings: &RenderSettings) {
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            return;
+        };
+        let view_projection: glm::Mat4 = settings.projection * settings.view;
+        let camera: glm::Vec3 = glm::inverse(&settings.view).column(3).xyz();
+        let mut stats: RenderStats = self.stats.get();
+        // Decals go over the finished world, pulled towards the camera so
+        // they do not fight with the faces they lie on
+        let decal_params: DrawParameters = DrawParameters {
+            viewport: self.viewport.get(),
+            depth: Depth {
+                test: DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            blend: Blend::alpha_blending(),
+            polygon_offset: PolygonOffset {
+                factor: -1.0,
+                units: -1.0,
+                fill: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let view_projection: [[f32; 4]; 4] = view_projection.into();
+        let camera_position: [f32; 3] = camera.into();
+        let mut offset: usize = 0;
+        for decal in decals.iter() {
+            let count: usize = decal.vec.len().saturating_sub(2) * 3;
+            let vertices: Option<VertexBufferSlice<Vertex>> = decal_layout.slice(offset..offset + count);
+            offset += count;
+            let (Some(vertices), Some(texture)) = (vertices, textures.get(decal.tex_index as usize)) else {
+                continue;
+            };
+            let drawn: std::result::Result<(), DrawError> = match texture {
+                TextureHandle::Uncompressed(texture) => frame.draw(
+                    vertices,
+                    NoIndices(PrimitiveType::TrianglesList),
+                    &self.decal_program,
+                    &uniform! {
+                        matrix: view_projection,
+                        diffuse: texture.sampled().wrap_function(SamplerWrapFunction::Clamp),
+                        fog_mode: settings.fog.shader_mode(),
+                        fog_color: settings.fog.color,
+                        fog_start: settings.fog.start,
+                        fog_end: settings.fog.end,
+                        fog_density: settings.fog.density,
+                        camera_position: camera_position,
+                    },
+                    &decal_params,
+                ),
+                TextureHandle::Compressed(texture, _) => frame.draw(
+                    vertices,
+                    NoIndices(PrimitiveType::TrianglesList),
+                    &self.decal_program,
+                    &uniform! {
+                        matrix: view_projection,
+                        diffuse: texture.sampled().wrap_function(SamplerWrapFunction::Clamp),
+                        fog_mode: settings.fog.shader_mode(),
+                        fog_color: settings.fog.color,
+                        fog_start: settings.fog.start,
+                        fog_end: settings.fog.end,
+                        fog_density: settings.fog.density,
+                        camera_position: camera_position,
+                    },
+                    &decal_params,
+                ),
+            };
+            match drawn {
+                Ok(()) => {
+                    stats.draw_calls += 1;
+                    stats.triangles += count / 3;
+                },
+                Err(error) => warn!(&crate::LOGGER, "Unable to draw decal with texture {}: {}", decal.tex_index, error),
+            };
+        }
+        self.stats.set(stats);
+    }
+
+    /// Copy of the colour buffer of `frame` as it is now, row 0 at the top
+    fn read_color(&self, frame: &Frame) -> Result<Image> {
+        let (width, height): (u32, u32) = frame.get_dimensions();
+        let texture: Texture2d = Texture2d::empty_with_format(
+            &self.display,
+            UncompressedFloatFormat::U8U8U8U8,
+            MipmapsOption::NoMipmap,
+            width,
+            height,
+        ).map_err(|error| Error::new(ErrorKind::Other, format!("Unable to create {}x{} capture texture: {}", width, height, error)))?;
+        let target: SimpleFrameBuffer = SimpleFrameBuffer::new(&self.display, &texture)
+            .map_err(|error| Error::new(ErrorKind::Other, format!("Unable to create capture framebuffer: {}", error)))?;
+        frame.blit_whole_color_to(
+            &target,
+            &BlitTarget {
+                left: 0,
+                bottom: 0,
+                width: width as i32,
+                height: height as i32,
+            },
+            MagnifySamplerFilter::Nearest,
+        );
+        let raw: RawImage2d<u8> = texture.read();
+        let mut image: Image = Image {
+            channels: 4,
+            width: raw.width as usize,
+            height: raw.height as usize,
+            data: raw.data.into_owned(),
+        };
+        // GL reads rows from the bottom up
+        image.flip_vertical()?;
+        return Ok(image);
+    }
+
 }
 
 impl Renderer for OpenGLRenderer {
 
+    fn capabilities(&self) -> RendererCapabilities {
+        return self.capabilities;
+    }
+
+    fn stats(&self) -> RenderStats {
+        return self.stats.get();
+    }
+
+    fn begin_frame(&self) {
+        self.stats.set(RenderStats::default());
+        if self.capture_requested.replace(false) {
+            self.captures.borrow_mut().clear();
+            self.capturing.set(true);
+        }
+        if let Some(unfinished) = self.frame.replace(Some(self.display.draw())) {
+            warn!(&crate::LOGGER, "Previous frame was not ended, discarding it");
+            let _ = unfinished.finish();
+        }
+        self.clear();
+    }
+
+    fn end_frame(&self) -> Result<()> {
+        self.capturing.set(false);
+        let Some(frame) = self.frame.take() else {
+            return Err(Error::new(ErrorKind::Other, "No frame to end, begin_frame was not called"));
+        };
+        return match frame.finish() {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Error::new(ErrorKind::Other, format!("Unable to swap buffers: {:?}", error))),
+        };
+    }
+
     fn resize_viewport(&self, width: usize, height: usize) {
-        todo!()
+        self.viewport.set(Some(Rect {
+            left: 0,
+            bottom: 0,
+            width: width.max(1) as u32,
+            height: height.max(1) as u32,
+        }));
+        debug!(&crate::LOGGER, "Resized viewport to {}x{}", width, height);
+    }
+
+    fn set_clear_color(&self, color: (f32, f32, f32, f32)) {
+        self.clear_color.set(color);
     }
 
     fn clear(&self) {
-        todo!()
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "clear called outside of a frame, skipping");
+            return;
+        };
+        frame.clear(self.viewport.get().as_ref(), Some(self.clear_color.get()), false, Some(1.0), None);
     }
 
     fn create_texture(&self, mipmaps: &Vec<&crate::resource::image::Image>) -> Result<SrgbTexture2d> {
         if mipmaps.len() < 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "At least one image must be provided to create a texture"));
         }
+        OpenGLRenderer::check_mip_chain(mipmaps)?;
+        let base: Cow<Image> = OpenGLRenderer::rgba(mipmaps[0])?;
         let raw = RawImage2d::from_raw_rgba_reversed(
-            &mipmaps[0].data,
-            (mipmaps[0].width as u32, mipmaps[0].height as u32)
+            &base.data,
+            (base.width as u32, base.height as u32)
         );
         let mipmaps_option = if mipmaps.len() > 1 {
-            MipmapsOption::EmptyMipmapsMax(mipmaps.len() as u32)
+            MipmapsOption::EmptyMipmapsMax(mipmaps.len() as u32 - 1)
         } else {
             MipmapsOption::AutoGeneratedMipmaps
         };
@@ -40,8 +1052,11 @@ impl Renderer for OpenGLRenderer {
             return Ok(texture);
         }
         for i in 1..mipmaps.len() {
-            let image: &crate::resource::image::Image = mipmaps[i];
-            texture.mipmap(1).unwrap().write(
+            let image: Cow<Image> = OpenGLRenderer::rgba(mipmaps[i])?;
+            let Some(level) = texture.mipmap(i as u32) else {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Texture has no mipmap level {}", i)));
+            };
+            level.write(
                 Rect {
                     left: 0,
                     bottom: 0,
@@ -57,73 +1072,683 @@ impl Renderer for OpenGLRenderer {
         return Ok(texture);
     }
 
+    fn update_texture(&self, texture: &SrgbTexture2d, x: usize, y: usize, image: &Image) -> Result<()> {
+        let (width, height): (usize, usize) = (texture.width() as usize, texture.height() as usize);
+        if x + image.width > width || y + image.height > height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}x{} region at ({}, {}) is outside the {}x{} texture", image.width, image.height, x, y, width, height),
+            ));
+        }
+        let rgba: Cow<Image> = OpenGLRenderer::rgba(image)?;
+        // Rows are uploaded bottom-up, as in create_texture
+        texture.write(
+            Rect {
+                left: x as u32,
+                bottom: (height - y - image.height) as u32,
+                width: image.width as u32,
+                height: image.height as u32,
+            },
+            RawImage2d::from_raw_rgba_reversed(&rgba.data, (image.width as u32, image.height as u32)),
+        );
+        return Ok(());
+    }
+
+    fn create_compressed_texture(&self, mipmaps: &Vec<&Image>, format: BlockFormat) -> Result<CompressedSrgbTexture2d> {
+        if mipmaps.len() < 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "At least one image must be provided to create a texture"));
+        }
+        if !self.capabilities.compressed_textures {
+            return Err(Error::new(ErrorKind::Unsupported, "Compressed sRGB textures are not supported"));
+        }
+        let gl_format: CompressedSrgbFormat = match format {
+            BlockFormat::Bc1 => CompressedSrgbFormat::S3tcDxt1NoAlpha,
+            BlockFormat::Bc3 => CompressedSrgbFormat::S3tcDxt5Alpha,
+        };
+        let mipmaps_option: CompressedMipmapsOption = if mipmaps.len() > 1 {
+            CompressedMipmapsOption::EmptyMipmapsMax(mipmaps.len() as u32 - 1)
+        } else {
+            CompressedMipmapsOption::NoMipmap
+        };
+        let texture: CompressedSrgbTexture2d = match CompressedSrgbTexture2d::with_compressed_data(
+            &self.display,
+            &OpenGLRenderer::compress_reversed(mipmaps[0], format),
+            mipmaps[0].width as u32,
+            mipmaps[0].height as u32,
+            gl_format,
+            mipmaps_option,
+        ) {
+            Ok(tex) => tex,
+            Err(error) => return Err(Error::new(ErrorKind::InvalidData, format!("Unable to create compressed level 0 mipmap: {}", error))),
+        };
+        for i in 1..mipmaps.len() {
+            let image: &Image = mipmaps[i];
+            let level = match texture.mipmap(i as u32) {
+                Some(level) => level,
+                None => break,
+            };
+            let written = level.write_compressed_data(
+                Rect {
+                    left: 0,
+                    bottom: 0,
+                    width: image.width as u32,
+                    height: image.height as u32,
+                },
+                &OpenGLRenderer::compress_reversed(image, format),
+                image.width as u32,
+                image.height as u32,
+                gl_format,
+            );
+            if written.is_err() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Unable to write compressed mipmap level {}", i)));
+            }
+        }
+        return Ok(texture);
+    }
+
     fn create_cube_texture(&self, sides: [crate::resource::image::Image; 6]) -> Result<SrgbCubemap> {
-        let cubemap: SrgbCubemap = match SrgbCubemap::empty_with_mipmaps(&self.display, MipmapsOption::AutoGeneratedMipmaps, 2) {
+        let size: usize = OpenGLRenderer::check_sky_faces(&sides)?;
+        let cubemap: SrgbCubemap = match SrgbCubemap::empty_with_mipmaps(&self.display, MipmapsOption::NoMipmap, size as u32) {
             Ok(tex) => tex,
-            Err(error) => return Err(Error::new(ErrorKind::InvalidData, format!("Unable to create empty cubemap of dimentsion 2: {}", error))),
-        };
-        for i in 0..sides.len() {
-            // TODO: Implement this, no direct texture binding available in glium for cubemaps,
-            // need to use framebuffers to bind the textures to instead. Not sure how to handle
-            // this:
-            //let  framebuffer1 = glium::framebuffer::SimpleFrameBuffer::new(&display,
-                            //cubemap.main_level().image(glium::texture::CubeLayer::PositiveX)).unwrap();
-            //let  framebuffer2 = glium::framebuffer::SimpleFrameBuffer::new(&display,
-                            //cubemap.main_level().image(glium::texture::CubeLayer::NegativeX)).unwrap();
-            //let  framebuffer3 = glium::framebuffer::SimpleFrameBuffer::new(&display,
-                            //cubemap.main_level().image(glium::texture::CubeLayer::PositiveY)).unwrap();
-            //let  framebuffer4 = glium::framebuffer::SimpleFrameBuffer::new(&display,
-                            //cubemap.main_level().image(glium::texture::CubeLayer::NegativeY)).unwrap();
-            //let  framebuffer5 = glium::framebuffer::SimpleFrameBuffer::new(&display,
-                            //cubemap.main_level().image(glium::texture::CubeLayer::PositiveZ)).unwrap();
-            //let  framebuffer6 = glium::framebuffer::SimpleFrameBuffer::new(&display,
-                            //cubemap.main_level().image(glium::texture::CubeLayer::NegativeZ)).unwrap();
-
-            //tex_posx.as_surface().blit_whole_color_to(&framebuffer1, &dest_rect1,
-                            //glium::uniforms::MagnifySamplerFilter::Linear);
-            //tex_negx.as_surface().blit_whole_color_to(&framebuffer2, &dest_rect1,
-                            //glium::uniforms::MagnifySamplerFilter::Linear);
-            //tex_negy.as_surface().blit_whole_color_to(&framebuffer3, &dest_rect1,
-                            //glium::uniforms::MagnifySamplerFilter::Linear);
-            //tex_posy.as_surface().blit_whole_color_to(&framebuffer4, &dest_rect1,
-                            //glium::uniforms::MagnifySamplerFilter::Linear);
-            //tex_posz.as_surface().blit_whole_color_to(&framebuffer5, &dest_rect1,
-                            //glium::uniforms::MagnifySamplerFilter::Linear);
-            //tex_negz.as_surface().blit_whole_color_to(&framebuffer6, &dest_rect1,
-                            //glium::uniforms::MagnifySamplerFilter::Linear);
-        }
-        todo!()
-    }
-
-    fn render_coords(&self, matrix: &glm::Mat4) {
-        // TODO: Attach rest of mipmaps via: SrgbTexture2d$mipmap(u32)?$write(Rect,Texture2dDataSource)
-        todo!()
-    }
-
-    fn render_skybox(&self, cubemap: &SrgbCubemap, matrix: &glm::Mat4) {
-        todo!()
-    }
-
-    fn render_static(&self, entities: &Vec<super::renderer::EntityData>,
-                     decals: &Vec<crate::map::bsp::Decal>,
-                     static_layout: &glium::VertexBuffer<super::renderer::VertexWithLM>,
-                     decal_layout: &glium::VertexBuffer<super::renderer::Vertex>,
-                     textures: &Vec<SrgbTexture2d>,
+            Err(error) => return Err(Error::new(ErrorKind::InvalidData, format!("Unable to create empty cubemap of dimension {}: {}", size, error))),
+        };
+        // Glium has no direct upload for cubemap faces, so each face goes
+        // through a 2D texture blitted into a framebuffer on the face
+        for (i, (layer, orientation)) in skybox_face_mapping().into_iter().enumerate() {
+            let mut face: Image = OpenGLRenderer::orient_sky_face(&sides[i], orientation)?;
+            // Cubemap faces start at the top row but create_texture uploads
+            // bottom-up, flipping first cancels that out
+            face.flip_vertical()?;
+            let texture: SrgbTexture2d = self.create_texture(&vec![&face])?;
+            let framebuffers = SimpleFrameBuffer::new(&self.display, &texture)
+                .and_then(|source: SimpleFrameBuffer| Ok((source, SimpleFrameBuffer::new(&self.display, cubemap.main_level().image(layer))?)));
+            let (source, target): (SimpleFrameBuffer, SimpleFrameBuffer) = match framebuffers {
+                Ok(framebuffers) => framebuffers,
+                Err(error) => return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unable to bind skybox face '{}' to a framebuffer: {:?}", SKY_NAME_SUFFIXES[i], error),
+                )),
+            };
+            source.blit_whole_color_to(
+                &target,
+                &BlitTarget {
+                    left: 0,
+                    bottom: 0,
+                    width: size as i32,
+                    height: size as i32,
+                },
+                MagnifySamplerFilter::Nearest,
+            );
+        }
+        return Ok(cubemap);
+    }
+
+    fn render_coords(&self, matrix: &glm::Mat4, length: f32, tick_spacing: Option<f32>) {
+        let _pass: PassScope = PassScope::new(self, "axes");
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_coords called outside of a frame, skipping");
+            return;
+        };
+        let params: DrawParameters = DrawParameters {
+            viewport: self.viewport.get(),
+            depth: Depth {
+                test: DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            line_width: if self.capabilities.wide_lines { Some(AXIS_LINE_WIDTH) } else { None },
+            ..Default::default()
+        };
+        let matrix: [[f32; 4]; 4] = (*matrix).into();
+        let mut stats: RenderStats = self.stats.get();
+        for (lines, color) in axis_lines(length, tick_spacing).iter().zip(AXIS_COLORS) {
+            let vertices: VertexBuffer<Vertex> = match VertexBuffer::new(&self.display, lines) {
+                Ok(vertices) => vertices,
+                Err(error) => {
+                    warn!(&crate::LOGGER, "Unable to create axis vertex buffer: {}", error);
+                    return;
+                },
+            };
+            let drawn: std::result::Result<(), DrawError> = frame.draw(
+                &vertices,
+                NoIndices(PrimitiveType::LinesList),
+                &self.lines_program,
+                &uniform! {
+                    matrix: matrix,
+                    line_color: color,
+                },
+                &params,
+            );
+            match drawn {
+                Ok(()) => stats.draw_calls += 1,
+                Err(error) => warn!(&crate::LOGGER, "Unable to draw axis: {}", error),
+            };
+        }
+        self.stats.set(stats);
+    }
+
+    fn render_skybox(&self, cubemap: &SrgbCubemap, matrix: &glm::Mat4, fog: [f32; 4]) {
+        let _pass: PassScope = PassScope::new(self, "sky");
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_skybox called outside of a frame, skipping");
+            return;
+        };
+        // The cube sits on the far plane and writes no depth, so anything
+        // drawn after it covers it. Seamless filtering across the faces is
+        // enabled by glium when the context supports it.
+        let params: DrawParameters = DrawParameters {
+            viewport: self.viewport.get(),
+            depth: Depth {
+                test: DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let matrix: [[f32; 4]; 4] = (*matrix).into();
+        let drawn: std::result::Result<(), DrawError> = frame.draw(
+            &self.skybox_cube,
+            NoIndices(PrimitiveType::TrianglesList),
+            &self.skybox_program,
+            &uniform! {
+                matrix: matrix,
+                skybox: cubemap.sampled()
+                    .wrap_function(SamplerWrapFunction::Clamp)
+                    .minify_filter(MinifySamplerFilter::Linear)
+                    .magnify_filter(MagnifySamplerFilter::Linear),
+                fog: fog,
+            },
+            &params,
+        );
+        match drawn {
+            Ok(()) => {
+                let mut stats: RenderStats = self.stats.get();
+                stats.draw_calls += 1;
+                stats.triangles += self.skybox_cube.len() / 3;
+                self.stats.set(stats);
+            },
+            Err(error) => warn!(&crate::LOGGER, "Unable to draw skybox: {}", error),
+        };
+    }
+
+    fn render_lines(&self, lines: VertexBufferSlice<Vertex>, matrix: &glm::Mat4, color: [f32; 4], depth_test: bool) {
+        let _pass: PassScope = PassScope::new(self, "lines");
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_lines called outside of a frame, skipping");
+            return;
+        };
+        let params: DrawParameters = DrawParameters {
+            viewport: self.viewport.get(),
+            depth: Depth {
+                test: if depth_test { DepthTest::IfLessOrEqual } else { DepthTest::Overwrite },
+                write: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let count: usize = lines.len();
+        let matrix: [[f32; 4]; 4] = (*matrix).into();
+        let drawn: std::result::Result<(), DrawError> = frame.draw(
+            lines,
+            NoIndices(PrimitiveType::LinesList),
+            &self.lines_program,
+            &uniform! {
+                matrix: matrix,
+                line_color: color,
+            },
+            &params,
+        );
+        match drawn {
+            Ok(()) => {
+                let mut stats: RenderStats = self.stats.get();
+                stats.draw_calls += 1;
+                self.stats.set(stats);
+            },
+            Err(error) => warn!(&crate::LOGGER, "Unable to draw {} lines: {}", count / 2, error),
+        };
+    }
+
+    fn render_fullscreen_effect(&self, color: [f32; 4], warp_amount: f32, time: f32) {
+        let _pass: PassScope = PassScope::new(self, "post");
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_fullscreen_effect called outside of a frame, skipping");
+            return;
+        };
+        let (width, height): (u32, u32) = frame.get_dimensions();
+        let mut texture_ref: RefMut<Option<Texture2d>> = self.post_texture.borrow_mut();
+        if texture_ref.as_ref().map_or(true, |texture: &Texture2d| texture.dimensions() != (width, height)) {
+            *texture_ref = match Texture2d::empty_with_format(
+                &self.display,
+                UncompressedFloatFormat::U8U8U8U8,
+                MipmapsOption::NoMipmap,
+                width,
+                height,
+            ) {
+                Ok(texture) => Some(texture),
+                Err(error) => {
+                    warn!(&crate::LOGGER, "Unable to create {}x{} post effect texture: {}", width, height, error);
+                    None
+                },
+            };
+        }
+        let Some(texture) = texture_ref.as_ref() else {
+            return;
+        };
+        let copied: std::result::Result<(), String> = SimpleFrameBuffer::new(&self.display, texture)
+            .map(|target: SimpleFrameBuffer| frame.blit_whole_color_to(
+                &target,
+                &BlitTarget {
+                    left: 0,
+                    bottom: 0,
+                    width: width as i32,
+                    height: height as i32,
+                },
+                MagnifySamplerFilter::Nearest,
+            ))
+            .map_err(|error| error.to_string());
+        if let Err(error) = copied {
+            warn!(&crate::LOGGER, "Unable to copy the frame for a post effect: {}", error);
+            return;
+        }
+        let params: DrawParameters = DrawParameters {
+            viewport: self.viewport.get(),
+            ..Default::default()
+        };
+        let drawn: std::result::Result<(), DrawError> = frame.draw(
+            EmptyVertexAttributes { len: 3 },
+            NoIndices(PrimitiveType::TrianglesList),
+            &self.post_program,
+            &uniform! {
+                scene: texture.sampled()
+                    .wrap_function(SamplerWrapFunction::Clamp)
+                    .minify_filter(MinifySamplerFilter::Linear)
+                    .magnify_filter(MagnifySamplerFilter::Linear),
+                tint: color,
+                warp: warp_amount,
+                time: time,
+            },
+            &params,
+        );
+        match drawn {
+            Ok(()) => {
+                let mut stats: RenderStats = self.stats.get();
+                stats.draw_calls += 1;
+                stats.triangles += 1;
+                self.stats.set(stats);
+            },
+            Err(error) => warn!(&crate::LOGGER, "Unable to draw post effect: {}", error),
+        };
+    }
+
+    fn render_static(&self,
+                     entities: &Vec<EntityData>,
+                     decals: &Vec<Decal>,
+                     static_layout: &VertexBuffer<VertexWithLM>,
+                     static_indices: &IndexBuffer<u32>,
+                     decal_layout: &VertexBuffer<Vertex>,
+                     textures: &Vec<TextureHandle>,
+                     animations: &Vec<TextureAnimation>,
                      lightmaps_atlas: &SrgbTexture2d,
-                     settings: &super::renderable::RenderSettings) {
-        todo!()
+                     lights: &[PointLight],
+                     settings: &RenderSettings) {
+        {
+            let _pass: PassScope = PassScope::new(self, "world");
+            self.render_entities(entities, static_layout, static_indices, textures, animations, lightmaps_atlas, lights, settings);
+        }
+        if settings.overdraw {
+            // Decals would hide the heatmap under them
+            return;
+        }
+        let _pass: PassScope = PassScope::new(self, "decals");
+        self.render_decals(decals, decal_layout, textures, settings);
+    }
+
+    fn init_imgui(&self, context: &mut imgui::Context) -> Result<()> {
+        let renderer: imgui_glium_renderer::Renderer = imgui_glium_renderer::Renderer::init(context, &self.display)
+            .map_err(|error: imgui_glium_renderer::RendererError| Error::new(
+                ErrorKind::Other,
+                format!("Unable to create imgui renderer: {}", error),
+            ))?;
+        self.imgui_renderer.replace(Some(renderer));
+        return Ok(());
+    }
+
+    fn create_imgui_texture(&self, image: &Image) -> Result<imgui::TextureId> {
+        let mut imgui_ref: RefMut<Option<imgui_glium_renderer::Renderer>> = self.imgui_renderer.borrow_mut();
+        let Some(imgui_renderer) = imgui_ref.as_mut() else {
+            return Err(Error::new(ErrorKind::Other, "Cannot create an imgui texture before init_imgui"));
+        };
+        let rgba: Image = image.to_rgba()?;
+        // imgui draws its textures without sRGB conversion, so the bytes are uploaded as they are
+        let raw: RawImage2d<u8> = RawImage2d::from_raw_rgba(rgba.data, (rgba.width as u32, rgba.height as u32));
+        let texture: Texture2d = match Texture2d::with_mipmaps(&self.display, raw, MipmapsOption::NoMipmap) {
+            Ok(texture) => texture,
+            Err(error) => return Err(Error::new(ErrorKind::Other, format!("Unable to create imgui texture: {}", error))),
+        };
+        return Ok(imgui_renderer.textures().insert(imgui_glium_renderer::Texture {
+            texture: std::rc::Rc::new(texture),
+            sampler: glium::uniforms::SamplerBehavior {
+                minify_filter: MinifySamplerFilter::Linear,
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                ..Default::default()
+            },
+        }));
+    }
+
+    fn remove_imgui_texture(&self, texture: imgui::TextureId) {
+        if let Some(imgui_renderer) = self.imgui_renderer.borrow_mut().as_mut() {
+            imgui_renderer.textures().remove(texture);
+        }
     }
 
     fn render_imgui(&self, data: &imgui::DrawData) {
-        todo!()
+        let _pass: PassScope = PassScope::new(self, "hud");
+        let mut imgui_ref: RefMut<Option<imgui_glium_renderer::Renderer>> = self.imgui_renderer.borrow_mut();
+        let Some(imgui_renderer) = imgui_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_imgui called before init_imgui, skipping");
+            return;
+        };
+        let mut frame_ref: RefMut<Option<Frame>> = self.frame.borrow_mut();
+        let Some(frame) = frame_ref.as_mut() else {
+            warn!(&crate::LOGGER, "render_imgui called outside of a frame, skipping");
+            return;
+        };
+        if let Err(error) = imgui_renderer.render(frame, data) {
+            warn!(&crate::LOGGER, "Unable to draw imgui frame: {}", error);
+        }
     }
 
     fn provide_facade(&self) -> &dyn glium::backend::Facade {
-        todo!()
+        return &self.display;
+    }
+
+    fn request_capture(&self) {
+        self.capture_requested.set(true);
+    }
+
+    fn capturing(&self) -> bool {
+        return self.capturing.get();
+    }
+
+    fn capture_pass(&self, name: &'static str, stats: RenderStats) {
+        let frame_ref: Ref<Option<Frame>> = self.frame.borrow();
+        let Some(frame) = frame_ref.as_ref() else {
+            return;
+        };
+        match self.read_color(frame) {
+            Ok(image) => self.captures.borrow_mut().push(PassCapture {
+                name,
+                image,
+                stats,
+            }),
+            Err(error) => warn!(&crate::LOGGER, "Unable to capture pass {}: {}", name, error),
+        };
+    }
+
+    fn take_captures(&self) -> Vec<PassCapture> {
+        if self.capturing.get() {
+            return Vec::new();
+        }
+        return std::mem::take(&mut *self.captures.borrow_mut());
+    }
+
+    fn screenshot(&self) -> Result<Image> {
+        return match self.display.read_front_buffer() {
+            Ok(raw) => OpenGLRenderer::image_from_gl_rows(raw),
+            Err(error) => Err(Error::new(ErrorKind::Other, format!("Unable to read the front buffer: {:?}", error))),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "render-tests")]
+    use glium::glutin::event_loop::{EventLoop, EventLoopBuilder};
+    #[cfg(feature = "render-tests")]
+    use glium::glutin::platform::unix::EventLoopBuilderExtUnix;
+    #[cfg(feature = "render-tests")]
+    use glium::glutin::window::WindowBuilder;
+
+    #[cfg(feature = "render-tests")]
+    use crate::rendering::renderer::save_pass_captures;
+
+    #[cfg(feature = "render-tests")]
+    fn hidden_renderer(event_loop: &EventLoop<()>) -> OpenGLRenderer {
+        let display: glium::Display = glium::Display::new(
+            WindowBuilder::new().with_visible(false).with_inner_size(glium::glutin::dpi::PhysicalSize::new(64, 48)),
+            glium::glutin::ContextBuilder::new().with_depth_buffer(24),
+            event_loop,
+        ).unwrap();
+        return OpenGLRenderer::new(display).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "render-tests")]
+    fn a_capture_saves_one_file_per_pass() {
+        let event_loop: EventLoop<()> = EventLoopBuilder::new().with_any_thread(true).build();
+        let renderer: OpenGLRenderer = hidden_renderer(&event_loop);
+        // Frames not requested pay nothing and capture nothing
+        renderer.begin_frame();
+        renderer.render_fullscreen_effect([1.0, 0.0, 0.0, 0.5], 0.0, 0.0);
+        renderer.end_frame().unwrap();
+        assert!(renderer.take_captures().is_empty());
+        renderer.request_capture();
+        renderer.begin_frame();
+        renderer.render_coords(&glm::identity(), 1.0, None);
+        renderer.render_fullscreen_effect([1.0, 0.0, 0.0, 0.5], 0.0, 0.0);
+        renderer.end_frame().unwrap();
+        let captures: Vec<PassCapture> = renderer.take_captures();
+        let names: Vec<&str> = captures.iter().map(|capture: &PassCapture| capture.name).collect();
+        assert_eq!(names, vec!["axes", "post"]);
+        assert_eq!(captures[1].stats.draw_calls, 1);
+        assert!(captures.iter().all(|capture: &PassCapture| capture.image.data.len() == capture.image.width * capture.image.height * 4));
+        let directory: std::path::PathBuf = std::env::temp_dir().join(format!("lambda-gl-capture-{}", std::process::id()));
+        let paths: Vec<std::path::PathBuf> = save_pass_captures(&captures, &directory).unwrap();
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), paths.len());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Mip chain of a square WAD texture, each level a solid grey
+    fn mip_chain(size: usize, channels: usize) -> Vec<Image> {
+        return (0..4).map(|level: usize| Image {
+            channels,
+            width: size >> level,
+            height: size >> level,
+            data: vec![level as u8 * 50; (size >> level) * (size >> level) * channels],
+        }).collect();
+    }
+
+    #[test]
+    fn mip_chains_must_halve_each_level() {
+        let levels: Vec<Image> = mip_chain(16, 4);
+        assert!(OpenGLRenderer::check_mip_chain(&levels.iter().collect()).is_ok());
+        // Levels stop halving at one texel
+        let thin: Vec<Image> = vec![Image::from((4, 1, 4)), Image::from((2, 1, 4)), Image::from((1, 1, 4))];
+        assert!(OpenGLRenderer::check_mip_chain(&thin.iter().collect()).is_ok());
+        let skipped: Vec<&Image> = vec![&levels[0], &levels[2]];
+        let error: Error = OpenGLRenderer::check_mip_chain(&skipped).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("level 1 is 4x4"), "{}", error);
+    }
+
+    #[test]
+    fn three_channel_images_upload_as_rgba() {
+        let atlas: Image = Image {
+            channels: 3,
+            width: 2,
+            height: 1,
+            data: vec![10, 20, 30, 40, 50, 60],
+        };
+        let rgba: Cow<Image> = OpenGLRenderer::rgba(&atlas).unwrap();
+        assert_eq!(rgba.data, vec![10, 20, 30, 255, 40, 50, 60, 255]);
+        let levels: Vec<Image> = mip_chain(8, 4);
+        assert!(matches!(OpenGLRenderer::rgba(&levels[3]).unwrap(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "render-tests")]
+    fn every_mip_level_is_uploaded() {
+        let event_loop: EventLoop<()> = EventLoopBuilder::new().with_any_thread(true).build();
+        let renderer: OpenGLRenderer = hidden_renderer(&event_loop);
+        let levels: Vec<Image> = mip_chain(16, 3);
+        let texture: SrgbTexture2d = renderer.create_texture(&levels.iter().collect()).unwrap();
+        assert_eq!(texture.get_mipmap_levels(), 4);
+        assert!(renderer.create_texture(&vec![&levels[0], &levels[2]]).is_err());
+    }
+
+    /// 2x2 RGB image whose texels are 1, 2, 3 and 4 in reading order
+    fn numbered_face() -> Image {
+        return Image {
+            channels: 3,
+            width: 2,
+            height: 2,
+            data: vec![1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4],
+        };
+    }
+
+    fn texels(image: &Image) -> Vec<u8> {
+        return image.data.chunks(image.channels).map(|texel: &[u8]| texel[0]).collect();
+    }
+
+    #[test]
+    fn every_sky_image_gets_its_own_cube_face() {
+        let mapping: [(CubeLayer, SkyFaceOrientation); 6] = skybox_face_mapping();
+        for (i, (layer, _)) in mapping.iter().enumerate() {
+            assert!(mapping[i + 1..].iter().all(|(other, _): &(CubeLayer, SkyFaceOrientation)| other != layer));
+        }
+        // The four sides are mirrored, up and down are turned to meet them
+        assert_eq!(mapping[4], (CubeLayer::PositiveX, SkyFaceOrientation::FlipHorizontal));
+        assert_eq!(mapping[2], (CubeLayer::PositiveY, SkyFaceOrientation::Transpose));
+        assert_eq!(mapping[3], (CubeLayer::NegativeY, SkyFaceOrientation::AntiTranspose));
+    }
+
+    #[test]
+    fn sky_faces_are_oriented_texel_exactly() {
+        let face: Image = numbered_face();
+        let flipped: Image = OpenGLRenderer::orient_sky_face(&face, SkyFaceOrientation::FlipHorizontal).unwrap();
+        assert_eq!(texels(&flipped), vec![2, 1, 4, 3]);
+        assert_eq!(flipped.channels, 4);
+        let transposed: Image = OpenGLRenderer::orient_sky_face(&face, SkyFaceOrientation::Transpose).unwrap();
+        assert_eq!(texels(&transposed), vec![1, 3, 2, 4]);
+        let anti_transposed: Image = OpenGLRenderer::orient_sky_face(&face, SkyFaceOrientation::AntiTranspose).unwrap();
+        assert_eq!(texels(&anti_transposed), vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn mismatched_sky_faces_are_named() {
+        let faces = || -> [Image; 6] { [(); 6].map(|_| Image::from((4, 4, 3))) };
+        assert_eq!(OpenGLRenderer::check_sky_faces(&faces()).unwrap(), 4);
+        let mut smaller: [Image; 6] = faces();
+        smaller[3] = Image::from((2, 2, 3));
+        let error: Error = OpenGLRenderer::check_sky_faces(&smaller).unwrap_err();
+        assert!(error.to_string().contains("'dn' is 2x2"), "{}", error);
+        let mut oblong: [Image; 6] = faces();
+        oblong[0] = Image::from((4, 2, 3));
+        assert!(OpenGLRenderer::check_sky_faces(&oblong).unwrap_err().to_string().contains("'ft' is 4x2"));
+    }
+
+    #[test]
+    fn read_back_rows_are_flipped_top_down() {
+        // Two pixels wide and three high, each row one value, bottom row first
+        let rows: Vec<u8> = [1u8, 2, 3].iter().flat_map(|row: &u8| [*row; 8]).collect();
+        let image: Image = OpenGLRenderer::image_from_gl_rows(RawImage2d::from_raw_rgba(rows, (2, 3))).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 3, 4));
+        assert_eq!(image.pixel(0, 0), Some(&[3u8, 3, 3, 3][..]));
+        assert_eq!(image.pixel(1, 2), Some(&[1u8, 1, 1, 1][..]));
+        assert_eq!(image.data[8..16], [2; 8]);
+    }
+
+    #[test]
+    fn short_read_backs_are_rejected() {
+        let raw: RawImage2d<u8> = RawImage2d {
+            data: Cow::Owned(vec![0; 12]),
+            width: 2,
+            height: 2,
+            format: glium::texture::ClientFormat::U8U8U8U8,
+        };
+        assert_eq!(OpenGLRenderer::image_from_gl_rows(raw).err().map(|error: Error| error.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    fn draw_state(render_mode: RenderMode) -> EntityDrawState {
+        return OpenGLRenderer::entity_draw_state(&EntityData {
+            face_render_info: Vec::new(),
+            model: Some(1),
+            origin: glm::vec3(0.0, 0.0, 0.0),
+            center: glm::vec3(0.0, 0.0, 0.0),
+            alpha: 0.5,
+            render_mode,
+            wave_height: 0.0,
+        });
+    }
+
+    #[test]
+    fn render_modes_pick_their_blending() {
+        let normal: EntityDrawState = draw_state(RenderMode::RenderModeNormal);
+        assert!(normal.blend.is_none() && !normal.alpha_test && normal.alpha == 1.0);
+        let solid: EntityDrawState = draw_state(RenderMode::RenderModeSolid);
+        assert!(solid.blend.is_none() && solid.alpha_test && solid.alpha == 1.0);
+        let texture: EntityDrawState = draw_state(RenderMode::RenderModeTexture);
+        assert_eq!(texture.blend, Some(Blend::alpha_blending()));
+        assert_eq!(texture.alpha, 0.5);
+        let additive: EntityDrawState = draw_state(RenderMode::RenderModeAdditive);
+        assert!(additive.additive && additive.lit);
+        assert!(matches!(
+            additive.blend.map(|blend: Blend| blend.color),
+            Some(BlendingFunction::Addition { source: LinearBlendingFactor::SourceAlpha, destination: LinearBlendingFactor::One }),
+        ));
+        let glow: EntityDrawState = draw_state(RenderMode::RenderModeGlow);
+        assert!(glow.additive && !glow.lit);
+    }
+
+    #[test]
+    fn wireframes_draw_lines_over_the_filled_faces() {
+        let viewport: Rect = Rect { left: 0, bottom: 0, width: 640, height: 480 };
+        let params: DrawParameters = OpenGLRenderer::wireframe_draw_parameters(Some(viewport));
+        assert_eq!(params.polygon_mode, PolygonMode::Line);
+        assert_eq!(params.viewport, Some(viewport));
+        // Drawn where the filled faces are, pulled forward so the lines win the depth test
+        assert_eq!(params.depth.test, DepthTest::IfLessOrEqual);
+        assert!(!params.depth.write);
+        assert!(params.polygon_offset.line && params.polygon_offset.factor < 0.0);
+    }
+
+    #[test]
+    fn overdraw_adds_every_layer_without_a_depth_test() {
+        let params: DrawParameters = OpenGLRenderer::overdraw_draw_parameters(None);
+        assert_eq!(params.polygon_mode, PolygonMode::Fill);
+        assert_eq!(params.depth.test, DepthTest::Overwrite);
+        assert!(!params.depth.write);
+        assert!(matches!(
+            params.blend.color,
+            BlendingFunction::Addition { source: LinearBlendingFactor::One, destination: LinearBlendingFactor::One },
+        ));
+        // Several layers stay distinguishable before the heatmap saturates
+        assert!(OVERDRAW_COLOR.iter().take(3).all(|channel: &f32| *channel * 8.0 < 1.0));
+    }
+
+    #[test]
+    fn water_is_flat_at_time_zero() {
+        assert_eq!(OpenGLRenderer::wave_amplitude(2.0, 0.0), 0.0);
+        assert_eq!(OpenGLRenderer::wave_amplitude(16.0, 0.0), 0.0);
+        // A quarter period in the wave reaches its full height
+        let crest: f32 = std::f32::consts::FRAC_PI_2 / WAVE_SPEED;
+        assert!((OpenGLRenderer::wave_amplitude(2.0, crest) - 2.0).abs() < 1e-5);
+        assert!((OpenGLRenderer::wave_amplitude(2.0, 3.0 * crest) + 2.0).abs() < 1e-5);
     }
 
-    fn screenshot(&self) -> crate::resource::image::Image {
-        todo!()
+    #[test]
+    fn shader_encoding_defines_encode_srgb_after_the_version() {
+        let fragment: &str = include_str!("shaders/lines.frag");
+        assert_eq!(OpenGLRenderer::fragment_source(fragment, GammaEncoding::Framebuffer), fragment);
+        let encoded: String = OpenGLRenderer::fragment_source(fragment, GammaEncoding::Shader);
+        assert!(encoded.starts_with("#version 140\n#define ENCODE_SRGB\n"));
+        assert_eq!(encoded.replacen("#define ENCODE_SRGB\n", "", 1), fragment);
     }
 
 }
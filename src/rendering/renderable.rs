@@ -1,9 +1,177 @@
-#[derive(Default, Debug, Clone, Copy)]
+use std::ops::BitOr;
+
+use crate::map::bsp::MAX_LIGHT_STYLES;
+use crate::rendering::fog::Fog;
+
+// Anisotropic filtering asked of world textures, most drivers support up to 16x
+pub const DEFAULT_ANISOTROPY: u16 = 16;
+
+/// Subset of the render passes to draw, used to isolate passes when
+/// comparing frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PassMask(u8);
+
+impl PassMask {
+    pub const NONE: PassMask = PassMask(0);
+    pub const SKYBOX: PassMask = PassMask(1 << 0);
+    pub const WORLD: PassMask = PassMask(1 << 1);
+    pub const BRUSH_ENTITIES: PassMask = PassMask(1 << 2);
+    pub const DECALS: PassMask = PassMask(1 << 3);
+    pub const WATER: PassMask = PassMask(1 << 4);
+    pub const HUD: PassMask = PassMask(1 << 5);
+    pub const ALL: PassMask = PassMask((1 << 6) - 1);
+
+    pub fn contains(&self, other: PassMask) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    pub fn intersects(&self, other: PassMask) -> bool {
+        return self.0 & other.0 != 0;
+    }
+
+    pub fn with(self, other: PassMask, enabled: bool) -> PassMask {
+        if enabled {
+            return PassMask(self.0 | other.0);
+        }
+        return PassMask(self.0 & !other.0);
+    }
+
+}
+
+impl BitOr for PassMask {
+    type Output = PassMask;
+
+    fn bitor(self, rhs: PassMask) -> PassMask {
+        return PassMask(self.0 | rhs.0);
+    }
+}
+
+impl Default for PassMask {
+    fn default() -> Self {
+        return PassMask::ALL;
+    }
+}
+
+/// Which terms of the static shader reach the screen, for telling texture
+/// problems from lighting ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightingMode {
+    #[default]
+    Combined, // Diffuse texture modulated by the lighting
+    Fullbright, // Diffuse texture alone
+    LightmapOnly, // Lighting over white
+}
+
+impl LightingMode {
+
+    /// Whether the static shader shows the diffuse texture and the lighting
+    pub fn shader_terms(&self) -> (bool, bool) {
+        return match self {
+            LightingMode::Combined => (true, true),
+            LightingMode::Fullbright => (true, false),
+            LightingMode::LightmapOnly => (false, true),
+        };
+    }
+
+    /// The mode after this one, wrapping around, for a toggle key
+    pub fn next(&self) -> LightingMode {
+        return match self {
+            LightingMode::Combined => LightingMode::Fullbright,
+            LightingMode::Fullbright => LightingMode::LightmapOnly,
+            LightingMode::LightmapOnly => LightingMode::Combined,
+        };
+    }
+
+}
+
+/// How changes to light style values reach the lightmap atlas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightstyleMode {
+    Gpu, // Every affected face recomposed in the frame its style changes and the whole atlas uploaded again
+    #[default]
+    Cpu, // Affected faces recomposed within a per frame budget, only their regions of the atlas uploaded
+    Off, // Lightmaps keep the style values they were last composed with
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct RenderSettings {
     pub projection: glm::Mat4,
     pub pitch: f32,
     pub yaw: f32,
     pub view: glm::Mat4,
+    pub passes: PassMask, // Passes enabled for normal rendering
+    pub render_only: Option<PassMask>, // Restricts the enabled passes further, for isolating a pass
+    pub time: f32, // Seconds since the map started, selects animated texture frames
+    pub disable_random_tiling: bool, // Draw random tiling textures with the variant each face references
+    pub show_tool_textures: bool, // Draw trigger, clip and other tool faces, and sky faces in colour, for debugging
+    pub lightstyle_values: [f32; MAX_LIGHT_STYLES], // Per frame scale of each light style, for flicker and pulse presets
+    pub lightmap_gamma: f32, // Lightmap texels are raised to 1 / gamma, 1.0 leaves them as compiled
+    pub lightmap_scale: f32, // Overbright multiplier applied after the gamma
+    pub lightstyle_mode: LightstyleMode,
+    pub batch_draws: bool, // Merge faces sharing a texture into fewer draw calls
+    pub leaf_outlines: bool, // Draw the bounding box of every non-solid leaf, the camera's in red
+    pub leaf_outlines_depth_test: bool, // Hide outlines behind geometry, off shows every leaf through walls
+    pub dynamic_lights: bool, // Add the diffuse light of the nearest light entities on top of the lightmaps
+    pub lighting_mode: LightingMode, // Applies to the world and brush entities, not the skybox or decals
+    pub wireframe: bool, // Outline the drawn triangles over the filled geometry
+    pub overdraw: bool, // Replace the frame with a heatmap of how many times each pixel is drawn
+    pub show_axes: bool, // Draw the world axes from the origin
+    pub axes_length: f32, // In world units
+    pub axes_ticks: bool, // Mark the axes every grid unit
+    pub water_wave_scale: f32, // Multiplies the wave height of water surfaces, 0.0 keeps them flat
+    pub fog: Fog, // Over the world, brush entities and decals, and the skybox when enabled
+    pub highlight_texture: Option<usize>, // Index into the map's textures, faces showing it are tinted
+    pub anisotropy: u16, // Requested anisotropic filtering of world textures, clamped to what the driver supports
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        return RenderSettings {
+            projection: glm::Mat4::default(),
+            pitch: 0.0,
+            yaw: 0.0,
+            view: glm::Mat4::default(),
+            passes: PassMask::default(),
+            render_only: None,
+            time: 0.0,
+            disable_random_tiling: false,
+            show_tool_textures: false,
+            lightstyle_values: [1.0; MAX_LIGHT_STYLES],
+            lightmap_gamma: 1.0,
+            lightmap_scale: 1.0,
+            lightstyle_mode: LightstyleMode::default(),
+            batch_draws: true,
+            leaf_outlines: false,
+            leaf_outlines_depth_test: true,
+            dynamic_lights: false,
+            lighting_mode: LightingMode::Combined,
+            wireframe: false,
+            overdraw: false,
+            show_axes: false,
+            axes_length: 512.0,
+            axes_ticks: true,
+            water_wave_scale: 1.0,
+            fog: Fog::default(),
+            highlight_texture: None,
+            anisotropy: DEFAULT_ANISOTROPY,
+        };
+    }
+}
+
+impl RenderSettings {
+
+    /// Passes to draw this frame
+    pub fn active_passes(&self) -> PassMask {
+        return match self.render_only {
+            Some(only) => PassMask(self.passes.0 & only.0),
+            None => self.passes,
+        };
+    }
+
+    pub fn draws(&self, pass: PassMask) -> bool {
+        return self.active_passes().contains(pass);
+    }
+
 }
 
 pub trait Renderable {
@@ -11,3 +179,29 @@ pub trait Renderable {
     fn render(&mut self, settings: &RenderSettings);
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_lighting_mode_selects_its_shader_terms() {
+        assert_eq!(LightingMode::Combined.shader_terms(), (true, true));
+        assert_eq!(LightingMode::Fullbright.shader_terms(), (true, false));
+        assert_eq!(LightingMode::LightmapOnly.shader_terms(), (false, true));
+        assert_eq!(RenderSettings::default().lighting_mode, LightingMode::Combined);
+    }
+
+    #[test]
+    fn lighting_modes_cycle_through_all_three() {
+        let mut mode: LightingMode = LightingMode::Combined;
+        let mut seen: Vec<LightingMode> = Vec::new();
+        for _ in 0..3 {
+            seen.push(mode);
+            mode = mode.next();
+        }
+        assert_eq!(mode, LightingMode::Combined);
+        assert_eq!(seen, vec![LightingMode::Combined, LightingMode::Fullbright, LightingMode::LightmapOnly]);
+    }
+
+}
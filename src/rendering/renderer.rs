@@ -1,13 +1,18 @@
 use glium::backend::Facade;
-use glium::texture::{SrgbCubemap, SrgbTexture2d};
-use glium::VertexBuffer;
+use glium::texture::{CompressedSrgbTexture2d, SrgbCubemap, SrgbTexture2d};
+use glium::vertex::VertexBufferSlice;
+use glium::{IndexBuffer, VertexBuffer};
 use std::boxed::Box;
 use std::io::Result;
+use std::path::{Path, PathBuf};
 
+use crate::map::animation::TextureAnimation;
 use crate::map::bsp::Decal;
 use crate::map::bsp30;
 use crate::rendering::renderable::RenderSettings;
+use crate::resource::block_compression::BlockFormat;
 use crate::resource::image::Image;
+use crate::scene::lights::PointLight;
 
 pub trait Texture {}
 pub trait Buffer {}
@@ -53,10 +58,179 @@ impl Default for VertexWithLM {
 
 implement_vertex!(VertexWithLM, position, normal, tex_coord, lightmap_coord);
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct FaceRenderInfo {
-    pub tex: Option<usize>, // Index into self.m_textures
-    pub offset: usize,
-    pub count: usize,
+    pub tex: Option<usize>, // Index into the textures passed to render_static, None draws the lightmap alone
+    pub animation: Option<usize>, // Index into the animation table, overrides tex with the frame at settings.time
+    pub offset: usize, // First index of the face in the static index buffer
+    pub count: usize, // Number of indices, three per triangle
+    pub depth_only: bool, // Written to the depth buffer only, e.g. sky faces the skybox shows through
+    pub uv_scroll: Option<glm::Vec2>, // Added to the texture coordinates in the vertex stage, for conveyors
+    pub masked: bool, // Texture has transparent texels to discard, whatever the entity's render mode
+    pub water: bool, // Liquid surface, warped and drawn with the transparent entities
+}
+
+impl FaceRenderInfo {
+
+    /// Texture shown on the face at `time`, the current frame when it is animated
+    pub fn texture_at(&self, animations: &Vec<TextureAnimation>, time: f32) -> Option<usize> {
+        return match self.animation.and_then(|animation: usize| animations.get(animation)) {
+            Some(animation) => Some(animation.frame_at(time, false)),
+            None => self.tex,
+        };
+    }
+
+    /// Whether the face shows a texture, as its own or any frame of its animation
+    pub fn uses_texture(&self, texture: usize, animations: &Vec<TextureAnimation>) -> bool {
+        return self.tex == Some(texture) || self.animation
+            .and_then(|animation: usize| animations.get(animation))
+            .map_or(false, |animation: &TextureAnimation| {
+                animation.frames.contains(&texture) || animation.toggled.contains(&texture)
+            });
+    }
+
+    /// Sort faces by the texture shown at `time` and merge faces that follow
+    /// each other in the index buffer and share their draw state into a
+    /// single draw. Animations are resolved, so the batches have none.
+    pub fn batch(faces: &Vec<FaceRenderInfo>, animations: &Vec<TextureAnimation>, time: f32) -> Vec<FaceRenderInfo> {
+        let mut sorted: Vec<FaceRenderInfo> = faces.iter()
+            .map(|face: &FaceRenderInfo| FaceRenderInfo {
+                tex: face.texture_at(animations, time),
+                animation: None,
+                ..face.clone()
+            })
+            .collect();
+        sorted.sort_by_key(|face: &FaceRenderInfo| (face.tex, face.depth_only, face.offset));
+        let mut batches: Vec<FaceRenderInfo> = Vec::with_capacity(sorted.len());
+        for face in sorted.into_iter() {
+            if let Some(last) = batches.last_mut() {
+                if last.tex == face.tex
+                    && last.depth_only == face.depth_only
+                    && last.uv_scroll == face.uv_scroll
+                    && last.masked == face.masked
+                    && last.water == face.water
+                    && last.offset + last.count == face.offset {
+                    last.count += face.count;
+                    continue;
+                }
+            }
+            batches.push(face);
+        }
+        return batches;
+    }
+
+}
+
+// Half the length of the ticks across the axes of the coordinate gizmo
+const AXIS_TICK_SIZE: f32 = 4.0;
+
+/// Line lists of the coordinate gizmo, one per axis in X, Y, Z order. Each
+/// starts with the axis itself, followed by a tick every `tick_spacing`
+/// units up to `length`, crossing the axis in the next axis' direction.
+pub fn axis_lines(length: f32, tick_spacing: Option<f32>) -> [Vec<Vertex>; 3] {
+    let length: f32 = length.max(0.0);
+    return [0, 1, 2].map(|axis: usize| {
+        let direction: glm::Vec3 = glm::Vec3::ith(axis, 1.0);
+        let across: glm::Vec3 = glm::Vec3::ith((axis + 1) % 3, AXIS_TICK_SIZE);
+        let mut points: Vec<glm::Vec3> = vec![glm::vec3(0.0, 0.0, 0.0), direction * length];
+        if let Some(spacing) = tick_spacing.filter(|spacing: &f32| *spacing > 0.0) {
+            let mut distance: f32 = spacing;
+            while distance <= length {
+                points.push(direction * distance - across);
+                points.push(direction * distance + across);
+                distance += spacing;
+            }
+        }
+        return points.into_iter()
+            .map(|point: glm::Vec3| Vertex {
+                position: point.into(),
+                ..Vertex::default()
+            })
+            .collect();
+    });
+}
+
+/// Work done drawing the last frame, for a debug overlay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: usize,
+    pub instanced_draw_calls: usize, // Of draw_calls, those drawing several entities at once
+    pub instances: usize, // Entities drawn by instanced draw calls
+    pub faces_rendered: usize,
+    pub triangles: usize,
+}
+
+impl RenderStats {
+
+    /// Work done since `earlier` was taken from the same frame
+    pub fn since(&self, earlier: &RenderStats) -> RenderStats {
+        return RenderStats {
+            draw_calls: self.draw_calls.saturating_sub(earlier.draw_calls),
+            instanced_draw_calls: self.instanced_draw_calls.saturating_sub(earlier.instanced_draw_calls),
+            instances: self.instances.saturating_sub(earlier.instances),
+            faces_rendered: self.faces_rendered.saturating_sub(earlier.faces_rendered),
+            triangles: self.triangles.saturating_sub(earlier.triangles),
+        };
+    }
+
+}
+
+/// Colour buffer read back at the end of a render pass of a captured frame
+pub struct PassCapture {
+    pub name: &'static str,
+    pub image: Image, // Row 0 at the top
+    pub stats: RenderStats, // Work done by the pass alone
+}
+
+/// Save the passes of a captured frame into `directory` as numbered PNGs
+/// in the order they were drawn, logging each pass's counters. Returns the
+/// paths written.
+pub fn save_pass_captures(captures: &[PassCapture], directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(captures.len());
+    for (i, capture) in captures.iter().enumerate() {
+        let path: PathBuf = directory.join(format!("{:02}-{}.png", i, capture.name));
+        capture.image.save(path.to_string_lossy().into_owned())?;
+        info!(
+            &crate::LOGGER,
+            "Pass {} {}: {} draw calls, {} triangles",
+            i,
+            capture.name,
+            capture.stats.draw_calls,
+            capture.stats.triangles,
+        );
+        paths.push(path);
+    }
+    return Ok(paths);
+}
+
+/// A named render pass, from its creation until it is dropped. Renderer
+/// methods open one before borrowing the frame so it closes after they
+/// finish drawing. On a captured frame the colour buffer is read back when
+/// the pass closes, on any other frame the scope only checks a flag.
+pub struct PassScope<'a> {
+    renderer: &'a dyn Renderer,
+    name: &'static str,
+    start: Option<RenderStats>, // Counters when the pass opened, only taken while capturing
+}
+
+impl<'a> PassScope<'a> {
+
+    pub fn new(renderer: &'a dyn Renderer, name: &'static str) -> Self {
+        return PassScope {
+            renderer,
+            name,
+            start: if renderer.capturing() { Some(renderer.stats()) } else { None },
+        };
+    }
+
+}
+
+impl Drop for PassScope<'_> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            self.renderer.capture_pass(self.name, self.renderer.stats().since(&start));
+        }
+    }
 }
 
 pub enum AttributeLayoutType {
@@ -74,43 +248,575 @@ pub struct AttributeLayout {
 
 pub struct EntityData {
     pub face_render_info: Vec<FaceRenderInfo>,
+    pub model: Option<usize>, // Brush model the faces come from, None for the world's own faces
     pub origin: glm::Vec3,
+    pub center: glm::Vec3, // Middle of the model's bounds in world space, used for sorting
     pub alpha: f32,
     pub render_mode: bsp30::RenderMode,
+    pub wave_height: f32, // Displacement of the entity's water surfaces in world units
+}
+
+impl EntityData {
+
+    /// Entities blended over what is behind them, which must be drawn after
+    /// the opaque ones and in back to front order
+    pub fn is_transparent(&self) -> bool {
+        return match self.render_mode {
+            bsp30::RenderMode::RenderModeNormal | bsp30::RenderMode::RenderModeSolid => false,
+            bsp30::RenderMode::RenderModeColor
+            | bsp30::RenderMode::RenderModeTexture
+            | bsp30::RenderMode::RenderModeGlow
+            | bsp30::RenderMode::RenderModeAdditive => true,
+        };
+    }
+
+    /// Indices of `entities` in drawing order: opaque entities front to back
+    /// so the depth test rejects hidden fragments early, then transparent
+    /// entities back to front so each blends over everything behind it
+    pub fn draw_order(entities: &[EntityData], camera: &glm::Vec3) -> Vec<usize> {
+        let distance = |i: &usize| -> f32 { glm::distance2(&entities[*i].center, camera) };
+        let (mut opaque, mut transparent): (Vec<usize>, Vec<usize>) = (0..entities.len())
+            .partition(|i: &usize| !entities[*i].is_transparent());
+        opaque.sort_by(|a: &usize, b: &usize| distance(a).total_cmp(&distance(b)));
+        transparent.sort_by(|a: &usize, b: &usize| distance(b).total_cmp(&distance(a)));
+        opaque.append(&mut transparent);
+        return opaque;
+    }
+
+    /// Whether `other` draws the same faces the same way, so both can be
+    /// drawn by one instanced draw with only their transforms and alpha differing
+    pub fn instances_with(&self, other: &EntityData) -> bool {
+        return self.model.is_some()
+            && self.model == other.model
+            && !self.is_transparent()
+            && self.render_mode == other.render_mode
+            && self.wave_height == other.wave_height
+            && self.face_render_info == other.face_render_info;
+    }
+
+    /// Split a drawing order into runs drawn together. Opaque entities of the
+    /// same model join the group of the first such entity in the order,
+    /// everything else, transparent entities included, keeps its own group
+    /// and its place so blending order is unchanged.
+    pub fn instance_groups(entities: &[EntityData], order: &[usize]) -> Vec<Vec<usize>> {
+        let mut groups: Vec<Vec<usize>> = Vec::with_capacity(order.len());
+        for index in order.iter() {
+            let entity: &EntityData = &entities[*index];
+            let group: Option<&mut Vec<usize>> = groups.iter_mut()
+                .find(|group: &&mut Vec<usize>| entities[group[0]].instances_with(entity));
+            match group {
+                Some(group) => group.push(*index),
+                None => groups.push(vec![*index]),
+            };
+        }
+        return groups;
+    }
+
+}
+
+/// Where linear shader output is encoded to sRGB for display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GammaEncoding {
+    Framebuffer, // The sRGB framebuffer encodes on write
+    Shader, // The framebuffer stores what it is given, so fragment shaders encode their output
+}
+
+/// Limits and optional features of the GPU/driver, queried once at renderer
+/// construction so consumers can degrade gracefully.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RendererCapabilities {
+    pub max_texture_size: u32,
+    pub max_anisotropy: Option<u16>,
+    pub srgb_framebuffer: bool,
+    pub instancing: bool,
+    pub compressed_textures: bool, // sRGB BC1 and BC3 uploads
+    pub wide_lines: bool, // Lines wider than a pixel, unavailable in forward compatible contexts
+}
+
+impl RendererCapabilities {
+
+    pub fn clamp_texture_size(&self, requested: u32) -> u32 {
+        return requested.min(self.max_texture_size).max(1);
+    }
+
+    pub fn clamp_anisotropy(&self, requested: u16) -> u16 {
+        return match self.max_anisotropy {
+            Some(max) => requested.min(max).max(1),
+            None => 1,
+        };
+    }
+
+    pub fn gamma_encoding(&self) -> GammaEncoding {
+        return if self.srgb_framebuffer { GammaEncoding::Framebuffer } else { GammaEncoding::Shader };
+    }
+
+}
+
+impl Default for RendererCapabilities {
+    fn default() -> Self {
+        // Minimums guaranteed by any GL 2.x driver
+        return Self {
+            max_texture_size: 2048,
+            max_anisotropy: None,
+            srgb_framebuffer: false,
+            instancing: false,
+            compressed_textures: false,
+            wide_lines: false,
+        };
+    }
+}
+
+/// World texture as uploaded to the GPU, either raw or block compressed
+pub enum TextureHandle {
+    Uncompressed(SrgbTexture2d),
+    Compressed(CompressedSrgbTexture2d, BlockFormat),
+}
+
+impl TextureHandle {
+
+    /// Approximate GPU memory used by the texture and its mip chain
+    pub fn estimated_bytes(&self) -> usize {
+        let (width, height, levels): (u32, u32, u32) = match self {
+            TextureHandle::Uncompressed(texture) => (texture.width(), texture.height(), texture.get_mipmap_levels()),
+            TextureHandle::Compressed(texture, _) => (texture.width(), texture.height(), texture.get_mipmap_levels()),
+        };
+        return (0..levels)
+            .map(|level: u32| {
+                let level_width: usize = (width >> level).max(1) as usize;
+                let level_height: usize = (height >> level).max(1) as usize;
+                return match self {
+                    TextureHandle::Uncompressed(_) => level_width * level_height * 4,
+                    TextureHandle::Compressed(_, format) => format.compressed_size(level_width, level_height),
+                };
+            })
+            .sum();
+    }
+
 }
 
 pub trait Renderer {
+    fn capabilities(&self) -> RendererCapabilities;
+    /// Counters of the frame being drawn, or of the last one once it has ended
+    fn stats(&self) -> RenderStats;
+    /// Start drawing a frame, every render call until `end_frame` draws into it
+    fn begin_frame(&self);
+    /// Finish the frame and present it
+    fn end_frame(&self) -> Result<()>;
+    /// Limit every following draw call to a `width` by `height` area at the bottom left
     fn resize_viewport(&self, width: usize, height: usize);
+    fn set_clear_color(&self, color: (f32, f32, f32, f32));
+    /// Clear the viewport of the current frame to the clear colour and the far depth
     fn clear(&self);
     fn create_texture(&self, mipmaps: &Vec<&Image>) -> Result<SrgbTexture2d>;
+    /// Overwrite the region of the base level of a texture from
+    /// `create_texture` that `image` covers with its top left at (`x`, `y`),
+    /// row 0 at the top
+    fn update_texture(&self, texture: &SrgbTexture2d, x: usize, y: usize, image: &Image) -> Result<()>;
+    fn create_compressed_texture(&self, mipmaps: &Vec<&Image>, format: BlockFormat) -> Result<CompressedSrgbTexture2d>;
     fn create_cube_texture(&self, sides: [Image; 6]) -> Result<SrgbCubemap>;
     //fn create_buffer(&self, data: &[T]) -> Box<dyn Buffer>;
     //fn create_input_layout(&self, buffer: &dyn Buffer, layout: &Vec<AttributeLayout>) -> dyn InputLayout;
-    fn render_coords(&self, matrix: &glm::Mat4);
-    fn render_skybox(&self, cubemap: &SrgbCubemap, matrix: &glm::Mat4);
+    /// Draw the world axes from the origin, X red, Y green and Z blue, with
+    /// a tick across each axis every `tick_spacing` units
+    fn render_coords(&self, matrix: &glm::Mat4, length: f32, tick_spacing: Option<f32>);
+    /// Draw the sky behind everything, mixed towards the colour of `fog` by its alpha
+    fn render_skybox(&self, cubemap: &SrgbCubemap, matrix: &glm::Mat4, fog: [f32; 4]);
+    /// Draw a line list in a flat colour, over everything when `depth_test` is off
+    fn render_lines(&self, lines: VertexBufferSlice<Vertex>, matrix: &glm::Mat4, color: [f32; 4], depth_test: bool);
+    /// Tint the finished frame towards `color` by its alpha and ripple it by
+    /// `warp_amount` of the screen, animated by `time` in seconds
+    fn render_fullscreen_effect(&self, color: [f32; 4], warp_amount: f32, time: f32);
     fn render_static(
         &self,
         entities: &Vec<EntityData>,
         decals: &Vec<Decal>,
         static_layout: &VertexBuffer<VertexWithLM>,
+        static_indices: &IndexBuffer<u32>,
         decal_layout: &VertexBuffer<Vertex>,
-        textures: &Vec<SrgbTexture2d>,
+        textures: &Vec<TextureHandle>,
+        animations: &Vec<TextureAnimation>,
         lightmaps_atlas: &SrgbTexture2d,
+        lights: &[PointLight], // Nearest lights first, at most MAX_POINT_LIGHTS are used
         settings: &RenderSettings,
     );
+    /// Set up drawing for an imgui context, uploading its font atlas
+    fn init_imgui(&self, context: &mut imgui::Context) -> Result<()>;
+    /// Upload an image for imgui widgets to show, after init_imgui
+    fn create_imgui_texture(&self, image: &Image) -> Result<imgui::TextureId>;
+    /// Release a texture from create_imgui_texture
+    fn remove_imgui_texture(&self, texture: imgui::TextureId);
+    /// Draw a finished imgui frame over the current frame
     fn render_imgui(&self, data: &imgui::DrawData);
     fn provide_facade(&self) -> &dyn Facade;
-    fn screenshot(&self) -> Image;
+    /// The last presented frame, row 0 at the top
+    fn screenshot(&self) -> Result<Image>;
+    /// Read back the colour buffer after each pass of the next frame
+    fn request_capture(&self);
+    /// Whether the frame being drawn is captured
+    fn capturing(&self) -> bool;
+    /// Read back the colour buffer for a pass that just ended, called by `PassScope`
+    fn capture_pass(&self, name: &'static str, stats: RenderStats);
+    /// Passes read back during the last captured frame, empty when none was
+    /// captured since the last call
+    fn take_captures(&self) -> Vec<PassCapture>;
 }
 
 pub trait Platform {
+    /// Window of `width` by `height` logical pixels on the monitor at index
+    /// `monitor`, the primary monitor when None
     fn create_window_and_context(
         &self,
         width: usize,
         height: usize,
         title: String,
-        monitor: usize,
-    ) -> glium::Display;
-    fn create_renderer() -> Box<dyn Renderer>;
+        monitor: Option<usize>,
+    ) -> Result<glium::Display>;
+    fn create_renderer(&self, display: glium::Display) -> Result<Box<dyn Renderer>>;
     fn swap_buffers(&self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(offset: usize) -> FaceRenderInfo {
+        return FaceRenderInfo {
+            tex: Some(0),
+            animation: None,
+            offset,
+            count: 6,
+            depth_only: false,
+            uv_scroll: None,
+            masked: false,
+            water: false,
+        };
+    }
+
+    fn textured(tex: usize, offset: usize) -> FaceRenderInfo {
+        return FaceRenderInfo {
+            tex: Some(tex),
+            ..face(offset)
+        };
+    }
+
+    fn ranges(batches: &Vec<FaceRenderInfo>) -> Vec<(Option<usize>, usize, usize)> {
+        return batches.iter().map(|batch: &FaceRenderInfo| (batch.tex, batch.offset, batch.count)).collect();
+    }
+
+    #[test]
+    fn adjacent_faces_sharing_a_texture_merge() {
+        let faces: Vec<FaceRenderInfo> = vec![textured(0, 6), textured(0, 0), textured(0, 12)];
+        assert_eq!(ranges(&FaceRenderInfo::batch(&faces, &Vec::new(), 0.0)), vec![(Some(0), 0, 18)]);
+    }
+
+    #[test]
+    fn faces_apart_in_the_index_buffer_stay_separate() {
+        let faces: Vec<FaceRenderInfo> = vec![textured(1, 6), textured(0, 0), textured(1, 12), textured(0, 18)];
+        assert_eq!(
+            ranges(&FaceRenderInfo::batch(&faces, &Vec::new(), 0.0)),
+            vec![(Some(0), 0, 6), (Some(0), 18, 6), (Some(1), 6, 12)],
+        );
+    }
+
+    #[test]
+    fn faces_drawn_differently_do_not_merge() {
+        let faces: Vec<FaceRenderInfo> = vec![
+            textured(0, 0),
+            FaceRenderInfo { depth_only: true, ..textured(0, 6) },
+            FaceRenderInfo { masked: true, ..textured(0, 12) },
+            FaceRenderInfo { uv_scroll: Some(glm::vec2(1.0, 0.0)), ..textured(0, 18) },
+        ];
+        assert_eq!(FaceRenderInfo::batch(&faces, &Vec::new(), 0.0).len(), 4);
+    }
+
+    #[test]
+    fn animated_faces_batch_by_their_current_frame() {
+        let animations: Vec<TextureAnimation> = vec![TextureAnimation {
+            name: "water".to_string(),
+            frames: vec![2, 3],
+            toggled: Vec::new(),
+        }];
+        let faces: Vec<FaceRenderInfo> = vec![
+            textured(2, 0),
+            FaceRenderInfo { animation: Some(0), ..textured(2, 6) },
+        ];
+        let batches: Vec<FaceRenderInfo> = FaceRenderInfo::batch(&faces, &animations, 0.0);
+        assert_eq!(ranges(&batches), vec![(Some(2), 0, 12)]);
+        assert!(batches.iter().all(|batch: &FaceRenderInfo| batch.animation.is_none()));
+        // A frame later the animated face shows another texture
+        assert_eq!(ranges(&FaceRenderInfo::batch(&faces, &animations, 0.15)), vec![(Some(2), 0, 6), (Some(3), 6, 6)]);
+    }
+
+    fn entity(model: Option<usize>, render_mode: bsp30::RenderMode, x: f32) -> EntityData {
+        return EntityData {
+            face_render_info: vec![face(model.unwrap_or(0) * 100), face(model.unwrap_or(0) * 100 + 6)],
+            model,
+            origin: glm::vec3(x, 0.0, 0.0),
+            center: glm::vec3(x, 0.0, 0.0),
+            alpha: 1.0,
+            render_mode,
+            wave_height: 0.0,
+        };
+    }
+
+    #[test]
+    fn entities_of_the_same_model_are_grouped() {
+        let entities: Vec<EntityData> = vec![
+            entity(Some(1), bsp30::RenderMode::RenderModeNormal, 0.0),
+            entity(Some(2), bsp30::RenderMode::RenderModeNormal, 10.0),
+            entity(Some(1), bsp30::RenderMode::RenderModeNormal, 20.0),
+            entity(Some(1), bsp30::RenderMode::RenderModeNormal, 30.0),
+        ];
+        let groups: Vec<Vec<usize>> = EntityData::instance_groups(&entities, &[0, 1, 2, 3]);
+        assert_eq!(groups, vec![vec![0, 2, 3], vec![1]]);
+    }
+
+    #[test]
+    fn groups_follow_the_drawing_order() {
+        let entities: Vec<EntityData> = vec![
+            entity(Some(1), bsp30::RenderMode::RenderModeNormal, 0.0),
+            entity(Some(1), bsp30::RenderMode::RenderModeNormal, 10.0),
+            entity(Some(2), bsp30::RenderMode::RenderModeNormal, 20.0),
+        ];
+        let groups: Vec<Vec<usize>> = EntityData::instance_groups(&entities, &[2, 1, 0]);
+        assert_eq!(groups, vec![vec![2], vec![1, 0]]);
+    }
+
+    #[test]
+    fn the_world_and_transparent_entities_are_never_grouped() {
+        let entities: Vec<EntityData> = vec![
+            entity(None, bsp30::RenderMode::RenderModeNormal, 0.0),
+            entity(None, bsp30::RenderMode::RenderModeNormal, 0.0),
+            entity(Some(3), bsp30::RenderMode::RenderModeTexture, 10.0),
+            entity(Some(3), bsp30::RenderMode::RenderModeTexture, 20.0),
+        ];
+        let groups: Vec<Vec<usize>> = EntityData::instance_groups(&entities, &[0, 1, 2, 3]);
+        assert_eq!(groups, vec![vec![0], vec![1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn entities_drawn_differently_are_not_grouped() {
+        let mut water: EntityData = entity(Some(1), bsp30::RenderMode::RenderModeNormal, 10.0);
+        water.wave_height = 4.0;
+        let mut culled: EntityData = entity(Some(1), bsp30::RenderMode::RenderModeNormal, 20.0);
+        culled.face_render_info.pop();
+        let entities: Vec<EntityData> = vec![
+            entity(Some(1), bsp30::RenderMode::RenderModeNormal, 0.0),
+            entity(Some(1), bsp30::RenderMode::RenderModeSolid, 5.0),
+            water,
+            culled,
+        ];
+        let groups: Vec<Vec<usize>> = EntityData::instance_groups(&entities, &[0, 1, 2, 3]);
+        assert_eq!(groups.len(), 4);
+    }
+
+    #[test]
+    fn opaque_entities_draw_front_to_back_then_transparent_back_to_front() {
+        let entities: Vec<EntityData> = vec![
+            entity(Some(1), bsp30::RenderMode::RenderModeTexture, 10.0),
+            entity(Some(2), bsp30::RenderMode::RenderModeNormal, 30.0),
+            entity(Some(3), bsp30::RenderMode::RenderModeAdditive, 40.0),
+            entity(Some(4), bsp30::RenderMode::RenderModeSolid, -5.0),
+            entity(Some(5), bsp30::RenderMode::RenderModeGlow, -20.0),
+        ];
+        assert_eq!(EntityData::draw_order(&entities, &glm::vec3(0.0, 0.0, 0.0)), vec![3, 1, 2, 4, 0]);
+        // Seen from the other end the transparent order reverses
+        assert_eq!(EntityData::draw_order(&entities, &glm::vec3(40.0, 0.0, 0.0)), vec![1, 3, 4, 0, 2]);
+    }
+
+    fn positions(lines: &Vec<Vertex>) -> Vec<[f32; 3]> {
+        return lines.iter().map(|vertex: &Vertex| vertex.position).collect();
+    }
+
+    #[test]
+    fn axes_run_from_the_origin_with_a_tick_every_spacing() {
+        let [x, y, z]: [Vec<Vertex>; 3] = axis_lines(200.0, Some(64.0));
+        assert_eq!(positions(&x), vec![
+            [0.0, 0.0, 0.0], [200.0, 0.0, 0.0],
+            [64.0, -4.0, 0.0], [64.0, 4.0, 0.0],
+            [128.0, -4.0, 0.0], [128.0, 4.0, 0.0],
+            [192.0, -4.0, 0.0], [192.0, 4.0, 0.0],
+        ]);
+        // Ticks cross each axis in the next one's direction
+        assert_eq!(positions(&y)[2..4], [[0.0, 64.0, -4.0], [0.0, 64.0, 4.0]]);
+        assert_eq!(positions(&z)[2..4], [[-4.0, 0.0, 64.0], [4.0, 0.0, 64.0]]);
+    }
+
+    #[test]
+    fn axes_without_ticks_are_single_lines() {
+        for tick_spacing in [None, Some(0.0), Some(-64.0), Some(512.0)] {
+            let lines: [Vec<Vertex>; 3] = axis_lines(256.0, tick_spacing);
+            assert!(lines.iter().all(|axis: &Vec<Vertex>| axis.len() == 2));
+        }
+        assert_eq!(positions(&axis_lines(-10.0, Some(64.0))[0]), vec![[0.0, 0.0, 0.0]; 2]);
+    }
+
+    fn capture(name: &'static str, draw_calls: usize) -> PassCapture {
+        return PassCapture {
+            name,
+            image: Image {
+                channels: 4,
+                width: 2,
+                height: 2,
+                data: vec![255; 16],
+            },
+            stats: RenderStats {
+                draw_calls,
+                ..RenderStats::default()
+            },
+        };
+    }
+
+    #[test]
+    fn pass_stats_count_only_work_since_the_pass_opened() {
+        let start: RenderStats = RenderStats {
+            draw_calls: 4,
+            faces_rendered: 10,
+            triangles: 20,
+            ..RenderStats::default()
+        };
+        let end: RenderStats = RenderStats {
+            draw_calls: 7,
+            faces_rendered: 10,
+            triangles: 26,
+            ..RenderStats::default()
+        };
+        assert_eq!(end.since(&start), RenderStats {
+            draw_calls: 3,
+            triangles: 6,
+            ..RenderStats::default()
+        });
+        assert_eq!(start.since(&end).draw_calls, 0);
+    }
+
+    #[test]
+    fn a_capture_saves_one_numbered_file_per_pass() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("lambda-pass-capture-{}", std::process::id()));
+        let captures: Vec<PassCapture> = vec![capture("sky", 1), capture("world", 12), capture("sky", 1)];
+        let paths: Vec<PathBuf> = save_pass_captures(&captures, &directory).unwrap();
+        let names: Vec<String> = paths.iter()
+            .map(|path: &PathBuf| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["00-sky.png", "01-world.png", "02-sky.png"]);
+        assert!(paths.iter().all(|path: &PathBuf| path.is_file()));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Renderer that draws nothing, recording the texture each face of the
+    /// entities given to `render_static` resolves to. Methods making GL
+    /// objects are never reached without a context.
+    #[derive(Default)]
+    struct RecordingRenderer {
+        stats: std::cell::Cell<RenderStats>,
+        drawn: std::cell::RefCell<Vec<Vec<Option<usize>>>>,
+    }
+
+    impl RecordingRenderer {
+
+        /// Texture drawn on each face of each entity, None for untextured
+        /// faces or indices past the end of the `texture_count` textures
+        fn resolve(entities: &Vec<EntityData>, texture_count: usize, animations: &Vec<TextureAnimation>, time: f32) -> Vec<Vec<Option<usize>>> {
+            return entities.iter()
+                .map(|entity: &EntityData| entity.face_render_info.iter()
+                    .map(|face: &FaceRenderInfo| face.texture_at(animations, time).filter(|index: &usize| *index < texture_count))
+                    .collect())
+                .collect();
+        }
+
+    }
+
+    impl Renderer for RecordingRenderer {
+        fn capabilities(&self) -> RendererCapabilities { return RendererCapabilities::default(); }
+        fn stats(&self) -> RenderStats { return self.stats.get(); }
+        fn begin_frame(&self) { self.stats.set(RenderStats::default()); }
+        fn end_frame(&self) -> Result<()> { return Ok(()); }
+        fn resize_viewport(&self, _: usize, _: usize) {}
+        fn set_clear_color(&self, _: (f32, f32, f32, f32)) {}
+        fn clear(&self) {}
+        fn create_texture(&self, _: &Vec<&Image>) -> Result<SrgbTexture2d> { unimplemented!() }
+        fn update_texture(&self, _: &SrgbTexture2d, _: usize, _: usize, _: &Image) -> Result<()> { return Ok(()); }
+        fn create_compressed_texture(&self, _: &Vec<&Image>, _: BlockFormat) -> Result<CompressedSrgbTexture2d> { unimplemented!() }
+        fn create_cube_texture(&self, _: [Image; 6]) -> Result<SrgbCubemap> { unimplemented!() }
+        fn render_coords(&self, _: &glm::Mat4, _: f32, _: Option<f32>) {}
+        fn render_skybox(&self, _: &SrgbCubemap, _: &glm::Mat4, _: [f32; 4]) {}
+        fn render_lines(&self, _: VertexBufferSlice<Vertex>, _: &glm::Mat4, _: [f32; 4], _: bool) {}
+        fn render_fullscreen_effect(&self, _: [f32; 4], _: f32, _: f32) {}
+        fn render_static(
+            &self,
+            entities: &Vec<EntityData>,
+            _: &Vec<Decal>,
+            _: &VertexBuffer<VertexWithLM>,
+            _: &IndexBuffer<u32>,
+            _: &VertexBuffer<Vertex>,
+            textures: &Vec<TextureHandle>,
+            animations: &Vec<TextureAnimation>,
+            _: &SrgbTexture2d,
+            _: &[PointLight],
+            settings: &RenderSettings,
+        ) {
+            *self.drawn.borrow_mut() = RecordingRenderer::resolve(entities, textures.len(), animations, settings.time);
+        }
+        fn init_imgui(&self, _: &mut imgui::Context) -> Result<()> { return Ok(()); }
+        fn create_imgui_texture(&self, _: &Image) -> Result<imgui::TextureId> { unimplemented!() }
+        fn remove_imgui_texture(&self, _: imgui::TextureId) {}
+        fn render_imgui(&self, _: &imgui::DrawData) {}
+        fn provide_facade(&self) -> &dyn Facade { unimplemented!() }
+        fn screenshot(&self) -> Result<Image> { unimplemented!() }
+        fn request_capture(&self) {}
+        fn capturing(&self) -> bool { return false; }
+        fn capture_pass(&self, _: &'static str, _: RenderStats) {}
+        fn take_captures(&self) -> Vec<PassCapture> { return Vec::new(); }
+    }
+
+    #[test]
+    fn entity_faces_resolve_to_indices_into_the_texture_list() {
+        let bsp: crate::map::bsp::BSP = crate::map::micro_map::load_micro_map(crate::map::load_options::ParseStrictness::Strict).unwrap();
+        // Built the way BSPRenderable does, each face naming its texture by index
+        let faces: Vec<FaceRenderInfo> = bsp.faces.iter()
+            .enumerate()
+            .map(|(i, bsp_face): (usize, &bsp30::Face)| FaceRenderInfo {
+                tex: Some(bsp.texture_infos[bsp_face.texture_info as usize].mip_tex_index as usize),
+                ..face(i * 6)
+            })
+            .collect();
+        let mut untextured: EntityData = entity(Some(1), bsp30::RenderMode::RenderModeNormal, 0.0);
+        untextured.face_render_info = faces.iter()
+            .map(|face: &FaceRenderInfo| FaceRenderInfo { tex: None, ..face.clone() })
+            .collect();
+        let entities: Vec<EntityData> = vec![
+            EntityData {
+                face_render_info: faces,
+                ..entity(None, bsp30::RenderMode::RenderModeNormal, 0.0)
+            },
+            untextured,
+        ];
+        let renderer: Box<dyn Renderer> = Box::new(RecordingRenderer::default());
+        renderer.begin_frame();
+        assert_eq!(renderer.stats(), RenderStats::default());
+        assert_eq!(
+            RecordingRenderer::resolve(&entities, bsp.m_textures.len(), &bsp.texture_animations, 0.0),
+            vec![vec![Some(0), Some(1)], vec![None, None]],
+        );
+        // An index past the uploaded textures draws untextured rather than another texture
+        assert_eq!(RecordingRenderer::resolve(&entities, 1, &bsp.texture_animations, 0.0)[0], vec![Some(0), None]);
+        assert!(renderer.end_frame().is_ok());
+    }
+
+    #[test]
+    fn anisotropy_clamps_to_the_driver_maximum() {
+        let capabilities: RendererCapabilities = RendererCapabilities { max_anisotropy: Some(8), ..RendererCapabilities::default() };
+        assert_eq!(capabilities.clamp_anisotropy(16), 8);
+        assert_eq!(capabilities.clamp_anisotropy(4), 4);
+        assert_eq!(capabilities.clamp_anisotropy(0), 1);
+        // Without the extension sampling stays isotropic
+        assert_eq!(RendererCapabilities::default().clamp_anisotropy(16), 1);
+    }
+
+    #[test]
+    fn shaders_encode_gamma_without_an_srgb_framebuffer() {
+        let srgb: RendererCapabilities = RendererCapabilities { srgb_framebuffer: true, ..RendererCapabilities::default() };
+        assert_eq!(srgb.gamma_encoding(), GammaEncoding::Framebuffer);
+        assert_eq!(RendererCapabilities::default().gamma_encoding(), GammaEncoding::Shader);
+    }
+
+}
@@ -0,0 +1,35 @@
+#version 140
+
+in vec2 v_tex_coord;
+in vec3 v_position;
+
+out vec4 color;
+
+uniform sampler2D diffuse;
+uniform int fog_mode;
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_density;
+uniform vec3 camera_position;
+
+// Matches Fog::visibility, 0 off, 1 linear, 2 exp2
+float fog_visibility(float dist) {
+    if (fog_mode == 1) {
+        return clamp((fog_end - dist) / max(fog_end - fog_start, 0.001), 0.0, 1.0);
+    }
+    if (fog_mode == 2) {
+        float thickness = fog_density * dist;
+        return clamp(exp(-thickness * thickness), 0.0, 1.0);
+    }
+    return 1.0;
+}
+
+void main() {
+    vec4 texel = texture(diffuse, v_tex_coord);
+    color = vec4(mix(fog_color, texel.rgb, fog_visibility(length(v_position - camera_position))), texel.a);
+#ifdef ENCODE_SRGB
+    // Defined when the framebuffer cannot encode to sRGB itself
+    color.rgb = pow(color.rgb, vec3(1.0 / 2.2));
+#endif
+}
@@ -0,0 +1,15 @@
+#version 140
+
+in vec3 position;
+in vec2 tex_coord;
+
+out vec2 v_tex_coord;
+out vec3 v_position;
+
+uniform mat4 matrix;
+
+void main() {
+    v_tex_coord = vec2(tex_coord.x, 1.0 - tex_coord.y);
+    v_position = position;
+    gl_Position = matrix * vec4(position, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 140
+
+out vec4 color;
+
+uniform vec4 line_color;
+
+void main() {
+    color = line_color;
+#ifdef ENCODE_SRGB
+    // Defined when the framebuffer cannot encode to sRGB itself
+    color.rgb = pow(color.rgb, vec3(1.0 / 2.2));
+#endif
+}
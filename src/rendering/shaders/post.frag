@@ -0,0 +1,20 @@
+#version 140
+
+in vec2 v_uv;
+
+out vec4 color;
+
+uniform sampler2D scene;
+uniform vec4 tint;
+uniform float warp;
+uniform float time;
+
+// Ripples across the screen and their speed in radians per second
+const float WARP_FREQUENCY = 12.0;
+const float WARP_SPEED = 2.0;
+
+void main() {
+    vec2 offset = vec2(sin(v_uv.y * WARP_FREQUENCY + time * WARP_SPEED), cos(v_uv.x * WARP_FREQUENCY + time * WARP_SPEED));
+    vec3 scene_color = texture(scene, clamp(v_uv + offset * warp, 0.0, 1.0)).rgb;
+    color = vec4(mix(scene_color, tint.rgb, tint.a), 1.0);
+}
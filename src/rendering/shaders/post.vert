@@ -0,0 +1,10 @@
+#version 140
+
+out vec2 v_uv;
+
+void main() {
+    // One triangle covering the screen, placed from the vertex index alone
+    vec2 corner = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+    v_uv = corner;
+    gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 140
+
+in vec3 v_direction;
+
+out vec4 color;
+
+uniform samplerCube skybox;
+uniform vec4 fog; // Colour, and how much of it covers the sky in alpha
+
+void main() {
+    vec4 sky = texture(skybox, v_direction);
+    color = vec4(mix(sky.rgb, fog.rgb, fog.a), sky.a);
+#ifdef ENCODE_SRGB
+    // Defined when the framebuffer cannot encode to sRGB itself
+    color.rgb = pow(color.rgb, vec3(1.0 / 2.2));
+#endif
+}
@@ -0,0 +1,13 @@
+#version 140
+
+in vec3 position;
+
+out vec3 v_direction;
+
+uniform mat4 matrix;
+
+void main() {
+    v_direction = position;
+    // w as depth puts the sky on the far plane after the divide
+    gl_Position = (matrix * vec4(position, 1.0)).xyww;
+}
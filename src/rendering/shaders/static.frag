@@ -0,0 +1,76 @@
+#version 140
+
+in vec2 v_tex_coord;
+in vec2 v_lightmap_coord;
+in vec3 v_position;
+in vec3 v_normal;
+in float v_alpha;
+
+out vec4 color;
+
+uniform sampler2D diffuse;
+uniform sampler2D lightmap;
+uniform bool textured;
+uniform bool alpha_test;
+uniform bool lit;
+uniform bool show_diffuse;
+uniform bool show_lighting;
+uniform bool water;
+uniform float time;
+
+// Texture coordinate wobble of water surfaces, in texture widths
+const float WATER_WARP = 0.03;
+
+// Matches MAX_POINT_LIGHTS, a light reaches as far as its intensity
+const int MAX_POINT_LIGHTS = 8;
+uniform int light_count;
+uniform vec3 light_positions[MAX_POINT_LIGHTS];
+uniform vec3 light_colors[MAX_POINT_LIGHTS];
+uniform float light_intensities[MAX_POINT_LIGHTS];
+
+vec3 point_lights() {
+    vec3 normal = normalize(v_normal);
+    vec3 total = vec3(0.0);
+    for (int i = 0; i < min(light_count, MAX_POINT_LIGHTS); i++) {
+        vec3 to_light = light_positions[i] - v_position;
+        float dist = length(to_light);
+        float attenuation = clamp(1.0 - dist / light_intensities[i], 0.0, 1.0);
+        float lambert = max(dot(normal, to_light / max(dist, 0.001)), 0.0);
+        total += light_colors[i] * attenuation * lambert;
+    }
+    return total;
+}
+
+uniform int fog_mode;
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_density;
+uniform vec3 camera_position;
+
+// Matches Fog::visibility, 0 off, 1 linear, 2 exp2
+float fog_visibility(float dist) {
+    if (fog_mode == 1) {
+        return clamp((fog_end - dist) / max(fog_end - fog_start, 0.001), 0.0, 1.0);
+    }
+    if (fog_mode == 2) {
+        float thickness = fog_density * dist;
+        return clamp(exp(-thickness * thickness), 0.0, 1.0);
+    }
+    return 1.0;
+}
+
+void main() {
+    vec2 uv = water ? v_tex_coord + WATER_WARP * sin(v_tex_coord.yx * 6.2831853 + time) : v_tex_coord;
+    vec4 texel = textured ? texture(diffuse, uv) : vec4(1.0);
+    if (alpha_test && texel.a < 0.5) {
+        discard;
+    }
+    vec3 light = lit && show_lighting ? texture(lightmap, v_lightmap_coord).rgb + point_lights() : vec3(1.0);
+    vec3 base = show_diffuse ? texel.rgb : vec3(1.0);
+    color = vec4(mix(fog_color, base * light, fog_visibility(length(v_position - camera_position))), texel.a * v_alpha);
+#ifdef ENCODE_SRGB
+    // Defined when the framebuffer cannot encode to sRGB itself
+    color.rgb = pow(color.rgb, vec3(1.0 / 2.2));
+#endif
+}
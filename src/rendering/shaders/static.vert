@@ -0,0 +1,39 @@
+#version 140
+
+in vec3 position;
+in vec2 tex_coord;
+in vec2 lightmap_coord;
+
+in vec3 normal;
+
+out vec2 v_tex_coord;
+out vec2 v_lightmap_coord;
+out vec3 v_position;
+out vec3 v_normal;
+out float v_alpha;
+
+uniform mat4 matrix;
+uniform vec3 origin;
+uniform float alpha;
+uniform bool water;
+uniform float wave_amplitude; // Height of the standing wave's crests this frame, zero at time zero
+
+// Water rises and falls in a standing wave across the surface
+const float WAVE_FREQUENCY = 0.0625;
+uniform vec2 uv_scroll;
+
+void main() {
+    // Coordinates start at the top row but textures are uploaded bottom-up
+    vec2 uv = tex_coord + uv_scroll;
+    v_tex_coord = vec2(uv.x, -uv.y);
+    v_lightmap_coord = vec2(lightmap_coord.x, 1.0 - lightmap_coord.y);
+    vec3 displaced = position;
+    if (water) {
+        vec3 world = position + origin;
+        displaced += normal * wave_amplitude * cos((world.x + world.y) * WAVE_FREQUENCY);
+    }
+    v_position = displaced + origin;
+    v_normal = normal;
+    v_alpha = alpha;
+    gl_Position = matrix * vec4(displaced, 1.0);
+}
@@ -1,45 +1,226 @@
-use crate::input::r#move::PlayerMove;
+use crate::core::scale::ScaleDefaults;
+use crate::input::r#move::{PlayerMove, UserCommand};
 
+/// Where the camera is and which way it faces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub origin: glm::Vec3,
+    pub angles: glm::Vec3, // Pitch, yaw and roll in degrees
+}
+
+impl CameraPose {
+
+    /// Pose `t` of the way from this one to `next`, angles turning the short
+    /// way round
+    pub fn lerp(&self, next: &CameraPose, t: f32) -> CameraPose {
+        let turn = |from: f32, to: f32| -> f32 {
+            let delta: f32 = (to - from) % 360.0;
+            let delta: f32 = if delta > 180.0 { delta - 360.0 } else if delta < -180.0 { delta + 360.0 } else { delta };
+            return from + delta * t;
+        };
+        return CameraPose {
+            origin: glm::lerp(&self.origin, &next.origin, t),
+            angles: glm::vec3(
+                turn(self.angles.x, next.angles.x),
+                turn(self.angles.y, next.angles.y),
+                turn(self.angles.z, next.angles.z),
+            ),
+        };
+    }
+
+}
+
+/// Camera moved by player commands at the simulation's tick rate. The view
+/// is drawn `interpolation` of the way from the pose before the last command
+/// to the pose after it, so motion stays smooth between ticks.
 pub struct Camera {
     player_move: Box<PlayerMove>,
+    previous: CameraPose, // Pose before the last command
+    interpolation: f32, // 0.0 draws the previous pose, 1.0 the latest
     pub viewport_width: usize,
     pub viewport_height: usize,
     pub fov_y: usize,
+    pub near_plane: f32, // Clip planes in world units
+    pub far_plane: f32,
 }
 
 impl Camera {
 
     pub fn new(player_move: Box<PlayerMove>) -> Self {
+        let previous: CameraPose = CameraPose {
+            origin: player_move.origin,
+            angles: player_move.angles,
+        };
         return Camera {
             player_move,
+            previous,
+            interpolation: 1.0,
             viewport_width: 0,
             viewport_height: 0,
             fov_y: 60,
+            near_plane: ScaleDefaults::default().near_plane,
+            far_plane: ScaleDefaults::default().far_plane,
+        };
+    }
+
+    /// Take the clip planes and gravity from a world scale's tunables
+    pub fn apply_scale(&mut self, scale: &ScaleDefaults) {
+        self.near_plane = scale.near_plane;
+        self.far_plane = scale.far_plane.max(scale.near_plane * 2.0);
+        self.player_move.gravity = scale.gravity;
+    }
+
+    /// Pose after the last command
+    pub fn latest_pose(&self) -> CameraPose {
+        return CameraPose {
+            origin: self.player_move.origin,
+            angles: self.player_move.angles,
         };
     }
 
+    /// Pose the view is drawn from
+    pub fn pose(&self) -> CameraPose {
+        return self.previous.lerp(&self.latest_pose(), self.interpolation);
+    }
+
+    /// Set how far the view is between the last two commands, from 0.0 to 1.0
+    pub fn set_interpolation(&mut self, interpolation: f32) {
+        self.interpolation = interpolation.clamp(0.0, 1.0);
+    }
+
     pub fn position(&self) -> glm::Vec3 {
-        return self.player_move.origin;
+        return self.pose().origin;
     }
 
     pub fn pitch(&self) -> f32 {
-        return self.player_move.angles.x;
+        return self.pose().angles.x;
     }
 
     pub fn yaw(&self) -> f32 {
-        return self.player_move.angles.y;
+        return self.pose().angles.y;
+    }
+
+    pub fn set_view(&mut self, position: glm::Vec3, direction: glm::Vec3) {
+        let dir: glm::Vec3 = glm::normalize(&direction);
+        self.player_move.origin = position;
+        // GoldSrc convention: positive pitch looks down
+        self.player_move.angles.x = -dir.z.clamp(-1.0, 1.0).asin().to_degrees();
+        self.player_move.angles.y = dir.y.atan2(dir.x).to_degrees();
+        self.previous = self.latest_pose();
+    }
+
+    /// Turn and move the camera by a user command, one simulation tick
+    pub fn run_command(&mut self, cmd: UserCommand) {
+        self.previous = self.latest_pose();
+        self.player_move.cmd = cmd;
+        self.player_move.player_move();
+    }
+
+    /// Move the camera without turning it. The view jumps straight there
+    /// rather than sliding over from the old position.
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.player_move.origin = position;
+        self.previous = self.latest_pose();
+    }
+
+    /// Unit vector the camera looks along
+    pub fn view_vector(&self) -> glm::Vec3 {
+        let (pitch, yaw): (f32, f32) = (self.pitch().to_radians(), self.yaw().to_radians());
+        return glm::vec3(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), -pitch.sin());
+    }
+
+    /// World to eye transform. GoldSrc is Z up looking along +X, so the
+    /// world is first turned to GL's Y up looking along -Z, as in Quake.
+    pub fn view_matrix(&self) -> glm::Mat4 {
+        let mut matrix: glm::Mat4 = glm::rotation(-90.0f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+        matrix = glm::rotate(&matrix, 90.0f32.to_radians(), &glm::vec3(0.0, 0.0, 1.0));
+        matrix = glm::rotate(&matrix, -self.pitch().to_radians(), &glm::vec3(0.0, 1.0, 0.0));
+        matrix = glm::rotate(&matrix, -self.yaw().to_radians(), &glm::vec3(0.0, 0.0, 1.0));
+        return glm::translate(&matrix, &-self.position());
+    }
+
+    pub fn projection_matrix(&self) -> glm::Mat4 {
+        return self.projection_matrix_scaled(1.0);
+    }
+
+    /// Projection with the vertical field of view multiplied by `fov_scale`
+    pub fn projection_matrix_scaled(&self, fov_scale: f32) -> glm::Mat4 {
+        let aspect: f32 = if self.viewport_height == 0 {
+            1.0
+        } else {
+            self.viewport_width as f32 / self.viewport_height as f32
+        };
+        return glm::perspective(aspect, (self.fov_y as f32 * fov_scale).to_radians(), self.near_plane, self.far_plane);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_projection_follows_the_viewport_aspect_ratio() {
+        let mut camera: Camera = Camera::new(Box::new(PlayerMove::default()));
+        let focal_length: f32 = 1.0 / ((camera.fov_y as f32).to_radians() * 0.5).tan();
+        for (width, height) in [(1280usize, 720usize), (720, 1280), (1024, 1024), (1, 3000)] {
+            camera.viewport_width = width;
+            camera.viewport_height = height;
+            let projection: glm::Mat4 = camera.projection_matrix();
+            // The vertical field of view is fixed, the horizontal one widens with the window
+            assert!((projection[(1, 1)] - focal_length).abs() < 1e-5);
+            assert!((projection[(1, 1)] / projection[(0, 0)] - width as f32 / height as f32).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn a_zero_height_viewport_projects_square() {
+        let mut camera: Camera = Camera::new(Box::new(PlayerMove::default()));
+        camera.viewport_width = 640;
+        camera.viewport_height = 0;
+        let projection: glm::Mat4 = camera.projection_matrix();
+        assert_eq!(projection[(0, 0)], projection[(1, 1)]);
+        assert!(projection.iter().all(|value: &f32| value.is_finite()));
+    }
+
+    fn pose(x: f32, yaw: f32) -> CameraPose {
+        return CameraPose {
+            origin: glm::vec3(x, 0.0, 64.0),
+            angles: glm::vec3(0.0, yaw, 0.0),
+        };
     }
 
-    pub fn view_vector() -> glm::Vec3 {
-        todo!()
+    #[test]
+    fn poses_interpolate_linearly() {
+        let (from, to): (CameraPose, CameraPose) = (pose(0.0, 10.0), pose(100.0, 50.0));
+        assert_eq!(from.lerp(&to, 0.0), from);
+        assert_eq!(from.lerp(&to, 1.0), to);
+        let quarter: CameraPose = from.lerp(&to, 0.25);
+        assert_eq!(quarter.origin, glm::vec3(25.0, 0.0, 64.0));
+        assert_eq!(quarter.angles.y, 20.0);
     }
 
-    pub fn view_matrix() -> glm::Mat4 {
-        todo!()
+    #[test]
+    fn angles_turn_the_short_way_round() {
+        assert_eq!(pose(0.0, 350.0).lerp(&pose(0.0, 10.0), 0.5).angles.y, 360.0);
+        assert_eq!(pose(0.0, 10.0).lerp(&pose(0.0, 350.0), 0.5).angles.y, 0.0);
+        assert_eq!(pose(0.0, -170.0).lerp(&pose(0.0, 170.0), 0.25).angles.y, -175.0);
     }
 
-    pub fn projection_matrix() -> glm::Mat4 {
-        todo!()
+    #[test]
+    fn the_view_is_drawn_between_the_last_two_ticks() {
+        let mut camera: Camera = Camera::new(Box::new(PlayerMove::default()));
+        camera.set_position(glm::vec3(0.0, 0.0, 64.0));
+        camera.previous = pose(-40.0, 0.0);
+        camera.set_interpolation(0.25);
+        assert_eq!(camera.position(), glm::vec3(-30.0, 0.0, 64.0));
+        camera.set_interpolation(2.0);
+        assert_eq!(camera.pose(), camera.latest_pose());
+        camera.set_interpolation(-1.0);
+        assert_eq!(camera.position(), glm::vec3(-40.0, 0.0, 64.0));
+        // Moving the camera directly leaves nothing to interpolate from
+        camera.set_position(glm::vec3(8.0, 8.0, 8.0));
+        assert_eq!(camera.position(), glm::vec3(8.0, 8.0, 8.0));
     }
 
 }
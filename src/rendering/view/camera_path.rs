@@ -0,0 +1,367 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::rendering::view::camera::Camera;
+use crate::util::mathutil::{catmull_rom, slerp_direction};
+
+pub const CAPTURE_TIMESTEP: f32 = 1.0 / 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+
+    /// Easing named in a path file
+    pub fn parse(name: &str) -> Option<Easing> {
+        return match name.to_ascii_lowercase().as_str() {
+            "linear" => Some(Easing::Linear),
+            "ease_in" => Some(Easing::EaseIn),
+            "ease_out" => Some(Easing::EaseOut),
+            "ease_in_out" => Some(Easing::EaseInOut),
+            _ => None,
+        };
+    }
+
+    pub fn apply(&self, t: f32) -> f32 {
+        let t: f32 = t.clamp(0.0, 1.0);
+        return match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        };
+    }
+
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PathKnot {
+    pub position: glm::Vec3,
+    pub direction: glm::Vec3,
+    pub duration: f32, // Seconds taken to travel to the next knot
+    pub easing: Easing,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PathSample {
+    pub position: glm::Vec3,
+    pub direction: glm::Vec3,
+}
+
+#[derive(Default, Clone)]
+pub struct CameraPath {
+    pub knots: Vec<PathKnot>,
+}
+
+impl CameraPath {
+
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Read a path file, one knot per line as `x y z dx dy dz duration [easing]`.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text: String = std::fs::read_to_string(path.as_ref())?;
+        return CameraPath::parse(&text);
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut path: CameraPath = CameraPath::new();
+        for (line_index, line) in text.lines().enumerate() {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| -> Error {
+                return Error::new(ErrorKind::InvalidData, format!("Camera path line {}: {}", line_index + 1, message));
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 7 && fields.len() != 8 {
+                return Err(invalid("expected x y z dx dy dz duration [easing]"));
+            }
+            let numbers: Vec<f32> = fields[..7].iter()
+                .map(|field: &&str| field.parse::<f32>())
+                .collect::<std::result::Result<Vec<f32>, _>>()
+                .map_err(|_| invalid("expected numbers for the position, direction and duration"))?;
+            let direction: glm::Vec3 = glm::vec3(numbers[3], numbers[4], numbers[5]);
+            if glm::length(&direction) <= f32::EPSILON {
+                return Err(invalid("direction must not be zero"));
+            }
+            let easing: Easing = match fields.get(7) {
+                Some(name) => Easing::parse(name).ok_or_else(|| invalid("unknown easing"))?,
+                None => Easing::Linear,
+            };
+            path.push(PathKnot {
+                position: glm::vec3(numbers[0], numbers[1], numbers[2]),
+                direction,
+                duration: numbers[6],
+                easing,
+            });
+        }
+        if path.knots.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "Camera path has no knots"));
+        }
+        return Ok(path);
+    }
+
+    pub fn push(&mut self, knot: PathKnot) {
+        self.knots.push(knot);
+    }
+
+    pub fn total_duration(&self) -> f32 {
+        if self.knots.len() < 2 {
+            return 0.0;
+        }
+        return self.knots[..self.knots.len() - 1].iter()
+            .map(|knot: &PathKnot| knot.duration.max(0.0))
+            .sum();
+    }
+
+    pub fn sample(&self, time: f32) -> Option<PathSample> {
+        if self.knots.is_empty() {
+            return None;
+        }
+        if self.knots.len() == 1 || time <= 0.0 {
+            return Some(PathSample {
+                position: self.knots[0].position,
+                direction: self.knots[0].direction,
+            });
+        }
+        let mut remaining: f32 = time;
+        for i in 0..self.knots.len() - 1 {
+            let duration: f32 = self.knots[i].duration.max(0.0);
+            if remaining > duration && i < self.knots.len() - 2 {
+                remaining -= duration;
+                continue;
+            }
+            let local_t: f32 = if duration > 0.0 { remaining / duration } else { 1.0 };
+            return Some(self.sample_segment(i, self.knots[i].easing.apply(local_t)));
+        }
+        return None;
+    }
+
+    fn sample_segment(&self, segment: usize, t: f32) -> PathSample {
+        let last: usize = self.knots.len() - 1;
+        let p0: glm::Vec3 = self.knots[segment.saturating_sub(1)].position;
+        let p1: glm::Vec3 = self.knots[segment].position;
+        let p2: glm::Vec3 = self.knots[(segment + 1).min(last)].position;
+        let p3: glm::Vec3 = self.knots[(segment + 2).min(last)].position;
+        return PathSample {
+            position: catmull_rom(p0, p1, p2, p3, t),
+            direction: slerp_direction(
+                self.knots[segment].direction,
+                self.knots[(segment + 1).min(last)].direction,
+                t,
+            ),
+        };
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackMode {
+    RealTime,
+    FixedStep(f32), // Advance by exactly this many seconds per frame, ignoring wall clock
+}
+
+pub struct PlaybackFrame {
+    pub frame_index: usize,
+    pub capture_name: Option<String>,
+}
+
+pub struct PathPlayback {
+    path: CameraPath,
+    mode: PlaybackMode,
+    time: f32,
+    frame_index: usize,
+    pub hide_hud: bool,
+}
+
+impl PathPlayback {
+
+    pub fn new(path: CameraPath, mode: PlaybackMode) -> Self {
+        return PathPlayback {
+            path,
+            mode,
+            time: 0.0,
+            frame_index: 0,
+            hide_hud: true,
+        };
+    }
+
+    pub fn capture(path: CameraPath) -> Self {
+        return PathPlayback::new(path, PlaybackMode::FixedStep(CAPTURE_TIMESTEP));
+    }
+
+    pub fn mode(&self) -> PlaybackMode {
+        return self.mode;
+    }
+
+    /// Seconds into the path of the last frame
+    pub fn time(&self) -> f32 {
+        return self.time;
+    }
+
+    /// Number of frames a fixed step playback of the given path will produce,
+    /// including the frames at both end points.
+    pub fn frame_count(path: &CameraPath, step: f32) -> usize {
+        if step <= 0.0 {
+            return 0;
+        }
+        return (path.total_duration() / step + 1.0e-4).floor() as usize + 1;
+    }
+
+    /// Produce the next frame of the playback, moving the camera along the path.
+    /// Returns `None` once the end of the path has been reached.
+    pub fn advance(&mut self, camera: &mut Camera, elapsed: f32) -> Option<PlaybackFrame> {
+        if self.frame_index > 0 {
+            self.time += match self.mode {
+                PlaybackMode::RealTime => elapsed,
+                PlaybackMode::FixedStep(step) => step,
+            };
+        }
+        let step_count: Option<usize> = match self.mode {
+            PlaybackMode::RealTime => None,
+            PlaybackMode::FixedStep(step) => Some(PathPlayback::frame_count(&self.path, step)),
+        };
+        let past_end: bool = match step_count {
+            Some(count) => self.frame_index >= count,
+            None => self.frame_index > 0 && self.time > self.path.total_duration(),
+        };
+        if past_end {
+            return None;
+        }
+        let sample: PathSample = self.path.sample(self.time.min(self.path.total_duration()))?;
+        camera.set_view(sample.position, sample.direction);
+        let frame: PlaybackFrame = PlaybackFrame {
+            frame_index: self.frame_index,
+            capture_name: step_count.map(|_| format!("frame_{:05}.png", self.frame_index)),
+        };
+        self.frame_index += 1;
+        return Some(frame);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::r#move::PlayerMove;
+
+    fn knot(x: f32, y: f32, z: f32, duration: f32) -> PathKnot {
+        return PathKnot {
+            position: glm::vec3(x, y, z),
+            direction: glm::vec3(1.0, 0.0, 0.0),
+            duration,
+            easing: Easing::Linear,
+        };
+    }
+
+    fn curved_path() -> CameraPath {
+        let mut path: CameraPath = CameraPath::new();
+        path.push(knot(0.0, 0.0, 0.0, 1.0));
+        path.push(knot(100.0, 0.0, 0.0, 1.0));
+        path.push(knot(150.0, 80.0, 0.0, 1.0));
+        path.push(knot(300.0, 100.0, 50.0, 1.0));
+        return path;
+    }
+
+    fn position(path: &CameraPath, time: f32) -> glm::Vec3 {
+        return path.sample(time).unwrap().position;
+    }
+
+    #[test]
+    fn passes_through_every_knot() {
+        let path: CameraPath = curved_path();
+        for (i, knot) in path.knots.iter().enumerate() {
+            let sampled: glm::Vec3 = position(&path, i as f32);
+            assert!(glm::distance(&sampled, &knot.position) < 1.0e-3, "knot {} sampled at {:?}", i, sampled);
+        }
+    }
+
+    #[test]
+    fn velocity_is_continuous_at_inner_knots() {
+        let path: CameraPath = curved_path();
+        let h: f32 = 1.0e-3;
+        for knot_time in [1.0f32, 2.0] {
+            let before: glm::Vec3 = (position(&path, knot_time) - position(&path, knot_time - h)) / h;
+            let after: glm::Vec3 = (position(&path, knot_time + h) - position(&path, knot_time)) / h;
+            assert!(
+                glm::distance(&before, &after) < 1.0,
+                "velocity jumps at t = {}: {:?} then {:?}",
+                knot_time,
+                before,
+                after,
+            );
+        }
+    }
+
+    #[test]
+    fn eased_segments_stop_at_their_knots() {
+        let mut path: CameraPath = curved_path();
+        for knot in path.knots.iter_mut() {
+            knot.easing = Easing::EaseInOut;
+        }
+        let h: f32 = 1.0e-3;
+        let before: glm::Vec3 = (position(&path, 1.0) - position(&path, 1.0 - h)) / h;
+        let after: glm::Vec3 = (position(&path, 1.0 + h) - position(&path, 1.0)) / h;
+        assert!(glm::length(&before) < 1.0);
+        assert!(glm::length(&after) < 1.0);
+    }
+
+    #[test]
+    fn fixed_step_capture_has_a_deterministic_frame_count() {
+        let mut path: CameraPath = CameraPath::new();
+        path.push(knot(0.0, 0.0, 0.0, 1.0));
+        path.push(knot(64.0, 0.0, 0.0, 0.5));
+        path.push(knot(64.0, 64.0, 0.0, 0.0));
+        let expected: usize = PathPlayback::frame_count(&path, CAPTURE_TIMESTEP);
+        assert_eq!(expected, 91);
+        // Wall clock time is ignored when capturing, so uneven frames give the same result
+        for elapsed in [0.0f32, 1.0 / 144.0, 0.25] {
+            let mut camera: Camera = Camera::new(Box::new(PlayerMove::default()));
+            let mut playback: PathPlayback = PathPlayback::capture(path.clone());
+            let mut names: Vec<String> = Vec::new();
+            while let Some(frame) = playback.advance(&mut camera, elapsed) {
+                names.push(frame.capture_name.unwrap());
+            }
+            assert_eq!(names.len(), expected);
+            assert_eq!(names.first().map(String::as_str), Some("frame_00000.png"));
+            assert_eq!(names.last().map(String::as_str), Some("frame_00090.png"));
+            // Playback stays finished
+            assert!(playback.advance(&mut camera, elapsed).is_none());
+            assert!(glm::distance(&camera.position(), &glm::vec3(64.0, 64.0, 0.0)) < 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn parses_path_files() {
+        let path: CameraPath = CameraPath::parse(
+            "# start in the courtyard\n\
+             0 0 64 1 0 0 2.5\n\
+             \n\
+             128 0 64 0 1 0 1 ease_in_out\n",
+        ).unwrap();
+        assert_eq!(path.knots.len(), 2);
+        assert_eq!(path.knots[0].duration, 2.5);
+        assert_eq!(path.knots[0].easing, Easing::Linear);
+        assert_eq!(path.knots[1].position, glm::vec3(128.0, 0.0, 64.0));
+        assert_eq!(path.knots[1].easing, Easing::EaseInOut);
+    }
+
+    #[test]
+    fn rejects_malformed_path_files() {
+        assert!(CameraPath::parse("").is_err());
+        assert!(CameraPath::parse("0 0 0 1 0 0").is_err());
+        assert!(CameraPath::parse("0 0 0 0 0 0 1").is_err());
+        assert!(CameraPath::parse("0 0 0 1 0 0 1 bounce").is_err());
+        assert!(CameraPath::parse("0 0 zero 1 0 0 1").is_err());
+    }
+
+}
@@ -0,0 +1,253 @@
+use crate::resource::image::Image;
+
+const BLOCK_DIM: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    Bc1, // Opaque RGB, 8 bytes per block
+    Bc3, // RGB with interpolated alpha, 16 bytes per block
+}
+
+impl BlockFormat {
+
+    pub fn block_bytes(&self) -> usize {
+        return match self {
+            BlockFormat::Bc1 => 8,
+            BlockFormat::Bc3 => 16,
+        };
+    }
+
+    /// Compressed size of a single image, edges are padded to whole blocks
+    pub fn compressed_size(&self, width: usize, height: usize) -> usize {
+        return width.div_ceil(BLOCK_DIM) * height.div_ceil(BLOCK_DIM) * self.block_bytes();
+    }
+
+    /// BC1 for fully opaque images, BC3 when any texel is not
+    pub fn for_image(image: &Image) -> BlockFormat {
+        if image.channels == 4 && image.data.chunks_exact(4).any(|texel: &[u8]| texel[3] != 255) {
+            return BlockFormat::Bc3;
+        }
+        return BlockFormat::Bc1;
+    }
+
+}
+
+/// Compress an RGB or RGBA image into blocks of the given format, rows of
+/// blocks ordered top to bottom. Partial edge blocks repeat the last texel.
+pub fn compress_image(image: &Image, format: BlockFormat) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(format.compressed_size(image.width, image.height));
+    if image.width == 0 || image.height == 0 {
+        return output;
+    }
+    for block_y in (0..image.height).step_by(BLOCK_DIM) {
+        for block_x in (0..image.width).step_by(BLOCK_DIM) {
+            let mut block: [[u8; 4]; 16] = [[0, 0, 0, 255]; 16];
+            for y in 0..BLOCK_DIM {
+                for x in 0..BLOCK_DIM {
+                    let px: usize = (block_x + x).min(image.width - 1);
+                    let py: usize = (block_y + y).min(image.height - 1);
+                    let offset: usize = (py * image.width + px) * image.channels;
+                    for c in 0..image.channels.min(4) {
+                        block[y * BLOCK_DIM + x][c] = image.data[offset + c];
+                    }
+                }
+            }
+            match format {
+                BlockFormat::Bc1 => output.extend_from_slice(&compress_block_bc1(&block)),
+                BlockFormat::Bc3 => output.extend_from_slice(&compress_block_bc3(&block)),
+            }
+        }
+    }
+    return output;
+}
+
+/// Range fit BC1 encoding, endpoints are the extremes of the block along the
+/// diagonal of its colour bounding box
+pub fn compress_block_bc1(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let mut min: [i32; 3] = [255; 3];
+    let mut max: [i32; 3] = [0; 3];
+    for texel in block.iter() {
+        for c in 0..3 {
+            min[c] = min[c].min(texel[c] as i32);
+            max[c] = max[c].max(texel[c] as i32);
+        }
+    }
+    let axis: [i32; 3] = [max[0] - min[0], max[1] - min[1], max[2] - min[2]];
+    let project = |texel: &[u8; 4]| -> i32 {
+        return (0..3).map(|c: usize| texel[c] as i32 * axis[c]).sum();
+    };
+    let mut low: &[u8; 4] = &block[0];
+    let mut high: &[u8; 4] = &block[0];
+    for texel in block.iter() {
+        if project(texel) < project(low) {
+            low = texel;
+        }
+        if project(texel) > project(high) {
+            high = texel;
+        }
+    }
+    let mut colour_0: u16 = pack_565(high);
+    let mut colour_1: u16 = pack_565(low);
+    if colour_0 < colour_1 {
+        std::mem::swap(&mut colour_0, &mut colour_1);
+    }
+    let mut encoded: [u8; 8] = [0; 8];
+    encoded[0..2].copy_from_slice(&colour_0.to_le_bytes());
+    encoded[2..4].copy_from_slice(&colour_1.to_le_bytes());
+    if colour_0 == colour_1 {
+        // Single colour, every index selects colour_0
+        return encoded;
+    }
+    let c0: [i32; 3] = unpack_565(colour_0);
+    let c1: [i32; 3] = unpack_565(colour_1);
+    // Palette order matches the index codes of four colour mode
+    let palette: [[i32; 3]; 4] = [
+        c0,
+        c1,
+        [(2 * c0[0] + c1[0]) / 3, (2 * c0[1] + c1[1]) / 3, (2 * c0[2] + c1[2]) / 3],
+        [(c0[0] + 2 * c1[0]) / 3, (c0[1] + 2 * c1[1]) / 3, (c0[2] + 2 * c1[2]) / 3],
+    ];
+    let mut indices: u32 = 0;
+    for (i, texel) in block.iter().enumerate() {
+        let nearest: usize = (0..4)
+            .min_by_key(|p: &usize| {
+                return (0..3)
+                    .map(|c: usize| (texel[c] as i32 - palette[*p][c]).pow(2))
+                    .sum::<i32>();
+            })
+            .unwrap();
+        indices |= (nearest as u32) << (i * 2);
+    }
+    encoded[4..8].copy_from_slice(&indices.to_le_bytes());
+    return encoded;
+}
+
+/// BC3 encoding, an interpolated alpha block followed by a BC1 colour block
+pub fn compress_block_bc3(block: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut encoded: [u8; 16] = [0; 16];
+    let alpha_0: u8 = block.iter().map(|texel: &[u8; 4]| texel[3]).max().unwrap();
+    let alpha_1: u8 = block.iter().map(|texel: &[u8; 4]| texel[3]).min().unwrap();
+    encoded[0] = alpha_0;
+    encoded[1] = alpha_1;
+    if alpha_0 != alpha_1 {
+        // Eight alpha mode as alpha_0 > alpha_1, codes 2..7 interpolate from alpha_0 to alpha_1
+        let (a0, a1): (i32, i32) = (alpha_0 as i32, alpha_1 as i32);
+        let mut palette: [i32; 8] = [a0, a1, 0, 0, 0, 0, 0, 0];
+        for code in 2..8 {
+            palette[code] = ((8 - code as i32) * a0 + (code as i32 - 1) * a1) / 7;
+        }
+        let mut indices: u64 = 0;
+        for (i, texel) in block.iter().enumerate() {
+            let nearest: usize = (0..8)
+                .min_by_key(|p: &usize| (texel[3] as i32 - palette[*p]).abs())
+                .unwrap();
+            indices |= (nearest as u64) << (i * 3);
+        }
+        encoded[2..8].copy_from_slice(&indices.to_le_bytes()[0..6]);
+    }
+    encoded[8..16].copy_from_slice(&compress_block_bc1(block));
+    return encoded;
+}
+
+fn pack_565(texel: &[u8; 4]) -> u16 {
+    let r: u16 = (texel[0] as u16 * 31 + 127) / 255;
+    let g: u16 = (texel[1] as u16 * 63 + 127) / 255;
+    let b: u16 = (texel[2] as u16 * 31 + 127) / 255;
+    return (r << 11) | (g << 5) | b;
+}
+
+fn unpack_565(colour: u16) -> [i32; 3] {
+    let r: i32 = ((colour >> 11) & 0x1F) as i32;
+    let g: i32 = ((colour >> 5) & 0x3F) as i32;
+    let b: i32 = (colour & 0x1F) as i32;
+    return [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colours of a BC1 block in four colour mode, in texel order
+    fn decode_bc1(encoded: &[u8]) -> [[i32; 3]; 16] {
+        let c0: [i32; 3] = unpack_565(u16::from_le_bytes([encoded[0], encoded[1]]));
+        let c1: [i32; 3] = unpack_565(u16::from_le_bytes([encoded[2], encoded[3]]));
+        let palette: [[i32; 3]; 4] = [
+            c0,
+            c1,
+            [(2 * c0[0] + c1[0]) / 3, (2 * c0[1] + c1[1]) / 3, (2 * c0[2] + c1[2]) / 3],
+            [(c0[0] + 2 * c1[0]) / 3, (c0[1] + 2 * c1[1]) / 3, (c0[2] + 2 * c1[2]) / 3],
+        ];
+        let indices: u32 = u32::from_le_bytes([encoded[4], encoded[5], encoded[6], encoded[7]]);
+        let mut colours: [[i32; 3]; 16] = [[0; 3]; 16];
+        for i in 0..16 {
+            colours[i] = palette[((indices >> (i * 2)) & 0b11) as usize];
+        }
+        return colours;
+    }
+
+    /// Alphas of a BC3 alpha block in eight alpha mode, in texel order
+    fn decode_bc3_alpha(encoded: &[u8]) -> [i32; 16] {
+        let (a0, a1): (i32, i32) = (encoded[0] as i32, encoded[1] as i32);
+        let mut palette: [i32; 8] = [a0, a1, 0, 0, 0, 0, 0, 0];
+        for code in 2..8 {
+            palette[code] = ((8 - code as i32) * a0 + (code as i32 - 1) * a1) / 7;
+        }
+        let mut raw: [u8; 8] = [0; 8];
+        raw[0..6].copy_from_slice(&encoded[2..8]);
+        let indices: u64 = u64::from_le_bytes(raw);
+        let mut alphas: [i32; 16] = [0; 16];
+        for i in 0..16 {
+            alphas[i] = palette[((indices >> (i * 3)) & 0b111) as usize];
+        }
+        return alphas;
+    }
+
+    #[test]
+    fn solid_blocks_encode_exactly() {
+        let encoded: [u8; 8] = compress_block_bc1(&[[255, 0, 0, 255]; 16]);
+        // Pure red is 0xF800 in 5:6:5, both endpoints the same and every index 0
+        assert_eq!(encoded, [0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0]);
+        assert!(decode_bc1(&encoded).iter().all(|colour: &[i32; 3]| *colour == [255, 0, 0]));
+    }
+
+    #[test]
+    fn two_colour_gradients_round_trip_within_tolerance() {
+        let (from, to): ([u8; 3], [u8; 3]) = ([16, 64, 200], [240, 160, 8]);
+        let mut block: [[u8; 4]; 16] = [[0; 4]; 16];
+        for (i, texel) in block.iter_mut().enumerate() {
+            let t: f32 = (i % BLOCK_DIM) as f32 / (BLOCK_DIM - 1) as f32;
+            for c in 0..3 {
+                texel[c] = (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t).round() as u8;
+            }
+            texel[3] = 255;
+        }
+        let decoded: [[i32; 3]; 16] = decode_bc1(&compress_block_bc1(&block));
+        for i in 0..16 {
+            for c in 0..3 {
+                assert!(
+                    (decoded[i][c] - block[i][c] as i32).abs() <= 8,
+                    "texel {} channel {} decoded as {} from {}", i, c, decoded[i][c], block[i][c],
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bc3_alpha_endpoints_are_the_block_extremes() {
+        let mut block: [[u8; 4]; 16] = [[128, 128, 128, 255]; 16];
+        for (i, texel) in block.iter_mut().enumerate() {
+            texel[3] = 20 + i as u8 * 14;
+        }
+        let encoded: [u8; 16] = compress_block_bc3(&block);
+        assert_eq!((encoded[0], encoded[1]), (230, 20));
+        let alphas: [i32; 16] = decode_bc3_alpha(&encoded);
+        for i in 0..16 {
+            assert!((alphas[i] - block[i][3] as i32).abs() <= 15, "texel {} alpha {} from {}", i, alphas[i], block[i][3]);
+        }
+        // The colour half is the block's BC1 encoding
+        assert_eq!(encoded[8..16], compress_block_bc1(&block));
+        // Uniform alpha needs no indices
+        assert_eq!(compress_block_bc3(&[[0, 0, 0, 77]; 16])[0..8], [77, 77, 0, 0, 0, 0, 0, 0]);
+    }
+
+}
@@ -1,6 +1,10 @@
 use std::io::{Result,Error,ErrorKind};
+use std::path::Path;
+use crate::util::memory::{HeapBytes, vec_bytes};
 use image::{
+    ColorType,
     DynamicImage,
+    ImageFormat,
     io::Reader as ImageReader
 };
 
@@ -19,7 +23,12 @@ impl Image {
     }
 
     pub fn load(path: String) -> Result<Self> {
-        let img: DynamicImage = match ImageReader::open(path)?.decode() {
+        return Image::from_path(path);
+    }
+
+    /// Decode an image file, the format is detected from its contents
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let img: DynamicImage = match ImageReader::open(path)?.with_guessed_format()?.decode() {
             Ok(value) => value,
             Err(error) => return Err(Error::new(ErrorKind::InvalidData, format!("{}", error))),
         };
@@ -35,8 +44,185 @@ impl Image {
         return &self.data[(y * self.width + x) * self.channels];
     }
 
-    pub fn save(&self, path: String) {
-        todo!()
+    /// Every channel of the pixel at `x`, `y`, `None` outside the image
+    pub fn pixel(&self, x: usize, y: usize) -> Option<&[u8]> {
+        let start: usize = self.pixel_offset(x, y)?;
+        return self.data.get(start..start + self.channels);
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> Option<&mut [u8]> {
+        let start: usize = self.pixel_offset(x, y)?;
+        return self.data.get_mut(start..start + self.channels);
+    }
+
+    /// Overwrite the pixel at `x`, `y` with one value per channel
+    pub fn put_pixel(&mut self, x: usize, y: usize, value: &[u8]) -> Result<()> {
+        if value.len() != self.channels {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Pixel has {} channels but the image has {}", value.len(), self.channels),
+            ));
+        }
+        let (width, height): (usize, usize) = (self.width, self.height);
+        return match self.pixel_mut(x, y) {
+            Some(pixel) => {
+                pixel.copy_from_slice(value);
+                Ok(())
+            },
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Pixel ({}, {}) is outside the {}x{} image", x, y, width, height),
+            )),
+        };
+    }
+
+    fn pixel_offset(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        return Some((y * self.width + x) * self.channels);
+    }
+
+    /// Reverse the row order in place
+    pub fn flip_vertical(&mut self) -> Result<()> {
+        self.check_size()?;
+        let row: usize = self.width * self.channels;
+        for y in 0..self.height / 2 {
+            let (top, bottom): (&mut [u8], &mut [u8]) = self.data.split_at_mut((self.height - 1 - y) * row);
+            top[y * row..(y + 1) * row].swap_with_slice(&mut bottom[..row]);
+        }
+        return Ok(());
+    }
+
+    /// Copy with four channels. Grey is spread over the colour channels and
+    /// missing alpha is filled with 255.
+    pub fn to_rgba(&self) -> Result<Image> {
+        self.check_size()?;
+        let pixels: std::slice::ChunksExact<u8> = self.data[..self.width * self.height * self.channels].chunks_exact(self.channels);
+        let data: Vec<u8> = match self.channels {
+            1 => pixels.flat_map(|pixel: &[u8]| [pixel[0], pixel[0], pixel[0], 255]).collect(),
+            2 => pixels.flat_map(|pixel: &[u8]| [pixel[0], pixel[0], pixel[0], pixel[1]]).collect(),
+            3 => pixels.flat_map(|pixel: &[u8]| [pixel[0], pixel[1], pixel[2], 255]).collect(),
+            4 => pixels.flatten().copied().collect(),
+            channels => return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot convert an image with {} channels to RGBA", channels),
+            )),
+        };
+        return Ok(Image {
+            channels: 4,
+            width: self.width,
+            height: self.height,
+            data,
+        });
+    }
+
+    /// Half size copy averaging each 2x2 block, as for the next mip level.
+    /// An odd last row or column is dropped, and a side of 1 stays 1.
+    pub fn resize_half(&self) -> Result<Image> {
+        self.check_size()?;
+        let width: usize = (self.width / 2).max(self.width.min(1));
+        let height: usize = (self.height / 2).max(self.height.min(1));
+        let mut data: Vec<u8> = Vec::with_capacity(width * height * self.channels);
+        for y in 0..height {
+            let rows: [usize; 2] = [(y * 2).min(self.height - 1), (y * 2 + 1).min(self.height - 1)];
+            for x in 0..width {
+                let columns: [usize; 2] = [(x * 2).min(self.width - 1), (x * 2 + 1).min(self.width - 1)];
+                for channel in 0..self.channels {
+                    let mut sum: u32 = 0;
+                    for row in rows {
+                        for column in columns {
+                            sum += self.data[(row * self.width + column) * self.channels + channel] as u32;
+                        }
+                    }
+                    data.push(((sum + 2) / 4) as u8);
+                }
+            }
+        }
+        return Ok(Image {
+            channels: self.channels,
+            width,
+            height,
+            data,
+        });
+    }
+
+    /// Copy of the `width` by `height` region with its top left at `x`, `y`
+    pub fn sub_image(&self, x: usize, y: usize, width: usize, height: usize) -> Result<Image> {
+        self.check_size()?;
+        if x.saturating_add(width) > self.width || y.saturating_add(height) > self.height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Region {}x{} at ({}, {}) exceeds the {}x{} image", width, height, x, y, self.width, self.height),
+            ));
+        }
+        let mut data: Vec<u8> = Vec::with_capacity(width * height * self.channels);
+        for row in y..y + height {
+            let start: usize = (row * self.width + x) * self.channels;
+            data.extend_from_slice(&self.data[start..start + width * self.channels]);
+        }
+        return Ok(Image {
+            channels: self.channels,
+            width,
+            height,
+            data,
+        });
+    }
+
+    fn check_size(&self) -> Result<()> {
+        if self.channels == 0 || self.channels > 4 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Unsupported channel count {}", self.channels)));
+        }
+        if self.data.len() < self.width * self.height * self.channels {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} bytes of data is too little for a {}x{}x{} image", self.data.len(), self.width, self.height, self.channels),
+            ));
+        }
+        return Ok(());
+    }
+
+    /// Write the image to disk as PNG, TGA, BMP or JPEG, chosen from the file
+    /// extension. Missing parent directories are created.
+    pub fn save(&self, path: String) -> Result<()> {
+        let extension: Option<String> = Path::new(&path).extension()
+            .map(|extension: &std::ffi::OsStr| extension.to_string_lossy().to_ascii_lowercase());
+        let format: ImageFormat = match extension.as_deref() {
+            Some("png") => ImageFormat::Png,
+            Some("tga") => ImageFormat::Tga,
+            Some("bmp") => ImageFormat::Bmp,
+            Some("jpg" | "jpeg") => ImageFormat::Jpeg,
+            _ => return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot infer an image format from the extension of {}", path),
+            )),
+        };
+        let color_type: ColorType = match self.channels {
+            1 => ColorType::L8,
+            2 => ColorType::La8,
+            3 => ColorType::Rgb8,
+            4 => ColorType::Rgba8,
+            channels => return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot save an image with {} channels", channels),
+            )),
+        };
+        self.check_size()?;
+        if let Some(parent) = Path::new(&path).parent().filter(|parent: &&Path| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        return match image::save_buffer_with_format(&path, &self.data, self.width as u32, self.height as u32, color_type, format) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Error::new(ErrorKind::Other, format!("{}", error))),
+        };
+    }
+
+}
+
+impl HeapBytes for Image {
+
+    fn heap_bytes(&self) -> usize {
+        return vec_bytes(&self.data);
     }
 
 }
@@ -74,13 +260,143 @@ impl From<(&Image, usize)> for Image {
 
 impl From<(usize, usize, usize)> for Image {
 
+    /// Black image of the given size
     fn from((width, height, channels): (usize, usize, usize)) -> Self {
         return Self {
             channels,
             width,
             height,
-            ..Self::default()
+            data: vec![0; width * height * channels],
         };
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize, channels: usize) -> Image {
+        return Image {
+            channels,
+            width,
+            height,
+            data: (0..width * height * channels).map(|i: usize| (i * 7 % 256) as u8).collect(),
+        };
+    }
+
+    /// Image of the given channels and bytes, `data.len()` must be a multiple of `width * channels`
+    fn image(width: usize, channels: usize, data: &[u8]) -> Image {
+        return Image {
+            channels,
+            width,
+            height: data.len() / (width * channels),
+            data: data.to_vec(),
+        };
+    }
+
+    #[test]
+    fn lossless_formats_round_trip() {
+        let directory: std::path::PathBuf = std::env::temp_dir().join(format!("lambda-image-{}", std::process::id()));
+        for (extension, channels) in [("png", 1), ("png", 3), ("png", 4), ("tga", 4), ("bmp", 3)] {
+            let image: Image = gradient(5, 3, channels);
+            // Nested so the parent directory has to be created
+            let path: String = directory.join(format!("{}/image.{}", channels, extension)).to_string_lossy().to_string();
+            image.save(path.clone()).unwrap();
+            let loaded: Image = Image::load(path).unwrap();
+            assert_eq!((loaded.width, loaded.height, loaded.channels), (5, 3, channels), "{} with {} channels", extension, channels);
+            assert_eq!(loaded.data, image.data, "{} with {} channels", extension, channels);
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn saving_needs_a_known_extension_and_channel_count() {
+        let path: String = std::env::temp_dir().join(format!("lambda-image-{}.pmg", std::process::id())).to_string_lossy().to_string();
+        assert_eq!(gradient(2, 2, 3).save(path).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let path: String = std::env::temp_dir().join(format!("lambda-image-{}.png", std::process::id())).to_string_lossy().to_string();
+        assert_eq!(gradient(2, 2, 5).save(path).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn flip_vertical_reverses_rows() {
+        let mut odd: Image = image(2, 1, &[1, 2, 3, 4, 5, 6]);
+        odd.flip_vertical().unwrap();
+        assert_eq!(odd.data, vec![5, 6, 3, 4, 1, 2]);
+        let mut even: Image = image(1, 3, &[1, 2, 3, 4, 5, 6]);
+        even.flip_vertical().unwrap();
+        assert_eq!(even.data, vec![4, 5, 6, 1, 2, 3]);
+    }
+
+    #[test]
+    fn to_rgba_expands_grey_and_fills_alpha() {
+        assert_eq!(image(2, 1, &[7, 9]).to_rgba().unwrap().data, vec![7, 7, 7, 255, 9, 9, 9, 255]);
+        assert_eq!(image(1, 2, &[7, 128]).to_rgba().unwrap().data, vec![7, 7, 7, 128]);
+        assert_eq!(image(1, 3, &[1, 2, 3]).to_rgba().unwrap().data, vec![1, 2, 3, 255]);
+        assert_eq!(image(1, 4, &[1, 2, 3, 4]).to_rgba().unwrap().data, vec![1, 2, 3, 4]);
+        assert!(Image { channels: 5, width: 1, height: 1, data: vec![0; 5] }.to_rgba().is_err());
+    }
+
+    #[test]
+    fn resize_half_averages_blocks() {
+        let half: Image = image(4, 1, &[0, 4, 8, 12, 8, 12, 16, 20, 100, 100, 0, 0, 100, 100, 0, 3]).resize_half().unwrap();
+        assert_eq!((half.width, half.height), (2, 2));
+        assert_eq!(half.data, vec![6, 14, 100, 1]);
+        // An odd last column is dropped and a single row stays a row
+        let row: Image = image(3, 1, &[10, 20, 90]).resize_half().unwrap();
+        assert_eq!((row.width, row.height, row.data), (1, 1, vec![15]));
+    }
+
+    #[test]
+    fn sub_image_copies_the_region() {
+        let source: Image = image(3, 2, &[1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9]);
+        let region: Image = source.sub_image(1, 1, 2, 2).unwrap();
+        assert_eq!((region.width, region.height), (2, 2));
+        assert_eq!(region.data, vec![5, 5, 6, 6, 8, 8, 9, 9]);
+        assert_eq!(source.sub_image(2, 0, 2, 1).err().map(|error: Error| error.kind()), Some(ErrorKind::InvalidInput));
+        assert!(image(2, 1, &[1, 2]).sub_image(0, 0, 2, 2).is_err());
+    }
+
+    #[test]
+    fn operations_reject_short_data() {
+        let short: Image = Image { channels: 3, width: 2, height: 2, data: vec![0; 6] };
+        assert_eq!(short.clone().flip_vertical().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(short.to_rgba().is_err() && short.resize_half().is_err());
+    }
+
+    #[test]
+    fn pixels_cover_every_channel_up_to_the_corners() {
+        let mut pixels: Image = image(2, 3, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(pixels.pixel(0, 0), Some(&[1u8, 2, 3][..]));
+        assert_eq!(pixels.pixel(1, 1), Some(&[10u8, 11, 12][..]));
+        pixels.pixel_mut(1, 0).unwrap()[2] = 0;
+        assert_eq!(pixels.pixel(1, 0), Some(&[4u8, 5, 0][..]));
+        pixels.put_pixel(0, 1, &[20, 21, 22]).unwrap();
+        assert_eq!(pixels.data[6..9], [20, 21, 22]);
+    }
+
+    #[test]
+    fn pixels_outside_the_image_are_none() {
+        let mut pixels: Image = image(2, 1, &[1, 2, 3, 4]);
+        assert_eq!(pixels.pixel(2, 0), None);
+        assert_eq!(pixels.pixel(0, 2), None);
+        assert!(pixels.pixel_mut(usize::MAX, 0).is_none());
+        assert_eq!(pixels.put_pixel(0, 2, &[0]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn put_pixel_needs_one_value_per_channel() {
+        let mut pixels: Image = image(1, 3, &[1, 2, 3]);
+        assert_eq!(pixels.put_pixel(0, 0, &[9, 9]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(pixels.put_pixel(0, 0, &[9, 9, 9, 9]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(pixels.data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn heap_bytes_count_the_pixel_data() {
+        assert_eq!(Image::from((4, 2, 3)).heap_bytes(), 24);
+        assert_eq!(Image::from((4, 2, 3)).to_rgba().unwrap().heap_bytes(), 32);
+        assert_eq!(Image::default().heap_bytes(), 0);
+    }
+
+}
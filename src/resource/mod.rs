@@ -1,2 +1,4 @@
 pub mod image;
 pub mod resource;
+pub mod block_compression;
+pub mod wad_manager;
@@ -0,0 +1,129 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::map::AssetPaths;
+use crate::map::bsp::DECAL_WADS;
+use crate::map::error::BspError;
+use crate::map::wad::{FileWadProvider, Wad, WadProvider};
+
+/// Keeps WADs open between map loads so maps and decals that share a WAD
+/// only open and index it once
+pub struct WadManager {
+    provider: Arc<dyn WadProvider>,
+    wads: HashMap<PathBuf, Wad>,
+    decal_wads: Vec<String>, // Opened for every map, relative to the provider
+    open_count: usize,
+}
+
+impl WadManager {
+
+    pub fn new(provider: Arc<dyn WadProvider>) -> Self {
+        return WadManager {
+            provider,
+            wads: HashMap::new(),
+            decal_wads: DECAL_WADS.to_vec(),
+            open_count: 0,
+        };
+    }
+
+    /// Manager searching the WAD directories in order, with their decal WADs
+    pub fn from_asset_paths(asset_paths: &AssetPaths) -> Self {
+        let mut manager: WadManager = WadManager::new(Arc::new(FileWadProvider::with_dirs(asset_paths.wad_dirs.clone())));
+        manager.set_decal_wads(asset_paths.decal_wad_names());
+        return manager;
+    }
+
+    pub fn decal_wads(&self) -> &Vec<String> {
+        return &self.decal_wads;
+    }
+
+    pub fn set_decal_wads(&mut self, decal_wads: Vec<String>) {
+        self.decal_wads = decal_wads;
+    }
+
+    /// Return the WAD with this name, opening it through the provider the
+    /// first time it is requested. Failures are not cached, so a WAD that
+    /// appears later can still be opened.
+    pub fn open(&mut self, name: &str) -> Result<&mut Wad, BspError> {
+        return match self.wads.entry(PathBuf::from(name)) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let wad: Wad = self.provider.open_wad(name)?;
+                self.open_count += 1;
+                debug!(&crate::LOGGER, "Opened WAD {}", name);
+                Ok(entry.insert(wad))
+            },
+        };
+    }
+
+    /// An already open WAD
+    pub fn get(&self, name: &str) -> Option<&Wad> {
+        return self.wads.get(Path::new(name));
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Wad> {
+        return self.wads.get_mut(Path::new(name));
+    }
+
+    /// Number of WADs opened through the provider over the manager's lifetime
+    pub fn open_count(&self) -> usize {
+        return self.open_count;
+    }
+
+    /// Close every open WAD, they are reopened on their next request
+    pub fn close_all(&mut self) {
+        self.wads.clear();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::bsp::BSP;
+    use crate::map::load_options::BspLoadOptions;
+    use crate::map::micro_map::{EmbeddedWads, MICRO_MAP, MICRO_WAD_NAME};
+
+    #[test]
+    fn maps_sharing_a_wad_open_it_once() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("lambda-wad-manager-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let maps: [PathBuf; 2] = [directory.join("first.bsp"), directory.join("second.bsp")];
+        for map in maps.iter() {
+            std::fs::write(map, MICRO_MAP).unwrap();
+        }
+        let mut wads: WadManager = WadManager::new(Arc::new(EmbeddedWads));
+        let loaded: Vec<Result<BSP, BspError>> = maps.iter()
+            .map(|map: &PathBuf| BSP::from_file_with_wads(map, &BspLoadOptions::default(), &mut wads))
+            .collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+        for bsp in loaded {
+            assert!(bsp.unwrap().missing_textures.is_empty());
+        }
+        assert_eq!(wads.open_count(), 1);
+        assert!(wads.get(MICRO_WAD_NAME).is_some());
+    }
+
+    #[test]
+    fn closed_wads_are_opened_again() {
+        let mut wads: WadManager = WadManager::new(Arc::new(EmbeddedWads));
+        wads.open(MICRO_WAD_NAME).unwrap();
+        wads.open(MICRO_WAD_NAME).unwrap();
+        assert_eq!(wads.open_count(), 1);
+        wads.close_all();
+        assert!(wads.get(MICRO_WAD_NAME).is_none());
+        wads.open(MICRO_WAD_NAME).unwrap();
+        assert_eq!(wads.open_count(), 2);
+    }
+
+    #[test]
+    fn failed_opens_are_not_cached() {
+        let mut wads: WadManager = WadManager::new(Arc::new(EmbeddedWads));
+        assert!(wads.open("valve/decals.wad").is_err());
+        assert!(wads.get("valve/decals.wad").is_none());
+        assert_eq!(wads.open_count(), 0);
+    }
+
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use crate::map::bsp30::{MAX_KEY, MAX_VALUE};
+
 #[derive(Clone)]
 pub struct Entity {
     pub (crate) properties: HashMap<String, String>,
@@ -8,29 +10,45 @@ pub struct Entity {
 impl Entity {
 
     pub fn new(properties_string: &String) -> Self {
-        let mut pos: usize = 0;
+        let mut violations: Vec<String> = Vec::new();
+        let entity: Entity = Entity::parse(properties_string, &mut violations);
+        for violation in violations {
+            warn!(&crate::LOGGER, "[entity] {}", violation);
+        }
+        return entity;
+    }
+
+    /// Parse the properties of an entity block, repairing spec violations
+    /// (over-long keys/values, duplicate keys, malformed pairs) and describing
+    /// each in `violations`. Never fails, returning whatever valid pairs were found.
+    pub fn parse(properties_string: &String, violations: &mut Vec<String>) -> Self {
         let mut instance: Entity = Entity {
             properties: HashMap::new(),
         };
+        let mut pos: usize = 0;
         loop {
-            if pos > properties_string.len() {
-                break;
+            let mut name: String = match next_quoted(properties_string, &mut pos, violations) {
+                Some(name) => name,
+                None => break,
+            };
+            let mut value: String = match next_quoted(properties_string, &mut pos, violations) {
+                Some(value) => value,
+                None => {
+                    violations.push(format!("Key '{}' has no value, skipping", name));
+                    break;
+                },
+            };
+            if name.len() >= MAX_KEY {
+                violations.push(format!("Key '{}' exceeds {} bytes, truncating", name, MAX_KEY - 1));
+                truncate_at_boundary(&mut name, MAX_KEY - 1);
+            }
+            if value.len() >= MAX_VALUE {
+                violations.push(format!("Value of '{}' exceeds {} bytes, truncating", name, MAX_VALUE - 1));
+                truncate_at_boundary(&mut value, MAX_VALUE - 1);
             }
-            if let Some(next_pos) = properties_string[pos..].find('"') {
-                pos += next_pos;
-            } else {
-                break;
+            if let Some(previous) = instance.properties.insert(name.clone(), value) {
+                violations.push(format!("Duplicate key '{}', replacing previous value '{}'", name, previous));
             }
-            pos += 1;
-            let mut end: usize = properties_string[pos..].find('"').unwrap();
-            let name: String = properties_string[pos..(pos + end)].to_string();
-            pos += end + 1;
-            pos += properties_string[pos..].find('"').unwrap();
-            pos += 1;
-            end = properties_string[pos..].find('"').unwrap();
-            let value: String = properties_string[pos..(pos + end)].to_string();
-            pos += end + 1;
-            instance.properties.insert(name, value);
         }
         return instance;
     }
@@ -39,4 +57,132 @@ impl Entity {
         return self.properties.get(name);
     }
 
+    /// Every key and value, in no particular order
+    pub fn properties(&self) -> &HashMap<String, String> {
+        return &self.properties;
+    }
+
+}
+
+/// Read the next quoted string at or after `pos`, advancing past its closing
+/// quote. Unquoted text before it is reported and skipped.
+fn next_quoted(string: &String, pos: &mut usize, violations: &mut Vec<String>) -> Option<String> {
+    let remaining: &str = &string[*pos..];
+    let open: usize = match remaining.find('"') {
+        Some(open) => open,
+        None => {
+            if !remaining.trim().is_empty() {
+                violations.push(format!("Ignoring stray text '{}'", remaining.trim()));
+            }
+            *pos = string.len();
+            return None;
+        },
+    };
+    if !remaining[..open].trim().is_empty() {
+        violations.push(format!("Ignoring stray text '{}'", remaining[..open].trim()));
+    }
+    let start: usize = *pos + open + 1;
+    return match string[start..].find('"') {
+        Some(length) => {
+            *pos = start + length + 1;
+            Some(string[start..start + length].to_string())
+        },
+        None => {
+            violations.push(format!("Unterminated quoted string '{}', skipping", &string[start..]));
+            *pos = string.len();
+            None
+        },
+    };
+}
+
+fn truncate_at_boundary(string: &mut String, max_len: usize) {
+    let mut len: usize = max_len.min(string.len());
+    while !string.is_char_boundary(len) {
+        len -= 1;
+    }
+    string.truncate(len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(properties: &str) -> (Entity, Vec<String>) {
+        let mut violations: Vec<String> = Vec::new();
+        let entity: Entity = Entity::parse(&properties.to_string(), &mut violations);
+        return (entity, violations);
+    }
+
+    #[test]
+    fn empty_strings_have_no_properties() {
+        let (entity, violations) = parse("");
+        assert!(entity.properties().is_empty());
+        assert!(violations.is_empty());
+        let (entity, violations) = parse("  \n ");
+        assert!(entity.properties().is_empty());
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn multiple_properties_are_all_kept() {
+        let (entity, violations) = parse("\n\"classname\" \"light\"\n\"origin\" \"0 0 64\"\n\"light\" \"300\"\n");
+        assert!(violations.is_empty(), "{:?}", violations);
+        assert_eq!(entity.properties().len(), 3);
+        assert_eq!(entity.find_property(&"classname".to_string()).unwrap(), "light");
+        assert_eq!(entity.find_property(&"origin".to_string()).unwrap(), "0 0 64");
+        assert_eq!(entity.find_property(&"light".to_string()).unwrap(), "300");
+    }
+
+    #[test]
+    fn keys_without_a_value_are_skipped() {
+        let (entity, violations) = parse("\"classname\" \"info_null\" \"targetname\"");
+        assert_eq!(entity.properties().len(), 1);
+        assert_eq!(entity.find_property(&"classname".to_string()).unwrap(), "info_null");
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("'targetname' has no value"), "{}", violations[0]);
+    }
+
+    #[test]
+    fn unbalanced_quotes_keep_the_pairs_before_them() {
+        let (entity, violations) = parse("\"classname\" \"worldspawn\" \"wad\" \"half.wad");
+        assert_eq!(entity.properties().len(), 1);
+        assert_eq!(entity.find_property(&"classname".to_string()).unwrap(), "worldspawn");
+        assert!(entity.find_property(&"wad".to_string()).is_none());
+        assert!(violations.iter().any(|violation: &String| violation.contains("Unterminated")), "{:?}", violations);
+        assert!(violations.iter().any(|violation: &String| violation.contains("'wad' has no value")), "{:?}", violations);
+    }
+
+    #[test]
+    fn stray_text_is_ignored() {
+        let (entity, violations) = parse("junk \"classname\" \"light\" trailing");
+        assert_eq!(entity.find_property(&"classname".to_string()).unwrap(), "light");
+        assert_eq!(violations.len(), 2, "{:?}", violations);
+    }
+
+    #[test]
+    fn over_long_keys_and_values_are_truncated() {
+        let key: String = "k".repeat(MAX_KEY + 4);
+        let value: String = "v".repeat(MAX_VALUE + 4);
+        let (entity, violations) = parse(&format!("\"{}\" \"{}\"", key, value));
+        assert_eq!(violations.len(), 2, "{:?}", violations);
+        let (stored_key, stored_value) = entity.properties().iter().next().unwrap();
+        assert_eq!(stored_key, &key[..MAX_KEY - 1]);
+        assert_eq!(stored_value, &value[..MAX_VALUE - 1]);
+    }
+
+    #[test]
+    fn truncation_stays_on_a_char_boundary() {
+        let value: String = format!("{}é", "v".repeat(MAX_VALUE - 2));
+        let (entity, _) = parse(&format!("\"message\" \"{}\"", value));
+        let stored: &String = entity.find_property(&"message".to_string()).unwrap();
+        assert_eq!(stored, &"v".repeat(MAX_VALUE - 2));
+    }
+
+    #[test]
+    fn duplicate_keys_keep_the_last_value() {
+        let (entity, violations) = parse("\"angle\" \"90\" \"angle\" \"180\"");
+        assert_eq!(entity.find_property(&"angle".to_string()).unwrap(), "180");
+        assert_eq!(violations.len(), 1);
+    }
+
 }
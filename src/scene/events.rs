@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use crate::scene::entity::Entity;
+
+// Guard against entities that fire each other in a zero delay cycle
+const MAX_DELIVERIES_PER_TICK: usize = 1024;
+
+// multi_manager keys that are entity properties rather than targets
+const MULTI_MANAGER_RESERVED_KEYS: [&str; 5] = ["classname", "targetname", "origin", "spawnflags", "wait"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UseType {
+    Toggle,
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone)]
+pub struct UseMessage {
+    pub activator: Option<usize>, // Entity index that started the chain
+    pub target_name: String,
+    pub use_type: UseType,
+    pub delay: f32, // Seconds from being sent until delivery
+}
+
+/// Called with the recipient entity index, returns any messages it fires in response
+pub type UseHandler = Box<dyn FnMut(usize, &UseMessage) -> Vec<UseMessage>>;
+
+struct QueuedMessage {
+    fire_time: f32,
+    message: UseMessage,
+}
+
+pub struct EventBus {
+    time: f32,
+    queue: Vec<QueuedMessage>, // Ordered by fire time, ties in send order
+    target_names: HashMap<String, Vec<usize>>,
+    handlers: HashMap<usize, UseHandler>,
+    uses: Vec<(usize, UseType)>, // Recipients delivered to since the last take_uses, in delivery order
+}
+
+impl EventBus {
+
+    pub fn new() -> Self {
+        return EventBus {
+            time: 0.0,
+            queue: Vec::new(),
+            target_names: HashMap::new(),
+            handlers: HashMap::new(),
+            uses: Vec::new(),
+        };
+    }
+
+    /// Index the targetnames of the given entities, register the built in
+    /// producers such as `multi_manager` and queue the targets of every
+    /// `trigger_auto`, which fire once when the map starts
+    pub fn from_entities(entities: &Vec<Entity>) -> Self {
+        let mut bus: EventBus = EventBus::new();
+        for (index, entity) in entities.iter().enumerate() {
+            if let Some(target_name) = entity.find_property(&"targetname".to_string()) {
+                bus.target_names.entry(target_name.clone()).or_insert_with(Vec::new).push(index);
+            }
+            match entity.find_property(&"classname".to_string()).map(String::as_str) {
+                Some("multi_manager") => bus.register(index, EventBus::multi_manager_handler(entity)),
+                Some("trigger_auto") => {
+                    if let Some(message) = EventBus::trigger_auto_message(index, entity) {
+                        bus.send(message);
+                    }
+                },
+                _ => {},
+            };
+        }
+        return bus;
+    }
+
+    pub fn register(&mut self, entity_index: usize, handler: UseHandler) {
+        self.handlers.insert(entity_index, handler);
+    }
+
+    pub fn pending(&self) -> usize {
+        return self.queue.len();
+    }
+
+    pub fn send(&mut self, message: UseMessage) {
+        let fire_time: f32 = self.time + message.delay.max(0.0);
+        let position: usize = self.queue.partition_point(|queued: &QueuedMessage| queued.fire_time <= fire_time);
+        self.queue.insert(position, QueuedMessage {
+            fire_time,
+            message,
+        });
+    }
+
+    /// Advance the bus clock and deliver every message due by the new time,
+    /// including zero delay messages fired during delivery. Returns the number
+    /// of handler invocations.
+    pub fn tick(&mut self, elapsed: f32) -> usize {
+        self.time += elapsed.max(0.0);
+        let mut delivered: usize = 0;
+        while self.queue.first().map_or(false, |queued: &QueuedMessage| queued.fire_time <= self.time) {
+            if delivered >= MAX_DELIVERIES_PER_TICK {
+                warn!(&crate::LOGGER, "Delivered {} messages this tick, deferring {} to the next", delivered, self.queue.len());
+                break;
+            }
+            let queued: QueuedMessage = self.queue.remove(0);
+            let recipients: Vec<usize> = match self.target_names.get(&queued.message.target_name) {
+                Some(recipients) => recipients.clone(),
+                None => {
+                    warn!(&crate::LOGGER, "Dropping message for unknown target '{}'", queued.message.target_name);
+                    continue;
+                },
+            };
+            for recipient in recipients {
+                self.uses.push((recipient, queued.message.use_type));
+                let handler: &mut UseHandler = match self.handlers.get_mut(&recipient) {
+                    Some(handler) => handler,
+                    None => {
+                        trace!(&crate::LOGGER, "Entity {} ({}) has no use handler", recipient, queued.message.target_name);
+                        continue;
+                    },
+                };
+                let fired: Vec<UseMessage> = handler(recipient, &queued.message);
+                delivered += 1;
+                for message in fired {
+                    self.send(message);
+                }
+            }
+        }
+        return delivered;
+    }
+
+    /// Entities delivered to since the last call with how they were used, for
+    /// state the bus does not own such as switchable lights
+    pub fn take_uses(&mut self) -> Vec<(usize, UseType)> {
+        return std::mem::take(&mut self.uses);
+    }
+
+    /// Message a `trigger_auto` sends to its `target` after its `delay`.
+    /// `triggerstate` 0 turns the target off, 1 on and anything else toggles it.
+    fn trigger_auto_message(index: usize, entity: &Entity) -> Option<UseMessage> {
+        let Some(target_name) = entity.find_property(&"target".to_string()) else {
+            warn!(&crate::LOGGER, "trigger_auto {} has no target, ignoring", index);
+            return None;
+        };
+        let use_type: UseType = match entity.find_property(&"triggerstate".to_string()).map(|state: &String| state.trim()) {
+            Some("0") => UseType::Off,
+            Some("1") => UseType::On,
+            _ => UseType::Toggle,
+        };
+        return Some(UseMessage {
+            activator: Some(index),
+            target_name: target_name.clone(),
+            use_type,
+            delay: entity.find_property(&"delay".to_string())
+                .and_then(|delay: &String| delay.trim().parse::<f32>().ok())
+                .unwrap_or(0.0),
+        });
+    }
+
+    /// Every non reserved key of a multi_manager is a target name, with its
+    /// value the delay in seconds. Duplicate targets are suffixed with `#n`.
+    fn multi_manager_handler(entity: &Entity) -> UseHandler {
+        let mut targets: Vec<(String, f32)> = entity.properties.iter()
+            .filter(|(key, _): &(&String, &String)| !MULTI_MANAGER_RESERVED_KEYS.contains(&key.as_str()))
+            .map(|(key, value): (&String, &String)| {
+                let name: &str = key.split('#').next().unwrap_or(key);
+                return (name.to_string(), value.trim().parse::<f32>().unwrap_or(0.0));
+            })
+            .collect();
+        // Property order is not preserved, fire in delay then name order for determinism
+        targets.sort_by(|a: &(String, f32), b: &(String, f32)| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        return Box::new(move |_: usize, message: &UseMessage| {
+            return targets.iter()
+                .map(|(target_name, delay): &(String, f32)| UseMessage {
+                    activator: message.activator,
+                    target_name: target_name.clone(),
+                    use_type: message.use_type,
+                    delay: *delay,
+                })
+                .collect();
+        });
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn entity(properties: &str) -> Entity {
+        return Entity::new(&properties.to_string());
+    }
+
+    fn message(target_name: &str, delay: f32) -> UseMessage {
+        return UseMessage {
+            activator: None,
+            target_name: target_name.to_string(),
+            use_type: UseType::Toggle,
+            delay,
+        };
+    }
+
+    /// Handler recording the recipient and target of each message it gets
+    fn recorder(log: &Rc<RefCell<Vec<(usize, String)>>>) -> UseHandler {
+        let log: Rc<RefCell<Vec<(usize, String)>>> = Rc::clone(log);
+        return Box::new(move |recipient: usize, message: &UseMessage| {
+            log.borrow_mut().push((recipient, message.target_name.clone()));
+            return Vec::new();
+        });
+    }
+
+    #[test]
+    fn delivers_in_delay_then_send_order() {
+        let entities: Vec<Entity> = vec![
+            entity("\"targetname\" \"late\""),
+            entity("\"targetname\" \"early\""),
+            entity("\"targetname\" \"tied\""),
+        ];
+        let mut bus: EventBus = EventBus::from_entities(&entities);
+        let log: Rc<RefCell<Vec<(usize, String)>>> = Rc::new(RefCell::new(Vec::new()));
+        for index in 0..entities.len() {
+            bus.register(index, recorder(&log));
+        }
+        bus.send(message("late", 2.0));
+        bus.send(message("early", 1.0));
+        bus.send(message("tied", 1.0));
+        assert_eq!(bus.tick(0.5), 0);
+        assert_eq!(bus.pending(), 3);
+        assert_eq!(bus.tick(0.5), 2);
+        assert_eq!(bus.tick(1.0), 1);
+        let order: Vec<usize> = log.borrow().iter().map(|(recipient, _): &(usize, String)| *recipient).collect();
+        assert_eq!(order, vec![1, 2, 0]);
+        assert_eq!(bus.pending(), 0);
+    }
+
+    #[test]
+    fn multi_manager_fans_out_with_its_delays() {
+        let entities: Vec<Entity> = vec![
+            entity("\"classname\" \"multi_manager\" \"targetname\" \"mm\" \"door\" \"0\" \"lamp\" \"1.5\" \"lamp#1\" \"3\""),
+            entity("\"targetname\" \"door\""),
+            entity("\"targetname\" \"lamp\""),
+        ];
+        let mut bus: EventBus = EventBus::from_entities(&entities);
+        let log: Rc<RefCell<Vec<(usize, String)>>> = Rc::new(RefCell::new(Vec::new()));
+        bus.register(1, recorder(&log));
+        bus.register(2, recorder(&log));
+        bus.send(message("mm", 0.0));
+        // The manager and its zero delay target are delivered in the same tick
+        assert_eq!(bus.tick(0.0), 2);
+        assert_eq!(*log.borrow(), vec![(1, "door".to_string())]);
+        assert_eq!(bus.pending(), 2);
+        bus.tick(1.5);
+        bus.tick(1.5);
+        assert_eq!(*log.borrow(), vec![(1, "door".to_string()), (2, "lamp".to_string()), (2, "lamp".to_string())]);
+        let uses: Vec<(usize, UseType)> = bus.take_uses();
+        assert_eq!(uses.iter().map(|(recipient, _): &(usize, UseType)| *recipient).collect::<Vec<usize>>(), vec![0, 1, 2, 2]);
+        assert!(bus.take_uses().is_empty());
+    }
+
+    #[test]
+    fn unknown_targets_are_dropped() {
+        let entities: Vec<Entity> = vec![entity("\"targetname\" \"known\"")];
+        let mut bus: EventBus = EventBus::from_entities(&entities);
+        let log: Rc<RefCell<Vec<(usize, String)>>> = Rc::new(RefCell::new(Vec::new()));
+        bus.register(0, recorder(&log));
+        bus.send(message("missing", 0.0));
+        bus.send(message("known", 0.0));
+        assert_eq!(bus.tick(0.1), 1);
+        assert_eq!(bus.pending(), 0);
+        assert_eq!(*log.borrow(), vec![(0, "known".to_string())]);
+        assert_eq!(bus.take_uses(), vec![(0, UseType::Toggle)]);
+    }
+
+    #[test]
+    fn trigger_auto_fires_its_target_after_its_delay() {
+        let entities: Vec<Entity> = vec![
+            entity("\"classname\" \"trigger_auto\" \"target\" \"lamp\" \"delay\" \"2\" \"triggerstate\" \"1\""),
+            entity("\"classname\" \"light\" \"targetname\" \"lamp\""),
+            entity("\"classname\" \"trigger_auto\""),
+        ];
+        let mut bus: EventBus = EventBus::from_entities(&entities);
+        assert_eq!(bus.pending(), 1);
+        bus.tick(1.0);
+        assert!(bus.take_uses().is_empty());
+        bus.tick(1.0);
+        assert_eq!(bus.take_uses(), vec![(1, UseType::On)]);
+    }
+
+}
@@ -0,0 +1,212 @@
+use crate::map::bsp::MAX_LIGHT_STYLES;
+use crate::scene::entity::Entity;
+
+// Lights passed to the static shader each frame, matches its array sizes
+pub const MAX_POINT_LIGHTS: usize = 8;
+// Brightness of a light without a `_light` key, as in the compile tools
+const DEFAULT_LIGHT: [f32; 4] = [255.0, 255.0, 255.0, 200.0];
+// The compile tools give named lights styles from here up so they can be switched
+pub const FIRST_SWITCHABLE_STYLE: usize = 32;
+// spawnflags bit of a named light that starts switched off
+const LIGHT_START_OFF: u32 = 1;
+
+/// A `light` entity. The colour is normalised to [0, 1] and the intensity
+/// is the compile tools' brightness, used as the radius the light reaches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub origin: glm::Vec3,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    pub style: Option<usize>, // Light style scaling the brightness, None for constant lights
+}
+
+impl PointLight {
+
+    /// Colour and intensity from a `_light` value. `r g b brightness` gives
+    /// both directly, `r g b` uses its brightest component as the intensity,
+    /// and a single value is a white light of that intensity.
+    pub fn parse_light(value: &str) -> Option<(glm::Vec3, f32)> {
+        let components: Vec<f32> = value.split_whitespace()
+            .map_while(|component: &str| component.parse::<f32>().ok())
+            .collect();
+        let (color, intensity): (glm::Vec3, f32) = match components[..] {
+            [r, g, b, brightness] => (glm::vec3(r, g, b) / 255.0, brightness),
+            [r, g, b] => {
+                let brightest: f32 = r.max(g).max(b);
+                if brightest <= 0.0 {
+                    return None;
+                }
+                (glm::vec3(r, g, b) / brightest, brightest)
+            },
+            [brightness] => (glm::vec3(1.0, 1.0, 1.0), brightness),
+            _ => return None,
+        };
+        if intensity <= 0.0 {
+            return None;
+        }
+        return Some((glm::clamp(&color, 0.0, 1.0), intensity));
+    }
+
+    /// Light described by a `light` entity, `None` for other entities or
+    /// lights without a usable origin
+    pub fn from_entity(entity: &Entity) -> Option<PointLight> {
+        if entity.find_property(&"classname".to_string()).map(String::as_str) != Some("light") {
+            return None;
+        }
+        let origin: Vec<f32> = entity.find_property(&"origin".to_string())?
+            .split_whitespace()
+            .map_while(|component: &str| component.parse::<f32>().ok())
+            .collect();
+        let [x, y, z] = origin[..] else {
+            warn!(&crate::LOGGER, "Light origin is not a 3D vector, skipping");
+            return None;
+        };
+        let (color, intensity): (glm::Vec3, f32) = match entity.find_property(&"_light".to_string()) {
+            Some(value) => PointLight::parse_light(value)?,
+            None => (glm::vec3(DEFAULT_LIGHT[0], DEFAULT_LIGHT[1], DEFAULT_LIGHT[2]) / 255.0, DEFAULT_LIGHT[3]),
+        };
+        let style: Option<usize> = entity.find_property(&"style".to_string())
+            .and_then(|style: &String| style.trim().parse::<usize>().ok())
+            .filter(|style: &usize| *style != 0 && *style < MAX_LIGHT_STYLES);
+        return Some(PointLight {
+            origin: glm::vec3(x, y, z),
+            color,
+            intensity,
+            style,
+        });
+    }
+
+    /// Style of a named light entity that can be switched on and off, with
+    /// whether it starts lit. None for other entities and constant lights.
+    pub fn switchable_style(entity: &Entity) -> Option<(usize, bool)> {
+        if !entity.find_property(&"classname".to_string()).map_or(false, |classname: &String| classname.starts_with("light")) {
+            return None;
+        }
+        entity.find_property(&"targetname".to_string())?;
+        let style: usize = entity.find_property(&"style".to_string())
+            .and_then(|style: &String| style.trim().parse::<usize>().ok())
+            .filter(|style: &usize| (FIRST_SWITCHABLE_STYLE..MAX_LIGHT_STYLES).contains(style))?;
+        let spawnflags: u32 = entity.find_property(&"spawnflags".to_string())
+            .and_then(|spawnflags: &String| spawnflags.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+        return Some((style, spawnflags & LIGHT_START_OFF == 0));
+    }
+
+    /// Every light in the entity list
+    pub fn extract(entities: &[Entity]) -> Vec<PointLight> {
+        let lights: Vec<PointLight> = entities.iter().filter_map(PointLight::from_entity).collect();
+        debug!(&crate::LOGGER, "Found {} point lights", lights.len());
+        return lights;
+    }
+
+    /// Intensity with the light's style applied, constant lights ignore the styles
+    pub fn styled_intensity(&self, light_styles: &[f32; MAX_LIGHT_STYLES]) -> f32 {
+        return match self.style {
+            Some(style) => self.intensity * light_styles[style],
+            None => self.intensity,
+        };
+    }
+
+    /// Up to `count` lights closest to `position`, nearest first, with their
+    /// intensity scaled by the light styles. Lights switched off by their
+    /// style are left out.
+    pub fn nearest(lights: &[PointLight], position: &glm::Vec3, count: usize, light_styles: &[f32; MAX_LIGHT_STYLES]) -> Vec<PointLight> {
+        let mut styled: Vec<PointLight> = lights.iter()
+            .map(|light: &PointLight| PointLight {
+                intensity: light.styled_intensity(light_styles),
+                ..*light
+            })
+            .filter(|light: &PointLight| light.intensity > 0.0)
+            .collect();
+        styled.sort_by(|a: &PointLight, b: &PointLight| {
+            glm::distance2(&a.origin, position).total_cmp(&glm::distance2(&b.origin, position))
+        });
+        styled.truncate(count);
+        return styled;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(properties: &str) -> Entity {
+        return Entity::new(&properties.to_string());
+    }
+
+    fn close(a: &glm::Vec3, b: &glm::Vec3) -> bool {
+        return glm::distance(a, b) < 1e-5;
+    }
+
+    #[test]
+    fn four_component_lights_give_colour_and_brightness() {
+        let (color, intensity): (glm::Vec3, f32) = PointLight::parse_light("255 200 100 300").unwrap();
+        assert!(close(&color, &glm::vec3(1.0, 200.0 / 255.0, 100.0 / 255.0)));
+        assert_eq!(intensity, 300.0);
+    }
+
+    #[test]
+    fn three_component_lights_take_the_brightest_as_intensity() {
+        let (color, intensity): (glm::Vec3, f32) = PointLight::parse_light("255 200 100").unwrap();
+        assert!(close(&color, &glm::vec3(1.0, 200.0 / 255.0, 100.0 / 255.0)));
+        assert_eq!(intensity, 255.0);
+        let (color, intensity): (glm::Vec3, f32) = PointLight::parse_light(" 100 50 0 ").unwrap();
+        assert!(close(&color, &glm::vec3(1.0, 0.5, 0.0)));
+        assert_eq!(intensity, 100.0);
+    }
+
+    #[test]
+    fn unusable_light_values_are_rejected() {
+        assert_eq!(PointLight::parse_light(""), None);
+        assert_eq!(PointLight::parse_light("255 200"), None);
+        assert_eq!(PointLight::parse_light("0 0 0"), None);
+        assert_eq!(PointLight::parse_light("255 255 255 0"), None);
+        assert_eq!(PointLight::parse_light("bright"), None);
+    }
+
+    #[test]
+    fn light_entities_become_point_lights() {
+        let light: PointLight = PointLight::from_entity(&entity(
+            "\"classname\" \"light\" \"origin\" \"16 -32 64\" \"_light\" \"255 200 100 300\" \"style\" \"2\"",
+        )).unwrap();
+        assert_eq!(light.origin, glm::vec3(16.0, -32.0, 64.0));
+        assert_eq!(light.intensity, 300.0);
+        assert_eq!(light.style, Some(2));
+        let plain: PointLight = PointLight::from_entity(&entity("\"classname\" \"light\" \"origin\" \"0 0 0\"")).unwrap();
+        assert_eq!((plain.intensity, plain.style), (200.0, None));
+        assert_eq!(PointLight::from_entity(&entity("\"classname\" \"info_player_start\" \"origin\" \"0 0 0\"")), None);
+    }
+
+    #[test]
+    fn only_named_lights_with_high_styles_switch() {
+        assert_eq!(PointLight::switchable_style(&entity("\"classname\" \"light\" \"targetname\" \"lamp\" \"style\" \"32\"")), Some((32, true)));
+        assert_eq!(
+            PointLight::switchable_style(&entity("\"classname\" \"light_spot\" \"targetname\" \"lamp\" \"style\" \"40\" \"spawnflags\" \"1\"")),
+            Some((40, false)),
+        );
+        assert_eq!(PointLight::switchable_style(&entity("\"classname\" \"light\" \"style\" \"32\"")), None);
+        assert_eq!(PointLight::switchable_style(&entity("\"classname\" \"light\" \"targetname\" \"lamp\" \"style\" \"5\"")), None);
+        assert_eq!(PointLight::switchable_style(&entity("\"classname\" \"func_door\" \"targetname\" \"lamp\" \"style\" \"32\"")), None);
+    }
+
+    #[test]
+    fn the_nearest_lit_lights_are_kept() {
+        let light = |x: f32, style: Option<usize>| -> PointLight {
+            return PointLight {
+                origin: glm::vec3(x, 0.0, 0.0),
+                color: glm::vec3(1.0, 1.0, 1.0),
+                intensity: 100.0,
+                style,
+            };
+        };
+        let lights: Vec<PointLight> = vec![light(50.0, None), light(-10.0, Some(3)), light(20.0, None), light(5.0, Some(4))];
+        let mut light_styles: [f32; MAX_LIGHT_STYLES] = [1.0; MAX_LIGHT_STYLES];
+        light_styles[3] = 0.5;
+        light_styles[4] = 0.0;
+        let nearest: Vec<PointLight> = PointLight::nearest(&lights, &glm::vec3(0.0, 0.0, 0.0), 2, &light_styles);
+        assert_eq!(nearest.iter().map(|light: &PointLight| light.origin.x).collect::<Vec<f32>>(), vec![-10.0, 20.0]);
+        assert_eq!(nearest[0].intensity, 50.0);
+    }
+
+}
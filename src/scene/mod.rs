@@ -1 +1,3 @@
 pub mod entity;
+pub mod events;
+pub mod lights;
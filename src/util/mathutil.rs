@@ -8,3 +8,31 @@ pub fn point_in_box(point: glm::Vec3, min: glm::Vec3, max: glm::Vec3) -> bool {
 pub fn point_in_plane(point: glm::Vec3, normal: glm::Vec3, dist: f32) -> bool {
     return (glm::dot(&point, &normal) - dist).abs() < EPSILON;
 }
+
+pub fn catmull_rom(p0: glm::Vec3, p1: glm::Vec3, p2: glm::Vec3, p3: glm::Vec3, t: f32) -> glm::Vec3 {
+    let t2: f32 = t * t;
+    let t3: f32 = t2 * t;
+    return ((p1 * 2.0)
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5;
+}
+
+pub fn slerp_direction(from: glm::Vec3, to: glm::Vec3, t: f32) -> glm::Vec3 {
+    let a: glm::Vec3 = glm::normalize(&from);
+    let b: glm::Vec3 = glm::normalize(&to);
+    let cos_theta: f32 = glm::dot(&a, &b).clamp(-1.0, 1.0);
+    if cos_theta > 1.0 - f32::EPSILON {
+        // Nearly parallel, fall back to a normalised lerp
+        return glm::normalize(&glm::lerp(&a, &b, t));
+    }
+    let theta: f32 = cos_theta.acos();
+    let sin_theta: f32 = theta.sin();
+    if sin_theta.abs() < f32::EPSILON {
+        // Opposite directions, rotate about any axis perpendicular to the start
+        let helper: glm::Vec3 = if a.z.abs() < 0.9 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+        let axis: glm::Vec3 = glm::normalize(&glm::cross(&a, &helper));
+        return glm::rotate_vec3(&a, std::f32::consts::PI * t, &axis);
+    }
+    return a * (((1.0 - t) * theta).sin() / sin_theta) + b * ((t * theta).sin() / sin_theta);
+}
@@ -0,0 +1,53 @@
+/// Bytes owned on the heap by a value, excluding the value itself
+pub trait HeapBytes {
+    fn heap_bytes(&self) -> usize;
+}
+
+/// Allocated size of a vector of plain data, by capacity rather than length
+pub fn vec_bytes<T>(vec: &Vec<T>) -> usize {
+    return vec.capacity() * std::mem::size_of::<T>();
+}
+
+/// Allocated size of a vector plus the heap data owned by its elements
+pub fn nested_vec_bytes<T: HeapBytes>(vec: &Vec<T>) -> usize {
+    return vec_bytes(vec) + vec.iter().map(|item: &T| item.heap_bytes()).sum::<usize>();
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    pub categories: Vec<(String, usize)>, // Category name and bytes, in insertion order
+}
+
+impl MemoryReport {
+
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Add bytes to a category, creating it if needed
+    pub fn add(&mut self, category: &str, bytes: usize) {
+        match self.categories.iter_mut().find(|(name, _): &&mut (String, usize)| name == category) {
+            Some((_, total)) => *total += bytes,
+            None => self.categories.push((category.to_string(), bytes)),
+        }
+    }
+
+    /// Bytes in a category, 0 when it was never added
+    pub fn bytes(&self, category: &str) -> usize {
+        return self.categories.iter()
+            .find(|(name, _): &&(String, usize)| name == category)
+            .map_or(0, |(_, bytes): &(String, usize)| *bytes);
+    }
+
+    pub fn total(&self) -> usize {
+        return self.categories.iter().map(|(_, bytes): &(String, usize)| bytes).sum();
+    }
+
+    pub fn log(&self) {
+        for (category, bytes) in self.categories.iter() {
+            info!(&crate::LOGGER, "{:>16}: {:>10.1} KiB", category, *bytes as f32 / 1024.0);
+        }
+        info!(&crate::LOGGER, "{:>16}: {:>10.1} KiB", "total", self.total() as f32 / 1024.0);
+    }
+
+}
@@ -1 +1,2 @@
 pub mod mathutil;
+pub mod memory;